@@ -1,7 +1,5 @@
-use std::net::{SocketAddr, UdpSocket};
-
 use coap::CoAPClient;
-use coap_lite::{link_format::LinkFormatWrite, CoapRequest, RequestType};
+use coap_lite::link_format::LinkFormatWrite;
 
 fn main() {
     let url = "coap://127.0.0.1:5683/hello";
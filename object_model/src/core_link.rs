@@ -1,6 +1,6 @@
 use crate::err::ObjectParserError;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CoreLink {
     pub link: String,
     pub object_id: u16,
@@ -9,6 +9,54 @@ pub struct CoreLink {
     pub resource_instance: Option<u16>,
 }
 
+impl CoreLink {
+    /// Creates a link from its IDs, every ID after the first `None` is ignored.
+    pub fn new(
+        object_id: u16,
+        object_instance: Option<u16>,
+        resource_id: Option<u16>,
+        resource_instance: Option<u16>,
+    ) -> Self {
+        let resource_id = object_instance.and(resource_id);
+        let resource_instance = resource_id.and(resource_instance);
+
+        let mut link = format!("</{}", object_id);
+        for id in [object_instance, resource_id, resource_instance]
+            .into_iter()
+            .flatten()
+        {
+            link.push_str(&format!("/{}", id));
+        }
+        link.push('>');
+
+        CoreLink {
+            link,
+            object_id,
+            object_instance,
+            resource_id,
+            resource_instance,
+        }
+    }
+
+    /// The IDs of the link in order, e.g. `[3, 0, 1]` for </3/0/1>
+    pub fn ids(&self) -> Vec<u16> {
+        [
+            Some(self.object_id),
+            self.object_instance,
+            self.resource_id,
+            self.resource_instance,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// The link as an URI path without the angle brackets, e.g. `/3/0/1` for </3/0/1>
+    pub fn path(&self) -> String {
+        self.link.replace(['<', '>'], "")
+    }
+}
+
 // CoRELink looks like </1/0/0>
 impl TryFrom<&str> for CoreLink {
     type Error = ObjectParserError;
@@ -28,10 +76,7 @@ impl TryFrom<&str> for CoreLink {
             resource_instance: None,
         };
 
-        for (index, id) in link.clone().replace(['<', '>'], "")[1..]
-            .split('/')
-            .enumerate()
-        {
+        for (index, id) in link.replace(['<', '>'], "")[1..].split('/').enumerate() {
             match index {
                 0 => parse_id(index, id).map(|value| core_link.object_id = value),
                 1 => parse_id(index, id).map(|value| core_link.object_instance = Some(value)),
@@ -95,6 +140,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_new() {
+        let core_link = CoreLink::new(3, Some(0), Some(7), None);
+        assert_eq!(core_link, CoreLink::try_from("</3/0/7>").unwrap());
+        assert_eq!(core_link.ids(), vec![3, 0, 7]);
+        assert_eq!(core_link.path(), "/3/0/7");
+
+        let core_link = CoreLink::new(3, None, Some(7), Some(1));
+        assert_eq!(core_link, CoreLink::try_from("</3>").unwrap());
+    }
+
     #[test]
     fn test_try_from_invalid_string() {
         let core_link = CoreLink::try_from("</a/2/b>");
@@ -75,7 +75,7 @@ impl fmt::Display for crate::ResourceModel {
             false => "Single",
         };
 
-        let resourcetype = match self.resourcetype.clone() {
+        let resourcetype = match self.resourcetype {
             None => "".to_string(),
             Some(v) => v.to_string(),
         };
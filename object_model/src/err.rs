@@ -1,6 +1,9 @@
 use std::{error::Error, fmt};
 
-use crate::{core_link::CoreLink, Version};
+use crate::{
+    core_link::CoreLink, value::ResourceValue, ResourceOperation, ResourceRange, ResourceType,
+    Version,
+};
 
 #[derive(Debug)]
 pub struct ObjectParserError {
@@ -59,3 +62,151 @@ impl fmt::Display for ModelNotFoundError {
 }
 
 impl std::error::Error for ModelNotFoundError {}
+
+#[derive(Debug)]
+pub enum ValueError {
+    NoType {
+        resource_id: u16,
+    },
+    WrongType {
+        expected: ResourceType,
+        actual: ResourceType,
+    },
+    OutOfRange {
+        value: ResourceValue,
+        range: ResourceRange,
+    },
+    // A single value for a multiple resource, it needs a resource instance
+    Multiple {
+        resource_id: u16,
+    },
+    // A resource instance of a single resource
+    NotMultiple {
+        resource_id: u16,
+    },
+    UnknownResource {
+        resource_id: u16,
+    },
+    OutsideInstance {
+        link: CoreLink,
+        instance: CoreLink,
+    },
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            ValueError::NoType { resource_id } => {
+                write!(f, "Resource {} has no type", resource_id)
+            }
+            ValueError::WrongType { expected, actual } => {
+                write!(f, "Expected a value of type {}, got {}", expected, actual)
+            }
+            ValueError::OutOfRange { value, range } => {
+                write!(f, "Value {:?} is not within range {}", value, range)
+            }
+            ValueError::Multiple { resource_id } => {
+                write!(
+                    f,
+                    "Resource {} has multiple instances, its values need a resource instance",
+                    resource_id
+                )
+            }
+            ValueError::NotMultiple { resource_id } => {
+                write!(f, "Resource {} has no resource instances", resource_id)
+            }
+            ValueError::UnknownResource { resource_id } => {
+                write!(
+                    f,
+                    "Resource {} is not part of the object model",
+                    resource_id
+                )
+            }
+            ValueError::OutsideInstance { link, instance } => {
+                write!(
+                    f,
+                    "Value {} is not part of object instance {}",
+                    link, instance
+                )
+            }
+        }
+    }
+}
+
+impl Error for ValueError {}
+
+// Why a value is not legal for a resource (instance), see ObjectModelStore::validate
+#[derive(Debug)]
+pub enum ValidationError {
+    ModelNotFound(ModelNotFoundError),
+    UnknownResource(CoreLink),
+    NotWritable {
+        link: CoreLink,
+        operations: Option<ResourceOperation>,
+    },
+    NoType(CoreLink),
+    WrongType {
+        link: CoreLink,
+        expected: ResourceType,
+        actual: ResourceType,
+    },
+    OutOfRange {
+        link: CoreLink,
+        value: ResourceValue,
+        range: ResourceRange,
+    },
+    // A value of a multiple resource without resource instance
+    MissingResourceInstance(CoreLink),
+    // A resource instance of a single resource
+    UnexpectedResourceInstance(CoreLink),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            ValidationError::ModelNotFound(err) => write!(f, "{}", err),
+            ValidationError::UnknownResource(link) => {
+                write!(f, "{} is not a resource of the object model", link)
+            }
+            ValidationError::NotWritable { link, operations } => match operations {
+                Some(operations) => write!(
+                    f,
+                    "Resource {} is not writable, its operations are {}",
+                    link, operations
+                ),
+                None => write!(f, "Resource {} has no operations", link),
+            },
+            ValidationError::NoType(link) => write!(f, "Resource {} has no type", link),
+            ValidationError::WrongType {
+                link,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Resource {} expects a value of type {}, got {}",
+                link, expected, actual
+            ),
+            ValidationError::OutOfRange { link, value, range } => write!(
+                f,
+                "Value {:?} for {} is not within range {}",
+                value, link, range
+            ),
+            ValidationError::MissingResourceInstance(link) => write!(
+                f,
+                "Resource {} has multiple instances, the value needs a resource instance",
+                link
+            ),
+            ValidationError::UnexpectedResourceInstance(link) => {
+                write!(f, "Resource {} has no resource instances", link)
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+impl From<ModelNotFoundError> for ValidationError {
+    fn from(err: ModelNotFoundError) -> Self {
+        ValidationError::ModelNotFound(err)
+    }
+}
@@ -1,13 +1,16 @@
 use core_link::CoreLink;
 use err::{ModelNotFoundError, ObjectParserError};
-use object_link::ObjectLink;
 use std::path::Path;
 use std::{collections::HashMap, hash::Hash};
 
 pub mod core_link;
 mod display;
-mod err;
+pub mod err;
 pub mod object_link;
+#[cfg(test)]
+mod test_models;
+mod validate;
+pub mod value;
 mod xml_parser;
 
 pub enum Model {
@@ -15,6 +18,7 @@ pub enum Model {
     Resource(ResourceModel),
 }
 
+#[derive(Default)]
 pub struct ObjectModelStore {
     models: HashMap<u16, ObjectModelVersions>,
 }
@@ -31,34 +35,23 @@ impl ObjectModelStore {
         Ok(())
     }
 
+    /// Adds a single (e.g. user provided) model to the store, replacing the model with the same ID and version.
+    pub fn add_model(&mut self, model: ObjectModel) {
+        self.models
+            .entry(model.id)
+            .or_insert_with(|| ObjectModelVersions {
+                versions: HashMap::new(),
+            })
+            .versions
+            .insert(model.version.clone(), model);
+    }
+
     pub fn get_model(
         &self,
         link: CoreLink,
         version: Option<Version>,
     ) -> Result<Model, ModelNotFoundError> {
-        let object_model = self
-            .models
-            .get(&link.object_id)
-            .ok_or(ModelNotFoundError::ObjectId(link.clone()))?;
-
-        let versioned_object_model =
-            match version {
-                Some(version) => {
-                    object_model
-                        .versions
-                        .get(&version)
-                        .ok_or(ModelNotFoundError::Version {
-                            version: version.clone(),
-                            link: link.clone(),
-                        })
-                }
-                None => object_model.versions.get(&Version::default()).ok_or(
-                    ModelNotFoundError::Version {
-                        version: Version::default(),
-                        link: link.clone(),
-                    },
-                ),
-            }?;
+        let versioned_object_model = self.versioned_model(&link, version)?;
 
         match link.resource_id {
             None => Ok(Model::Object(versioned_object_model.clone())),
@@ -69,6 +62,61 @@ impl ObjectModelStore {
                 .map(|model| Model::Resource(model.clone())),
         }
     }
+
+    /// Returns the model of an object, in the default version if `version` is None.
+    pub fn get_object_model(
+        &self,
+        object_id: u16,
+        version: Option<Version>,
+    ) -> Result<ObjectModel, ModelNotFoundError> {
+        let link = CoreLink::new(object_id, None, None, None);
+        self.versioned_model(&link, version).cloned()
+    }
+
+    /// Returns the newest version of an object model that belongs to the given LwM2M version
+    /// or an earlier one, e.g. Security 1.1 for LwM2M 1.2 when there is no Security 1.2.
+    pub fn get_object_model_for(
+        &self,
+        object_id: u16,
+        lwm2m_version: &Version,
+    ) -> Result<ObjectModel, ModelNotFoundError> {
+        let link = CoreLink::new(object_id, None, None, None);
+        let object_model = self
+            .models
+            .get(&object_id)
+            .ok_or(ModelNotFoundError::ObjectId(link.clone()))?;
+
+        object_model
+            .versions
+            .values()
+            .filter(|model| &model.lwm2m_version <= lwm2m_version)
+            .max_by(|a, b| a.version.cmp(&b.version))
+            .cloned()
+            .ok_or(ModelNotFoundError::Version {
+                version: lwm2m_version.clone(),
+                link,
+            })
+    }
+
+    fn versioned_model(
+        &self,
+        link: &CoreLink,
+        version: Option<Version>,
+    ) -> Result<&ObjectModel, ModelNotFoundError> {
+        let object_model = self
+            .models
+            .get(&link.object_id)
+            .ok_or(ModelNotFoundError::ObjectId(link.clone()))?;
+
+        let version = version.unwrap_or_default();
+        object_model
+            .versions
+            .get(&version)
+            .ok_or(ModelNotFoundError::Version {
+                version,
+                link: link.clone(),
+            })
+    }
 }
 
 #[derive(Debug)]
@@ -114,7 +162,71 @@ pub struct ResourceModel {
     multiple: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl ObjectModel {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mandatory(&self) -> bool {
+        self.mandatory
+    }
+
+    pub fn multiple(&self) -> bool {
+        self.multiple
+    }
+
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    pub fn lwm2m_version(&self) -> &Version {
+        &self.lwm2m_version
+    }
+
+    pub fn resources(&self) -> &HashMap<u16, ResourceModel> {
+        &self.resources
+    }
+}
+
+impl ResourceModel {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mandatory(&self) -> bool {
+        self.mandatory
+    }
+
+    pub fn multiple(&self) -> bool {
+        self.multiple
+    }
+
+    pub fn range(&self) -> Option<&ResourceRange> {
+        self.range.as_ref()
+    }
+
+    pub fn units(&self) -> Option<&str> {
+        self.units.as_deref()
+    }
+
+    pub fn operations(&self) -> Option<ResourceOperation> {
+        self.operations
+    }
+
+    pub fn resourcetype(&self) -> Option<ResourceType> {
+        self.resourcetype
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResourceOperation {
     Read,
     Write,
@@ -122,30 +234,35 @@ pub enum ResourceOperation {
     Execute,
 }
 
-#[derive(Debug, Clone)]
+// The type of a resource, values of a resource are held by value::ResourceValue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ResourceType {
-    String(Option<String>),
-    Integer(Option<i64>),
-    UnsignedInteger(Option<u64>),
-    Opaque(Option<Vec<u8>>),
-    Float(Option<f64>),
-    Boolean(Option<bool>),
-    ObjectLink(Option<ObjectLink>), //e.g. <1/0/3>
-    Time(Option<u64>),              // In case of Execute operation
-    CoreLink(Option<CoreLink>),
+    String,
+    Integer,
+    UnsignedInteger,
+    Opaque,
+    Float,
+    Boolean,
+    ObjectLink, //e.g. 1:3
+    Time,
+    CoreLink,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ResourceRange {
     Numerical(i64, i64),         //start..end  or start-end INCLUSIVE
+    UnsignedNumerical(u64, u64), //start..end with the end beyond i64, INCLUSIVE
+    Float(f64, f64),             //start..end with decimals, INCLUSIVE
     NumericalDiscrete(Vec<i64>), //a,b,c, ...
     DiscreteLength(Vec<u64>),    //specific byte lengths
     Length(u64, u64),            //min..max string bytes
     StringEnum(Vec<String>),     //Possible values for the string
+    Mixed(Vec<ResourceRange>),   //a..b, c, ... the value has to be in one of them
     Other(String),               //If enumeration is not able to be determined
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+// Ordered by the text, which matches the numbers as both are single digits
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Version {
     oma_version: String,
 }
@@ -244,4 +361,34 @@ mod tests {
             assert_eq!(resource_model.name, "Sensor Value".to_string());
         }
     }
+
+    #[test]
+    fn test_get_object_model_for() {
+        let version = |version| Version::try_from(version).unwrap();
+        let security = |object_version, lwm2m_version| {
+            let mut model = test_models::object(0, "LWM2M Security", true, []);
+            model.version = version(object_version);
+            model.lwm2m_version = version(lwm2m_version);
+            model
+        };
+        let mut store = ObjectModelStore::default();
+        store.add_model(security("1.0", "1.0"));
+        store.add_model(security("1.1", "1.1"));
+
+        let model = |lwm2m_version| store.get_object_model_for(0, &version(lwm2m_version));
+        assert_eq!(model("1.0").unwrap().version, version("1.0"));
+        assert_eq!(model("1.1").unwrap().version, version("1.1"));
+        assert_eq!(model("1.2").unwrap().version, version("1.1"));
+        assert!(matches!(
+            store.get_object_model_for(1, &version("1.1")),
+            Err(ModelNotFoundError::ObjectId(_))
+        ));
+
+        let mut store = ObjectModelStore::default();
+        store.add_model(security("1.1", "1.1"));
+        assert!(matches!(
+            store.get_object_model_for(0, &version("1.0")),
+            Err(ModelNotFoundError::Version { .. })
+        ));
+    }
 }
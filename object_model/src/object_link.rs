@@ -1,6 +1,6 @@
 use crate::err::ObjectParserError;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ObjectLink {
     pub link: String,
     pub object_id: u16,
@@ -11,23 +11,16 @@ impl TryFrom<String> for ObjectLink {
     type Error = ObjectParserError;
 
     fn try_from(link: String) -> Result<Self, Self::Error> {
-        let mut object_link = ObjectLink {
-            link: link.clone(),
-            object_id: 0,
-            object_instance: 0,
-        };
-        let split: Vec<&str> = link.split(':').collect();
+        let (object_id, object_instance) = link
+            .split_once(':')
+            .filter(|(_, object_instance)| !object_instance.contains(':'))
+            .ok_or_else(|| ObjectParserError::new("Object Link should match u16:u16 pattern"))?;
 
-        if split.len() > 2 {
-            return Err(ObjectParserError::new(
-                "Object Link should match u16:u16 pattern",
-            ));
-        }
-
-        object_link.object_id = parse_id(0, split[0])?;
-        object_link.object_instance = parse_id(1, split[1])?;
-
-        Ok(object_link)
+        Ok(ObjectLink {
+            object_id: parse_id(0, object_id)?,
+            object_instance: parse_id(1, object_instance)?,
+            link,
+        })
     }
 }
 
@@ -74,4 +67,22 @@ mod tests {
             assert_eq!(e.to_string(), "Object Link index 0, value abc is not a u16");
         }
     }
+
+    #[test]
+    fn test_malformed_object_link() {
+        for link in ["3", "", "3:", ":0"] {
+            let object_link = ObjectLink::try_from(link.to_string());
+            assert!(object_link.is_err(), "{}", link);
+        }
+        let object_link = ObjectLink::try_from("3:x".to_string());
+        assert_eq!(
+            object_link.unwrap_err().to_string(),
+            "Object Link index 1, value x is not a u16"
+        );
+        let object_link = ObjectLink::try_from("65536:0".to_string());
+        assert_eq!(
+            object_link.unwrap_err().to_string(),
+            "Object Link index 0, value 65536 is not a u16"
+        );
+    }
 }
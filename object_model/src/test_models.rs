@@ -0,0 +1,53 @@
+// Shared builders for the models of the unit tests, so every test does not repeat the
+// builder boilerplate of the xml parser.
+use std::collections::HashMap;
+
+use crate::{
+    ObjectModel, ObjectModelBuilder, ResourceModel, ResourceModelBuilder, ResourceOperation,
+    ResourceRange, ResourceType,
+};
+
+/// An optional resource with the given id, e.g. 5700, and a generic name.
+pub fn resource(
+    id: u16,
+    operations: Option<ResourceOperation>,
+    resourcetype: Option<ResourceType>,
+    range: Option<ResourceRange>,
+    multiple: bool,
+) -> ResourceModel {
+    ResourceModelBuilder::default()
+        .id(id)
+        .name(format!("Resource {}", id))
+        .mandatory(false)
+        .operations(operations)
+        .resourcetype(resourcetype)
+        .range(range)
+        .multiple(multiple)
+        .build()
+        .unwrap()
+}
+
+/// An optional object in the default version, with the URN the registry gives its id.
+pub fn object(
+    id: u16,
+    name: &str,
+    multiple: bool,
+    resources: impl IntoIterator<Item = ResourceModel>,
+) -> ObjectModel {
+    // The OMA range ends at 1023, objects above are registered by third parties
+    let kind = if id < 1024 { "oma" } else { "ext" };
+    ObjectModelBuilder::default()
+        .id(id)
+        .name(name.to_owned())
+        .mandatory(false)
+        .multiple(multiple)
+        .urn(format!("urn:oma:lwm2m:{}:{}", kind, id))
+        .resources(
+            resources
+                .into_iter()
+                .map(|resource| (resource.id(), resource))
+                .collect::<HashMap<_, _>>(),
+        )
+        .build()
+        .unwrap()
+}
@@ -0,0 +1,219 @@
+use crate::{
+    core_link::CoreLink,
+    err::{ValidationError, ValueError},
+    value::ResourceValue,
+    ObjectModel, ObjectModelStore, ResourceModel, ResourceOperation, Version,
+};
+
+impl ObjectModelStore {
+    /// Checks whether a value may be written to a resource (instance), e.g. /3303/0/5700.
+    ///
+    /// # Arguments
+    ///
+    /// * `link` - The resource or resource instance the value is for
+    /// * `value` - The value to check
+    /// * `version` - The version of the object model, the default version if None
+    pub fn validate(
+        &self,
+        link: &CoreLink,
+        value: &ResourceValue,
+        version: Option<Version>,
+    ) -> Result<(), ValidationError> {
+        self.get_object_model(link.object_id, version)?
+            .validate_write(link, value)
+    }
+}
+
+impl ObjectModel {
+    /// Checks that a value may be written to a resource (instance) of this object, see `validate_value`.
+    pub fn validate_write(
+        &self,
+        link: &CoreLink,
+        value: &ResourceValue,
+    ) -> Result<(), ValidationError> {
+        let resource = self.resource_of(link)?;
+        match resource.operations() {
+            Some(ResourceOperation::Write) | Some(ResourceOperation::ReadWrite) => {}
+            operations => {
+                return Err(ValidationError::NotWritable {
+                    link: link.clone(),
+                    operations,
+                })
+            }
+        }
+        self.validate_value(link, value)
+    }
+
+    /// Checks that a value is legal for a resource (instance) of this object: the resource exists,
+    /// resource instances are only used for multiple resources and the value has the type
+    /// of the resource and lies within its range. Whether the resource is writable is not checked,
+    /// e.g. for values the device reported.
+    pub fn validate_value(
+        &self,
+        link: &CoreLink,
+        value: &ResourceValue,
+    ) -> Result<(), ValidationError> {
+        let resource = self.resource_of(link)?;
+        match (resource.multiple(), link.resource_instance) {
+            (true, None) => return Err(ValidationError::MissingResourceInstance(link.clone())),
+            (false, Some(_)) => {
+                return Err(ValidationError::UnexpectedResourceInstance(link.clone()))
+            }
+            _ => {}
+        }
+        resource
+            .check_value(value)
+            .map_err(|err| ValidationError::from_value_error(link, err))
+    }
+
+    fn resource_of(&self, link: &CoreLink) -> Result<&ResourceModel, ValidationError> {
+        match (link.object_id, link.object_instance, link.resource_id) {
+            (object_id, Some(_), Some(resource_id)) if object_id == self.id => self
+                .resources
+                .get(&resource_id)
+                .ok_or(ValidationError::UnknownResource(link.clone())),
+            _ => Err(ValidationError::UnknownResource(link.clone())),
+        }
+    }
+}
+
+impl ValidationError {
+    fn from_value_error(link: &CoreLink, err: ValueError) -> Self {
+        let link = link.clone();
+        match err {
+            ValueError::NoType { .. } => ValidationError::NoType(link),
+            ValueError::WrongType { expected, actual } => ValidationError::WrongType {
+                link,
+                expected,
+                actual,
+            },
+            ValueError::OutOfRange { value, range } => {
+                ValidationError::OutOfRange { link, value, range }
+            }
+            ValueError::Multiple { .. } => ValidationError::MissingResourceInstance(link),
+            ValueError::NotMultiple { .. } => ValidationError::UnexpectedResourceInstance(link),
+            ValueError::UnknownResource { .. } | ValueError::OutsideInstance { .. } => {
+                ValidationError::UnknownResource(link)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_models::{object, resource},
+        ResourceRange, ResourceType,
+    };
+
+    // A part of the Temperature object
+    fn store() -> ObjectModelStore {
+        use ResourceOperation::*;
+        let resources = [
+            resource(5601, Some(Read), Some(ResourceType::Float), None, false),
+            resource(5605, Some(Execute), None, None, false),
+            resource(
+                5700,
+                Some(ReadWrite),
+                Some(ResourceType::Float),
+                Some(ResourceRange::Float(-40.0, 85.0)),
+                false,
+            ),
+            resource(
+                5701,
+                Some(ReadWrite),
+                Some(ResourceType::String),
+                None,
+                false,
+            ),
+            resource(
+                5750,
+                Some(ReadWrite),
+                Some(ResourceType::String),
+                None,
+                true,
+            ),
+        ];
+        let mut store = ObjectModelStore::default();
+        store.add_model(object(3303, "Temperature", true, resources));
+        store
+    }
+
+    fn validate(link: &str, value: ResourceValue) -> Result<(), ValidationError> {
+        store().validate(&CoreLink::try_from(link).unwrap(), &value, None)
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("</3303/0/5700>", ResourceValue::Float(21.5)).is_ok());
+        assert!(validate(
+            "</3303/0/5750/1>",
+            ResourceValue::String("Kitchen".to_owned())
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        assert!(matches!(
+            validate("</3303/0/5700>", ResourceValue::String("warm".to_owned())),
+            Err(ValidationError::WrongType {
+                expected: ResourceType::Float,
+                actual: ResourceType::String,
+                ..
+            })
+        ));
+        assert!(matches!(
+            validate("</3303/0/5700>", ResourceValue::Float(100.0)),
+            Err(ValidationError::OutOfRange { .. })
+        ));
+        assert!(matches!(
+            validate(
+                "</3303/0/5750>",
+                ResourceValue::String("Kitchen".to_owned())
+            ),
+            Err(ValidationError::MissingResourceInstance(_))
+        ));
+        assert!(matches!(
+            validate("</3303/0/5701/0>", ResourceValue::String("Cel".to_owned())),
+            Err(ValidationError::UnexpectedResourceInstance(_))
+        ));
+        assert!(matches!(
+            validate("</3303/0/5601>", ResourceValue::Float(21.5)),
+            Err(ValidationError::NotWritable {
+                operations: Some(ResourceOperation::Read),
+                ..
+            })
+        ));
+        assert!(matches!(
+            validate("</3303/0/5605>", ResourceValue::String("now".to_owned())),
+            Err(ValidationError::NotWritable { .. })
+        ));
+        assert!(matches!(
+            validate("</3303/0/9999>", ResourceValue::Float(21.5)),
+            Err(ValidationError::UnknownResource(_))
+        ));
+        assert!(matches!(
+            validate("</3303/0>", ResourceValue::Float(21.5)),
+            Err(ValidationError::UnknownResource(_))
+        ));
+        assert!(matches!(
+            validate("</3304/0/5700>", ResourceValue::Float(21.5)),
+            Err(ValidationError::ModelNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_value_read_only() {
+        let store = store();
+        let model = store.get_object_model(3303, None).unwrap();
+        let link = CoreLink::try_from("</3303/0/5601>").unwrap();
+        assert!(model
+            .validate_value(&link, &ResourceValue::Float(21.5))
+            .is_ok());
+        assert!(model
+            .validate_write(&link, &ResourceValue::Float(21.5))
+            .is_err());
+    }
+}
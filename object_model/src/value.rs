@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    core_link::CoreLink, err::ValueError, object_link::ObjectLink, ObjectModel, ResourceModel,
+    ResourceRange, ResourceType,
+};
+
+/// The value of a resource (instance), its variant is the type of the resource.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourceValue {
+    String(String),
+    Integer(i64),
+    UnsignedInteger(u64),
+    Opaque(Vec<u8>),
+    Float(f64),
+    Boolean(bool),
+    ObjectLink(ObjectLink),
+    Time(u64),
+    CoreLink(CoreLink),
+}
+
+impl ResourceValue {
+    pub fn resource_type(&self) -> ResourceType {
+        match self {
+            ResourceValue::String(_) => ResourceType::String,
+            ResourceValue::Integer(_) => ResourceType::Integer,
+            ResourceValue::UnsignedInteger(_) => ResourceType::UnsignedInteger,
+            ResourceValue::Opaque(_) => ResourceType::Opaque,
+            ResourceValue::Float(_) => ResourceType::Float,
+            ResourceValue::Boolean(_) => ResourceType::Boolean,
+            ResourceValue::ObjectLink(_) => ResourceType::ObjectLink,
+            ResourceValue::Time(_) => ResourceType::Time,
+            ResourceValue::CoreLink(_) => ResourceType::CoreLink,
+        }
+    }
+}
+
+impl ResourceModel {
+    /// Checks that a value has the type of this resource and lies within its range.
+    /// Whether the value belongs to a single resource or a resource instance is not checked.
+    pub fn check_value(&self, value: &ResourceValue) -> Result<(), ValueError> {
+        let resourcetype = self.resourcetype.ok_or(ValueError::NoType {
+            resource_id: self.id,
+        })?;
+        if resourcetype != value.resource_type() {
+            return Err(ValueError::WrongType {
+                expected: resourcetype,
+                actual: value.resource_type(),
+            });
+        }
+        match &self.range {
+            Some(range) if !range.contains(value) => Err(ValueError::OutOfRange {
+                value: value.clone(),
+                range: range.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ResourceRange {
+    /// Whether a value lies within the range. Ranges that do not apply to the type of the value,
+    /// e.g. a length for an integer, and ranges that could not be parsed accept every value.
+    pub fn contains(&self, value: &ResourceValue) -> bool {
+        self.check(value).unwrap_or(true)
+    }
+
+    // None if the range does not apply to the value
+    fn check(&self, value: &ResourceValue) -> Option<bool> {
+        let integer = match value {
+            ResourceValue::Integer(value) => Some(i128::from(*value)),
+            ResourceValue::UnsignedInteger(value) => Some(i128::from(*value)),
+            _ => None,
+        };
+        let number = match value {
+            ResourceValue::Float(value) => Some(*value),
+            _ => integer.map(|integer| integer as f64),
+        };
+        let length = match value {
+            ResourceValue::String(value) => Some(value.len() as u64),
+            ResourceValue::Opaque(value) => Some(value.len() as u64),
+            _ => None,
+        };
+        match self {
+            ResourceRange::Numerical(start, end) => match (integer, number) {
+                (Some(integer), _) => {
+                    Some(i128::from(*start) <= integer && integer <= i128::from(*end))
+                }
+                (None, Some(number)) => Some(*start as f64 <= number && number <= *end as f64),
+                _ => None,
+            },
+            ResourceRange::UnsignedNumerical(start, end) => match (integer, number) {
+                (Some(integer), _) => {
+                    Some(i128::from(*start) <= integer && integer <= i128::from(*end))
+                }
+                (None, Some(number)) => Some(*start as f64 <= number && number <= *end as f64),
+                _ => None,
+            },
+            ResourceRange::Float(start, end) => {
+                number.map(|number| *start <= number && number <= *end)
+            }
+            ResourceRange::NumericalDiscrete(values) => {
+                integer.map(|integer| values.iter().any(|allowed| i128::from(*allowed) == integer))
+            }
+            ResourceRange::DiscreteLength(lengths) => {
+                length.map(|length| lengths.contains(&length))
+            }
+            ResourceRange::Length(min, max) => {
+                length.map(|length| *min <= length && length <= *max)
+            }
+            ResourceRange::StringEnum(values) => match value {
+                ResourceValue::String(value) => Some(values.contains(value)),
+                _ => None,
+            },
+            ResourceRange::Mixed(ranges) => ranges
+                .iter()
+                .filter_map(|range| range.check(value))
+                .reduce(|contained, other| contained || other),
+            ResourceRange::Other(_) => None,
+        }
+    }
+}
+
+/// An instance of a multiple resource, e.g. the value of /3/0/7/1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceInstance {
+    id: u16,
+    value: ResourceValue,
+}
+
+impl ResourceInstance {
+    /// Creates an instance of the multiple resource `model`, refusing values that do not fit it.
+    pub fn new(id: u16, value: ResourceValue, model: &ResourceModel) -> Result<Self, ValueError> {
+        if !model.multiple {
+            return Err(ValueError::NotMultiple {
+                resource_id: model.id,
+            });
+        }
+        model.check_value(&value)?;
+        Ok(ResourceInstance { id, value })
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn value(&self) -> &ResourceValue {
+        &self.value
+    }
+}
+
+/// The value(s) of a resource of an object instance.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resource {
+    Single(ResourceValue),
+    Multiple(BTreeMap<u16, ResourceInstance>),
+}
+
+impl Resource {
+    /// Creates the value of the single resource `model`, refusing values that do not fit it.
+    pub fn single(value: ResourceValue, model: &ResourceModel) -> Result<Self, ValueError> {
+        if model.multiple {
+            return Err(ValueError::Multiple {
+                resource_id: model.id,
+            });
+        }
+        model.check_value(&value)?;
+        Ok(Resource::Single(value))
+    }
+
+    /// Creates the instances of the multiple resource `model`, keyed by resource instance ID.
+    pub fn multiple(
+        values: impl IntoIterator<Item = (u16, ResourceValue)>,
+        model: &ResourceModel,
+    ) -> Result<Self, ValueError> {
+        let instances = values
+            .into_iter()
+            .map(|(id, value)| Ok((id, ResourceInstance::new(id, value, model)?)))
+            .collect::<Result<_, ValueError>>()?;
+        Ok(Resource::Multiple(instances))
+    }
+}
+
+/// An object instance with the values of its resources, e.g. /3/0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectInstance {
+    object_id: u16,
+    id: u16,
+    resources: BTreeMap<u16, Resource>,
+}
+
+impl ObjectInstance {
+    /// Builds an object instance from the values of its resources (instances), keyed by their links.
+    /// Every value is checked against the resource it belongs to, values of multiple resources
+    /// have to be linked by resource instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the object instance
+    /// * `values` - The values of the instance, all links need to point below /`object`/`id`
+    /// * `model` - The model of the object
+    pub fn from_values(
+        id: u16,
+        values: &HashMap<CoreLink, ResourceValue>,
+        model: &ObjectModel,
+    ) -> Result<Self, ValueError> {
+        let mut resources = BTreeMap::new();
+        for (link, value) in values {
+            let resource_id = match (link.object_id, link.object_instance, link.resource_id) {
+                (object_id, Some(instance), Some(resource_id))
+                    if object_id == model.id && instance == id =>
+                {
+                    resource_id
+                }
+                _ => {
+                    return Err(ValueError::OutsideInstance {
+                        link: link.clone(),
+                        instance: CoreLink::new(model.id, Some(id), None, None),
+                    })
+                }
+            };
+            let resource = model
+                .resources
+                .get(&resource_id)
+                .ok_or(ValueError::UnknownResource { resource_id })?;
+
+            match link.resource_instance {
+                None => {
+                    resources.insert(resource_id, Resource::single(value.clone(), resource)?);
+                }
+                Some(instance_id) => {
+                    let instance = ResourceInstance::new(instance_id, value.clone(), resource)?;
+                    match resources
+                        .entry(resource_id)
+                        .or_insert_with(|| Resource::Multiple(BTreeMap::new()))
+                    {
+                        Resource::Multiple(instances) => {
+                            instances.insert(instance_id, instance);
+                        }
+                        Resource::Single(_) => {
+                            unreachable!("resources are either single or multiple")
+                        }
+                    }
+                }
+            }
+        }
+        Ok(ObjectInstance {
+            object_id: model.id,
+            id,
+            resources,
+        })
+    }
+
+    pub fn object_id(&self) -> u16 {
+        self.object_id
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn link(&self) -> CoreLink {
+        CoreLink::new(self.object_id, Some(self.id), None, None)
+    }
+
+    pub fn resources(&self) -> &BTreeMap<u16, Resource> {
+        &self.resources
+    }
+
+    /// The values of the instance keyed by the link of each resource (instance).
+    pub fn values(&self) -> HashMap<CoreLink, ResourceValue> {
+        let link = |resource_id, instance_id| {
+            CoreLink::new(
+                self.object_id,
+                Some(self.id),
+                Some(resource_id),
+                instance_id,
+            )
+        };
+        self.resources
+            .iter()
+            .flat_map(|(resource_id, resource)| match resource {
+                Resource::Single(value) => vec![(link(*resource_id, None), value.clone())],
+                Resource::Multiple(instances) => instances
+                    .values()
+                    .map(|instance| {
+                        (
+                            link(*resource_id, Some(instance.id)),
+                            instance.value.clone(),
+                        )
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_models, ResourceOperation};
+
+    fn resource(id: u16, resourcetype: ResourceType, multiple: bool) -> ResourceModel {
+        test_models::resource(
+            id,
+            Some(ResourceOperation::ReadWrite),
+            Some(resourcetype),
+            None,
+            multiple,
+        )
+    }
+
+    fn object() -> ObjectModel {
+        let mut battery = resource(9, ResourceType::Integer, false);
+        battery.range = Some(ResourceRange::Numerical(0, 100));
+        test_models::object(
+            3,
+            "Device",
+            false,
+            [
+                resource(0, ResourceType::String, false),
+                resource(7, ResourceType::Integer, true),
+                battery,
+            ],
+        )
+    }
+
+    fn link(link: &str) -> CoreLink {
+        CoreLink::try_from(link).unwrap()
+    }
+
+    #[test]
+    fn test_check_value() {
+        let model = object();
+        let battery = &model.resources()[&9];
+        assert!(battery.check_value(&ResourceValue::Integer(87)).is_ok());
+        assert!(matches!(
+            battery.check_value(&ResourceValue::Integer(101)),
+            Err(ValueError::OutOfRange { .. })
+        ));
+        assert!(matches!(
+            battery.check_value(&ResourceValue::String("87".to_owned())),
+            Err(ValueError::WrongType {
+                expected: ResourceType::Integer,
+                actual: ResourceType::String
+            })
+        ));
+    }
+
+    #[test]
+    fn test_range_contains() {
+        let percent = ResourceRange::Numerical(0, 100);
+        assert!(percent.contains(&ResourceValue::Integer(100)));
+        assert!(percent.contains(&ResourceValue::Float(99.5)));
+        assert!(!percent.contains(&ResourceValue::UnsignedInteger(101)));
+
+        let unsigned = ResourceRange::UnsignedNumerical(0, u64::MAX);
+        assert!(unsigned.contains(&ResourceValue::UnsignedInteger(u64::MAX)));
+        assert!(!unsigned.contains(&ResourceValue::Integer(-1)));
+
+        let latitude = ResourceRange::Float(-90.0, 90.0);
+        assert!(latitude.contains(&ResourceValue::Float(-45.5)));
+        assert!(!latitude.contains(&ResourceValue::Integer(91)));
+
+        let lengths = ResourceRange::DiscreteLength(vec![16, 32]);
+        assert!(lengths.contains(&ResourceValue::Opaque(vec![0; 16])));
+        assert!(!lengths.contains(&ResourceValue::String("key".to_owned())));
+
+        let mixed = ResourceRange::Mixed(vec![
+            ResourceRange::Numerical(0, 10),
+            ResourceRange::NumericalDiscrete(vec![255]),
+            ResourceRange::StringEnum(vec!["U".to_owned()]),
+        ]);
+        assert!(mixed.contains(&ResourceValue::Integer(255)));
+        assert!(!mixed.contains(&ResourceValue::Integer(11)));
+        assert!(mixed.contains(&ResourceValue::String("U".to_owned())));
+        assert!(!mixed.contains(&ResourceValue::String("M".to_owned())));
+
+        // Ranges that do not apply to the value, or could not be parsed, accept it
+        assert!(percent.contains(&ResourceValue::String("many".to_owned())));
+        assert!(ResourceRange::Other("See description".to_owned())
+            .contains(&ResourceValue::Integer(-1)));
+    }
+
+    #[test]
+    fn test_object_instance() {
+        let model = object();
+        let values = HashMap::from([
+            (link("</3/0/0>"), ResourceValue::String("ACME".to_owned())),
+            (link("</3/0/7/0>"), ResourceValue::Integer(3800)),
+            (link("</3/0/7/1>"), ResourceValue::Integer(5000)),
+        ]);
+
+        let instance = ObjectInstance::from_values(0, &values, &model).unwrap();
+        assert_eq!(instance.link(), link("</3/0>"));
+        assert_eq!(
+            instance.resources()[&0],
+            Resource::Single(ResourceValue::String("ACME".to_owned()))
+        );
+        match &instance.resources()[&7] {
+            Resource::Multiple(instances) => {
+                assert_eq!(instances.keys().copied().collect::<Vec<_>>(), vec![0, 1]);
+                assert_eq!(instances[&1].value(), &ResourceValue::Integer(5000));
+            }
+            resource => panic!("Multiple resource expected, got {:?}", resource),
+        }
+        assert_eq!(instance.values(), values);
+    }
+
+    #[test]
+    fn test_object_instance_invalid_values() {
+        let model = object();
+        let invalid = [
+            // Multiple resource without resource instance
+            (link("</3/0/7>"), ResourceValue::Integer(3800)),
+            // Single resource with resource instance
+            (link("</3/0/0/0>"), ResourceValue::String("ACME".to_owned())),
+            // Other object instance
+            (link("</3/1/0>"), ResourceValue::String("ACME".to_owned())),
+            // Unknown resource
+            (link("</3/0/1000>"), ResourceValue::Integer(1)),
+            // Out of range
+            (link("</3/0/9>"), ResourceValue::Integer(-1)),
+        ];
+        for (link, value) in invalid {
+            let values = HashMap::from([(link.clone(), value)]);
+            assert!(
+                ObjectInstance::from_values(0, &values, &model).is_err(),
+                "{}",
+                link
+            );
+        }
+    }
+}
@@ -1,9 +1,24 @@
 use crate::err::ObjectParserError;
 use crate::*;
+use regex::Regex;
 use roxmltree::{Document, Node};
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, sync::LazyLock};
 use walkdir::WalkDir;
 
+const NUMBER: &str = r"[+-]?(?:0[xX][0-9a-fA-F]+|\d+(?:\.\d+)?(?:[eE][+-]?\d+)?)";
+static BYTES: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(.*?)\s*(bytes?|octets?)$").unwrap());
+static RANGE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"^({})\s*(?:\.\.\.?|-|–|to)\s*({})$",
+        NUMBER, NUMBER
+    ))
+    .unwrap()
+});
+static MAXIMUM: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(?:up to|max(?:imum)?\.?|<=?)\s*(\d+)$").unwrap());
+static WORD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[\w.+/-]+$").unwrap());
+
 /// Returns a hashmap with objectmodels sorted by IDs and versions. Will return an error if a single object fails
 /// This function is meant for well known models (e.g. from the lwm2m registry) that strictly follow the xsd.
 /// Use ... for user provided models.
@@ -215,15 +230,15 @@ fn parse_resource(
                 )),
             },
             "Type" => match child.text() {
-                Some("String") => Ok(resource_model.resourcetype(Some(ResourceType::String(None)))),
-                Some("Integer") => Ok(resource_model.resourcetype(Some(ResourceType::Integer(None)))),
-                Some("Unsigned Integer") => Ok(resource_model.resourcetype(Some(ResourceType::UnsignedInteger(None)))),
-                Some("Float") => Ok(resource_model.resourcetype(Some(ResourceType::Float(None)))),
-                Some("Boolean") => Ok(resource_model.resourcetype(Some(ResourceType::Boolean(None)))),
-                Some("Opaque") => Ok(resource_model.resourcetype(Some(ResourceType::Opaque(None)))),
-                Some("Time") => Ok(resource_model.resourcetype(Some(ResourceType::Time(None)))),
-                Some("Objlnk") => Ok(resource_model.resourcetype(Some(ResourceType::ObjectLink(None)))),
-                Some("Corelnk") => Ok(resource_model.resourcetype(Some(ResourceType::CoreLink(None)))),
+                Some("String") => Ok(resource_model.resourcetype(Some(ResourceType::String))),
+                Some("Integer") => Ok(resource_model.resourcetype(Some(ResourceType::Integer))),
+                Some("Unsigned Integer") => Ok(resource_model.resourcetype(Some(ResourceType::UnsignedInteger))),
+                Some("Float") => Ok(resource_model.resourcetype(Some(ResourceType::Float))),
+                Some("Boolean") => Ok(resource_model.resourcetype(Some(ResourceType::Boolean))),
+                Some("Opaque") => Ok(resource_model.resourcetype(Some(ResourceType::Opaque))),
+                Some("Time") => Ok(resource_model.resourcetype(Some(ResourceType::Time))),
+                Some("Objlnk") => Ok(resource_model.resourcetype(Some(ResourceType::ObjectLink))),
+                Some("Corelnk") => Ok(resource_model.resourcetype(Some(ResourceType::CoreLink))),
                 None => Ok(&mut resource_model),
                 Some(value) => Err(ObjectParserError::new(
                     &format!("Resource Type can be String, Integer, Float, Boolean, Opaque, Time, Objlnk or empty, is: {}", value),
@@ -250,15 +265,282 @@ fn parse_resource(
         .map_err(|err| ObjectParserError::new(err.to_string().as_str()))
 }
 
+// The forms used in the OMA registry, e.g. "0..100", "0-100", "1,2,5", "0..255 bytes", "U, M, H"
+// and lists mixing ranges and values. Anything else, like prose, is kept as Other.
 fn parse_range_enumeration(enumeration: &str) -> ResourceRange {
-    // TODO: parse the enumeration
-    ResourceRange::Other(enumeration.to_owned())
+    let other = || ResourceRange::Other(enumeration.to_owned());
+    let text = enumeration.trim();
+
+    if let Some(captures) = BYTES.captures(text) {
+        return parse_lengths(&captures[1]).unwrap_or_else(other);
+    }
+
+    let parts = split_parts(text);
+    if parts.is_empty() {
+        return other();
+    }
+    match parts
+        .iter()
+        .map(|part| parse_numeric(part))
+        .collect::<Option<Vec<ResourceRange>>>()
+    {
+        Some(ranges) => combine(ranges),
+        None => parse_string_enum(&parts).unwrap_or_else(other),
+    }
+}
+
+fn split_parts(text: &str) -> Vec<&str> {
+    text.split([',', ';'])
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+// A single value or an inclusive range, e.g. "-40", "0x1F", "0..100", "0-100", "-90.0..90.0"
+fn parse_numeric(part: &str) -> Option<ResourceRange> {
+    if let Some(value) = parse_integer(part) {
+        return match i64::try_from(value) {
+            Ok(value) => Some(ResourceRange::NumericalDiscrete(vec![value])),
+            Err(_) => integer_range(value, value),
+        };
+    }
+    let captures = RANGE.captures(part)?;
+    match (parse_integer(&captures[1]), parse_integer(&captures[2])) {
+        (Some(start), Some(end)) if start <= end => integer_range(start, end),
+        (Some(_), Some(_)) => None,
+        _ => {
+            let start: f64 = captures[1].parse().ok()?;
+            let end: f64 = captures[2].parse().ok()?;
+            (start <= end).then_some(ResourceRange::Float(start, end))
+        }
+    }
+}
+
+// Bounds beyond i64 are kept exact as long as they fit u64, e.g. "0..0xFFFFFFFFFFFFFFFF"
+fn integer_range(start: i128, end: i128) -> Option<ResourceRange> {
+    if let (Ok(start), Ok(end)) = (i64::try_from(start), i64::try_from(end)) {
+        return Some(ResourceRange::Numerical(start, end));
+    }
+    Some(ResourceRange::UnsignedNumerical(
+        u64::try_from(start).ok()?,
+        u64::try_from(end).ok()?,
+    ))
+}
+
+fn parse_integer(text: &str) -> Option<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None if digits.chars().all(|c| c.is_ascii_digit()) => digits.parse().ok()?,
+        None => return None,
+    };
+    Some(if negative { -value } else { value })
+}
+
+// Single values are merged, a list of only values is NumericalDiscrete
+fn combine(ranges: Vec<ResourceRange>) -> ResourceRange {
+    let mut values = vec![];
+    let mut others = vec![];
+    for range in ranges {
+        match range {
+            ResourceRange::NumericalDiscrete(discrete) => values.extend(discrete),
+            range => others.push(range),
+        }
+    }
+    if !values.is_empty() {
+        others.push(ResourceRange::NumericalDiscrete(values));
+    }
+    match others.len() {
+        1 => others.remove(0),
+        _ => ResourceRange::Mixed(others),
+    }
+}
+
+// The part before "bytes", e.g. "0..255", "16,32" or "up to 255"
+fn parse_lengths(text: &str) -> Option<ResourceRange> {
+    if let Some(captures) = MAXIMUM.captures(text.trim()) {
+        return Some(ResourceRange::Length(0, captures[1].parse().ok()?));
+    }
+
+    let parts = split_parts(text);
+    if parts.is_empty() {
+        return None;
+    }
+    let ranges = parts
+        .iter()
+        .map(|part| parse_numeric(part))
+        .collect::<Option<Vec<ResourceRange>>>()?;
+    to_length(combine(ranges))
+}
+
+fn to_length(range: ResourceRange) -> Option<ResourceRange> {
+    match range {
+        ResourceRange::NumericalDiscrete(values) => Some(ResourceRange::DiscreteLength(
+            values
+                .into_iter()
+                .map(|value| u64::try_from(value).ok())
+                .collect::<Option<Vec<u64>>>()?,
+        )),
+        ResourceRange::Numerical(min, max) => Some(ResourceRange::Length(
+            u64::try_from(min).ok()?,
+            u64::try_from(max).ok()?,
+        )),
+        ResourceRange::UnsignedNumerical(min, max) => Some(ResourceRange::Length(min, max)),
+        ResourceRange::Mixed(ranges) => Some(ResourceRange::Mixed(
+            ranges
+                .into_iter()
+                .map(to_length)
+                .collect::<Option<Vec<ResourceRange>>>()?,
+        )),
+        _ => None,
+    }
+}
+
+// Quoted strings or single words, e.g. "U, M, H" or '"Cel", "Far"'. A single unquoted word
+// is more likely a note than the only allowed value.
+fn parse_string_enum(parts: &[&str]) -> Option<ResourceRange> {
+    let mut quoted = false;
+    let values = parts
+        .iter()
+        .map(|part| {
+            for quote in ['"', '\''] {
+                if let Some(value) = part
+                    .strip_prefix(quote)
+                    .and_then(|part| part.strip_suffix(quote))
+                {
+                    quoted = true;
+                    return Some(value.to_owned());
+                }
+            }
+            WORD.is_match(part).then(|| part.to_string())
+        })
+        .collect::<Option<Vec<String>>>()?;
+    (quoted || values.len() > 1).then_some(ResourceRange::StringEnum(values))
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use std::path::PathBuf;
 
+    #[test]
+    fn test_parse_range_enumeration() {
+        let ranges = [
+            ("0..100", ResourceRange::Numerical(0, 100)),
+            ("0-100", ResourceRange::Numerical(0, 100)),
+            ("-40..85", ResourceRange::Numerical(-40, 85)),
+            ("-40-85", ResourceRange::Numerical(-40, 85)),
+            (" 0 .. 0xFF ", ResourceRange::Numerical(0, 255)),
+            ("-90.0..90.0", ResourceRange::Float(-90.0, 90.0)),
+            (
+                "0..18446744073709551615",
+                ResourceRange::UnsignedNumerical(0, u64::MAX),
+            ),
+            (
+                "0..0xFFFFFFFFFFFFFFFF",
+                ResourceRange::UnsignedNumerical(0, u64::MAX),
+            ),
+            (
+                "-9223372036854775808..9223372036854775807",
+                ResourceRange::Numerical(i64::MIN, i64::MAX),
+            ),
+            ("1,2,5", ResourceRange::NumericalDiscrete(vec![1, 2, 5])),
+            ("0..255 bytes", ResourceRange::Length(0, 255)),
+            ("1-32 Bytes", ResourceRange::Length(1, 32)),
+            ("16, 32 bytes", ResourceRange::DiscreteLength(vec![16, 32])),
+            ("up to 255 bytes", ResourceRange::Length(0, 255)),
+            (
+                "U, M, H",
+                ResourceRange::StringEnum(vec!["U".to_owned(), "M".to_owned(), "H".to_owned()]),
+            ),
+            ("\"Cel\"", ResourceRange::StringEnum(vec!["Cel".to_owned()])),
+            (
+                "0..10, 20, 30",
+                ResourceRange::Mixed(vec![
+                    ResourceRange::Numerical(0, 10),
+                    ResourceRange::NumericalDiscrete(vec![20, 30]),
+                ]),
+            ),
+            (
+                "0..8, 16..32 bytes",
+                ResourceRange::Mixed(vec![
+                    ResourceRange::Length(0, 8),
+                    ResourceRange::Length(16, 32),
+                ]),
+            ),
+        ];
+        for (enumeration, expected) in ranges {
+            assert_eq!(
+                parse_range_enumeration(enumeration),
+                expected,
+                "{}",
+                enumeration
+            );
+        }
+
+        for enumeration in [
+            "",
+            "See description",
+            "100..0",
+            "1: Normal, 2: Low",
+            "-1..10 bytes",
+        ] {
+            assert_eq!(
+                parse_range_enumeration(enumeration),
+                ResourceRange::Other(enumeration.to_owned()),
+                "{}",
+                enumeration
+            );
+        }
+    }
+
+    // Share of the registry's range enumerations that have to parse into a structured range,
+    // anything below means the parser lost a notation the registry uses
+    const MIN_RANGE_COVERAGE: f64 = 0.9;
+
+    #[test]
+    fn range_coverage() {
+        let directory_path = "lwm2m-registry/version_history";
+        let models = get_models_from_dir(&PathBuf::from(directory_path)).unwrap();
+        let ranges: Vec<&ResourceRange> = models
+            .values()
+            .flat_map(|versions| versions.versions.values())
+            .flat_map(|model| model.resources.values())
+            .filter_map(|resource| resource.range.as_ref())
+            .collect();
+        let unparsed: Vec<&String> = ranges
+            .iter()
+            .filter_map(|range| match range {
+                ResourceRange::Other(enumeration) => Some(enumeration),
+                _ => None,
+            })
+            .collect();
+
+        let parsed = ranges.len() - unparsed.len();
+        println!(
+            "Parsed {} of {} range enumerations ({:.1}%)",
+            parsed,
+            ranges.len(),
+            100.0 * parsed as f64 / ranges.len() as f64
+        );
+        for enumeration in &unparsed {
+            println!("Not parsed: {:?}", enumeration);
+        }
+        let coverage = parsed as f64 / ranges.len() as f64;
+        assert!(
+            coverage >= MIN_RANGE_COVERAGE,
+            "{:.1}% of the range enumerations parsed, expected at least {:.1}%",
+            100.0 * coverage,
+            100.0 * MIN_RANGE_COVERAGE
+        );
+    }
+
     #[test]
     fn parse_all() {
         let directory_path = "lwm2m-registry/version_history";
@@ -0,0 +1,598 @@
+use object_model::{core_link::CoreLink, object_link::ObjectLink, value::ResourceValue};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    lwm2m_requests::registration_request::Lwm2mVersion,
+    oscore::{context::OscoreParameters, err::OscoreError, OscoreContexts},
+    transport::{dtls::PskStore, trust_store::LocalTrustStore},
+};
+
+// Object IDs of the objects a bootstrap configuration writes
+pub const SECURITY_OBJECT_ID: u16 = 0;
+pub const SERVER_OBJECT_ID: u16 = 1;
+pub const OSCORE_OBJECT_ID: u16 = 21;
+// The LwM2M version of configurations that do not name one, the first with OSCORE
+pub const DEFAULT_LWM2M_VERSION: Lwm2mVersion = Lwm2mVersion::V11;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#E-1-0-E1-LwM2M-Object-LwM2M-Security
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SecurityMode {
+    #[serde(rename = "psk")]
+    PreSharedKey,
+    #[serde(rename = "rpk")]
+    RawPublicKey,
+    #[serde(rename = "x509")]
+    #[serde(alias = "certificate")]
+    Certificate,
+    #[serde(rename = "nosec")]
+    NoSec,
+    #[serde(rename = "est")]
+    CertificateWithEst,
+}
+
+impl From<SecurityMode> for i64 {
+    fn from(mode: SecurityMode) -> i64 {
+        match mode {
+            SecurityMode::PreSharedKey => 0,
+            SecurityMode::RawPublicKey => 1,
+            SecurityMode::Certificate => 2,
+            SecurityMode::NoSec => 3,
+            SecurityMode::CertificateWithEst => 4,
+        }
+    }
+}
+
+/// An instance of the LwM2M Security object (/0), how the device connects to one server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityInstance {
+    pub instance_id: u16,
+    pub server_uri: String,
+    pub bootstrap_server: bool,
+    pub security_mode: SecurityMode,
+    pub public_key_or_identity: Vec<u8>,
+    pub server_public_key: Vec<u8>,
+    pub secret_key: Vec<u8>,
+    // Links the instance to the Server object instance with the same ID, bootstrap servers have none
+    pub short_server_id: Option<u16>,
+    // The instance of the OSCORE object (/21) that protects the connection to the server
+    pub oscore_instance: Option<u16>,
+    // Any other resources of the instance by resource ID, e.g. the Client Hold Off Time (11)
+    pub resources: BTreeMap<u16, ResourceValue>,
+}
+
+impl SecurityInstance {
+    pub fn link(&self) -> CoreLink {
+        CoreLink::new(SECURITY_OBJECT_ID, Some(self.instance_id), None, None)
+    }
+
+    /// The values of the resources of the instance, keyed by their links.
+    pub fn values(&self) -> HashMap<CoreLink, ResourceValue> {
+        let mut values = HashMap::from([
+            (0, ResourceValue::String(self.server_uri.clone())),
+            (1, ResourceValue::Boolean(self.bootstrap_server)),
+            (2, ResourceValue::Integer(self.security_mode.into())),
+            (
+                3,
+                ResourceValue::Opaque(self.public_key_or_identity.clone()),
+            ),
+            (4, ResourceValue::Opaque(self.server_public_key.clone())),
+            (5, ResourceValue::Opaque(self.secret_key.clone())),
+        ]);
+        if let Some(short_server_id) = self.short_server_id {
+            values.insert(10, ResourceValue::Integer(short_server_id.into()));
+        }
+        if let Some(instance_id) = self.oscore_instance {
+            let link = ObjectLink {
+                link: format!("{}:{}", OSCORE_OBJECT_ID, instance_id),
+                object_id: OSCORE_OBJECT_ID,
+                object_instance: instance_id,
+            };
+            values.insert(17, ResourceValue::ObjectLink(link));
+        }
+        values.extend(self.resources.clone());
+        resource_values(&self.link(), values)
+    }
+}
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#E-2-0-E2-LwM2M-Object-LwM2M-Server
+/// An instance of the LwM2M Server object (/1), how the device behaves towards one server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerInstance {
+    pub instance_id: u16,
+    pub short_server_id: u16,
+    // In seconds
+    pub lifetime: u32,
+    pub default_min_period: Option<u32>,
+    pub default_max_period: Option<u32>,
+    pub notification_storing: bool,
+    // e.g. U for UDP, see the b parameter of the registration
+    pub binding: String,
+    // Any other resources of the instance by resource ID, e.g. Disable Timeout (5)
+    pub resources: BTreeMap<u16, ResourceValue>,
+}
+
+impl ServerInstance {
+    pub fn link(&self) -> CoreLink {
+        CoreLink::new(SERVER_OBJECT_ID, Some(self.instance_id), None, None)
+    }
+
+    /// The values of the resources of the instance, keyed by their links.
+    pub fn values(&self) -> HashMap<CoreLink, ResourceValue> {
+        let mut values = HashMap::from([
+            (0, ResourceValue::Integer(self.short_server_id.into())),
+            (1, ResourceValue::Integer(self.lifetime.into())),
+            (6, ResourceValue::Boolean(self.notification_storing)),
+            (7, ResourceValue::String(self.binding.clone())),
+        ]);
+        if let Some(period) = self.default_min_period {
+            values.insert(2, ResourceValue::Integer(period.into()));
+        }
+        if let Some(period) = self.default_max_period {
+            values.insert(3, ResourceValue::Integer(period.into()));
+        }
+        values.extend(self.resources.clone());
+        resource_values(&self.link(), values)
+    }
+}
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#E-5-0-E5-LwM2M-Object-OSCORE
+/// An instance of the OSCORE object (/21), the security context of the device towards one
+/// server. Sender and recipient are seen from the device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscoreInstance {
+    pub instance_id: u16,
+    pub master_secret: Vec<u8>,
+    pub sender_id: Vec<u8>,
+    pub recipient_id: Vec<u8>,
+    pub master_salt: Vec<u8>,
+    pub id_context: Option<Vec<u8>>,
+    // Any other resources of the instance by resource ID, e.g. the AEAD Algorithm (3)
+    pub resources: BTreeMap<u16, ResourceValue>,
+}
+
+impl OscoreInstance {
+    pub fn link(&self) -> CoreLink {
+        CoreLink::new(OSCORE_OBJECT_ID, Some(self.instance_id), None, None)
+    }
+
+    /// The values of the resources of the instance, keyed by their links.
+    pub fn values(&self) -> HashMap<CoreLink, ResourceValue> {
+        let mut values = HashMap::from([
+            (0, ResourceValue::Opaque(self.master_secret.clone())),
+            (1, ResourceValue::Opaque(self.sender_id.clone())),
+            (2, ResourceValue::Opaque(self.recipient_id.clone())),
+            (5, ResourceValue::Opaque(self.master_salt.clone())),
+        ]);
+        if let Some(id_context) = &self.id_context {
+            values.insert(6, ResourceValue::Opaque(id_context.clone()));
+        }
+        values.extend(self.resources.clone());
+        resource_values(&self.link(), values)
+    }
+
+    /// The parameters of the security context on the side of the server, which sends with
+    /// the Recipient ID of the device.
+    pub fn parameters(&self) -> OscoreParameters {
+        OscoreParameters {
+            master_secret: self.master_secret.clone(),
+            master_salt: self.master_salt.clone(),
+            sender_id: self.recipient_id.clone(),
+            recipient_id: self.sender_id.clone(),
+            id_context: self.id_context.clone(),
+        }
+    }
+}
+
+// Keys resource values by their links below `instance`
+fn resource_values(
+    instance: &CoreLink,
+    values: HashMap<u16, ResourceValue>,
+) -> HashMap<CoreLink, ResourceValue> {
+    values
+        .into_iter()
+        .map(|(resource_id, value)| {
+            let link = CoreLink::new(
+                instance.object_id,
+                instance.object_instance,
+                Some(resource_id),
+                None,
+            );
+            (link, value)
+        })
+        .collect()
+}
+
+/// What the bootstrap server writes to a device: the servers it should connect to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootstrapConfig {
+    // The version of the objects the instances are written as, that of the device
+    pub lwm2m_version: Lwm2mVersion,
+    pub security: Vec<SecurityInstance>,
+    pub servers: Vec<ServerInstance>,
+    pub oscore: Vec<OscoreInstance>,
+}
+
+/// Bootstrap configurations keyed by the endpoint name of the device they are for,
+/// or by a prefix of the endpoint names of a group of devices.
+#[derive(Debug, Default)]
+pub struct BootstrapConfigStore {
+    configs: HashMap<String, BootstrapConfig>,
+    // Sorted from the longest to the shortest prefix, an empty prefix matches every device
+    prefixes: Vec<(String, BootstrapConfig)>,
+}
+
+impl BootstrapConfigStore {
+    /// Adds the configuration for an endpoint name, replacing the one that was there.
+    /// A pattern ending in * is a prefix, e.g. sensor-* or * for every device.
+    pub fn add(&mut self, pattern: &str, config: BootstrapConfig) {
+        let prefix = match pattern.strip_suffix('*') {
+            Some(prefix) => prefix,
+            None => {
+                self.configs.insert(pattern.to_owned(), config);
+                return;
+            }
+        };
+        self.prefixes.retain(|(existing, _)| existing != prefix);
+        self.prefixes.push((prefix.to_owned(), config));
+        self.prefixes
+            .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+    }
+
+    /// The configuration of a device: the one for its endpoint name, otherwise the one of the
+    /// longest prefix it matches.
+    pub fn get(&self, device_endpoint: &str) -> Option<&BootstrapConfig> {
+        self.configs.get(device_endpoint).or_else(|| {
+            self.prefixes
+                .iter()
+                .find(|(prefix, _)| device_endpoint.starts_with(prefix.as_str()))
+                .map(|(_, config)| config)
+        })
+    }
+
+    /// Whether the configuration of a device gives it credentials: a pre-shared key, raw public
+    /// key, certificate or OSCORE context. Such a device may not register or bootstrap anonymously.
+    pub fn has_credentials(&self, device_endpoint: &str) -> bool {
+        self.get(device_endpoint).is_some_and(|config| {
+            config.security.iter().any(|security| {
+                security.security_mode != SecurityMode::NoSec || security.oscore_instance.is_some()
+            })
+        })
+    }
+
+    /// The pre-shared keys the devices use to connect to this server over DTLS, taken from
+    /// the PSK Security instances that are not for a bootstrap server. Keys in the configuration
+    /// of a pattern are left out, a device can only register with the endpoint name its PSK
+    /// identity names.
+    pub fn psk_store(&self) -> PskStore {
+        let mut store = PskStore::default();
+        for security in self.configs.values().flat_map(|config| &config.security) {
+            if security.security_mode == SecurityMode::PreSharedKey && !security.bootstrap_server {
+                store.add(&security.public_key_or_identity, &security.secret_key);
+            }
+        }
+        store
+    }
+
+    /// The raw public keys of the devices that connect to this server, bound to their endpoint
+    /// names. Keys in the configuration of a pattern are left out as they name no device.
+    pub fn trust_store(&self) -> LocalTrustStore {
+        let mut store = LocalTrustStore::default();
+        for (device_endpoint, config) in &self.configs {
+            for security in &config.security {
+                if security.security_mode == SecurityMode::RawPublicKey
+                    && !security.bootstrap_server
+                {
+                    store.add_public_key(&security.public_key_or_identity, device_endpoint);
+                }
+            }
+        }
+        store
+    }
+
+    /// The OSCORE security contexts of the devices, taken from the OSCORE instances that
+    /// Security instances of servers other than a bootstrap server link to. Contexts in the
+    /// configuration of a pattern are left out as they name no device.
+    pub fn oscore_contexts(&self) -> Result<OscoreContexts, OscoreError> {
+        let mut contexts = OscoreContexts::default();
+        for (device_endpoint, config) in &self.configs {
+            let linked = config
+                .security
+                .iter()
+                .filter(|security| !security.bootstrap_server)
+                .filter_map(|security| security.oscore_instance);
+            for instance_id in linked {
+                let oscore = config
+                    .oscore
+                    .iter()
+                    .find(|oscore| oscore.instance_id == instance_id);
+                if let Some(oscore) = oscore {
+                    contexts.add(device_endpoint, &oscore.parameters())?;
+                }
+            }
+        }
+        Ok(contexts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{test_client::certificate, trust_store::TrustStore, PeerIdentity};
+    use openssl::pkey::{PKey, Private};
+
+    #[test]
+    fn test_security_values() {
+        let security = SecurityInstance {
+            instance_id: 1,
+            server_uri: "coaps://lwm2m.example.com:5684".to_owned(),
+            bootstrap_server: false,
+            security_mode: SecurityMode::PreSharedKey,
+            public_key_or_identity: b"device123".to_vec(),
+            server_public_key: vec![],
+            secret_key: vec![0x01, 0x02],
+            short_server_id: Some(101),
+            oscore_instance: None,
+            resources: BTreeMap::from([(11, ResourceValue::Integer(10))]),
+        };
+        let values = security.values();
+        assert_eq!(values.len(), 8);
+        assert_eq!(
+            values[&CoreLink::try_from("</0/1/2>").unwrap()],
+            ResourceValue::Integer(0)
+        );
+        assert_eq!(
+            values[&CoreLink::try_from("</0/1/10>").unwrap()],
+            ResourceValue::Integer(101)
+        );
+
+        let bootstrap = SecurityInstance {
+            bootstrap_server: true,
+            short_server_id: None,
+            ..security
+        };
+        assert!(!bootstrap
+            .values()
+            .contains_key(&CoreLink::try_from("</0/1/10>").unwrap()));
+    }
+
+    #[test]
+    fn test_server_values() {
+        let server = ServerInstance {
+            instance_id: 0,
+            short_server_id: 101,
+            lifetime: 3600,
+            default_min_period: None,
+            default_max_period: Some(300),
+            notification_storing: true,
+            binding: "U".to_owned(),
+            resources: BTreeMap::new(),
+        };
+        assert_eq!(
+            server.values(),
+            HashMap::from([
+                (
+                    CoreLink::try_from("</1/0/0>").unwrap(),
+                    ResourceValue::Integer(101)
+                ),
+                (
+                    CoreLink::try_from("</1/0/1>").unwrap(),
+                    ResourceValue::Integer(3600)
+                ),
+                (
+                    CoreLink::try_from("</1/0/3>").unwrap(),
+                    ResourceValue::Integer(300)
+                ),
+                (
+                    CoreLink::try_from("</1/0/6>").unwrap(),
+                    ResourceValue::Boolean(true)
+                ),
+                (
+                    CoreLink::try_from("</1/0/7>").unwrap(),
+                    ResourceValue::String("U".to_owned())
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_store_patterns() {
+        let config = |lifetime| BootstrapConfig {
+            lwm2m_version: Lwm2mVersion::V10,
+            security: vec![],
+            servers: vec![ServerInstance {
+                instance_id: 0,
+                short_server_id: 101,
+                lifetime,
+                default_min_period: None,
+                default_max_period: None,
+                notification_storing: false,
+                binding: "U".to_owned(),
+                resources: BTreeMap::new(),
+            }],
+            oscore: vec![],
+        };
+        let mut store = BootstrapConfigStore::default();
+        store.add("*", config(1));
+        store.add("sensor-*", config(2));
+        store.add("sensor-42", config(3));
+        store.add("sensor-4*", config(4));
+
+        let lifetime = |endpoint| store.get(endpoint).map(|config| config.servers[0].lifetime);
+        assert_eq!(lifetime("sensor-42"), Some(3));
+        assert_eq!(lifetime("sensor-43"), Some(4));
+        assert_eq!(lifetime("sensor-1"), Some(2));
+        assert_eq!(lifetime("meter-1"), Some(1));
+
+        let mut store = BootstrapConfigStore::default();
+        store.add("sensor-*", config(2));
+        assert!(store.get("meter-1").is_none());
+    }
+
+    #[test]
+    fn test_psk_store() {
+        let security = |instance_id, bootstrap_server, identity: &[u8]| SecurityInstance {
+            instance_id,
+            server_uri: "coaps://lwm2m.example.com:5684".to_owned(),
+            bootstrap_server,
+            security_mode: SecurityMode::PreSharedKey,
+            public_key_or_identity: identity.to_vec(),
+            server_public_key: vec![],
+            secret_key: vec![0x01, 0x02],
+            short_server_id: (!bootstrap_server).then_some(101),
+            oscore_instance: None,
+            resources: BTreeMap::new(),
+        };
+        let mut store = BootstrapConfigStore::default();
+        store.add(
+            "sensor-42",
+            BootstrapConfig {
+                lwm2m_version: Lwm2mVersion::V10,
+                security: vec![
+                    security(0, true, b"bootstrap"),
+                    security(1, false, b"sensor-42"),
+                ],
+                servers: vec![],
+                oscore: vec![],
+            },
+        );
+        store.add(
+            "meter-*",
+            BootstrapConfig {
+                lwm2m_version: Lwm2mVersion::V10,
+                security: vec![SecurityInstance {
+                    security_mode: SecurityMode::NoSec,
+                    ..security(0, false, b"meter")
+                }],
+                servers: vec![],
+                oscore: vec![],
+            },
+        );
+        store.add(
+            "sensor-*",
+            BootstrapConfig {
+                lwm2m_version: Lwm2mVersion::V10,
+                security: vec![security(0, false, b"sensor")],
+                servers: vec![],
+                oscore: vec![],
+            },
+        );
+
+        let credentials = store.psk_store();
+        assert_eq!(credentials.key(b"sensor-42"), Some([0x01, 0x02].as_slice()));
+        assert_eq!(credentials.key(b"bootstrap"), None);
+        assert_eq!(credentials.key(b"meter"), None);
+        // Patterns name no device
+        assert_eq!(credentials.key(b"sensor"), None);
+    }
+
+    #[test]
+    fn test_trust_store() {
+        let (device, device_key) = certificate("device123", &[], None);
+        let (sensor, sensor_key) = certificate("sensor-1", &[], None);
+        let security = |public_key: &PKey<Private>| SecurityInstance {
+            instance_id: 0,
+            server_uri: "coaps://lwm2m.example.com:5684".to_owned(),
+            bootstrap_server: false,
+            security_mode: SecurityMode::RawPublicKey,
+            public_key_or_identity: public_key.public_key_to_der().unwrap(),
+            server_public_key: vec![],
+            secret_key: vec![],
+            short_server_id: Some(101),
+            oscore_instance: None,
+            resources: BTreeMap::new(),
+        };
+        let mut store = BootstrapConfigStore::default();
+        store.add(
+            "device123",
+            BootstrapConfig {
+                lwm2m_version: Lwm2mVersion::V10,
+                security: vec![security(&device_key)],
+                servers: vec![],
+                oscore: vec![],
+            },
+        );
+        store.add(
+            "sensor-*",
+            BootstrapConfig {
+                lwm2m_version: Lwm2mVersion::V10,
+                security: vec![security(&sensor_key)],
+                servers: vec![],
+                oscore: vec![],
+            },
+        );
+
+        let trust_store = store.trust_store();
+        assert_eq!(
+            trust_store.verify(&[device]),
+            Some(PeerIdentity::RawPublicKey {
+                endpoint_name: "device123".to_owned(),
+                public_key: device_key.public_key_to_der().unwrap(),
+            })
+        );
+        assert_eq!(trust_store.verify(&[sensor]), None);
+    }
+
+    #[test]
+    fn test_oscore_contexts() {
+        let oscore = |instance_id, sender_id: u8| OscoreInstance {
+            instance_id,
+            master_secret: (0..16).collect(),
+            sender_id: vec![sender_id],
+            recipient_id: vec![],
+            master_salt: vec![],
+            id_context: None,
+            resources: BTreeMap::new(),
+        };
+        let security = |instance_id, bootstrap_server, oscore_instance| SecurityInstance {
+            instance_id,
+            server_uri: "coap://lwm2m.example.com:5683".to_owned(),
+            bootstrap_server,
+            security_mode: SecurityMode::NoSec,
+            public_key_or_identity: vec![],
+            server_public_key: vec![],
+            secret_key: vec![],
+            short_server_id: (!bootstrap_server).then_some(101),
+            oscore_instance,
+            resources: BTreeMap::new(),
+        };
+        assert_eq!(
+            security(0, false, Some(1)).values()[&CoreLink::try_from("</0/0/17>").unwrap()],
+            ResourceValue::ObjectLink(ObjectLink::try_from("21:1".to_owned()).unwrap())
+        );
+        assert_eq!(oscore(1, 0x01).values().len(), 4);
+
+        let mut store = BootstrapConfigStore::default();
+        store.add(
+            "device123",
+            BootstrapConfig {
+                lwm2m_version: Lwm2mVersion::V11,
+                security: vec![security(0, true, Some(0)), security(1, false, Some(1))],
+                servers: vec![],
+                oscore: vec![oscore(0, 0x01), oscore(1, 0x02)],
+            },
+        );
+        store.add(
+            "sensor-*",
+            BootstrapConfig {
+                lwm2m_version: Lwm2mVersion::V11,
+                security: vec![security(0, false, Some(0))],
+                servers: vec![],
+                oscore: vec![oscore(0, 0x03)],
+            },
+        );
+
+        assert!(store.has_credentials("device123"));
+        assert!(store.has_credentials("sensor-1"));
+        assert!(!store.has_credentials("device456"));
+
+        let contexts = store.oscore_contexts().unwrap();
+        // The device sends with its Sender ID, the kid the server looks up
+        assert_eq!(contexts.find(&[0x02], None), Some("device123"));
+        let context = contexts.get("device123").unwrap();
+        assert_eq!(context.sender_id(), &[] as &[u8]);
+        // The context towards the bootstrap server and the one of the pattern are left out
+        assert_eq!(contexts.find(&[0x01], None), None);
+        assert_eq!(contexts.find(&[0x03], None), None);
+    }
+}
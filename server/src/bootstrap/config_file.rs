@@ -0,0 +1,876 @@
+use object_model::{
+    core_link::CoreLink,
+    object_link::ObjectLink,
+    value::{ObjectInstance, ResourceValue},
+    ObjectModel, ObjectModelStore, ResourceType, Version,
+};
+use serde::Deserialize;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    hash::Hash,
+    path::Path,
+};
+
+use super::{
+    config::{
+        BootstrapConfig, BootstrapConfigStore, OscoreInstance, SecurityInstance, SecurityMode,
+        ServerInstance, DEFAULT_LWM2M_VERSION, OSCORE_OBJECT_ID, SECURITY_OBJECT_ID,
+        SERVER_OBJECT_ID,
+    },
+    err::ConfigError,
+};
+use crate::{lwm2m_requests::registration_request::Lwm2mVersion, oscore::context::SecurityContext};
+
+// Bootstrap configurations as they are written by hand, in TOML or in the same structure in JSON:
+//
+// [endpoints."sensor-*"]
+// lwm2m_version = "1.0"
+//
+// [[endpoints."sensor-*".security]]
+// server_uri = "coaps://lwm2m.example.com:5684"
+// security_mode = "psk"
+// psk_identity = "sensor"
+// psk_key = "000102030405060708090a0b0c0d0e0f"
+// short_server_id = 101
+//
+// [[endpoints."sensor-*".servers]]
+// short_server_id = 101
+// lifetime = 3600
+// binding = "U"
+// resources = { 5 = 86400 }
+//
+// A Security instance protected with OSCORE links to an instance of the OSCORE object (21),
+// with the Sender ID of the device and the Recipient ID it expects from the server:
+//
+// [[endpoints.device123.security]]
+// server_uri = "coap://lwm2m.example.com:5683"
+// security_mode = "nosec"
+// short_server_id = 101
+// oscore_instance = 0
+//
+// [[endpoints.device123.oscore]]
+// master_secret = "0102030405060708090a0b0c0d0e0f10"
+// master_salt = "9e7ca92223786340"
+// sender_id = "01"
+// recipient_id = ""
+//
+// Endpoints ending in * are prefixes, see `BootstrapConfigStore::add`. The LwM2M version of the
+// devices defaults to 1.1, the instances are written as the objects of that version.
+// Keys are hex encoded, other resources of an instance can be set by resource ID.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    endpoints: BTreeMap<String, EndpointEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EndpointEntry {
+    #[serde(default = "default_lwm2m_version")]
+    lwm2m_version: Lwm2mVersion,
+    #[serde(default)]
+    security: Vec<SecurityEntry>,
+    #[serde(default)]
+    servers: Vec<ServerEntry>,
+    #[serde(default)]
+    oscore: Vec<OscoreEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SecurityEntry {
+    // Defaults to the position in the list
+    instance_id: Option<u16>,
+    server_uri: String,
+    #[serde(default)]
+    bootstrap_server: bool,
+    security_mode: SecurityMode,
+    // The PSK identity as text and the key in hex, only for the psk mode
+    psk_identity: Option<String>,
+    psk_key: Option<String>,
+    // Key or certificate of the device and of the server in hex, for the other modes
+    public_key: Option<String>,
+    server_public_key: Option<String>,
+    secret_key: Option<String>,
+    short_server_id: Option<u16>,
+    // The instance ID of the OSCORE instance that protects the connection
+    oscore_instance: Option<u16>,
+    #[serde(default)]
+    resources: BTreeMap<String, EntryValue>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServerEntry {
+    // Defaults to the position in the list
+    instance_id: Option<u16>,
+    short_server_id: u16,
+    lifetime: u32,
+    default_min_period: Option<u32>,
+    default_max_period: Option<u32>,
+    #[serde(default)]
+    notification_storing: bool,
+    #[serde(default = "default_binding")]
+    binding: String,
+    #[serde(default)]
+    resources: BTreeMap<String, EntryValue>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OscoreEntry {
+    // Defaults to the position in the list
+    instance_id: Option<u16>,
+    // All in hex, the IDs are those of the device and may be empty
+    master_secret: String,
+    sender_id: String,
+    recipient_id: String,
+    master_salt: Option<String>,
+    id_context: Option<String>,
+    #[serde(default)]
+    resources: BTreeMap<String, EntryValue>,
+}
+
+fn default_lwm2m_version() -> Lwm2mVersion {
+    DEFAULT_LWM2M_VERSION
+}
+
+fn default_binding() -> String {
+    "U".to_owned()
+}
+
+// A value of a resource set by ID, converted once the type of the resource is known
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum EntryValue {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl BootstrapConfigStore {
+    /// Loads the bootstrap configurations from a TOML or JSON file, depending on its extension.
+    /// Every configuration is checked against the Security (0) and Server (1) object models
+    /// of its LwM2M version, and the OSCORE (21) object model if it uses OSCORE.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The configuration file
+    /// * `models` - The object models the configurations are checked against
+    pub fn load(path: &Path, models: &ObjectModelStore) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml(&content, models),
+            Some("json") => Self::from_json(&content, models),
+            _ => Err(ConfigError::UnsupportedFormat(path.to_owned())),
+        }
+    }
+
+    pub fn from_toml(content: &str, models: &ObjectModelStore) -> Result<Self, ConfigError> {
+        let file: ConfigFile =
+            toml::from_str(content).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        Self::from_file(file, models)
+    }
+
+    pub fn from_json(content: &str, models: &ObjectModelStore) -> Result<Self, ConfigError> {
+        let file: ConfigFile =
+            serde_json::from_str(content).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        Self::from_file(file, models)
+    }
+
+    fn from_file(file: ConfigFile, models: &ObjectModelStore) -> Result<Self, ConfigError> {
+        // The models of the objects a configuration writes, by the LwM2M version it is written in
+        // and whether it uses OSCORE
+        let mut versioned_models = HashMap::new();
+        let mut store = BootstrapConfigStore::default();
+        for (endpoint, entry) in file.endpoints {
+            let uses_oscore = entry.uses_oscore();
+            if uses_oscore && entry.lwm2m_version == Lwm2mVersion::V10 {
+                return Err(ConfigError::invalid(
+                    &endpoint,
+                    "OSCORE needs LwM2M 1.1 or later",
+                ));
+            }
+            let key = (Version::from(entry.lwm2m_version), uses_oscore);
+            let config_models = match versioned_models.entry(key) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let config_models = ConfigModels::get(models, &entry.key().0, uses_oscore)?;
+                    entry.insert(config_models)
+                }
+            };
+            let config = entry.into_config(&endpoint, config_models)?;
+            store.add(&endpoint, config);
+        }
+        Ok(store)
+    }
+}
+
+// The models of the Security (0), Server (1) and, only for configurations that use it,
+// OSCORE (21) objects in one LwM2M version
+struct ConfigModels {
+    security: ObjectModel,
+    server: ObjectModel,
+    oscore: Option<ObjectModel>,
+}
+
+impl ConfigModels {
+    fn get(
+        models: &ObjectModelStore,
+        lwm2m_version: &Version,
+        uses_oscore: bool,
+    ) -> Result<Self, ConfigError> {
+        Ok(ConfigModels {
+            security: models.get_object_model_for(SECURITY_OBJECT_ID, lwm2m_version)?,
+            server: models.get_object_model_for(SERVER_OBJECT_ID, lwm2m_version)?,
+            oscore: match uses_oscore {
+                true => Some(models.get_object_model_for(OSCORE_OBJECT_ID, lwm2m_version)?),
+                false => None,
+            },
+        })
+    }
+}
+
+impl EndpointEntry {
+    fn uses_oscore(&self) -> bool {
+        !self.oscore.is_empty()
+            || self
+                .security
+                .iter()
+                .any(|entry| entry.oscore_instance.is_some())
+    }
+
+    fn into_config(
+        self,
+        endpoint: &str,
+        models: &ConfigModels,
+    ) -> Result<BootstrapConfig, ConfigError> {
+        if self.security.is_empty() {
+            return Err(ConfigError::invalid(endpoint, "no Security instance"));
+        }
+        let security = self
+            .security
+            .into_iter()
+            .enumerate()
+            .map(|(position, entry)| entry.into_instance(endpoint, position, &models.security))
+            .collect::<Result<Vec<_>, _>>()?;
+        let servers = self
+            .servers
+            .into_iter()
+            .enumerate()
+            .map(|(position, entry)| entry.into_instance(endpoint, position, &models.server))
+            .collect::<Result<Vec<_>, _>>()?;
+        let oscore = match &models.oscore {
+            Some(model) => self
+                .oscore
+                .into_iter()
+                .enumerate()
+                .map(|(position, entry)| entry.into_instance(endpoint, position, model))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![],
+        };
+
+        check_unique(endpoint, security.iter().map(|instance| instance.link()))?;
+        check_unique(endpoint, servers.iter().map(|instance| instance.link()))?;
+        check_unique(endpoint, oscore.iter().map(|instance| instance.link()))?;
+        check_unique(
+            endpoint,
+            servers
+                .iter()
+                .map(|server| format!("short_server_id {}", server.short_server_id)),
+        )?;
+
+        // The Short Server ID links the Security instance of a server to its Server instance
+        for instance in &security {
+            match (instance.bootstrap_server, instance.short_server_id) {
+                (true, _) => {}
+                (false, None) => {
+                    return Err(ConfigError::invalid(
+                        endpoint,
+                        format!("{} has no short_server_id", instance.link()),
+                    ))
+                }
+                (false, Some(short_server_id)) => {
+                    if !servers
+                        .iter()
+                        .any(|server| server.short_server_id == short_server_id)
+                    {
+                        return Err(ConfigError::invalid(
+                            endpoint,
+                            format!("no server with short_server_id {}", short_server_id),
+                        ));
+                    }
+                }
+            }
+        }
+
+        for instance in &security {
+            if let Some(instance_id) = instance.oscore_instance {
+                if !oscore
+                    .iter()
+                    .any(|oscore| oscore.instance_id == instance_id)
+                {
+                    return Err(ConfigError::invalid(
+                        endpoint,
+                        format!(
+                            "{} links to no OSCORE instance {}",
+                            instance.link(),
+                            instance_id
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(BootstrapConfig {
+            lwm2m_version: self.lwm2m_version,
+            security,
+            servers,
+            oscore,
+        })
+    }
+}
+
+fn check_unique<T: Eq + Hash + fmt::Display>(
+    endpoint: &str,
+    items: impl IntoIterator<Item = T>,
+) -> Result<(), ConfigError> {
+    let mut seen = HashSet::new();
+    for item in items {
+        if seen.contains(&item) {
+            return Err(ConfigError::invalid(
+                endpoint,
+                format!("{} is configured twice", item),
+            ));
+        }
+        seen.insert(item);
+    }
+    Ok(())
+}
+
+impl SecurityEntry {
+    fn into_instance(
+        self,
+        endpoint: &str,
+        position: usize,
+        model: &ObjectModel,
+    ) -> Result<SecurityInstance, ConfigError> {
+        let instance_id = instance_id(endpoint, self.instance_id, position)?;
+        let link = CoreLink::new(SECURITY_OBJECT_ID, Some(instance_id), None, None);
+        let invalid =
+            |message: String| ConfigError::invalid(endpoint, format!("{}: {}", link, message));
+
+        let hex = |value: Option<String>| {
+            value
+                .map(|value| decode_hex(&value).map_err(&invalid))
+                .transpose()
+                .map(Option::unwrap_or_default)
+        };
+        let uses_psk = self.psk_identity.is_some() || self.psk_key.is_some();
+        let (public_key_or_identity, secret_key) = match (self.security_mode, uses_psk) {
+            (SecurityMode::PreSharedKey, _) => {
+                if self.public_key.is_some() || self.secret_key.is_some() {
+                    return Err(invalid(
+                        "the psk mode takes psk_identity and psk_key".to_owned(),
+                    ));
+                }
+                match (self.psk_identity, self.psk_key) {
+                    (Some(identity), Some(key)) => (identity.into_bytes(), hex(Some(key))?),
+                    _ => {
+                        return Err(invalid(
+                            "the psk mode needs a psk_identity and a psk_key".to_owned(),
+                        ))
+                    }
+                }
+            }
+            (_, true) => {
+                return Err(invalid(
+                    "psk_identity and psk_key are only used by the psk mode".to_owned(),
+                ))
+            }
+            (_, false) => (hex(self.public_key)?, hex(self.secret_key)?),
+        };
+
+        let instance = SecurityInstance {
+            instance_id,
+            server_uri: self.server_uri,
+            bootstrap_server: self.bootstrap_server,
+            security_mode: self.security_mode,
+            public_key_or_identity,
+            server_public_key: hex(self.server_public_key)?,
+            secret_key,
+            short_server_id: self.short_server_id,
+            oscore_instance: self.oscore_instance,
+            resources: BTreeMap::new(),
+        };
+        let named = instance.values();
+        let resources = resources(&link, self.resources, &named, model).map_err(invalid)?;
+        let instance = SecurityInstance {
+            resources,
+            ..instance
+        };
+        ObjectInstance::from_values(instance_id, &instance.values(), model)
+            .map_err(|err| invalid(err.to_string()))?;
+        Ok(instance)
+    }
+}
+
+impl ServerEntry {
+    fn into_instance(
+        self,
+        endpoint: &str,
+        position: usize,
+        model: &ObjectModel,
+    ) -> Result<ServerInstance, ConfigError> {
+        let instance_id = instance_id(endpoint, self.instance_id, position)?;
+        let link = CoreLink::new(SERVER_OBJECT_ID, Some(instance_id), None, None);
+        let invalid =
+            |message: String| ConfigError::invalid(endpoint, format!("{}: {}", link, message));
+
+        // U(DP), T(CP), S(MS), N(on-IP) and Q(ueue mode) of LwM2M 1.0
+        if self.binding.is_empty() || !self.binding.chars().all(|mode| "UTSNQ".contains(mode)) {
+            return Err(invalid(format!("binding {} is not valid", self.binding)));
+        }
+
+        let instance = ServerInstance {
+            instance_id,
+            short_server_id: self.short_server_id,
+            lifetime: self.lifetime,
+            default_min_period: self.default_min_period,
+            default_max_period: self.default_max_period,
+            notification_storing: self.notification_storing,
+            binding: self.binding,
+            resources: BTreeMap::new(),
+        };
+        let named = instance.values();
+        let resources = resources(&link, self.resources, &named, model).map_err(invalid)?;
+        let instance = ServerInstance {
+            resources,
+            ..instance
+        };
+        ObjectInstance::from_values(instance_id, &instance.values(), model)
+            .map_err(|err| invalid(err.to_string()))?;
+        Ok(instance)
+    }
+}
+
+impl OscoreEntry {
+    fn into_instance(
+        self,
+        endpoint: &str,
+        position: usize,
+        model: &ObjectModel,
+    ) -> Result<OscoreInstance, ConfigError> {
+        let instance_id = instance_id(endpoint, self.instance_id, position)?;
+        let link = CoreLink::new(OSCORE_OBJECT_ID, Some(instance_id), None, None);
+        let invalid =
+            |message: String| ConfigError::invalid(endpoint, format!("{}: {}", link, message));
+
+        let instance = OscoreInstance {
+            instance_id,
+            master_secret: decode_hex(&self.master_secret).map_err(invalid)?,
+            sender_id: decode_hex(&self.sender_id).map_err(invalid)?,
+            recipient_id: decode_hex(&self.recipient_id).map_err(invalid)?,
+            master_salt: match self.master_salt {
+                Some(salt) => decode_hex(&salt).map_err(invalid)?,
+                None => vec![],
+            },
+            id_context: self
+                .id_context
+                .map(|id_context| decode_hex(&id_context))
+                .transpose()
+                .map_err(invalid)?,
+            resources: BTreeMap::new(),
+        };
+        // The server derives its context from the same parameters when it starts
+        SecurityContext::derive(&instance.parameters()).map_err(|err| invalid(err.to_string()))?;
+
+        let named = instance.values();
+        let resources = resources(&link, self.resources, &named, model).map_err(invalid)?;
+        let instance = OscoreInstance {
+            resources,
+            ..instance
+        };
+        ObjectInstance::from_values(instance_id, &instance.values(), model)
+            .map_err(|err| invalid(err.to_string()))?;
+        Ok(instance)
+    }
+}
+
+fn instance_id(
+    endpoint: &str,
+    instance_id: Option<u16>,
+    position: usize,
+) -> Result<u16, ConfigError> {
+    match instance_id {
+        Some(instance_id) => Ok(instance_id),
+        None => u16::try_from(position)
+            .map_err(|_| ConfigError::invalid(endpoint, "too many instances")),
+    }
+}
+
+// Converts the resources set by ID to the types of their models
+fn resources(
+    instance: &CoreLink,
+    entries: BTreeMap<String, EntryValue>,
+    named: &HashMap<CoreLink, ResourceValue>,
+    model: &ObjectModel,
+) -> Result<BTreeMap<u16, ResourceValue>, String> {
+    entries
+        .into_iter()
+        .map(|(resource_id, value)| {
+            let resource_id: u16 = resource_id
+                .parse()
+                .map_err(|_| format!("{} is not a resource ID", resource_id))?;
+            let link = CoreLink::new(
+                instance.object_id,
+                instance.object_instance,
+                Some(resource_id),
+                None,
+            );
+            if named.contains_key(&link) {
+                return Err(format!("{} is already set by its name", link));
+            }
+            let resource = model.resources().get(&resource_id).ok_or_else(|| {
+                format!("{} is not a resource of the {} object", link, model.name())
+            })?;
+            let resourcetype = resource
+                .resourcetype()
+                .ok_or_else(|| format!("{} has no value", link))?;
+            let value = value.to_resource_value(resourcetype).ok_or_else(|| {
+                format!("{:?} is not a valid {} for {}", value, resourcetype, link)
+            })?;
+            Ok((resource_id, value))
+        })
+        .collect()
+}
+
+impl EntryValue {
+    fn to_resource_value(&self, resourcetype: ResourceType) -> Option<ResourceValue> {
+        match (resourcetype, self) {
+            (ResourceType::String, EntryValue::String(value)) => {
+                Some(ResourceValue::String(value.clone()))
+            }
+            (ResourceType::Integer, EntryValue::Integer(value)) => {
+                Some(ResourceValue::Integer(*value))
+            }
+            (ResourceType::UnsignedInteger, EntryValue::Integer(value)) => u64::try_from(*value)
+                .ok()
+                .map(ResourceValue::UnsignedInteger),
+            (ResourceType::Time, EntryValue::Integer(value)) => {
+                u64::try_from(*value).ok().map(ResourceValue::Time)
+            }
+            (ResourceType::Float, EntryValue::Float(value)) => Some(ResourceValue::Float(*value)),
+            (ResourceType::Float, EntryValue::Integer(value)) => {
+                Some(ResourceValue::Float(*value as f64))
+            }
+            (ResourceType::Boolean, EntryValue::Boolean(value)) => {
+                Some(ResourceValue::Boolean(*value))
+            }
+            (ResourceType::Opaque, EntryValue::String(value)) => {
+                decode_hex(value).ok().map(ResourceValue::Opaque)
+            }
+            (ResourceType::ObjectLink, EntryValue::String(value)) => {
+                ObjectLink::try_from(value.clone())
+                    .ok()
+                    .map(ResourceValue::ObjectLink)
+            }
+            (ResourceType::CoreLink, EntryValue::String(value)) => {
+                CoreLink::try_from(value.as_str())
+                    .ok()
+                    .map(ResourceValue::CoreLink)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, String> {
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        return Err(format!("{} is not a hex string", value));
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|start| {
+            u8::from_str_radix(&value[start..start + 2], 16)
+                .map_err(|_| format!("{} is not a hex string", value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lwm2m_operations::test_device::{
+        oscore_object, security_object, security_object_v11, server_object,
+    };
+
+    fn models() -> ObjectModelStore {
+        let mut models = ObjectModelStore::default();
+        models.add_model(security_object());
+        models.add_model(security_object_v11());
+        models.add_model(server_object());
+        models.add_model(oscore_object());
+        models
+    }
+
+    fn from_toml(content: &str) -> Result<BootstrapConfigStore, ConfigError> {
+        BootstrapConfigStore::from_toml(content, &models())
+    }
+
+    const CONFIG: &str = r#"
+        [[endpoints."sensor-*".security]]
+        server_uri = "coaps://lwm2m.example.com:5684"
+        security_mode = "psk"
+        psk_identity = "sensor"
+        psk_key = "000102030405060708090a0b0c0d0e0f"
+        short_server_id = 101
+        resources = { 11 = 10 }
+
+        [[endpoints."sensor-*".security]]
+        instance_id = 5
+        server_uri = "coap://bootstrap.example.com:5683"
+        security_mode = "nosec"
+        bootstrap_server = true
+
+        [[endpoints."sensor-*".servers]]
+        short_server_id = 101
+        lifetime = 3600
+        resources = { 5 = 86400 }
+    "#;
+
+    #[test]
+    fn test_from_toml() {
+        let store = from_toml(CONFIG).unwrap();
+        assert!(store.get("meter-1").is_none());
+        let config = store.get("sensor-1").unwrap();
+        assert_eq!(config.lwm2m_version, DEFAULT_LWM2M_VERSION);
+
+        assert_eq!(
+            config.security[0],
+            SecurityInstance {
+                instance_id: 0,
+                server_uri: "coaps://lwm2m.example.com:5684".to_owned(),
+                bootstrap_server: false,
+                security_mode: SecurityMode::PreSharedKey,
+                public_key_or_identity: b"sensor".to_vec(),
+                server_public_key: vec![],
+                secret_key: (0..16).collect(),
+                short_server_id: Some(101),
+                oscore_instance: None,
+                resources: BTreeMap::from([(11, ResourceValue::Integer(10))]),
+            }
+        );
+        assert_eq!(config.security[1].instance_id, 5);
+        assert_eq!(config.security[1].security_mode, SecurityMode::NoSec);
+        assert_eq!(
+            config.servers,
+            vec![ServerInstance {
+                instance_id: 0,
+                short_server_id: 101,
+                lifetime: 3600,
+                default_min_period: None,
+                default_max_period: None,
+                notification_storing: false,
+                binding: "U".to_owned(),
+                resources: BTreeMap::from([(5, ResourceValue::Integer(86400))]),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_from_json() {
+        let json = r#"{"endpoints": {"device123": {
+            "lwm2m_version": "1.0",
+            "security": [{"server_uri": "coap://lwm2m.example.com:5683", "security_mode": "nosec",
+                          "short_server_id": 1}],
+            "servers": [{"short_server_id": 1, "lifetime": 300, "binding": "UQ"}]
+        }}}"#;
+        let store = BootstrapConfigStore::from_json(json, &models()).unwrap();
+        let config = store.get("device123").unwrap();
+        assert_eq!(config.servers[0].binding, "UQ");
+        assert_eq!(config.lwm2m_version, Lwm2mVersion::V10);
+        assert!(store.get("device1234").is_none());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let server = r#"servers = [{ short_server_id = 101, lifetime = 3600 }]"#;
+        let security = |entry: &str| {
+            format!(
+                "[endpoints.device123]\nsecurity = [{{ server_uri = \"coap://lwm2m.example.com\", short_server_id = 101, {} }}]\n{}",
+                entry, server
+            )
+        };
+        let invalid = [
+            // Unknown resource ID
+            security(r#"security_mode = "nosec", resources = { 99 = 1 }"#),
+            // Resource ID that is no number
+            security(r#"security_mode = "nosec", resources = { ssid = 1 }"#),
+            // Resource that is set by name
+            security(r#"security_mode = "nosec", resources = { 10 = 102 }"#),
+            // Value of the wrong type
+            security(r#"security_mode = "nosec", resources = { 11 = "ten" }"#),
+            // PSK mode without a key
+            security(r#"security_mode = "psk", psk_identity = "device123""#),
+            // Key that is not hex
+            security(r#"security_mode = "psk", psk_identity = "device123", psk_key = "xyz0""#),
+            // PSK identity in another mode
+            security(r#"security_mode = "rpk", psk_identity = "device123""#),
+        ];
+        for content in invalid {
+            assert!(
+                matches!(from_toml(&content), Err(ConfigError::Invalid { .. })),
+                "{}",
+                content
+            );
+        }
+
+        let invalid = [
+            // No server with the Short Server ID
+            "[endpoints.device123]\nsecurity = [{ server_uri = \"coap://a\", security_mode = \"nosec\", short_server_id = 102 }]\n".to_owned() + server,
+            // No Short Server ID
+            "[endpoints.device123]\nsecurity = [{ server_uri = \"coap://a\", security_mode = \"nosec\" }]\n".to_owned() + server,
+            // Two Security instances with the same ID
+            "[endpoints.device123]\nsecurity = [{ server_uri = \"coap://a\", security_mode = \"nosec\", short_server_id = 101 }, { instance_id = 0, server_uri = \"coap://b\", security_mode = \"nosec\", bootstrap_server = true }]\n".to_owned() + server,
+            // Invalid binding
+            "[endpoints.device123]\nsecurity = [{ server_uri = \"coap://a\", security_mode = \"nosec\", short_server_id = 101 }]\nservers = [{ short_server_id = 101, lifetime = 3600, binding = \"X\" }]".to_owned(),
+            // No Security instance
+            "[endpoints.device123]\n".to_owned() + server,
+        ];
+        for content in invalid {
+            assert!(
+                matches!(from_toml(&content), Err(ConfigError::Invalid { .. })),
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn test_oscore() {
+        let config = |oscore_instance: u16, sender_id: &str| {
+            format!(
+                r#"
+                [[endpoints.device123.security]]
+                server_uri = "coap://lwm2m.example.com:5683"
+                security_mode = "nosec"
+                short_server_id = 101
+                oscore_instance = {}
+
+                [[endpoints.device123.servers]]
+                short_server_id = 101
+                lifetime = 3600
+
+                [[endpoints.device123.oscore]]
+                master_secret = "0102030405060708090a0b0c0d0e0f10"
+                master_salt = "9e7ca92223786340"
+                sender_id = "{}"
+                recipient_id = ""
+                resources = {{ 3 = 10 }}
+                "#,
+                oscore_instance, sender_id
+            )
+        };
+        let store = from_toml(&config(0, "01")).unwrap();
+        let config_123 = store.get("device123").unwrap();
+        assert_eq!(config_123.security[0].oscore_instance, Some(0));
+        assert_eq!(
+            config_123.oscore,
+            vec![OscoreInstance {
+                instance_id: 0,
+                master_secret: (1..=16).collect(),
+                sender_id: vec![0x01],
+                recipient_id: vec![],
+                master_salt: vec![0x9e, 0x7c, 0xa9, 0x22, 0x23, 0x78, 0x63, 0x40],
+                id_context: None,
+                resources: BTreeMap::from([(3, ResourceValue::Integer(10))]),
+            }]
+        );
+        assert!(store.oscore_contexts().unwrap().get("device123").is_some());
+
+        // Next to a configuration of the same version without OSCORE
+        let anonymous = r#"
+            [[endpoints.anonymous.security]]
+            server_uri = "coap://bootstrap.example.com:5683"
+            security_mode = "nosec"
+            bootstrap_server = true
+        "#;
+        let store = from_toml(&format!("{}{}", anonymous, config(0, "01"))).unwrap();
+        assert!(store.get("anonymous").unwrap().oscore.is_empty());
+        assert_eq!(store.get("device123").unwrap().oscore.len(), 1);
+
+        // No OSCORE instance 1, OSCORE for LwM2M 1.0, and IDs that are the same or too long
+        // for the nonce
+        let lwm2m_10 = format!(
+            "[endpoints.device123]\nlwm2m_version = \"1.0\"\n{}",
+            config(0, "01")
+        );
+        for content in [
+            config(1, "01"),
+            lwm2m_10,
+            config(0, ""),
+            config(0, "0102030405060708"),
+        ] {
+            assert!(
+                matches!(from_toml(&content), Err(ConfigError::Invalid { .. })),
+                "{}",
+                content
+            );
+        }
+
+        // The model of the OSCORE object is only needed when it is used
+        let mut models = ObjectModelStore::default();
+        models.add_model(security_object());
+        models.add_model(server_object());
+        assert!(BootstrapConfigStore::from_toml(CONFIG, &models).is_ok());
+        assert!(matches!(
+            BootstrapConfigStore::from_toml(&config(0, "01"), &models),
+            Err(ConfigError::ModelNotFound(_))
+        ));
+        // Security 1.0 has no resource to link the OSCORE instance with
+        models.add_model(oscore_object());
+        assert!(matches!(
+            BootstrapConfigStore::from_toml(&config(0, "01"), &models),
+            Err(ConfigError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let invalid = [
+            // Typo in a field name
+            "[endpoints.device123]\nsecurity = [{ server_url = \"coap://a\", security_mode = \"nosec\" }]",
+            // Unknown security mode
+            "[endpoints.device123]\nsecurity = [{ server_uri = \"coap://a\", security_mode = \"tls\" }]",
+            "[endpoints.device123",
+            // Unknown LwM2M version
+            "[endpoints.device123]\nlwm2m_version = \"2.0\"",
+        ];
+        for content in invalid {
+            assert!(
+                matches!(from_toml(content), Err(ConfigError::Parse(_))),
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn test_without_models() {
+        let result = BootstrapConfigStore::from_toml(CONFIG, &ObjectModelStore::default());
+        assert!(matches!(result, Err(ConfigError::ModelNotFound(_))));
+    }
+
+    #[test]
+    fn test_load_unsupported_format() {
+        let path = std::env::temp_dir().join("lwm2m-bootstrap-test.yaml");
+        fs::write(&path, CONFIG).unwrap();
+        let result = BootstrapConfigStore::load(&path, &models());
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(ConfigError::UnsupportedFormat(_))));
+    }
+}
@@ -0,0 +1,60 @@
+use object_model::err::ModelNotFoundError;
+use std::{error::Error, fmt, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    // Only .toml and .json files can be loaded
+    UnsupportedFormat(PathBuf),
+    Parse(String),
+    ModelNotFound(ModelNotFoundError),
+    // The configuration of an endpoint (pattern) does not fit the Security or Server object
+    Invalid { endpoint: String, message: String },
+}
+
+impl ConfigError {
+    pub(super) fn invalid(endpoint: &str, message: impl Into<String>) -> Self {
+        ConfigError::Invalid {
+            endpoint: endpoint.to_owned(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            ConfigError::Io(err) => write!(f, "Could not read bootstrap configuration: {}", err),
+            ConfigError::UnsupportedFormat(path) => write!(
+                f,
+                "Bootstrap configuration {} is neither TOML nor JSON",
+                path.display()
+            ),
+            ConfigError::Parse(message) => {
+                write!(f, "Invalid bootstrap configuration: {}", message)
+            }
+            ConfigError::ModelNotFound(err) => write!(f, "{}", err),
+            ConfigError::Invalid { endpoint, message } => {
+                write!(
+                    f,
+                    "Invalid bootstrap configuration for {}: {}",
+                    endpoint, message
+                )
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<ModelNotFoundError> for ConfigError {
+    fn from(err: ModelNotFoundError) -> Self {
+        ConfigError::ModelNotFound(err)
+    }
+}
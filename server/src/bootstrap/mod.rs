@@ -0,0 +1,700 @@
+use coap_lite::{
+    option_value::OptionValueU16, CoapOption, MessageClass, Packet, RequestType, ResponseType,
+};
+use coap_server::app::CoapError;
+use object_model::{
+    core_link::CoreLink, value::ObjectInstance, value::ResourceValue, ObjectModel,
+    ObjectModelStore, Version,
+};
+use std::{collections::HashMap, str, sync::Arc, time::Duration};
+use tokio::{sync::broadcast, time};
+
+use crate::{
+    content_format::{self, Lwm2mContentFormat},
+    lwm2m_operations::err::OperationError,
+    lwm2m_requests::{
+        bootstrap_request::Lwm2mBootstrapRequest, registration_request::parse_link_format,
+    },
+    transport::{
+        client::{ClientError, CoapClient},
+        Peer,
+    },
+};
+use config::{BootstrapConfigStore, OSCORE_OBJECT_ID, SECURITY_OBJECT_ID, SERVER_OBJECT_ID};
+
+pub mod config;
+mod config_file;
+pub mod err;
+
+// Time a device gets to answer a single step, including the retransmissions of the request
+const STEP_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#6-1-0-61-Bootstrap-Interface
+/// Answers Bootstrap-Requests on /bs and provisions the devices with the configuration
+/// that is stored for their endpoint name.
+pub struct BootstrapServer {
+    configs: BootstrapConfigStore,
+    models: Arc<ObjectModelStore>,
+    client: CoapClient,
+    step_timeout: Duration,
+    reports_tx: broadcast::Sender<Arc<BootstrapReport>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootstrapStep {
+    // Bootstrap-Discover of the root, tells what the device had before it is deleted
+    Discover,
+    // Bootstrap-Delete of the root, removes every instance the device allows to remove
+    Delete,
+    // Bootstrap-Write of a Security or Server object instance
+    Write(CoreLink),
+    Finish,
+}
+
+#[derive(Debug)]
+pub enum StepOutcome {
+    Done,
+    // The object (instances) a Bootstrap-Discover returned
+    Discovered(Vec<CoreLink>),
+    Failed(OperationError),
+    // The device did not answer within the step timeout
+    TimedOut,
+}
+
+/// What happened in each step of a bootstrap sequence, in order. The sequence stops at the
+/// first step that failed, apart from Bootstrap-Discover which LwM2M 1.0 devices do not support.
+#[derive(Debug)]
+pub struct BootstrapReport {
+    pub device_endpoint: String,
+    pub steps: Vec<(BootstrapStep, StepOutcome)>,
+}
+
+impl BootstrapReport {
+    /// Whether the device acknowledged the Bootstrap-Finish, i.e. it has its configuration.
+    pub fn is_complete(&self) -> bool {
+        matches!(
+            self.steps.last(),
+            Some((BootstrapStep::Finish, StepOutcome::Done))
+        )
+    }
+}
+
+/// A bootstrap sequence for one device, with the Bootstrap-Write payloads encoded up front.
+pub struct BootstrapSession {
+    device_endpoint: String,
+    peer: Peer,
+    writes: Vec<(CoreLink, Lwm2mContentFormat, Vec<u8>)>,
+    client: CoapClient,
+    step_timeout: Duration,
+}
+
+impl BootstrapServer {
+    pub fn new(
+        configs: BootstrapConfigStore,
+        models: Arc<ObjectModelStore>,
+        client: CoapClient,
+    ) -> Self {
+        let (reports_tx, _) = broadcast::channel(64);
+        BootstrapServer {
+            configs,
+            models,
+            client,
+            step_timeout: STEP_TIMEOUT,
+            reports_tx,
+        }
+    }
+
+    pub fn with_step_timeout(mut self, step_timeout: Duration) -> Self {
+        self.step_timeout = step_timeout;
+        self
+    }
+
+    /// The reports of the bootstrap sequences `start` runs, once each sequence ended.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<BootstrapReport>> {
+        self.reports_tx.subscribe()
+    }
+
+    /// Checks a Bootstrap-Request and prepares the sequence that provisions the device.
+    /// The sequence is only run once the request was answered, see `start`.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The parsed Bootstrap-Request
+    /// * `peer` - The source address of the request and the socket it was received on
+    pub fn accept(
+        &self,
+        request: &Lwm2mBootstrapRequest,
+        peer: Peer,
+    ) -> Result<BootstrapSession, CoapError> {
+        let config = self.configs.get(&request.device_endpoint).ok_or_else(|| {
+            CoapError::bad_request(format!(
+                "No bootstrap configuration for {}",
+                request.device_endpoint
+            ))
+        })?;
+        let format = write_format(request.preferred_format);
+
+        let lwm2m_version = Version::from(config.lwm2m_version);
+        let security_model = self.model(SECURITY_OBJECT_ID, &lwm2m_version)?;
+        let server_model = self.model(SERVER_OBJECT_ID, &lwm2m_version)?;
+        let oscore_model = match config.oscore.is_empty() {
+            true => None,
+            false => Some(self.model(OSCORE_OBJECT_ID, &lwm2m_version)?),
+        };
+        let instances = config
+            .security
+            .iter()
+            .map(|security| (security.link(), security.values(), &security_model))
+            .chain(
+                config
+                    .servers
+                    .iter()
+                    .map(|server| (server.link(), server.values(), &server_model)),
+            )
+            .chain(config.oscore.iter().filter_map(|oscore| {
+                Some((oscore.link(), oscore.values(), oscore_model.as_ref()?))
+            }));
+        let writes = instances
+            .map(|(link, values, model)| {
+                let payload = encode_instance(format, &link, &values, model).map_err(|err| {
+                    CoapError::internal(format!(
+                        "Invalid bootstrap configuration for {}: {}",
+                        request.device_endpoint, err
+                    ))
+                })?;
+                Ok((link, format, payload))
+            })
+            .collect::<Result<Vec<_>, CoapError>>()?;
+
+        Ok(BootstrapSession {
+            device_endpoint: request.device_endpoint.clone(),
+            peer,
+            writes,
+            client: self.client.clone(),
+            step_timeout: self.step_timeout,
+        })
+    }
+
+    /// Runs the sequence of `session` once the CoAP server sent its response to the
+    /// Bootstrap-Request, a device ignores bootstrap requests before. The report is published
+    /// to the subscribers, the sequence does not run if the response is not sent within the
+    /// step timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The session `accept` prepared for the request
+    /// * `token` - The token of the Bootstrap-Request
+    pub fn start(&self, session: BootstrapSession, token: &[u8]) {
+        let sent = self.client.response_sent(session.peer.address, token);
+        let step_timeout = self.step_timeout;
+        let reports_tx = self.reports_tx.clone();
+        tokio::spawn(async move {
+            if !matches!(time::timeout(step_timeout, sent).await, Ok(Ok(()))) {
+                return;
+            }
+            let report = session.run().await;
+            // Nobody may be listening
+            let _ = reports_tx.send(Arc::new(report));
+        });
+    }
+
+    /// Whether the device is provisioned with credentials, so it has to authenticate to
+    /// register or bootstrap, see `BootstrapConfigStore::has_credentials`.
+    pub fn requires_authentication(&self, device_endpoint: &str) -> bool {
+        self.configs.has_credentials(device_endpoint)
+    }
+
+    fn model(&self, object_id: u16, lwm2m_version: &Version) -> Result<ObjectModel, CoapError> {
+        self.models
+            .get_object_model_for(object_id, lwm2m_version)
+            .map_err(|_| {
+                CoapError::internal(format!(
+                    "Object {} has no model for LwM2M {}",
+                    object_id, lwm2m_version
+                ))
+            })
+    }
+}
+
+// The format the device asked for with pct if it can hold an object instance, TLV otherwise,
+// which every LwM2M 1.0 device supports
+fn write_format(preferred_format: Option<u16>) -> Lwm2mContentFormat {
+    match preferred_format.and_then(|format| Lwm2mContentFormat::try_from(format).ok()) {
+        Some(
+            format @ (Lwm2mContentFormat::SenmlJson
+            | Lwm2mContentFormat::SenmlCbor
+            | Lwm2mContentFormat::Lwm2mCbor),
+        ) => format,
+        _ => Lwm2mContentFormat::Tlv,
+    }
+}
+
+// Checks the values against the model, the resources of the Security object cannot be
+// written by the Device Management interface so this is not done with `validate_write`
+fn encode_instance(
+    format: Lwm2mContentFormat,
+    link: &CoreLink,
+    values: &HashMap<CoreLink, ResourceValue>,
+    model: &ObjectModel,
+) -> Result<Vec<u8>, OperationError> {
+    let instance_id = link.object_instance.unwrap_or_default();
+    ObjectInstance::from_values(instance_id, values, model).map_err(|err| {
+        OperationError::InvalidValue {
+            link: link.clone(),
+            message: err.to_string(),
+        }
+    })?;
+    Ok(content_format::encode(format, values, link, model)?)
+}
+
+impl BootstrapSession {
+    /// Runs the bootstrap sequence: Bootstrap-Discover, Bootstrap-Delete of everything on the
+    /// device, a Bootstrap-Write of every configured Security and Server instance and
+    /// Bootstrap-Finish. Every step has to be answered within the step timeout.
+    pub async fn run(self) -> BootstrapReport {
+        let mut report = BootstrapReport {
+            device_endpoint: self.device_endpoint.clone(),
+            steps: vec![],
+        };
+
+        let mut discover = new_request(RequestType::Get, &[]);
+        discover.add_option_as(
+            CoapOption::Accept,
+            OptionValueU16(Lwm2mContentFormat::LinkFormat.into()),
+        );
+        let outcome = match self.step(discover, ResponseType::Content).await {
+            Ok(response) => match discovered_links(&response) {
+                Ok(links) => StepOutcome::Discovered(links),
+                Err(err) => StepOutcome::Failed(err),
+            },
+            Err(outcome) => outcome,
+        };
+        report.steps.push((BootstrapStep::Discover, outcome));
+
+        let mut steps = vec![(
+            BootstrapStep::Delete,
+            new_request(RequestType::Delete, &[]),
+            ResponseType::Deleted,
+        )];
+        for (link, format, payload) in &self.writes {
+            let path: Vec<String> = link.ids().iter().map(|id| id.to_string()).collect();
+            let mut request = new_request(RequestType::Put, &path);
+            request.add_option_as(CoapOption::ContentFormat, OptionValueU16((*format).into()));
+            request.payload = payload.clone();
+            steps.push((
+                BootstrapStep::Write(link.clone()),
+                request,
+                ResponseType::Changed,
+            ));
+        }
+        steps.push((
+            BootstrapStep::Finish,
+            new_request(RequestType::Post, &["bs".to_owned()]),
+            ResponseType::Changed,
+        ));
+
+        for (step, request, expected) in steps {
+            let outcome = match self.step(request, expected).await {
+                Ok(_) => StepOutcome::Done,
+                Err(outcome) => outcome,
+            };
+            let done = matches!(outcome, StepOutcome::Done);
+            report.steps.push((step, outcome));
+            if !done {
+                break;
+            }
+        }
+        report
+    }
+
+    // Sends the request of a step, errors are the outcome of the failed step
+    async fn step(&self, request: Packet, expected: ResponseType) -> Result<Packet, StepOutcome> {
+        let response = match time::timeout(
+            self.step_timeout,
+            self.client.send(request, self.peer.clone()),
+        )
+        .await
+        {
+            Ok(Ok(response)) => response,
+            Ok(Err(ClientError::Timeout)) | Err(_) => return Err(StepOutcome::TimedOut),
+            Ok(Err(err)) => return Err(StepOutcome::Failed(err.into())),
+        };
+        OperationError::check_response(&response, expected).map_err(StepOutcome::Failed)?;
+        Ok(response)
+    }
+}
+
+// Creates a request for a path, an empty path is the root of the device (/)
+fn new_request(method: RequestType, path: &[String]) -> Packet {
+    let mut request = Packet::new();
+    request.header.code = MessageClass::Request(method);
+    for segment in path {
+        request.add_option(CoapOption::UriPath, segment.clone().into_bytes());
+    }
+    request
+}
+
+fn discovered_links(response: &Packet) -> Result<Vec<CoreLink>, OperationError> {
+    let payload = str::from_utf8(&response.payload)
+        .map_err(|err| OperationError::InvalidResponse(err.to_string()))?;
+    let objects =
+        parse_link_format(payload).map_err(|err| OperationError::InvalidResponse(err.message))?;
+    // Skips the </> link with the LwM2M version
+    Ok(objects.iter().filter_map(|object| object.link()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lwm2m_operations::test_device::{
+        device_peer, new_device, oscore_object, response, security_object, security_object_v11,
+        server_object, uri_path, TestDevice, ENDPOINT,
+    };
+    use crate::lwm2m_requests::registration_request::Lwm2mVersion;
+    use config::{BootstrapConfig, OscoreInstance, SecurityInstance, SecurityMode, ServerInstance};
+    use object_model::object_link::ObjectLink;
+    use std::collections::BTreeMap;
+
+    fn config() -> BootstrapConfig {
+        BootstrapConfig {
+            lwm2m_version: Lwm2mVersion::V10,
+            security: vec![SecurityInstance {
+                instance_id: 0,
+                server_uri: "coap://lwm2m.example.com:5683".to_owned(),
+                bootstrap_server: false,
+                security_mode: SecurityMode::NoSec,
+                public_key_or_identity: vec![],
+                server_public_key: vec![],
+                secret_key: vec![],
+                short_server_id: Some(101),
+                oscore_instance: None,
+                resources: BTreeMap::new(),
+            }],
+            servers: vec![ServerInstance {
+                instance_id: 0,
+                short_server_id: 101,
+                lifetime: 3600,
+                default_min_period: None,
+                default_max_period: None,
+                notification_storing: false,
+                binding: "U".to_owned(),
+                resources: BTreeMap::new(),
+            }],
+            oscore: vec![],
+        }
+    }
+
+    fn setup() -> (BootstrapServer, TestDevice) {
+        let mut configs = BootstrapConfigStore::default();
+        configs.add(ENDPOINT, config());
+        let mut models = ObjectModelStore::default();
+        models.add_model(security_object());
+        models.add_model(server_object());
+        let (client, device) = new_device();
+        (
+            BootstrapServer::new(configs, Arc::new(models), client)
+                .with_step_timeout(Duration::from_secs(5)),
+            device,
+        )
+    }
+
+    fn bootstrap_request(preferred_format: Option<u16>) -> Lwm2mBootstrapRequest {
+        Lwm2mBootstrapRequest {
+            device_endpoint: ENDPOINT.to_owned(),
+            preferred_format,
+        }
+    }
+
+    fn session(server: &BootstrapServer) -> BootstrapSession {
+        server
+            .accept(&bootstrap_request(None), device_peer())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap() {
+        let (server, mut device) = setup();
+        let session = session(&server);
+
+        let (report, requests) = tokio::join!(session.run(), async {
+            let mut requests = vec![];
+            requests.push(
+                device
+                    .respond(response(
+                        ResponseType::Content,
+                        Some(Lwm2mContentFormat::LinkFormat),
+                        b"</>;lwm2m=1.1,</0/0>,</1>,</3/0>",
+                    ))
+                    .await,
+            );
+            requests.push(
+                device
+                    .respond(response(ResponseType::Deleted, None, b""))
+                    .await,
+            );
+            for _ in 0..3 {
+                requests.push(
+                    device
+                        .respond(response(ResponseType::Changed, None, b""))
+                        .await,
+                );
+            }
+            requests
+        });
+
+        assert!(report.is_complete(), "{:?}", report);
+        assert_eq!(report.device_endpoint, ENDPOINT);
+        let steps: Vec<BootstrapStep> = report.steps.iter().map(|(step, _)| step.clone()).collect();
+        assert_eq!(
+            steps,
+            vec![
+                BootstrapStep::Discover,
+                BootstrapStep::Delete,
+                BootstrapStep::Write(CoreLink::try_from("</0/0>").unwrap()),
+                BootstrapStep::Write(CoreLink::try_from("</1/0>").unwrap()),
+                BootstrapStep::Finish,
+            ]
+        );
+        match &report.steps[0].1 {
+            StepOutcome::Discovered(links) => assert_eq!(
+                links,
+                &["</0/0>", "</1>", "</3/0>"]
+                    .map(|link| CoreLink::try_from(link).unwrap())
+                    .to_vec()
+            ),
+            outcome => panic!("Discovered links expected, got {:?}", outcome),
+        }
+
+        let methods: Vec<MessageClass> =
+            requests.iter().map(|request| request.header.code).collect();
+        assert_eq!(
+            methods,
+            [
+                RequestType::Get,
+                RequestType::Delete,
+                RequestType::Put,
+                RequestType::Put,
+                RequestType::Post
+            ]
+            .map(MessageClass::Request)
+            .to_vec()
+        );
+        let paths: Vec<Vec<String>> = requests.iter().map(uri_path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                vec![],
+                vec![],
+                vec!["0".to_owned(), "0".to_owned()],
+                vec!["1".to_owned(), "0".to_owned()],
+                vec!["bs".to_owned()],
+            ]
+        );
+
+        // The Security instance is written in TLV, the only format every device supports
+        let write = &requests[2];
+        assert_eq!(
+            write
+                .get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat)
+                .unwrap()
+                .unwrap()
+                .0,
+            u16::from(Lwm2mContentFormat::Tlv)
+        );
+        let security_link = CoreLink::try_from("</0/0>").unwrap();
+        let values = content_format::decode(
+            Lwm2mContentFormat::Tlv,
+            &write.payload,
+            &security_link,
+            &security_object(),
+        )
+        .unwrap();
+        assert_eq!(values, config().security[0].values());
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_preferred_format() {
+        let (server, mut device) = setup();
+        let session = server
+            .accept(&bootstrap_request(Some(110)), device_peer())
+            .unwrap();
+
+        let (report, requests) = tokio::join!(session.run(), async {
+            let mut requests = vec![];
+            // A LwM2M 1.0 device does not know Bootstrap-Discover
+            requests.push(
+                device
+                    .respond(response(ResponseType::BadRequest, None, b""))
+                    .await,
+            );
+            requests.push(
+                device
+                    .respond(response(ResponseType::Deleted, None, b""))
+                    .await,
+            );
+            for _ in 0..3 {
+                requests.push(
+                    device
+                        .respond(response(ResponseType::Changed, None, b""))
+                        .await,
+                );
+            }
+            requests
+        });
+
+        assert!(report.is_complete(), "{:?}", report);
+        assert!(matches!(
+            report.steps[0],
+            (
+                BootstrapStep::Discover,
+                StepOutcome::Failed(OperationError::BadRequest)
+            )
+        ));
+        assert_eq!(
+            requests[3]
+                .get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat)
+                .unwrap()
+                .unwrap()
+                .0,
+            u16::from(Lwm2mContentFormat::SenmlJson)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_write_failed() {
+        let (server, mut device) = setup();
+        let session = session(&server);
+
+        let (report, _) = tokio::join!(session.run(), async {
+            device
+                .respond(response(ResponseType::Content, None, b"</0/0>"))
+                .await;
+            device
+                .respond(response(ResponseType::Deleted, None, b""))
+                .await;
+            device
+                .respond(response(ResponseType::BadRequest, None, b""))
+                .await;
+        });
+
+        assert!(!report.is_complete());
+        assert_eq!(report.steps.len(), 3);
+        assert!(matches!(
+            &report.steps[2],
+            (
+                BootstrapStep::Write(_),
+                StepOutcome::Failed(OperationError::BadRequest)
+            )
+        ));
+        // Bootstrap-Finish is not sent to a device without its configuration
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bootstrap_timeout() {
+        let (server, mut device) = setup();
+        let session = session(&server);
+
+        // The device answers the Discover but never the Delete
+        let (report, _) = tokio::join!(
+            session.run(),
+            device.respond(response(ResponseType::Content, None, b"</0/0>"))
+        );
+
+        assert!(!report.is_complete());
+        assert_eq!(report.steps.len(), 2);
+        assert!(matches!(
+            report.steps[1],
+            (BootstrapStep::Delete, StepOutcome::TimedOut)
+        ));
+    }
+
+    #[test]
+    fn test_accept_unknown_endpoint() {
+        let (server, _device) = setup();
+        let request = Lwm2mBootstrapRequest {
+            device_endpoint: "device456".to_owned(),
+            preferred_format: None,
+        };
+        let result = server.accept(&request, device_peer());
+        assert_eq!(result.err().unwrap().code, Some(ResponseType::BadRequest));
+    }
+
+    #[test]
+    fn test_accept_without_model() {
+        let (mut server, _device) = setup();
+        let mut models = ObjectModelStore::default();
+        models.add_model(security_object());
+        server.models = Arc::new(models);
+        let result = server.accept(&bootstrap_request(None), device_peer());
+        assert_eq!(
+            result.err().unwrap().code,
+            Some(ResponseType::InternalServerError)
+        );
+    }
+
+    #[test]
+    fn test_accept_oscore() {
+        let (mut server, _device) = setup();
+        let mut config = config();
+        config.lwm2m_version = Lwm2mVersion::V11;
+        config.security[0].oscore_instance = Some(0);
+        config.oscore.push(OscoreInstance {
+            instance_id: 0,
+            master_secret: (0..16).collect(),
+            sender_id: vec![0x01],
+            recipient_id: vec![],
+            master_salt: vec![],
+            id_context: None,
+            resources: BTreeMap::new(),
+        });
+        let mut configs = BootstrapConfigStore::default();
+        configs.add(ENDPOINT, config.clone());
+        server.configs = configs;
+        // Without the model of the OSCORE object the instance cannot be written
+        assert!(server
+            .accept(&bootstrap_request(None), device_peer())
+            .is_err());
+
+        let mut models = ObjectModelStore::default();
+        models.add_model(security_object());
+        models.add_model(server_object());
+        models.add_model(oscore_object());
+        server.models = Arc::new(models);
+        // Security 1.0 has no resource to link the OSCORE instance with
+        assert!(server
+            .accept(&bootstrap_request(None), device_peer())
+            .is_err());
+
+        let mut models = ObjectModelStore::default();
+        models.add_model(security_object());
+        models.add_model(security_object_v11());
+        models.add_model(server_object());
+        models.add_model(oscore_object());
+        server.models = Arc::new(models);
+        let session = session(&server);
+        let links: Vec<String> = session
+            .writes
+            .iter()
+            .map(|(link, _, _)| link.to_string())
+            .collect();
+        assert_eq!(links, vec!["</0/0>", "</1/0>", "</21/0>"]);
+        let (security_link, format, payload) = &session.writes[0];
+        let values =
+            content_format::decode(*format, payload, security_link, &security_object_v11())
+                .unwrap();
+        assert_eq!(
+            values[&CoreLink::try_from("</0/0/17>").unwrap()],
+            ResourceValue::ObjectLink(ObjectLink::try_from("21:0".to_owned()).unwrap())
+        );
+
+        // Written as the objects of LwM2M 1.0 nothing links to the OSCORE instance
+        config.lwm2m_version = Lwm2mVersion::V10;
+        let mut configs = BootstrapConfigStore::default();
+        configs.add(ENDPOINT, config);
+        server.configs = configs;
+        assert!(server
+            .accept(&bootstrap_request(None), device_peer())
+            .is_err());
+    }
+}
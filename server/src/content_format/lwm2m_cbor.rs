@@ -0,0 +1,341 @@
+use ciborium::value::{Integer, Value as CborValue};
+use object_model::{
+    core_link::CoreLink, object_link::ObjectLink, value::ResourceValue, ResourceType,
+};
+use std::collections::BTreeMap;
+
+use super::CodecError;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#7-4-6-0-746-LwM2M-CBOR
+// Nested maps keyed by the ids of the path from the root, a key can also be an array of
+// several ids to skip levels, e.g. {[3, 0]: {0: "ACME", 7: {0: 3800}}}.
+// Epoch based time values are tagged with this tag (RFC 8949 section 3.4.2).
+const EPOCH_TIME_TAG: u64 = 1;
+
+/// Decodes a LwM2M-CBOR payload into the values it contains, keyed by the link of each value.
+/// The values are converted once the resource types are known, see `to_resource_value`.
+pub fn decode(payload: &[u8]) -> Result<Vec<(CoreLink, CborValue)>, CodecError> {
+    let value: CborValue = ciborium::de::from_reader(payload)
+        .map_err(|err| CodecError::new(&format!("Invalid LwM2M-CBOR: {}", err)))?;
+    let mut values = vec![];
+    collect(value, &[], &mut values)?;
+    Ok(values)
+}
+
+fn collect(
+    map: CborValue,
+    path: &[u16],
+    values: &mut Vec<(CoreLink, CborValue)>,
+) -> Result<(), CodecError> {
+    let entries = match map {
+        CborValue::Map(entries) => entries,
+        _ => {
+            return Err(CodecError::new(&format!(
+                "LwM2M-CBOR value below {:?} is not a map",
+                path
+            )))
+        }
+    };
+    for (key, value) in entries {
+        let mut child = path.to_vec();
+        match key {
+            CborValue::Array(ids) => {
+                for id in ids {
+                    child.push(cbor_id(&id)?);
+                }
+            }
+            id => child.push(cbor_id(&id)?),
+        }
+        if child.len() > 4 {
+            return Err(CodecError::new(&format!(
+                "LwM2M-CBOR path {:?} is too deep",
+                child
+            )));
+        }
+
+        match value {
+            CborValue::Map(_) => collect(value, &child, values)?,
+            // Resources without a value are not reported
+            CborValue::Null => {}
+            value => {
+                if child.len() < 3 {
+                    return Err(CodecError::new(&format!(
+                        "LwM2M-CBOR path {:?} is not a resource",
+                        child
+                    )));
+                }
+                let link = CoreLink::new(
+                    child[0],
+                    child.get(1).copied(),
+                    child.get(2).copied(),
+                    child.get(3).copied(),
+                );
+                values.push((link, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn cbor_id(id: &CborValue) -> Result<u16, CodecError> {
+    match id {
+        CborValue::Integer(id) => u16::try_from(*id).ok(),
+        _ => None,
+    }
+    .ok_or_else(|| CodecError::new(&format!("LwM2M-CBOR key {:?} is not an id", id)))
+}
+
+/// Encodes values into a LwM2M-CBOR payload, nested from the root.
+pub fn encode<'a>(
+    values: impl IntoIterator<Item = (&'a CoreLink, &'a ResourceValue)>,
+) -> Result<Vec<u8>, CodecError> {
+    let mut root = Node::default();
+    for (link, value) in values {
+        let ids = link.ids();
+        if ids.len() < 3 {
+            return Err(CodecError::new(&format!("{} is not a resource", link)));
+        }
+        // A value can not be written next to values below it, e.g. </3/0/7> and </3/0/7/0>
+        let nested = || CodecError::new(&format!("{} is nested with another value", link));
+        let mut node = &mut root;
+        for id in ids {
+            if node.value.is_some() {
+                return Err(nested());
+            }
+            node = node.children.entry(id).or_default();
+        }
+        if !node.children.is_empty() {
+            return Err(nested());
+        }
+        node.value = Some(from_resource_value(value));
+    }
+
+    let mut payload = vec![];
+    ciborium::ser::into_writer(&root.into_cbor(), &mut payload)
+        .map_err(|err| CodecError::new(&format!("Could not encode LwM2M-CBOR: {}", err)))?;
+    Ok(payload)
+}
+
+// Sorted so the ids appear in order in the payload
+#[derive(Default)]
+struct Node {
+    value: Option<CborValue>,
+    children: BTreeMap<u16, Node>,
+}
+
+impl Node {
+    fn into_cbor(self) -> CborValue {
+        match self.value {
+            Some(value) => value,
+            None => CborValue::Map(
+                self.children
+                    .into_iter()
+                    .map(|(id, child)| (CborValue::Integer(id.into()), child.into_cbor()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Converts a LwM2M-CBOR value into a value of the given resource type.
+pub fn to_resource_value(
+    value: &CborValue,
+    resourcetype: ResourceType,
+) -> Result<ResourceValue, CodecError> {
+    let converted = match (resourcetype, value) {
+        (ResourceType::String, CborValue::Text(value)) => {
+            Some(ResourceValue::String(value.clone()))
+        }
+        (ResourceType::Integer, CborValue::Integer(value)) => {
+            i64::try_from(*value).ok().map(ResourceValue::Integer)
+        }
+        (ResourceType::UnsignedInteger, CborValue::Integer(value)) => u64::try_from(*value)
+            .ok()
+            .map(ResourceValue::UnsignedInteger),
+        (ResourceType::Float, CborValue::Float(value)) => Some(ResourceValue::Float(*value)),
+        (ResourceType::Float, CborValue::Integer(value)) => {
+            Some(ResourceValue::Float(i128::from(*value) as f64))
+        }
+        (ResourceType::Boolean, CborValue::Bool(value)) => Some(ResourceValue::Boolean(*value)),
+        (ResourceType::Opaque, CborValue::Bytes(value)) => {
+            Some(ResourceValue::Opaque(value.clone()))
+        }
+        // Time is sent as a plain or a tagged epoch time
+        (ResourceType::Time, CborValue::Tag(EPOCH_TIME_TAG, value)) => {
+            return to_resource_value(value, resourcetype)
+        }
+        (ResourceType::Time, CborValue::Integer(value)) => {
+            u64::try_from(*value).ok().map(ResourceValue::Time)
+        }
+        (ResourceType::ObjectLink, CborValue::Text(value)) => ObjectLink::try_from(value.clone())
+            .ok()
+            .map(ResourceValue::ObjectLink),
+        (ResourceType::CoreLink, CborValue::Text(value)) => CoreLink::try_from(value.as_str())
+            .ok()
+            .map(ResourceValue::CoreLink),
+        _ => None,
+    };
+    converted.ok_or(CodecError::new(&format!(
+        "LwM2M-CBOR value {:?} is not a valid {}",
+        value, resourcetype
+    )))
+}
+
+fn from_resource_value(value: &ResourceValue) -> CborValue {
+    match value {
+        ResourceValue::String(value) => CborValue::Text(value.clone()),
+        ResourceValue::Integer(value) => CborValue::Integer((*value).into()),
+        ResourceValue::UnsignedInteger(value) => CborValue::Integer((*value).into()),
+        ResourceValue::Opaque(value) => CborValue::Bytes(value.clone()),
+        ResourceValue::Float(value) => CborValue::Float(*value),
+        ResourceValue::Boolean(value) => CborValue::Bool(*value),
+        ResourceValue::ObjectLink(value) => {
+            CborValue::Text(format!("{}:{}", value.object_id, value.object_instance))
+        }
+        ResourceValue::Time(value) => CborValue::Tag(
+            EPOCH_TIME_TAG,
+            Box::new(CborValue::Integer(Integer::from(*value))),
+        ),
+        ResourceValue::CoreLink(value) => CborValue::Text(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(link: &str) -> CoreLink {
+        CoreLink::try_from(link).unwrap()
+    }
+
+    fn cbor(value: CborValue) -> Vec<u8> {
+        let mut payload = vec![];
+        ciborium::ser::into_writer(&value, &mut payload).unwrap();
+        payload
+    }
+
+    fn id(id: u16) -> CborValue {
+        CborValue::Integer(id.into())
+    }
+
+    #[test]
+    fn test_encode() {
+        let values = [
+            (link("</3/0/0>"), ResourceValue::String("ACME".to_owned())),
+            (link("</3/0/7/1>"), ResourceValue::Integer(5000)),
+            (link("</3/0/7/0>"), ResourceValue::Integer(3800)),
+            (link("</3/0/13>"), ResourceValue::Time(1700000000)),
+        ];
+        let expected = CborValue::Map(vec![(
+            id(3),
+            CborValue::Map(vec![(
+                id(0),
+                CborValue::Map(vec![
+                    (id(0), CborValue::Text("ACME".to_owned())),
+                    (
+                        id(7),
+                        CborValue::Map(vec![
+                            (id(0), CborValue::Integer(3800.into())),
+                            (id(1), CborValue::Integer(5000.into())),
+                        ]),
+                    ),
+                    (
+                        id(13),
+                        CborValue::Tag(
+                            EPOCH_TIME_TAG,
+                            Box::new(CborValue::Integer(1700000000.into())),
+                        ),
+                    ),
+                ]),
+            )]),
+        )]);
+        let payload = encode(values.iter().map(|(link, value)| (link, value))).unwrap();
+        assert_eq!(payload, cbor(expected));
+
+        // A resource and one of its instances, in either order
+        let resource = (link("</3/0/7>"), ResourceValue::Integer(3800));
+        let instance = (link("</3/0/7/0>"), ResourceValue::Integer(3800));
+        for nested in [[&resource, &instance], [&instance, &resource]] {
+            assert!(encode(nested.map(|(link, value)| (link, value))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_compacted_paths() {
+        let payload = cbor(CborValue::Map(vec![
+            (
+                CborValue::Array(vec![id(3), id(0)]),
+                CborValue::Map(vec![
+                    (id(9), CborValue::Integer(87.into())),
+                    (
+                        CborValue::Array(vec![id(7), id(1)]),
+                        CborValue::Integer(5000.into()),
+                    ),
+                    (id(0), CborValue::Null),
+                ]),
+            ),
+            (
+                CborValue::Array(vec![id(3303), id(0), id(5700)]),
+                CborValue::Float(21.5),
+            ),
+        ]));
+        assert_eq!(
+            decode(&payload).unwrap(),
+            vec![
+                (link("</3/0/9>"), CborValue::Integer(87.into())),
+                (link("</3/0/7/1>"), CborValue::Integer(5000.into())),
+                (link("</3303/0/5700>"), CborValue::Float(21.5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let invalid = [
+            // Not a map
+            CborValue::Array(vec![id(3)]),
+            // Value above resource level
+            CborValue::Map(vec![(id(3), CborValue::Integer(1.into()))]),
+            // Key that is not an id
+            CborValue::Map(vec![(
+                CborValue::Text("3".to_owned()),
+                CborValue::Map(vec![]),
+            )]),
+            // Too deep
+            CborValue::Map(vec![(
+                CborValue::Array(vec![id(3), id(0), id(7), id(0), id(1)]),
+                CborValue::Integer(1.into()),
+            )]),
+        ];
+        for value in invalid {
+            assert!(decode(&cbor(value.clone())).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_resource_values() {
+        let values = [
+            ResourceValue::String("ACME".to_owned()),
+            ResourceValue::Integer(-5),
+            ResourceValue::UnsignedInteger(u64::MAX),
+            ResourceValue::Float(21.5),
+            ResourceValue::Boolean(true),
+            ResourceValue::Opaque(vec![0x00, 0xFF]),
+            ResourceValue::Time(1700000000),
+            ResourceValue::ObjectLink(ObjectLink::try_from("3303:0".to_owned()).unwrap()),
+            ResourceValue::CoreLink(link("</3/0>")),
+        ];
+        for value in values {
+            let converted = from_resource_value(&value);
+            assert_eq!(
+                to_resource_value(&converted, value.resource_type()).unwrap(),
+                value
+            );
+        }
+        assert_eq!(
+            to_resource_value(&CborValue::Integer(1700000000.into()), ResourceType::Time).unwrap(),
+            ResourceValue::Time(1700000000)
+        );
+        assert!(to_resource_value(&CborValue::Bool(true), ResourceType::Integer).is_err());
+    }
+}
@@ -0,0 +1,434 @@
+use object_model::{
+    core_link::CoreLink, value::ResourceValue, ObjectModel, ResourceModel, ResourceType,
+};
+use std::{collections::HashMap, error::Error, fmt};
+
+use crate::lwm2m_requests::registration_request::Lwm2mVersion;
+use senml::{ResolvedRecord, SenmlRecord};
+
+mod lwm2m_cbor;
+mod opaque;
+pub mod senml;
+mod text;
+mod tlv;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#7-0-7-Data-Formats-for-Transferring-Resource-Information
+// coap_lite::ContentFormat does not know the LwM2M specific formats, so they are listed here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lwm2mContentFormat {
+    TextPlain,
+    LinkFormat,
+    OctetStream,
+    Cbor,
+    SenmlJson,
+    SenmlCbor,
+    Tlv,
+    Lwm2mJson,
+    Lwm2mCbor,
+}
+
+impl TryFrom<u16> for Lwm2mContentFormat {
+    type Error = CodecError;
+
+    fn try_from(number: u16) -> Result<Self, Self::Error> {
+        match number {
+            0 => Ok(Lwm2mContentFormat::TextPlain),
+            40 => Ok(Lwm2mContentFormat::LinkFormat),
+            42 => Ok(Lwm2mContentFormat::OctetStream),
+            60 => Ok(Lwm2mContentFormat::Cbor),
+            110 => Ok(Lwm2mContentFormat::SenmlJson),
+            112 => Ok(Lwm2mContentFormat::SenmlCbor),
+            11542 => Ok(Lwm2mContentFormat::Tlv),
+            11543 => Ok(Lwm2mContentFormat::Lwm2mJson),
+            11544 => Ok(Lwm2mContentFormat::Lwm2mCbor),
+            _ => Err(CodecError::new(&format!(
+                "Content format {} is not a LwM2M content format",
+                number
+            ))),
+        }
+    }
+}
+
+impl From<Lwm2mContentFormat> for u16 {
+    fn from(format: Lwm2mContentFormat) -> u16 {
+        match format {
+            Lwm2mContentFormat::TextPlain => 0,
+            Lwm2mContentFormat::LinkFormat => 40,
+            Lwm2mContentFormat::OctetStream => 42,
+            Lwm2mContentFormat::Cbor => 60,
+            Lwm2mContentFormat::SenmlJson => 110,
+            Lwm2mContentFormat::SenmlCbor => 112,
+            Lwm2mContentFormat::Tlv => 11542,
+            Lwm2mContentFormat::Lwm2mJson => 11543,
+            Lwm2mContentFormat::Lwm2mCbor => 11544,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CodecError {
+    message: String,
+}
+
+impl CodecError {
+    pub fn new(message: &str) -> Self {
+        CodecError {
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for CodecError {}
+
+/// Returns the content format to exchange the value(s) of `link` with a device in.
+/// Single resources use plain text (or octet stream for opaque resources),
+/// everything else needs a format that can hold multiple resources.
+///
+/// # Arguments
+///
+/// * `version` - The LwM2M version the device registered with
+/// * `supported` - The formats the device listed in its ct attribute, in its order of preference
+/// * `link` - The link that is read or written
+/// * `model` - The model of the object the link points to
+pub fn preferred_format(
+    version: Lwm2mVersion,
+    supported: &[Lwm2mContentFormat],
+    link: &CoreLink,
+    model: &ObjectModel,
+) -> Lwm2mContentFormat {
+    let single_resource = resource_model(link, model)
+        .map(|resource| !resource.multiple() || link.resource_instance.is_some())
+        .unwrap_or(false);
+
+    match (single_resource, version) {
+        (true, _) => match resource_type(link, model) {
+            Ok(ResourceType::Opaque) => Lwm2mContentFormat::OctetStream,
+            _ => Lwm2mContentFormat::TextPlain,
+        },
+        // LwM2M 1.0 has no ct attribute and only knows TLV (and JSON) for multiple resources
+        (false, Lwm2mVersion::V10) => Lwm2mContentFormat::Tlv,
+        // The compact format of LwM2M 1.2, unless the device listed formats without it
+        (false, Lwm2mVersion::V12)
+            if supported.is_empty() || supported.contains(&Lwm2mContentFormat::Lwm2mCbor) =>
+        {
+            Lwm2mContentFormat::Lwm2mCbor
+        }
+        (false, _) => supported
+            .iter()
+            .copied()
+            .find(|format| {
+                matches!(
+                    format,
+                    Lwm2mContentFormat::SenmlJson
+                        | Lwm2mContentFormat::SenmlCbor
+                        | Lwm2mContentFormat::Tlv
+                )
+            })
+            .unwrap_or(Lwm2mContentFormat::SenmlJson),
+    }
+}
+
+/// Returns the content format of composite operations, which can only use SenML.
+///
+/// # Arguments
+///
+/// * `supported` - The formats the device listed in its ct attribute, in its order of preference
+pub fn composite_format(supported: &[Lwm2mContentFormat]) -> Lwm2mContentFormat {
+    supported
+        .iter()
+        .copied()
+        .find(|format| {
+            matches!(
+                format,
+                Lwm2mContentFormat::SenmlJson | Lwm2mContentFormat::SenmlCbor
+            )
+        })
+        .unwrap_or(Lwm2mContentFormat::SenmlJson)
+}
+
+/// Decodes a payload into the values it contains, keyed by the link of each resource (instance).
+///
+/// # Arguments
+///
+/// * `format` - The content format of the payload
+/// * `payload` - The raw payload
+/// * `link` - The link that was requested, values in the payload are relative to it
+/// * `model` - The model of the object the link points to
+pub fn decode(
+    format: Lwm2mContentFormat,
+    payload: &[u8],
+    link: &CoreLink,
+    model: &ObjectModel,
+) -> Result<HashMap<CoreLink, ResourceValue>, CodecError> {
+    match format {
+        Lwm2mContentFormat::TextPlain => {
+            let value = text::decode(payload, single_resource_type(format, link, model)?)?;
+            Ok(HashMap::from([(link.clone(), value)]))
+        }
+        Lwm2mContentFormat::OctetStream => {
+            let value = opaque::decode(payload, single_resource_type(format, link, model)?)?;
+            Ok(HashMap::from([(link.clone(), value)]))
+        }
+        Lwm2mContentFormat::Tlv => tlv::decode(payload, link, model),
+        Lwm2mContentFormat::Lwm2mCbor => lwm2m_cbor::decode(payload)?
+            .into_iter()
+            .map(|(value_link, value)| {
+                check_below(&value_link, link)?;
+                let value =
+                    lwm2m_cbor::to_resource_value(&value, resource_type(&value_link, model)?)?;
+                Ok((value_link, value))
+            })
+            .collect(),
+        Lwm2mContentFormat::SenmlJson | Lwm2mContentFormat::SenmlCbor => {
+            let records = decode_records(format, payload)?;
+            for record in &records {
+                check_below(&record.link, link)?;
+            }
+            composite_values(records, &HashMap::from([(link.object_id, model.clone())]))
+        }
+        _ => Err(CodecError::new(&format!(
+            "Decoding {:?} is not supported",
+            format
+        ))),
+    }
+}
+
+/// Encodes values into a payload of the given content format.
+///
+/// # Arguments
+///
+/// * `format` - The content format of the payload
+/// * `values` - The values to encode, keyed by the link of each resource (instance)
+/// * `link` - The link the payload is sent to, all values should be below it
+/// * `model` - The model of the object the link points to
+pub fn encode(
+    format: Lwm2mContentFormat,
+    values: &HashMap<CoreLink, ResourceValue>,
+    link: &CoreLink,
+    model: &ObjectModel,
+) -> Result<Vec<u8>, CodecError> {
+    match format {
+        Lwm2mContentFormat::TextPlain => text::encode(single_value(format, values, link, model)?),
+        Lwm2mContentFormat::OctetStream => {
+            opaque::encode(single_value(format, values, link, model)?)
+        }
+        Lwm2mContentFormat::Tlv => tlv::encode(values, link, model),
+        Lwm2mContentFormat::SenmlJson | Lwm2mContentFormat::SenmlCbor => {
+            for value_link in values.keys() {
+                check_below(value_link, link)?;
+                resource_type(value_link, model)?;
+            }
+            encode_composite(format, values)
+        }
+        Lwm2mContentFormat::Lwm2mCbor => {
+            for value_link in values.keys() {
+                check_below(value_link, link)?;
+                resource_type(value_link, model)?;
+            }
+            lwm2m_cbor::encode(values)
+        }
+        _ => Err(CodecError::new(&format!(
+            "Encoding {:?} is not supported",
+            format
+        ))),
+    }
+}
+
+/// Encodes a list of links, the payload of Read-Composite and Observe-Composite requests.
+pub fn encode_paths(format: Lwm2mContentFormat, links: &[CoreLink]) -> Result<Vec<u8>, CodecError> {
+    let records: Vec<SenmlRecord> = links
+        .iter()
+        .map(|link| SenmlRecord {
+            name: Some(link.path()),
+            ..Default::default()
+        })
+        .collect();
+    encode_senml(format, &records)
+}
+
+/// Encodes the values of several objects into one payload, e.g. for a Write-Composite request.
+/// Every record carries the full path of its resource (instance).
+pub fn encode_composite(
+    format: Lwm2mContentFormat,
+    values: &HashMap<CoreLink, ResourceValue>,
+) -> Result<Vec<u8>, CodecError> {
+    let mut values: Vec<(&CoreLink, &ResourceValue)> = values.iter().collect();
+    values.sort_by_key(|(link, _)| link.ids());
+    let records = values
+        .into_iter()
+        .map(|(link, value)| SenmlRecord {
+            name: Some(link.path()),
+            value: Some(senml::from_resource_value(value)),
+            ..Default::default()
+        })
+        .collect::<Vec<SenmlRecord>>();
+    encode_senml(format, &records)
+}
+
+/// Decodes a payload that holds the values of several objects, e.g. a Read-Composite response.
+///
+/// # Arguments
+///
+/// * `format` - The content format of the payload
+/// * `payload` - The raw payload
+/// * `models` - The models of the objects the values can belong to, keyed by object id
+pub fn decode_composite(
+    format: Lwm2mContentFormat,
+    payload: &[u8],
+    models: &HashMap<u16, ObjectModel>,
+) -> Result<HashMap<CoreLink, ResourceValue>, CodecError> {
+    if format == Lwm2mContentFormat::Lwm2mCbor {
+        return lwm2m_cbor::decode(payload)?
+            .into_iter()
+            .map(|(link, value)| {
+                let model = composite_model(&link, models)?;
+                let value = lwm2m_cbor::to_resource_value(&value, resource_type(&link, model)?)?;
+                Ok((link, value))
+            })
+            .collect();
+    }
+    composite_values(decode_records(format, payload)?, models)
+}
+
+/// Decodes a SenML payload into its records, with the names and times resolved.
+/// The values are converted once the models of the objects are known, see `composite_values`.
+pub fn decode_records(
+    format: Lwm2mContentFormat,
+    payload: &[u8],
+) -> Result<Vec<ResolvedRecord>, CodecError> {
+    senml::resolve(decode_senml(format, payload)?)
+}
+
+/// Converts decoded records into values of the resource types of their models.
+/// When a resource has several records, e.g. measurements at different times, the last one is kept.
+///
+/// # Arguments
+///
+/// * `records` - The records as returned by `decode_records`
+/// * `models` - The models of the objects the values can belong to, keyed by object id
+pub fn composite_values(
+    records: Vec<ResolvedRecord>,
+    models: &HashMap<u16, ObjectModel>,
+) -> Result<HashMap<CoreLink, ResourceValue>, CodecError> {
+    records
+        .into_iter()
+        .map(|record| {
+            let link = record.link;
+            let model = composite_model(&link, models)?;
+            let value = record
+                .value
+                .ok_or(CodecError::new(&format!("{} has no value", link)))?;
+            let value = senml::to_resource_value(&value, resource_type(&link, model)?)?;
+            Ok((link, value))
+        })
+        .collect()
+}
+
+fn composite_model<'a>(
+    link: &CoreLink,
+    models: &'a HashMap<u16, ObjectModel>,
+) -> Result<&'a ObjectModel, CodecError> {
+    models.get(&link.object_id).ok_or(CodecError::new(&format!(
+        "{} is not part of the expected objects",
+        link
+    )))
+}
+
+/// Checks that `value_link` is `link` or lies below it, e.g. </3/0/1> below </3/0>.
+pub fn check_below(value_link: &CoreLink, link: &CoreLink) -> Result<(), CodecError> {
+    if value_link.ids().starts_with(&link.ids()) {
+        Ok(())
+    } else {
+        Err(CodecError::new(&format!(
+            "{} is not below {}",
+            value_link, link
+        )))
+    }
+}
+
+fn encode_senml(
+    format: Lwm2mContentFormat,
+    records: &[SenmlRecord],
+) -> Result<Vec<u8>, CodecError> {
+    match format {
+        Lwm2mContentFormat::SenmlJson => senml::to_json(records),
+        Lwm2mContentFormat::SenmlCbor => senml::to_cbor(records),
+        _ => Err(CodecError::new(&format!(
+            "{:?} is not a SenML format",
+            format
+        ))),
+    }
+}
+
+fn decode_senml(
+    format: Lwm2mContentFormat,
+    payload: &[u8],
+) -> Result<Vec<SenmlRecord>, CodecError> {
+    match format {
+        Lwm2mContentFormat::SenmlJson => senml::from_json(payload),
+        Lwm2mContentFormat::SenmlCbor => senml::from_cbor(payload),
+        _ => Err(CodecError::new(&format!(
+            "{:?} is not a SenML format",
+            format
+        ))),
+    }
+}
+
+// The value for a format that can only hold the value of the resource `link` points to
+fn single_value<'a>(
+    format: Lwm2mContentFormat,
+    values: &'a HashMap<CoreLink, ResourceValue>,
+    link: &CoreLink,
+    model: &ObjectModel,
+) -> Result<&'a ResourceValue, CodecError> {
+    single_resource_type(format, link, model)?;
+    match (values.len(), values.get(link)) {
+        (1, Some(value)) => Ok(value),
+        _ => Err(CodecError::new(&format!(
+            "Exactly one value for {} expected",
+            link
+        ))),
+    }
+}
+
+fn resource_model<'a>(link: &CoreLink, model: &'a ObjectModel) -> Option<&'a ResourceModel> {
+    link.resource_id
+        .and_then(|resource_id| model.resources().get(&resource_id))
+}
+
+// The type of the resource the link points to, for formats that carry exactly one value.
+// Multiple resources have to be addressed by resource instance, their other instances
+// could not be represented in the payload.
+fn single_resource_type(
+    format: Lwm2mContentFormat,
+    link: &CoreLink,
+    model: &ObjectModel,
+) -> Result<ResourceType, CodecError> {
+    let resourcetype = resource_type(link, model)?;
+    match resource_model(link, model) {
+        Some(resource) if resource.multiple() && link.resource_instance.is_none() => {
+            Err(CodecError::new(&format!(
+                "Resource {} has multiple instances, {:?} can only hold the value of one resource instance",
+                link, format
+            )))
+        }
+        _ => Ok(resourcetype),
+    }
+}
+
+// The type of the single resource the link points to
+fn resource_type(link: &CoreLink, model: &ObjectModel) -> Result<ResourceType, CodecError> {
+    let resource = resource_model(link, model).ok_or(CodecError::new(&format!(
+        "Link {} does not point to a known resource",
+        link
+    )))?;
+    resource
+        .resourcetype()
+        .ok_or(CodecError::new(&format!("Resource {} has no type", link)))
+}
@@ -0,0 +1,26 @@
+use object_model::{value::ResourceValue, ResourceType};
+
+use super::CodecError;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#7-3-1-Opaque
+/// Decodes an application/octet-stream payload, which is only valid for opaque resources.
+pub fn decode(payload: &[u8], resourcetype: ResourceType) -> Result<ResourceValue, CodecError> {
+    match resourcetype {
+        ResourceType::Opaque => Ok(ResourceValue::Opaque(payload.to_vec())),
+        _ => Err(CodecError::new(&format!(
+            "Octet stream can only be decoded as Opaque, resource is {}",
+            resourcetype
+        ))),
+    }
+}
+
+/// Encodes an opaque value as an application/octet-stream payload.
+pub fn encode(value: &ResourceValue) -> Result<Vec<u8>, CodecError> {
+    match value {
+        ResourceValue::Opaque(bytes) => Ok(bytes.clone()),
+        _ => Err(CodecError::new(&format!(
+            "Only Opaque values can be encoded as octet stream, value is {}",
+            value.resource_type()
+        ))),
+    }
+}
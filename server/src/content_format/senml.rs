@@ -0,0 +1,505 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::{Integer, Value as CborValue};
+use object_model::{
+    core_link::CoreLink, object_link::ObjectLink, value::ResourceValue, ResourceType,
+};
+use serde_json::{Map, Value as JsonValue};
+
+use super::CodecError;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#7-4-4-0-744-SenML-JSON
+// and https://datatracker.ietf.org/doc/html/rfc8428, only the fields LwM2M uses are kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SenmlRecord {
+    pub base_name: Option<String>,
+    pub base_time: Option<f64>,
+    pub name: Option<String>,
+    pub time: Option<f64>,
+    pub value: Option<SenmlValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SenmlValue {
+    // Kept apart from floats so 64 bit (unsigned) integers don't lose precision
+    Integer(i128),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Data(Vec<u8>),
+    // LwM2M extension for Objlnk values ("vlo")
+    ObjectLink(String),
+}
+
+// Labels of the SenML CBOR representation, https://datatracker.ietf.org/doc/html/rfc8428#section-6
+const CBOR_BASE_NAME: i64 = -2;
+const CBOR_BASE_TIME: i64 = -3;
+const CBOR_NAME: i64 = 0;
+const CBOR_VALUE: i64 = 2;
+const CBOR_STRING_VALUE: i64 = 3;
+const CBOR_BOOLEAN_VALUE: i64 = 4;
+const CBOR_TIME: i64 = 6;
+const CBOR_DATA_VALUE: i64 = 8;
+// Not registered for CBOR, so it uses the JSON label in both representations
+const OBJECT_LINK_VALUE: &str = "vlo";
+
+pub fn to_json(records: &[SenmlRecord]) -> Result<Vec<u8>, CodecError> {
+    let array = records
+        .iter()
+        .map(|record| {
+            let mut map = Map::new();
+            if let Some(base_name) = &record.base_name {
+                map.insert("bn".to_owned(), JsonValue::from(base_name.as_str()));
+            }
+            if let Some(base_time) = record.base_time {
+                map.insert("bt".to_owned(), JsonValue::from(base_time));
+            }
+            if let Some(name) = &record.name {
+                map.insert("n".to_owned(), JsonValue::from(name.as_str()));
+            }
+            if let Some(time) = record.time {
+                map.insert("t".to_owned(), JsonValue::from(time));
+            }
+            let (label, value) = match &record.value {
+                Some(SenmlValue::Integer(value)) => {
+                    match (i64::try_from(*value), u64::try_from(*value)) {
+                        (Ok(value), _) => ("v", JsonValue::from(value)),
+                        (_, Ok(value)) => ("v", JsonValue::from(value)),
+                        _ => {
+                            return Err(CodecError::new(&format!(
+                                "{} does not fit in 64 bits",
+                                value
+                            )))
+                        }
+                    }
+                }
+                Some(SenmlValue::Float(value)) => ("v", JsonValue::from(*value)),
+                Some(SenmlValue::String(value)) => ("vs", JsonValue::from(value.as_str())),
+                Some(SenmlValue::Boolean(value)) => ("vb", JsonValue::from(*value)),
+                Some(SenmlValue::Data(value)) => {
+                    ("vd", JsonValue::from(URL_SAFE_NO_PAD.encode(value)))
+                }
+                Some(SenmlValue::ObjectLink(value)) => {
+                    (OBJECT_LINK_VALUE, JsonValue::from(value.as_str()))
+                }
+                None => return Ok(JsonValue::Object(map)),
+            };
+            map.insert(label.to_owned(), value);
+            Ok(JsonValue::Object(map))
+        })
+        .collect::<Result<Vec<JsonValue>, CodecError>>()?;
+    serde_json::to_vec(&JsonValue::Array(array))
+        .map_err(|err| CodecError::new(&format!("Could not encode SenML JSON: {}", err)))
+}
+
+pub fn from_json(payload: &[u8]) -> Result<Vec<SenmlRecord>, CodecError> {
+    let value: JsonValue = serde_json::from_slice(payload)
+        .map_err(|err| CodecError::new(&format!("Invalid SenML JSON: {}", err)))?;
+    let records = value
+        .as_array()
+        .ok_or(CodecError::new("SenML JSON payload is not an array"))?;
+
+    records
+        .iter()
+        .map(|record| {
+            let fields = record
+                .as_object()
+                .ok_or(CodecError::new("SenML JSON record is not an object"))?;
+            let mut senml = SenmlRecord::default();
+            for (label, value) in fields {
+                let invalid =
+                    || CodecError::new(&format!("Invalid SenML JSON {}: {}", label, value));
+                match label.as_str() {
+                    "bn" => senml.base_name = Some(value.as_str().ok_or_else(invalid)?.to_owned()),
+                    "bt" => senml.base_time = Some(value.as_f64().ok_or_else(invalid)?),
+                    "n" => senml.name = Some(value.as_str().ok_or_else(invalid)?.to_owned()),
+                    "t" => senml.time = Some(value.as_f64().ok_or_else(invalid)?),
+                    "v" => {
+                        let number = match (value.as_i64(), value.as_u64(), value.as_f64()) {
+                            (Some(value), _, _) => SenmlValue::Integer(value.into()),
+                            (_, Some(value), _) => SenmlValue::Integer(value.into()),
+                            (_, _, Some(value)) => SenmlValue::Float(value),
+                            _ => return Err(invalid()),
+                        };
+                        senml.value = Some(number);
+                    }
+                    "vs" => {
+                        let value = value.as_str().ok_or_else(invalid)?;
+                        senml.value = Some(SenmlValue::String(value.to_owned()));
+                    }
+                    "vb" => {
+                        senml.value =
+                            Some(SenmlValue::Boolean(value.as_bool().ok_or_else(invalid)?))
+                    }
+                    "vd" => {
+                        let data = URL_SAFE_NO_PAD
+                            .decode(value.as_str().ok_or_else(invalid)?.trim_end_matches('='))
+                            .map_err(|_| invalid())?;
+                        senml.value = Some(SenmlValue::Data(data));
+                    }
+                    OBJECT_LINK_VALUE => {
+                        let value = value.as_str().ok_or_else(invalid)?;
+                        senml.value = Some(SenmlValue::ObjectLink(value.to_owned()));
+                    }
+                    // Other fields such as units are not used by LwM2M
+                    _ => {}
+                }
+            }
+            Ok(senml)
+        })
+        .collect()
+}
+
+pub fn to_cbor(records: &[SenmlRecord]) -> Result<Vec<u8>, CodecError> {
+    let label = |label: i64| CborValue::Integer(Integer::from(label));
+    let array = records
+        .iter()
+        .map(|record| {
+            let mut map = vec![];
+            if let Some(base_name) = &record.base_name {
+                map.push((label(CBOR_BASE_NAME), CborValue::Text(base_name.clone())));
+            }
+            if let Some(base_time) = record.base_time {
+                map.push((label(CBOR_BASE_TIME), CborValue::Float(base_time)));
+            }
+            if let Some(name) = &record.name {
+                map.push((label(CBOR_NAME), CborValue::Text(name.clone())));
+            }
+            if let Some(time) = record.time {
+                map.push((label(CBOR_TIME), CborValue::Float(time)));
+            }
+            match &record.value {
+                Some(SenmlValue::Integer(value)) => {
+                    let value = Integer::try_from(*value).map_err(|_| {
+                        CodecError::new(&format!("{} does not fit in 64 bits", value))
+                    })?;
+                    map.push((label(CBOR_VALUE), CborValue::Integer(value)));
+                }
+                Some(SenmlValue::Float(value)) => {
+                    map.push((label(CBOR_VALUE), CborValue::Float(*value)))
+                }
+                Some(SenmlValue::String(value)) => {
+                    map.push((label(CBOR_STRING_VALUE), CborValue::Text(value.clone())))
+                }
+                Some(SenmlValue::Boolean(value)) => {
+                    map.push((label(CBOR_BOOLEAN_VALUE), CborValue::Bool(*value)))
+                }
+                Some(SenmlValue::Data(value)) => {
+                    map.push((label(CBOR_DATA_VALUE), CborValue::Bytes(value.clone())))
+                }
+                Some(SenmlValue::ObjectLink(value)) => map.push((
+                    CborValue::Text(OBJECT_LINK_VALUE.to_owned()),
+                    CborValue::Text(value.clone()),
+                )),
+                None => {}
+            }
+            Ok(CborValue::Map(map))
+        })
+        .collect::<Result<Vec<CborValue>, CodecError>>()?;
+
+    let mut payload = vec![];
+    ciborium::ser::into_writer(&CborValue::Array(array), &mut payload)
+        .map_err(|err| CodecError::new(&format!("Could not encode SenML CBOR: {}", err)))?;
+    Ok(payload)
+}
+
+pub fn from_cbor(payload: &[u8]) -> Result<Vec<SenmlRecord>, CodecError> {
+    let value: CborValue = ciborium::de::from_reader(payload)
+        .map_err(|err| CodecError::new(&format!("Invalid SenML CBOR: {}", err)))?;
+    let records = match value {
+        CborValue::Array(records) => records,
+        _ => return Err(CodecError::new("SenML CBOR payload is not an array")),
+    };
+
+    records
+        .into_iter()
+        .map(|record| {
+            let fields = match record {
+                CborValue::Map(fields) => fields,
+                _ => return Err(CodecError::new("SenML CBOR record is not a map")),
+            };
+            let mut senml = SenmlRecord::default();
+            for (key, value) in fields {
+                let invalid =
+                    || CodecError::new(&format!("Invalid SenML CBOR value for label {:?}", key));
+                let label = match &key {
+                    CborValue::Integer(label) => i64::try_from(*label).map_err(|_| invalid())?,
+                    CborValue::Text(label) if label == OBJECT_LINK_VALUE => match value {
+                        CborValue::Text(value) => {
+                            senml.value = Some(SenmlValue::ObjectLink(value));
+                            continue;
+                        }
+                        _ => return Err(invalid()),
+                    },
+                    // Other fields such as units are not used by LwM2M
+                    _ => continue,
+                };
+                match (label, value) {
+                    (CBOR_BASE_NAME, CborValue::Text(value)) => senml.base_name = Some(value),
+                    (CBOR_BASE_TIME, value) => {
+                        senml.base_time = Some(cbor_float(&value).ok_or_else(invalid)?)
+                    }
+                    (CBOR_NAME, CborValue::Text(value)) => senml.name = Some(value),
+                    (CBOR_TIME, value) => {
+                        senml.time = Some(cbor_float(&value).ok_or_else(invalid)?)
+                    }
+                    (CBOR_VALUE, CborValue::Integer(value)) => {
+                        senml.value = Some(SenmlValue::Integer(value.into()))
+                    }
+                    (CBOR_VALUE, CborValue::Float(value)) => {
+                        senml.value = Some(SenmlValue::Float(value))
+                    }
+                    (CBOR_STRING_VALUE, CborValue::Text(value)) => {
+                        senml.value = Some(SenmlValue::String(value))
+                    }
+                    (CBOR_BOOLEAN_VALUE, CborValue::Bool(value)) => {
+                        senml.value = Some(SenmlValue::Boolean(value))
+                    }
+                    (CBOR_DATA_VALUE, CborValue::Bytes(value)) => {
+                        senml.value = Some(SenmlValue::Data(value))
+                    }
+                    (CBOR_BASE_NAME, _)
+                    | (CBOR_NAME, _)
+                    | (CBOR_VALUE, _)
+                    | (CBOR_STRING_VALUE, _)
+                    | (CBOR_BOOLEAN_VALUE, _)
+                    | (CBOR_DATA_VALUE, _) => return Err(invalid()),
+                    _ => {}
+                }
+            }
+            Ok(senml)
+        })
+        .collect()
+}
+
+fn cbor_float(value: &CborValue) -> Option<f64> {
+    match value {
+        CborValue::Float(value) => Some(*value),
+        CborValue::Integer(value) => Some(i128::from(*value) as f64),
+        _ => None,
+    }
+}
+
+/// A record with its base name and base time applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedRecord {
+    pub link: CoreLink,
+    // Seconds since the epoch, or relative to now when below 2^28 (RFC 8428 section 4.5.3)
+    pub time: Option<f64>,
+    pub value: Option<SenmlValue>,
+}
+
+/// Resolves the base names and base times of the records into the link and time of each record.
+pub fn resolve(records: Vec<SenmlRecord>) -> Result<Vec<ResolvedRecord>, CodecError> {
+    let mut base_name = String::new();
+    let mut base_time = None;
+    records
+        .into_iter()
+        .map(|record| {
+            // A base name or base time applies to all following records until it is replaced
+            if let Some(name) = record.base_name {
+                base_name = name;
+            }
+            if let Some(time) = record.base_time {
+                base_time = Some(time);
+            }
+            let name = format!("{}{}", base_name, record.name.unwrap_or_default());
+            let link = CoreLink::try_from(format!("<{}>", name.trim_end_matches('/')).as_str())
+                .map_err(|_| {
+                    CodecError::new(&format!("SenML name {} is not a LwM2M path", name))
+                })?;
+            let time = match (base_time, record.time) {
+                (None, None) => None,
+                (base_time, time) => Some(base_time.unwrap_or(0.0) + time.unwrap_or(0.0)),
+            };
+            Ok(ResolvedRecord {
+                link,
+                time,
+                value: record.value,
+            })
+        })
+        .collect()
+}
+
+/// Converts a SenML value into a value of the given resource type.
+pub fn to_resource_value(
+    value: &SenmlValue,
+    resourcetype: ResourceType,
+) -> Result<ResourceValue, CodecError> {
+    let converted = match (resourcetype, value) {
+        (ResourceType::String, SenmlValue::String(value)) => {
+            Some(ResourceValue::String(value.clone()))
+        }
+        (ResourceType::Integer, SenmlValue::Integer(value)) => {
+            i64::try_from(*value).ok().map(ResourceValue::Integer)
+        }
+        (ResourceType::UnsignedInteger, SenmlValue::Integer(value)) => u64::try_from(*value)
+            .ok()
+            .map(ResourceValue::UnsignedInteger),
+        (ResourceType::Float, SenmlValue::Float(value)) => Some(ResourceValue::Float(*value)),
+        (ResourceType::Float, SenmlValue::Integer(value)) => {
+            Some(ResourceValue::Float(*value as f64))
+        }
+        (ResourceType::Boolean, SenmlValue::Boolean(value)) => Some(ResourceValue::Boolean(*value)),
+        (ResourceType::Opaque, SenmlValue::Data(value)) => {
+            Some(ResourceValue::Opaque(value.clone()))
+        }
+        (ResourceType::Time, SenmlValue::Integer(value)) => {
+            u64::try_from(*value).ok().map(ResourceValue::Time)
+        }
+        (ResourceType::ObjectLink, SenmlValue::ObjectLink(value)) => {
+            ObjectLink::try_from(value.clone())
+                .ok()
+                .map(ResourceValue::ObjectLink)
+        }
+        // Core links are sent as string values
+        (ResourceType::CoreLink, SenmlValue::String(value)) => CoreLink::try_from(value.as_str())
+            .ok()
+            .map(ResourceValue::CoreLink),
+        _ => None,
+    };
+    converted.ok_or(CodecError::new(&format!(
+        "SenML value {:?} is not a valid {}",
+        value, resourcetype
+    )))
+}
+
+/// Converts the value of a resource into a SenML value.
+pub fn from_resource_value(value: &ResourceValue) -> SenmlValue {
+    match value {
+        ResourceValue::String(value) => SenmlValue::String(value.clone()),
+        ResourceValue::Integer(value) => SenmlValue::Integer((*value).into()),
+        ResourceValue::UnsignedInteger(value) => SenmlValue::Integer((*value).into()),
+        ResourceValue::Opaque(value) => SenmlValue::Data(value.clone()),
+        ResourceValue::Float(value) => SenmlValue::Float(*value),
+        ResourceValue::Boolean(value) => SenmlValue::Boolean(*value),
+        ResourceValue::ObjectLink(value) => {
+            SenmlValue::ObjectLink(format!("{}:{}", value.object_id, value.object_instance))
+        }
+        ResourceValue::Time(value) => SenmlValue::Integer((*value).into()),
+        ResourceValue::CoreLink(value) => SenmlValue::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<SenmlRecord> {
+        vec![
+            SenmlRecord {
+                base_name: Some("/3/0/".to_owned()),
+                name: Some("0".to_owned()),
+                value: Some(SenmlValue::String("ACME".to_owned())),
+                ..Default::default()
+            },
+            SenmlRecord {
+                name: Some("9".to_owned()),
+                value: Some(SenmlValue::Integer(87)),
+                ..Default::default()
+            },
+            SenmlRecord {
+                base_name: Some("/5/0/".to_owned()),
+                base_time: Some(1700000000.0),
+                name: Some("0".to_owned()),
+                time: Some(5.0),
+                value: Some(SenmlValue::Data(vec![0x00, 0xFF, 0x10])),
+            },
+            SenmlRecord {
+                name: Some("/3/0/22/0".to_owned()),
+                base_name: Some(String::new()),
+                value: Some(SenmlValue::ObjectLink("3303:0".to_owned())),
+                ..Default::default()
+            },
+            SenmlRecord {
+                base_name: Some("/3303/0/".to_owned()),
+                name: Some("5700".to_owned()),
+                value: Some(SenmlValue::Float(21.5)),
+                ..Default::default()
+            },
+            SenmlRecord {
+                name: Some("/1/0/6".to_owned()),
+                base_name: Some(String::new()),
+                value: Some(SenmlValue::Boolean(true)),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let payload = to_json(&records()).unwrap();
+        assert_eq!(from_json(&payload).unwrap(), records());
+    }
+
+    #[test]
+    fn test_cbor_round_trip() {
+        let payload = to_cbor(&records()).unwrap();
+        assert_eq!(from_cbor(&payload).unwrap(), records());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let payload =
+            br#"[{"bn":"/3/0/","n":"0","vs":"ACME"},{"n":"9","v":87},{"n":"13","v":1.5e9}]"#;
+        let records = from_json(payload).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].value, Some(SenmlValue::Integer(87)));
+        assert_eq!(records[2].value, Some(SenmlValue::Float(1.5e9)));
+        assert!(from_json(br#"{"n":"9"}"#).is_err());
+        assert!(from_json(br#"[{"n":9}]"#).is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let resolved: Vec<(String, Option<f64>)> = resolve(records())
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.link.path(), record.time))
+            .collect();
+        // The base time applies to all records after it
+        assert_eq!(
+            resolved,
+            vec![
+                ("/3/0/0".to_owned(), None),
+                ("/3/0/9".to_owned(), None),
+                ("/5/0/0".to_owned(), Some(1700000005.0)),
+                ("/3/0/22/0".to_owned(), Some(1700000000.0)),
+                ("/3303/0/5700".to_owned(), Some(1700000000.0)),
+                ("/1/0/6".to_owned(), Some(1700000000.0))
+            ]
+        );
+
+        let invalid = vec![SenmlRecord {
+            name: Some("temperature".to_owned()),
+            ..Default::default()
+        }];
+        assert!(resolve(invalid).is_err());
+    }
+
+    #[test]
+    fn test_resource_values() {
+        let values = [
+            ResourceValue::String("ACME".to_owned()),
+            ResourceValue::Integer(-5),
+            ResourceValue::UnsignedInteger(u64::MAX),
+            ResourceValue::Float(21.5),
+            ResourceValue::Boolean(false),
+            ResourceValue::Opaque(vec![0x01, 0x02]),
+            ResourceValue::Time(1700000000),
+            ResourceValue::ObjectLink(ObjectLink::try_from("3303:1".to_owned()).unwrap()),
+        ];
+        for value in values {
+            let senml = from_resource_value(&value);
+            assert_eq!(
+                to_resource_value(&senml, value.resource_type()).unwrap(),
+                value
+            );
+        }
+        assert!(
+            to_resource_value(&SenmlValue::Integer(-1), ResourceType::UnsignedInteger).is_err()
+        );
+        assert!(
+            to_resource_value(&SenmlValue::String("1".to_owned()), ResourceType::Integer).is_err()
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use object_model::{
+    core_link::CoreLink, object_link::ObjectLink, value::ResourceValue, ResourceType,
+};
+use std::str;
+
+use super::CodecError;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#7-3-Plain-Text
+/// Decodes a text/plain payload into a value of the given resource type.
+pub fn decode(payload: &[u8], resourcetype: ResourceType) -> Result<ResourceValue, CodecError> {
+    let text = str::from_utf8(payload)
+        .map_err(|_| CodecError::new("Plain text payload is not valid utf8"))?;
+
+    match resourcetype {
+        ResourceType::String => Ok(ResourceValue::String(text.to_owned())),
+        ResourceType::Integer => parse(text, "Integer").map(ResourceValue::Integer),
+        ResourceType::UnsignedInteger => {
+            parse(text, "Unsigned Integer").map(ResourceValue::UnsignedInteger)
+        }
+        ResourceType::Float => parse(text, "Float").map(ResourceValue::Float),
+        ResourceType::Boolean => match text {
+            "0" => Ok(ResourceValue::Boolean(false)),
+            "1" => Ok(ResourceValue::Boolean(true)),
+            _ => Err(CodecError::new(&format!(
+                "Boolean should be 0 or 1, is {}",
+                text
+            ))),
+        },
+        ResourceType::Time => parse(text, "Time").map(ResourceValue::Time),
+        ResourceType::ObjectLink => ObjectLink::try_from(text.to_owned())
+            .map(ResourceValue::ObjectLink)
+            .map_err(|err| CodecError::new(&err.to_string())),
+        ResourceType::CoreLink => CoreLink::try_from(text)
+            .map(ResourceValue::CoreLink)
+            .map_err(|err| CodecError::new(&err.to_string())),
+        ResourceType::Opaque => Err(CodecError::new(
+            "Opaque resources can not be decoded from plain text",
+        )),
+    }
+}
+
+/// Encodes a value as a text/plain payload.
+pub fn encode(value: &ResourceValue) -> Result<Vec<u8>, CodecError> {
+    let text = match value {
+        ResourceValue::String(v) => v.clone(),
+        ResourceValue::Integer(v) => v.to_string(),
+        ResourceValue::UnsignedInteger(v) => v.to_string(),
+        ResourceValue::Float(v) => v.to_string(),
+        ResourceValue::Boolean(v) => u8::from(*v).to_string(),
+        ResourceValue::Time(v) => v.to_string(),
+        ResourceValue::ObjectLink(v) => format!("{}:{}", v.object_id, v.object_instance),
+        ResourceValue::CoreLink(v) => v.to_string(),
+        ResourceValue::Opaque(_) => {
+            return Err(CodecError::new(
+                "Opaque resources can not be encoded as plain text",
+            ))
+        }
+    };
+    Ok(text.into_bytes())
+}
+
+fn parse<T: str::FromStr>(text: &str, type_name: &str) -> Result<T, CodecError> {
+    text.trim()
+        .parse()
+        .map_err(|_| CodecError::new(&format!("{} value expected, is {}", type_name, text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_values() {
+        assert_eq!(
+            decode(b"Open Mobile Alliance", ResourceType::String).unwrap(),
+            ResourceValue::String("Open Mobile Alliance".to_string())
+        );
+        assert_eq!(
+            decode(b"-42", ResourceType::Integer).unwrap(),
+            ResourceValue::Integer(-42)
+        );
+        assert_eq!(
+            decode(b"42", ResourceType::UnsignedInteger).unwrap(),
+            ResourceValue::UnsignedInteger(42)
+        );
+        assert_eq!(
+            decode(b"21.5", ResourceType::Float).unwrap(),
+            ResourceValue::Float(21.5)
+        );
+        assert_eq!(
+            decode(b"1", ResourceType::Boolean).unwrap(),
+            ResourceValue::Boolean(true)
+        );
+        assert_eq!(
+            decode(b"1700000000", ResourceType::Time).unwrap(),
+            ResourceValue::Time(1700000000)
+        );
+        assert_eq!(
+            decode(b"3:0", ResourceType::ObjectLink).unwrap(),
+            ResourceValue::ObjectLink(ObjectLink::try_from("3:0".to_string()).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_encode_values() {
+        assert_eq!(
+            encode(&ResourceValue::Integer(-42)).unwrap(),
+            b"-42".to_vec()
+        );
+        assert_eq!(
+            encode(&ResourceValue::Boolean(false)).unwrap(),
+            b"0".to_vec()
+        );
+        assert_eq!(
+            encode(&ResourceValue::ObjectLink(
+                ObjectLink::try_from("3:0".to_string()).unwrap()
+            ))
+            .unwrap(),
+            b"3:0".to_vec()
+        );
+        assert!(encode(&ResourceValue::Opaque(vec![0x01])).is_err());
+    }
+
+    #[test]
+    fn test_decode_invalid_values() {
+        assert!(decode(b"abc", ResourceType::Integer).is_err());
+        assert!(decode(b"-1", ResourceType::UnsignedInteger).is_err());
+        assert!(decode(b"true", ResourceType::Boolean).is_err());
+        assert!(decode(b"AAEC", ResourceType::Opaque).is_err());
+    }
+}
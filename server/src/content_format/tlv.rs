@@ -0,0 +1,660 @@
+use object_model::{
+    core_link::CoreLink, object_link::ObjectLink, value::ResourceValue, ObjectModel, ResourceType,
+};
+use std::collections::{BTreeMap, HashMap};
+
+use super::{check_below, resource_type, CodecError};
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#7-4-3-0-743-TLV
+// The two highest bits of the type byte tell what the identifier of an entry points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    ObjectInstance,
+    ResourceInstance,
+    MultipleResource,
+    Resource,
+}
+
+impl Kind {
+    fn bits(self) -> u8 {
+        match self {
+            Kind::ObjectInstance => 0b00,
+            Kind::ResourceInstance => 0b01,
+            Kind::MultipleResource => 0b10,
+            Kind::Resource => 0b11,
+        }
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b00 => Kind::ObjectInstance,
+            0b01 => Kind::ResourceInstance,
+            0b10 => Kind::MultipleResource,
+            _ => Kind::Resource,
+        }
+    }
+
+    // Object instances and multiple resources hold other entries, the others hold a value
+    fn nested(self) -> bool {
+        matches!(self, Kind::ObjectInstance | Kind::MultipleResource)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry<'a> {
+    kind: Kind,
+    id: u16,
+    value: &'a [u8],
+}
+
+/// Decodes a TLV payload into the values it contains, keyed by the link of each resource (instance).
+///
+/// # Arguments
+///
+/// * `payload` - The raw payload
+/// * `link` - The link that was requested, entries in the payload are relative to it
+/// * `model` - The model of the object the link points to
+pub fn decode(
+    payload: &[u8],
+    link: &CoreLink,
+    model: &ObjectModel,
+) -> Result<HashMap<CoreLink, ResourceValue>, CodecError> {
+    let parent = CoreLink::new(link.object_id, link.object_instance, link.resource_id, None);
+    let mut values = HashMap::new();
+    collect(payload, &parent, model, &mut values)?;
+    Ok(values)
+}
+
+// Decodes the entries of `payload` into `values`, `parent` is the link of the entry that holds them
+fn collect(
+    payload: &[u8],
+    parent: &CoreLink,
+    model: &ObjectModel,
+    values: &mut HashMap<CoreLink, ResourceValue>,
+) -> Result<(), CodecError> {
+    for entry in entries(payload)? {
+        let missing = |level: &str| {
+            CodecError::new(&format!(
+                "TLV {:?} {} below {} has no {}",
+                entry.kind, entry.id, parent, level
+            ))
+        };
+        let link = match entry.kind {
+            Kind::ObjectInstance => CoreLink::new(parent.object_id, Some(entry.id), None, None),
+            Kind::MultipleResource | Kind::Resource => CoreLink::new(
+                parent.object_id,
+                Some(
+                    parent
+                        .object_instance
+                        .ok_or_else(|| missing("object instance"))?,
+                ),
+                Some(entry.id),
+                None,
+            ),
+            Kind::ResourceInstance => CoreLink::new(
+                parent.object_id,
+                Some(
+                    parent
+                        .object_instance
+                        .ok_or_else(|| missing("object instance"))?,
+                ),
+                Some(parent.resource_id.ok_or_else(|| missing("resource"))?),
+                Some(entry.id),
+            ),
+        };
+
+        if entry.kind.nested() {
+            collect(entry.value, &link, model, values)?;
+        } else {
+            let value = decode_value(entry.value, resource_type(&link, model)?)?;
+            values.insert(link, value);
+        }
+    }
+    Ok(())
+}
+
+fn entries(payload: &[u8]) -> Result<Vec<Entry<'_>>, CodecError> {
+    let truncated = || CodecError::new("TLV payload is truncated");
+    let mut entries = vec![];
+    let mut rest = payload;
+    while let Some((&type_byte, tail)) = rest.split_first() {
+        rest = tail;
+        let id_length = if type_byte & 0b0010_0000 == 0 { 1 } else { 2 };
+        let length_length = usize::from((type_byte >> 3) & 0b11);
+        let header = id_length + length_length;
+        if rest.len() < header {
+            return Err(truncated());
+        }
+
+        let id = read_unsigned(&rest[..id_length]) as u16;
+        let length = match length_length {
+            0 => usize::from(type_byte & 0b111),
+            _ => read_unsigned(&rest[id_length..header]) as usize,
+        };
+        rest = &rest[header..];
+        if rest.len() < length {
+            return Err(truncated());
+        }
+        entries.push(Entry {
+            kind: Kind::from_bits(type_byte >> 6),
+            id,
+            value: &rest[..length],
+        });
+        rest = &rest[length..];
+    }
+    Ok(entries)
+}
+
+fn read_unsigned(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |acc, byte| (acc << 8) | u64::from(*byte))
+}
+
+/// Encodes values into a TLV payload.
+///
+/// # Arguments
+///
+/// * `values` - The values to encode, keyed by the link of each resource (instance)
+/// * `link` - The link the payload is sent to, all values should be below it
+/// * `model` - The model of the object the link points to
+pub fn encode(
+    values: &HashMap<CoreLink, ResourceValue>,
+    link: &CoreLink,
+    model: &ObjectModel,
+) -> Result<Vec<u8>, CodecError> {
+    // Sorted so instances and resources appear in order of their ids
+    let mut tree: BTreeMap<u16, BTreeMap<u16, Resource>> = BTreeMap::new();
+    for (value_link, value) in values {
+        check_below(value_link, link)?;
+        let (instance_id, resource_id) = match (value_link.object_instance, value_link.resource_id)
+        {
+            (Some(instance_id), Some(resource_id)) => (instance_id, resource_id),
+            _ => {
+                return Err(CodecError::new(&format!(
+                    "{} is not a resource",
+                    value_link
+                )))
+            }
+        };
+        // Checks that the resource is known
+        resource_type(value_link, model)?;
+
+        let resource = tree
+            .entry(instance_id)
+            .or_default()
+            .entry(resource_id)
+            .or_insert_with(|| match value_link.resource_instance {
+                Some(_) => Resource::Multiple(BTreeMap::new()),
+                None => Resource::Single(value),
+            });
+        match (resource, value_link.resource_instance) {
+            (Resource::Multiple(instances), Some(instance)) => {
+                instances.insert(instance, value);
+            }
+            (Resource::Single(_), None) => {}
+            _ => {
+                return Err(CodecError::new(&format!(
+                    "{} is both a single and a multiple resource",
+                    value_link
+                )))
+            }
+        }
+    }
+
+    let mut payload = vec![];
+    for (instance_id, resources) in tree {
+        let mut instance = vec![];
+        for (resource_id, resource) in resources {
+            match resource {
+                Resource::Single(value) => write_entry(
+                    &mut instance,
+                    Kind::Resource,
+                    resource_id,
+                    &encode_value(value)?,
+                )?,
+                Resource::Multiple(instances) => {
+                    let mut multiple = vec![];
+                    for (resource_instance, value) in instances {
+                        write_entry(
+                            &mut multiple,
+                            Kind::ResourceInstance,
+                            resource_instance,
+                            &encode_value(value)?,
+                        )?;
+                    }
+                    // A single resource instance is written as is
+                    if link.resource_instance.is_some() {
+                        instance.extend(multiple);
+                    } else {
+                        write_entry(
+                            &mut instance,
+                            Kind::MultipleResource,
+                            resource_id,
+                            &multiple,
+                        )?;
+                    }
+                }
+            }
+        }
+        // Only a payload for a whole object wraps the resources in their object instance
+        if link.object_instance.is_none() {
+            write_entry(&mut payload, Kind::ObjectInstance, instance_id, &instance)?;
+        } else {
+            payload.extend(instance);
+        }
+    }
+    Ok(payload)
+}
+
+enum Resource<'a> {
+    Single(&'a ResourceValue),
+    Multiple(BTreeMap<u16, &'a ResourceValue>),
+}
+
+fn write_entry(payload: &mut Vec<u8>, kind: Kind, id: u16, value: &[u8]) -> Result<(), CodecError> {
+    let mut type_byte = kind.bits() << 6;
+    if id > u16::from(u8::MAX) {
+        type_byte |= 0b0010_0000;
+    }
+    let length = value.len();
+    let length_bytes = match length {
+        0..=7 => {
+            type_byte |= length as u8;
+            0
+        }
+        8..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x1_0000..=0xff_ffff => 3,
+        _ => {
+            return Err(CodecError::new(&format!(
+                "{} bytes do not fit in a TLV entry",
+                length
+            )))
+        }
+    };
+    type_byte |= length_bytes << 3;
+
+    payload.push(type_byte);
+    if id > u16::from(u8::MAX) {
+        payload.extend(id.to_be_bytes());
+    } else {
+        payload.push(id as u8);
+    }
+    payload.extend(&(length as u32).to_be_bytes()[4 - usize::from(length_bytes)..]);
+    payload.extend(value);
+    Ok(())
+}
+
+fn decode_value(bytes: &[u8], resourcetype: ResourceType) -> Result<ResourceValue, CodecError> {
+    let invalid = || {
+        CodecError::new(&format!(
+            "{} bytes are not a valid TLV {}",
+            bytes.len(),
+            resourcetype
+        ))
+    };
+    let value = match resourcetype {
+        ResourceType::String => {
+            ResourceValue::String(String::from_utf8(bytes.to_vec()).map_err(|_| invalid())?)
+        }
+        ResourceType::Integer => ResourceValue::Integer(signed(bytes).ok_or_else(invalid)?),
+        ResourceType::UnsignedInteger => match bytes.len() {
+            1 | 2 | 4 | 8 => ResourceValue::UnsignedInteger(read_unsigned(bytes)),
+            _ => return Err(invalid()),
+        },
+        ResourceType::Float => match bytes.len() {
+            4 => ResourceValue::Float(
+                f32::from_be_bytes(bytes.try_into().map_err(|_| invalid())?).into(),
+            ),
+            8 => ResourceValue::Float(f64::from_be_bytes(bytes.try_into().map_err(|_| invalid())?)),
+            _ => return Err(invalid()),
+        },
+        ResourceType::Boolean => match bytes {
+            [0] => ResourceValue::Boolean(false),
+            [1] => ResourceValue::Boolean(true),
+            _ => return Err(invalid()),
+        },
+        // Time is a signed integer of seconds since the epoch, dates before it are not supported
+        ResourceType::Time => ResourceValue::Time(
+            signed(bytes)
+                .and_then(|time| u64::try_from(time).ok())
+                .ok_or_else(invalid)?,
+        ),
+        ResourceType::ObjectLink if bytes.len() == 4 => {
+            let object_link = format!(
+                "{}:{}",
+                read_unsigned(&bytes[..2]),
+                read_unsigned(&bytes[2..])
+            );
+            ResourceValue::ObjectLink(ObjectLink::try_from(object_link).map_err(|_| invalid())?)
+        }
+        ResourceType::ObjectLink => return Err(invalid()),
+        ResourceType::Opaque => ResourceValue::Opaque(bytes.to_vec()),
+        ResourceType::CoreLink => {
+            let text = std::str::from_utf8(bytes).map_err(|_| invalid())?;
+            ResourceValue::CoreLink(CoreLink::try_from(text).map_err(|_| invalid())?)
+        }
+    };
+    Ok(value)
+}
+
+// Integers are sent in 1, 2, 4 or 8 bytes, two's complement
+fn signed(bytes: &[u8]) -> Option<i64> {
+    match bytes.len() {
+        1 => Some(i8::from_be_bytes(bytes.try_into().ok()?).into()),
+        2 => Some(i16::from_be_bytes(bytes.try_into().ok()?).into()),
+        4 => Some(i32::from_be_bytes(bytes.try_into().ok()?).into()),
+        8 => Some(i64::from_be_bytes(bytes.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn encode_value(value: &ResourceValue) -> Result<Vec<u8>, CodecError> {
+    let bytes = match value {
+        ResourceValue::String(value) => value.as_bytes().to_vec(),
+        ResourceValue::Integer(value) => encode_signed(*value),
+        ResourceValue::UnsignedInteger(value) => {
+            let bytes = value.to_be_bytes();
+            let length = match *value {
+                0..=0xff => 1,
+                0x100..=0xffff => 2,
+                0x1_0000..=0xffff_ffff => 4,
+                _ => 8,
+            };
+            bytes[8 - length..].to_vec()
+        }
+        // Floats that fit in single precision are sent as such
+        ResourceValue::Float(value) if f64::from(*value as f32) == *value => {
+            (*value as f32).to_be_bytes().to_vec()
+        }
+        ResourceValue::Float(value) => value.to_be_bytes().to_vec(),
+        ResourceValue::Boolean(value) => vec![u8::from(*value)],
+        ResourceValue::Time(value) => encode_signed(
+            i64::try_from(*value)
+                .map_err(|_| CodecError::new(&format!("Time {} is out of range", value)))?,
+        ),
+        ResourceValue::ObjectLink(value) => [
+            value.object_id.to_be_bytes(),
+            value.object_instance.to_be_bytes(),
+        ]
+        .concat(),
+        ResourceValue::Opaque(value) => value.clone(),
+        ResourceValue::CoreLink(value) => value.to_string().into_bytes(),
+    };
+    Ok(bytes)
+}
+
+fn encode_signed(value: i64) -> Vec<u8> {
+    if let Ok(value) = i8::try_from(value) {
+        value.to_be_bytes().to_vec()
+    } else if let Ok(value) = i16::try_from(value) {
+        value.to_be_bytes().to_vec()
+    } else if let Ok(value) = i32::try_from(value) {
+        value.to_be_bytes().to_vec()
+    } else {
+        value.to_be_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lwm2m_operations::test_device::{device_object, resource};
+    use object_model::{ObjectModelBuilder, ResourceOperation};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn link(link: &str) -> CoreLink {
+        CoreLink::try_from(link).unwrap()
+    }
+
+    // The Device object instance example of the specification, without the resources the model lacks
+    fn device_instance() -> (Vec<u8>, HashMap<CoreLink, ResourceValue>) {
+        let mut payload = vec![0xC8, 0x00, 0x14];
+        payload.extend(b"Open Mobile Alliance");
+        payload.extend([
+            0x88, 0x07, 0x08, 0x42, 0x00, 0x0E, 0xD8, 0x42, 0x01, 0x13, 0x88,
+        ]);
+        payload.extend([0xC1, 0x09, 0x64]);
+        let values = HashMap::from([
+            (
+                link("</3/0/0>"),
+                ResourceValue::String("Open Mobile Alliance".to_owned()),
+            ),
+            (link("</3/0/7/0>"), ResourceValue::Integer(3800)),
+            (link("</3/0/7/1>"), ResourceValue::Integer(5000)),
+            (link("</3/0/9>"), ResourceValue::Integer(100)),
+        ]);
+        (payload, values)
+    }
+
+    #[test]
+    fn test_decode_instance() {
+        let (payload, values) = device_instance();
+        assert_eq!(
+            decode(&payload, &link("</3/0>"), &device_object()).unwrap(),
+            values
+        );
+    }
+
+    #[test]
+    fn test_encode_instance() {
+        let (payload, values) = device_instance();
+        assert_eq!(
+            encode(&values, &link("</3/0>"), &device_object()).unwrap(),
+            payload
+        );
+    }
+
+    #[test]
+    fn test_length_limit() {
+        // The largest length fits in 3 bytes
+        let mut payload = vec![];
+        let value = vec![b'a'; 0xff_ffff];
+        write_entry(&mut payload, Kind::Resource, 0, &value).unwrap();
+        assert_eq!(payload[..5], [0xD8, 0x00, 0xff, 0xff, 0xff]);
+        assert_eq!(payload.len(), 5 + value.len());
+
+        let value = "a".repeat(0x100_0000);
+        let values = HashMap::from([(link("</3/0/0>"), ResourceValue::String(value))]);
+        assert!(encode(&values, &link("</3/0/0>"), &device_object()).is_err());
+    }
+
+    #[test]
+    fn test_object_and_resource_levels() {
+        let model = device_object();
+        let (instance, values) = device_instance();
+
+        let mut object = vec![0x08, 0x00, instance.len() as u8];
+        object.extend(&instance);
+        assert_eq!(encode(&values, &link("</3>"), &model).unwrap(), object);
+        assert_eq!(decode(&object, &link("</3>"), &model).unwrap(), values);
+
+        let battery = HashMap::from([(link("</3/0/9>"), ResourceValue::Integer(100))]);
+        let payload = [0xC1, 0x09, 0x64];
+        assert_eq!(
+            encode(&battery, &link("</3/0/9>"), &model).unwrap(),
+            payload
+        );
+        assert_eq!(
+            decode(&payload, &link("</3/0/9>"), &model).unwrap(),
+            battery
+        );
+
+        let voltage = HashMap::from([(link("</3/0/7/1>"), ResourceValue::Integer(5000))]);
+        let payload = [0x42, 0x01, 0x13, 0x88];
+        assert_eq!(
+            encode(&voltage, &link("</3/0/7/1>"), &model).unwrap(),
+            payload
+        );
+        assert_eq!(
+            decode(&payload, &link("</3/0/7/1>"), &model).unwrap(),
+            voltage
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let model = device_object();
+        let invalid: [&[u8]; 5] = [
+            // Truncated value
+            &[0xC1, 0x09],
+            // Truncated length
+            &[0xC8, 0x00],
+            // Integers are 1, 2, 4 or 8 bytes
+            &[0xC3, 0x09, 0x00, 0x00, 0x64],
+            // Unknown resource
+            &[0xC1, 0x63, 0x64],
+            // Resource without an object instance
+            &[0x08, 0x00, 0x03, 0xC1, 0x09, 0x64, 0xC1, 0x09, 0x64],
+        ];
+        for payload in invalid {
+            assert!(
+                decode(payload, &link("</3>"), &model).is_err(),
+                "{:x?} should not decode",
+                payload
+            );
+        }
+    }
+
+    // Every resource type once as a single resource (0-8) and once as a multiple resource (300-308)
+    fn all_types_object() -> ObjectModel {
+        let types = [
+            ResourceType::String,
+            ResourceType::Integer,
+            ResourceType::UnsignedInteger,
+            ResourceType::Float,
+            ResourceType::Boolean,
+            ResourceType::Opaque,
+            ResourceType::Time,
+            ResourceType::ObjectLink,
+            ResourceType::CoreLink,
+        ];
+        let resources = types
+            .into_iter()
+            .enumerate()
+            .flat_map(|(index, resourcetype)| {
+                let id = index as u16;
+                [
+                    resource(
+                        id,
+                        "single",
+                        ResourceOperation::ReadWrite,
+                        Some(resourcetype),
+                        false,
+                        false,
+                    ),
+                    resource(
+                        300 + id,
+                        "multiple",
+                        ResourceOperation::ReadWrite,
+                        Some(resourcetype),
+                        true,
+                        false,
+                    ),
+                ]
+            });
+        ObjectModelBuilder::default()
+            .id(1000)
+            .name("All types".to_owned())
+            .urn("urn:oma:lwm2m:x:1000".to_owned())
+            .mandatory(false)
+            .multiple(true)
+            .resources(
+                resources
+                    .map(|resource| (resource.id(), resource))
+                    .collect(),
+            )
+            .build()
+            .unwrap()
+    }
+
+    fn random_value(rng: &mut StdRng, resourcetype: ResourceType) -> ResourceValue {
+        let length = match rng.gen_range(0..50) {
+            0 => rng.gen_range(256..70_000),
+            _ => rng.gen_range(0..40),
+        };
+        match resourcetype {
+            ResourceType::String => {
+                ResourceValue::String((0..length).map(|_| rng.gen::<char>()).collect())
+            }
+            ResourceType::Integer => {
+                ResourceValue::Integer(rng.gen::<i64>() >> rng.gen_range(0..64))
+            }
+            ResourceType::UnsignedInteger => {
+                ResourceValue::UnsignedInteger(rng.gen::<u64>() >> rng.gen_range(0..64))
+            }
+            ResourceType::Float => match rng.gen::<bool>() {
+                true => ResourceValue::Float(f64::from(rng.gen::<f32>())),
+                false => ResourceValue::Float(rng.gen_range(-1e300..1e300)),
+            },
+            ResourceType::Boolean => ResourceValue::Boolean(rng.gen()),
+            ResourceType::Opaque => ResourceValue::Opaque((0..length).map(|_| rng.gen()).collect()),
+            ResourceType::Time => {
+                ResourceValue::Time(rng.gen_range(0..=i64::MAX as u64) >> rng.gen_range(0..63))
+            }
+            ResourceType::ObjectLink => ResourceValue::ObjectLink(
+                ObjectLink::try_from(format!("{}:{}", rng.gen::<u16>(), rng.gen::<u16>())).unwrap(),
+            ),
+            ResourceType::CoreLink => ResourceValue::CoreLink(CoreLink::new(
+                rng.gen(),
+                Some(rng.gen()),
+                Some(rng.gen()),
+                None,
+            )),
+        }
+    }
+
+    fn random_instance(
+        rng: &mut StdRng,
+        model: &ObjectModel,
+        instance_id: u16,
+    ) -> HashMap<CoreLink, ResourceValue> {
+        let mut values = HashMap::new();
+        for (resource_id, resource) in model.resources() {
+            let resourcetype = resource.resourcetype().unwrap();
+            if !rng.gen_bool(0.7) {
+                continue;
+            }
+            if resource.multiple() {
+                for _ in 0..rng.gen_range(1..4) {
+                    let link = CoreLink::new(
+                        model.id(),
+                        Some(instance_id),
+                        Some(*resource_id),
+                        Some(rng.gen()),
+                    );
+                    values.insert(link, random_value(rng, resourcetype));
+                }
+            } else {
+                let link = CoreLink::new(model.id(), Some(instance_id), Some(*resource_id), None);
+                values.insert(link, random_value(rng, resourcetype));
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let model = all_types_object();
+        let mut rng = StdRng::seed_from_u64(11542);
+        for _ in 0..200 {
+            let instance_id = rng.gen();
+            let link = CoreLink::new(model.id(), Some(instance_id), None, None);
+            let values = random_instance(&mut rng, &model, instance_id);
+            let payload = encode(&values, &link, &model).unwrap();
+            assert_eq!(decode(&payload, &link, &model).unwrap(), values);
+        }
+
+        for _ in 0..50 {
+            let link = CoreLink::new(model.id(), None, None, None);
+            let mut values = HashMap::new();
+            for _ in 0..rng.gen_range(1..4) {
+                let instance_id = rng.gen();
+                values.extend(random_instance(&mut rng, &model, instance_id));
+            }
+            let payload = encode(&values, &link, &model).unwrap();
+            assert_eq!(decode(&payload, &link, &model).unwrap(), values);
+        }
+    }
+}
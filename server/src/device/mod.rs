@@ -1,29 +1,74 @@
 use chrono::prelude::*;
-use coap_lite::link_format;
-use object_model::ObjectModel;
+use object_model::{core_link::CoreLink, ObjectModel, Version};
 use rand::{distributions::Alphanumeric, Rng};
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
 
-use crate::lwm2m_requests::registration_request::{
-    Lwm2mRegistrationObject, Lwm2mRegistrationRequest, Lwm2mVersion,
+use crate::content_format::Lwm2mContentFormat;
+use crate::lwm2m_requests::{
+    attributes::Lwm2mAttribute,
+    registration_request::{
+        Lwm2mBindMode, Lwm2mRegistrationObject, Lwm2mRegistrationRequest, Lwm2mVersion,
+    },
+    update_request::Lwm2mUpdateRequest,
 };
+use crate::transport::{Peer, PeerIdentity, Route};
+
+pub mod registry;
+pub mod send;
 
 pub struct Device {
     models: HashMap<u16, ObjectModel>,
     device_endpoint: String,
     server_endpoint: String,
+    address: SocketAddr,
+    version: Lwm2mVersion,
+    binding_mode: Lwm2mBindMode,
+    sms_number: Option<String>,
+    objects: Vec<Lwm2mRegistrationObject>,
     lifetime: Duration,
     last_seen: DateTime<Utc>,
+    // How the device authenticated when it registered, None over plain UDP
+    identity: Option<PeerIdentity>,
 }
 
 impl Device {
-    pub fn new(new_reg: Lwm2mRegistrationRequest) -> Self {
+    pub fn new(
+        new_reg: Lwm2mRegistrationRequest,
+        address: SocketAddr,
+        identity: Option<PeerIdentity>,
+    ) -> Self {
         Self {
             models: HashMap::new(),
             last_seen: Utc::now(),
             device_endpoint: new_reg.device_endpoint,
+            address,
+            version: new_reg.version,
+            binding_mode: new_reg.binding_mode,
+            sms_number: new_reg.sms_number,
+            objects: new_reg.objects,
             lifetime: Duration::from_secs(new_reg.lifetime),
             server_endpoint: Self::new_endpoint(),
+            identity,
+        }
+    }
+
+    /// Applies a registration update. Only the parameters present in the update are changed,
+    /// the device is always marked as seen.
+    pub fn update(&mut self, update: Lwm2mUpdateRequest, address: SocketAddr) {
+        self.last_seen = Utc::now();
+        // The source address can change between updates, e.g. because of NAT rebinding
+        self.address = address;
+        if let Some(lifetime) = update.lifetime {
+            self.lifetime = Duration::from_secs(lifetime);
+        }
+        if let Some(binding_mode) = update.binding_mode {
+            self.binding_mode = binding_mode;
+        }
+        if let Some(sms_number) = update.sms_number {
+            self.sms_number = Some(sms_number);
+        }
+        if let Some(objects) = update.objects {
+            self.objects = objects;
         }
     }
 
@@ -36,15 +81,212 @@ impl Device {
             .map(char::from)
             .collect()
     }
+
+    pub fn device_endpoint(&self) -> &str {
+        &self.device_endpoint
+    }
+
+    pub fn server_endpoint(&self) -> &str {
+        &self.server_endpoint
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn version(&self) -> Lwm2mVersion {
+        self.version
+    }
+
+    pub fn binding_mode(&self) -> Lwm2mBindMode {
+        self.binding_mode
+    }
+
+    pub fn sms_number(&self) -> Option<&str> {
+        self.sms_number.as_deref()
+    }
+
+    /// Where the server reaches the device: its address, on the socket it authenticated on.
+    pub fn peer(&self) -> Peer {
+        Peer::new(self.address, Route::of(self.identity.as_ref()))
+    }
+
+    /// The identity the device authenticated with when it registered over DTLS.
+    pub fn identity(&self) -> Option<&PeerIdentity> {
+        self.identity.as_ref()
+    }
+
+    /// The version of an object as registered by the device, the default version if none was given.
+    pub fn object_version(&self, object_id: u16) -> Version {
+        self.objects
+            .iter()
+            .filter(|object| object.link().map(|link| link.object_id) == Some(object_id))
+            .flat_map(|object| object.attributes())
+            .find_map(|attribute| match attribute {
+                Lwm2mAttribute::ObjectVersion(version) => Version::try_from(version.as_str()).ok(),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// The content formats the device listed in the ct attribute of its registration, if any.
+    pub fn content_formats(&self) -> Vec<Lwm2mContentFormat> {
+        self.objects
+            .iter()
+            .flat_map(|object| object.attributes())
+            .find_map(|attribute| match attribute {
+                Lwm2mAttribute::ContentType(formats) => Some(formats.clone()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// The instances of an object the device is known to have.
+    pub fn object_instances(&self, object_id: u16) -> Vec<u16> {
+        self.objects
+            .iter()
+            .filter_map(|object| object.link())
+            .filter(|link| link.object_id == object_id && link.resource_id.is_none())
+            .filter_map(|link| link.object_instance)
+            .collect()
+    }
+
+    /// Whether `link` points into an object (instance) the device registered.
+    /// Objects registered without instances accept any instance.
+    pub fn has_object(&self, link: &CoreLink) -> bool {
+        let registered = self
+            .objects
+            .iter()
+            .filter_map(|object| object.link())
+            .any(|object| object.object_id == link.object_id && object.resource_id.is_none());
+        let instances = self.object_instances(link.object_id);
+        match link.object_instance {
+            Some(instance_id) if !instances.is_empty() => instances.contains(&instance_id),
+            _ => registered,
+        }
+    }
+
+    /// Records an object instance that was created on the device after it registered.
+    pub fn add_object_instance(&mut self, object_id: u16, instance_id: u16) {
+        if !self.object_instances(object_id).contains(&instance_id) {
+            let link = CoreLink::new(object_id, Some(instance_id), None, None);
+            self.objects.push(Lwm2mRegistrationObject::new(&link));
+        }
+    }
+
+    /// Forgets an object instance that was deleted on the device.
+    pub fn remove_object_instance(&mut self, object_id: u16, instance_id: u16) {
+        let link = CoreLink::new(object_id, Some(instance_id), None, None);
+        self.objects
+            .retain(|object| object.link() != Some(link.clone()));
+    }
+
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    pub fn last_seen(&self) -> DateTime<Utc> {
+        self.last_seen
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::Device;
+    use crate::content_format::Lwm2mContentFormat;
+    use crate::lwm2m_requests::registration_request::{
+        parse_link_format, Lwm2mBindMode, Lwm2mRegistrationObject, Lwm2mRegistrationRequest,
+        Lwm2mVersion,
+    };
+    use object_model::core_link::CoreLink;
 
     #[test]
     fn get_endpoint() {
         println!("New device endpoint: {}", Device::new_endpoint());
     }
+
+    #[test]
+    fn test_object_instances() {
+        let mut device = Device::new(
+            Lwm2mRegistrationRequest {
+                device_endpoint: "device123".to_owned(),
+                lifetime: 3600,
+                version: Lwm2mVersion::V11,
+                binding_mode: Lwm2mBindMode::Udp,
+                sms_number: None,
+                objects: vec![],
+            },
+            "127.0.0.1:56830".parse().unwrap(),
+            None,
+        );
+        device.add_object_instance(3303, 0);
+        device.add_object_instance(3303, 1);
+        device.add_object_instance(3303, 1);
+        assert_eq!(device.object_instances(3303), vec![0, 1]);
+
+        device.remove_object_instance(3303, 0);
+        assert_eq!(device.object_instances(3303), vec![1]);
+        assert!(device.object_instances(3).is_empty());
+    }
+
+    #[test]
+    fn test_content_formats() {
+        let device = Device::new(
+            Lwm2mRegistrationRequest {
+                device_endpoint: "device123".to_owned(),
+                lifetime: 3600,
+                version: Lwm2mVersion::V11,
+                binding_mode: Lwm2mBindMode::Udp,
+                sms_number: None,
+                objects: parse_link_format("</>;rt=\"oma.lwm2m\";ct=\"60 112\",</3/0>").unwrap(),
+            },
+            "127.0.0.1:56830".parse().unwrap(),
+            None,
+        );
+        assert_eq!(
+            device.content_formats(),
+            vec![Lwm2mContentFormat::Cbor, Lwm2mContentFormat::SenmlCbor]
+        );
+
+        // A format the server does not know leaves the choice to the version default
+        let device = Device::new(
+            Lwm2mRegistrationRequest {
+                device_endpoint: "device123".to_owned(),
+                lifetime: 3600,
+                version: Lwm2mVersion::V11,
+                binding_mode: Lwm2mBindMode::Udp,
+                sms_number: None,
+                objects: parse_link_format("</>;rt=\"oma.lwm2m\";ct=50,</3/0>").unwrap(),
+            },
+            "127.0.0.1:56830".parse().unwrap(),
+            None,
+        );
+        assert!(device.content_formats().is_empty());
+    }
+
+    #[test]
+    fn test_has_object() {
+        let device = Device::new(
+            Lwm2mRegistrationRequest {
+                device_endpoint: "device123".to_owned(),
+                lifetime: 3600,
+                version: Lwm2mVersion::V11,
+                binding_mode: Lwm2mBindMode::Udp,
+                sms_number: None,
+                objects: ["</3/0>", "</3303>"]
+                    .into_iter()
+                    .map(|link| Lwm2mRegistrationObject::new(&CoreLink::try_from(link).unwrap()))
+                    .collect(),
+            },
+            "127.0.0.1:56830".parse().unwrap(),
+            None,
+        );
+        let has_object = |link: &str| device.has_object(&CoreLink::try_from(link).unwrap());
+        assert!(has_object("</3/0/9>"));
+        assert!(has_object("</3>"));
+        assert!(!has_object("</3/1/9>"));
+        assert!(has_object("</3303/7/5700>"));
+        assert!(!has_object("</5/0/1>"));
+    }
 }
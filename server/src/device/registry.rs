@@ -0,0 +1,465 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+use timer_tracker::TimerTracker;
+use tokio::sync::{broadcast, mpsc, RwLock};
+
+use super::Device;
+use crate::lwm2m_requests::{
+    registration_request::Lwm2mRegistrationRequest, update_request::Lwm2mUpdateRequest,
+};
+use crate::transport::PeerIdentity;
+
+#[derive(Default)]
+struct Registrations {
+    // Registered devices, keyed by their server endpoint (the {location} in /rd/{location})
+    devices: HashMap<String, Device>,
+    // Device endpoint name (ep) to server endpoint
+    locations: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistrationEvent {
+    Registered {
+        device_endpoint: String,
+        location: String,
+    },
+    Updated {
+        device_endpoint: String,
+        location: String,
+    },
+    Deregistered {
+        device_endpoint: String,
+        location: String,
+    },
+    // The device did not send an update within its lifetime
+    Expired {
+        device_endpoint: String,
+        location: String,
+    },
+}
+
+/// In-memory store of all devices that are currently registered with the server.
+/// Registrations that are not updated within their lifetime are evicted automatically.
+pub struct DeviceRegistry {
+    registrations: Arc<RwLock<Registrations>>,
+    // Kept so the tracker keeps running for as long as the registry exists
+    tracker: TimerTracker,
+    timers_tx: mpsc::Sender<(String, Duration)>,
+    events_tx: broadcast::Sender<RegistrationEvent>,
+}
+
+impl DeviceRegistry {
+    /// Creates an empty registry. Must be called from within a tokio runtime,
+    /// the lifetime timers and their expiry run as tasks on it.
+    pub fn new() -> Self {
+        let tracker = TimerTracker::new();
+        let timers_tx = tracker.register();
+        let (events_tx, _) = broadcast::channel(1024);
+        let registrations = Arc::new(RwLock::new(Registrations::default()));
+
+        tokio::spawn(expire_registrations(
+            tracker.subscribe(),
+            registrations.clone(),
+            events_tx.clone(),
+        ));
+
+        DeviceRegistry {
+            registrations,
+            tracker,
+            timers_tx,
+            events_tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RegistrationEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Registers a device and returns the server endpoint it can be reached at under /rd.
+    /// When a device registers again with the same endpoint name its old registration is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The parsed registration request
+    /// * `address` - The source address the registration request was received from
+    /// * `identity` - The identity the device authenticated with, None over plain UDP
+    pub async fn register(
+        &self,
+        request: Lwm2mRegistrationRequest,
+        address: SocketAddr,
+        identity: Option<PeerIdentity>,
+    ) -> String {
+        let device = Device::new(request, address, identity);
+        let location = device.server_endpoint.clone();
+        let device_endpoint = device.device_endpoint.clone();
+        let lifetime = device.lifetime;
+
+        {
+            let mut registrations = self.registrations.write().await;
+            if let Some(old_location) = registrations
+                .locations
+                .insert(device_endpoint.clone(), location.clone())
+            {
+                registrations.devices.remove(&old_location);
+            }
+            registrations.devices.insert(location.clone(), device);
+        }
+
+        self.arm_timer(&location, lifetime).await;
+        let _ = self.events_tx.send(RegistrationEvent::Registered {
+            device_endpoint,
+            location: location.clone(),
+        });
+        location
+    }
+
+    /// Applies a registration update to the device registered at `location`
+    /// and restarts its lifetime. Returns false if no device is registered at that location.
+    pub async fn update(
+        &self,
+        location: &str,
+        update: Lwm2mUpdateRequest,
+        address: SocketAddr,
+    ) -> bool {
+        let (device_endpoint, lifetime) =
+            match self.registrations.write().await.devices.get_mut(location) {
+                Some(device) => {
+                    device.update(update, address);
+                    (device.device_endpoint.clone(), device.lifetime)
+                }
+                None => return false,
+            };
+
+        self.arm_timer(location, lifetime).await;
+        let _ = self.events_tx.send(RegistrationEvent::Updated {
+            device_endpoint,
+            location: location.to_owned(),
+        });
+        true
+    }
+
+    /// Removes the device registered at `location`, returning it if it existed.
+    pub async fn deregister(&self, location: &str) -> Option<Device> {
+        let device = remove_device(&self.registrations, location).await?;
+        let _ = self.events_tx.send(RegistrationEvent::Deregistered {
+            device_endpoint: device.device_endpoint.clone(),
+            location: location.to_owned(),
+        });
+        Some(device)
+    }
+
+    pub async fn is_registered(&self, location: &str) -> bool {
+        self.registrations
+            .read()
+            .await
+            .devices
+            .contains_key(location)
+    }
+
+    pub async fn location(&self, device_endpoint: &str) -> Option<String> {
+        self.registrations
+            .read()
+            .await
+            .locations
+            .get(device_endpoint)
+            .cloned()
+    }
+
+    /// The endpoint name of the device registered from `address`, if any.
+    pub async fn device_endpoint_at(&self, address: SocketAddr) -> Option<String> {
+        self.registrations
+            .read()
+            .await
+            .devices
+            .values()
+            .find(|device| device.address == address)
+            .map(|device| device.device_endpoint.clone())
+    }
+
+    /// Calls `f` with the device registered under the endpoint name `device_endpoint`.
+    /// Returns None if no such device is registered.
+    pub async fn with_device<T>(
+        &self,
+        device_endpoint: &str,
+        f: impl FnOnce(&Device) -> T,
+    ) -> Option<T> {
+        let registrations = self.registrations.read().await;
+        let location = registrations.locations.get(device_endpoint)?;
+        registrations.devices.get(location).map(f)
+    }
+
+    /// Like `with_device`, for the device registered at `location`.
+    pub async fn with_device_at<T>(
+        &self,
+        location: &str,
+        f: impl FnOnce(&Device) -> T,
+    ) -> Option<T> {
+        self.registrations.read().await.devices.get(location).map(f)
+    }
+
+    /// Like `with_device`, but `f` may change the device.
+    pub async fn with_device_mut<T>(
+        &self,
+        device_endpoint: &str,
+        f: impl FnOnce(&mut Device) -> T,
+    ) -> Option<T> {
+        let mut registrations = self.registrations.write().await;
+        let location = registrations.locations.get(device_endpoint)?.clone();
+        registrations.devices.get_mut(&location).map(f)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.registrations.read().await.devices.len()
+    }
+
+    // (Re)starts the lifetime timer of a registration, replacing any running timer.
+    async fn arm_timer(&self, location: &str, lifetime: Duration) {
+        let _ = self.timers_tx.send((location.to_owned(), lifetime)).await;
+    }
+}
+
+async fn remove_device(registrations: &RwLock<Registrations>, location: &str) -> Option<Device> {
+    let mut registrations = registrations.write().await;
+    let device = registrations.devices.remove(location)?;
+    registrations.locations.remove(&device.device_endpoint);
+    Some(device)
+}
+
+async fn expire_registrations(
+    mut timeout_rx: broadcast::Receiver<String>,
+    registrations: Arc<RwLock<Registrations>>,
+    events_tx: broadcast::Sender<RegistrationEvent>,
+) {
+    loop {
+        match timeout_rx.recv().await {
+            Ok(location) => {
+                // Timers of registrations that were removed or replaced in the meantime are not
+                // cancelled, those simply don't match a device anymore.
+                if let Some(device) = remove_device(&registrations, &location).await {
+                    let _ = events_tx.send(RegistrationEvent::Expired {
+                        device_endpoint: device.device_endpoint,
+                        location,
+                    });
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lwm2m_requests::registration_request::{Lwm2mBindMode, Lwm2mVersion};
+    use tokio::time;
+
+    fn registration_request(device_endpoint: &str) -> Lwm2mRegistrationRequest {
+        Lwm2mRegistrationRequest {
+            device_endpoint: device_endpoint.to_owned(),
+            lifetime: 3600,
+            version: Lwm2mVersion::V11,
+            binding_mode: Lwm2mBindMode::Udp,
+            sms_number: None,
+            objects: vec![],
+        }
+    }
+
+    fn address() -> SocketAddr {
+        "127.0.0.1:56830".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_device() {
+        let registry = DeviceRegistry::new();
+        let location = registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+
+        assert!(registry.is_registered(&location).await);
+        assert_eq!(registry.location("device123").await, Some(location));
+        assert_eq!(registry.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_register_device_twice_replaces_registration() {
+        let registry = DeviceRegistry::new();
+        let first = registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+        let second = registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+
+        assert_ne!(first, second);
+        assert!(!registry.is_registered(&first).await);
+        assert!(registry.is_registered(&second).await);
+        assert_eq!(registry.location("device123").await, Some(second));
+        assert_eq!(registry.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_register_multiple_devices() {
+        let registry = DeviceRegistry::new();
+        registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+        registry
+            .register(registration_request("device456"), address(), None)
+            .await;
+
+        assert_eq!(registry.len().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_update_device() {
+        let registry = DeviceRegistry::new();
+        let location = registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+        let new_address: SocketAddr = "127.0.0.1:56831".parse().unwrap();
+        let update = Lwm2mUpdateRequest {
+            lifetime: Some(60),
+            binding_mode: Some(Lwm2mBindMode::Tcp),
+            ..Default::default()
+        };
+
+        assert!(registry.update(&location, update, new_address).await);
+        let registrations = registry.registrations.read().await;
+        let device = registrations.devices.get(&location).unwrap();
+        assert_eq!(device.lifetime(), std::time::Duration::from_secs(60));
+        assert_eq!(device.binding_mode(), Lwm2mBindMode::Tcp);
+        assert_eq!(device.address(), new_address);
+    }
+
+    #[tokio::test]
+    async fn test_device_endpoint_at() {
+        let registry = DeviceRegistry::new();
+        registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+
+        assert_eq!(
+            registry.device_endpoint_at(address()).await,
+            Some("device123".to_owned())
+        );
+        let other: SocketAddr = "127.0.0.1:56831".parse().unwrap();
+        assert_eq!(registry.device_endpoint_at(other).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_device_identity() {
+        let registry = DeviceRegistry::new();
+        let identity = PeerIdentity::Psk("device123".to_owned());
+        let location = registry
+            .register(
+                registration_request("device123"),
+                address(),
+                Some(identity.clone()),
+            )
+            .await;
+
+        let registered = |device: &Device| device.identity().cloned();
+        assert_eq!(
+            registry.with_device_at(&location, registered).await,
+            Some(Some(identity))
+        );
+        assert_eq!(registry.with_device_at("unknown", registered).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_update_unknown_location() {
+        let registry = DeviceRegistry::new();
+        let updated = registry
+            .update("unknown", Lwm2mUpdateRequest::default(), address())
+            .await;
+        assert!(!updated);
+    }
+
+    #[tokio::test]
+    async fn test_deregister_device() {
+        let registry = DeviceRegistry::new();
+        let location = registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+
+        let device = registry.deregister(&location).await;
+        assert_eq!(
+            device.map(|device| device.device_endpoint),
+            Some("device123".to_string())
+        );
+        assert!(!registry.is_registered(&location).await);
+        assert_eq!(registry.location("device123").await, None);
+        assert!(registry.deregister(&location).await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_registration_expires() {
+        let registry = DeviceRegistry::new();
+        let mut events = registry.subscribe();
+        let location = registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+
+        time::sleep(Duration::from_secs(3599)).await;
+        assert!(registry.is_registered(&location).await);
+
+        time::sleep(Duration::from_secs(2)).await;
+        assert!(!registry.is_registered(&location).await);
+        assert_eq!(registry.location("device123").await, None);
+
+        assert!(matches!(
+            events.recv().await,
+            Ok(RegistrationEvent::Registered { .. })
+        ));
+        assert_eq!(
+            events.recv().await.unwrap(),
+            RegistrationEvent::Expired {
+                device_endpoint: "device123".to_string(),
+                location
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_update_rearms_lifetime() {
+        let registry = DeviceRegistry::new();
+        let location = registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+
+        time::sleep(Duration::from_secs(3000)).await;
+        let update = Lwm2mUpdateRequest {
+            lifetime: Some(60),
+            ..Default::default()
+        };
+        assert!(registry.update(&location, update, address()).await);
+
+        // The original lifetime of 3600s has passed, but the update re-armed the timer with 60s
+        time::sleep(Duration::from_secs(59)).await;
+        assert!(registry.is_registered(&location).await);
+        registry
+            .update(&location, Lwm2mUpdateRequest::default(), address())
+            .await;
+        time::sleep(Duration::from_secs(59)).await;
+        assert!(registry.is_registered(&location).await);
+
+        time::sleep(Duration::from_secs(2)).await;
+        assert!(!registry.is_registered(&location).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replaced_registration_does_not_expire_new_one() {
+        let registry = DeviceRegistry::new();
+        registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+        time::sleep(Duration::from_secs(1800)).await;
+        let location = registry
+            .register(registration_request("device123"), address(), None)
+            .await;
+
+        // The timer of the first registration fires here, but must not evict the second one
+        time::sleep(Duration::from_secs(1801)).await;
+        assert!(registry.is_registered(&location).await);
+    }
+}
@@ -0,0 +1,276 @@
+use coap_lite::ResponseType;
+use coap_server::app::CoapError;
+use object_model::{core_link::CoreLink, value::ResourceValue, ObjectModel, ObjectModelStore};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use tokio::sync::broadcast;
+
+use super::registry::DeviceRegistry;
+use crate::{
+    content_format,
+    lwm2m_requests::{registration_request::Lwm2mVersion, send_request::Lwm2mSendRequest},
+    transport::PeerIdentity,
+};
+
+/// Values a device pushed to the server with the Send operation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentValues {
+    pub device_endpoint: String,
+    pub values: HashMap<CoreLink, ResourceValue>,
+}
+
+/// Accepts the values registered devices send to /dp and publishes them to all subscribers.
+pub struct SendReceiver {
+    registry: Arc<DeviceRegistry>,
+    models: Arc<ObjectModelStore>,
+    values_tx: broadcast::Sender<SentValues>,
+}
+
+impl SendReceiver {
+    pub fn new(registry: Arc<DeviceRegistry>, models: Arc<ObjectModelStore>) -> Self {
+        let (values_tx, _) = broadcast::channel(1024);
+        SendReceiver {
+            registry,
+            models,
+            values_tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SentValues> {
+        self.values_tx.subscribe()
+    }
+
+    /// Checks the values of a Send request against the registration of its sender,
+    /// converts them to the types of their object models and publishes them.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The parsed Send request
+    /// * `address` - The source address the request was received from
+    /// * `identity` - The identity the sender authenticated with, None for an anonymous sender
+    pub async fn receive(
+        &self,
+        request: Lwm2mSendRequest,
+        address: SocketAddr,
+        identity: Option<&PeerIdentity>,
+    ) -> Result<SentValues, CoapError> {
+        // Devices are identified by the address they registered or last updated from
+        let device_endpoint = self
+            .registry
+            .device_endpoint_at(address)
+            .await
+            .ok_or_else(|| {
+                CoapError::for_code(ResponseType::Forbidden, "Sender is not registered")
+            })?;
+
+        let versions = self
+            .registry
+            .with_device(&device_endpoint, |device| {
+                // The address alone can be spoofed, the sender has to be the device that registered
+                if device.identity() != identity {
+                    return Err(CoapError::for_code(
+                        ResponseType::Forbidden,
+                        "Sender does not have the identity of the registration",
+                    ));
+                }
+                if device.version() == Lwm2mVersion::V10 {
+                    return Err(CoapError::method_not_allowed());
+                }
+                request
+                    .records
+                    .iter()
+                    .map(|record| {
+                        let link = &record.link;
+                        if !device.has_object(link) {
+                            return Err(CoapError::for_code(
+                                ResponseType::NotFound,
+                                format!("{} is not registered by the device", link),
+                            ));
+                        }
+                        Ok((link.object_id, device.object_version(link.object_id)))
+                    })
+                    .collect::<Result<HashMap<_, _>, CoapError>>()
+            })
+            .await
+            .ok_or_else(|| {
+                CoapError::for_code(ResponseType::Forbidden, "Sender is not registered")
+            })??;
+
+        let models = versions
+            .into_iter()
+            .map(|(object_id, version)| {
+                let model = self
+                    .models
+                    .get_object_model(object_id, Some(version))
+                    .map_err(|_| {
+                        CoapError::for_code(
+                            ResponseType::NotFound,
+                            format!("Object {} has no model", object_id),
+                        )
+                    })?;
+                Ok((object_id, model))
+            })
+            .collect::<Result<HashMap<u16, ObjectModel>, CoapError>>()?;
+        let values = content_format::composite_values(request.records, &models)
+            .map_err(|err| CoapError::bad_request(err.to_string()))?;
+        // Read-only resources are what devices usually report, only legal values are accepted
+        for (link, value) in &values {
+            models[&link.object_id]
+                .validate_value(link, value)
+                .map_err(|err| CoapError::bad_request(err.to_string()))?;
+        }
+
+        let sent = SentValues {
+            device_endpoint,
+            values,
+        };
+        // Nobody listening is not an error, the device has delivered its values either way
+        let _ = self.values_tx.send(sent.clone());
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        content_format::{
+            senml::{ResolvedRecord, SenmlValue},
+            Lwm2mContentFormat,
+        },
+        lwm2m_operations::test_device::{device_object, temperature_object},
+        lwm2m_requests::registration_request::{
+            Lwm2mBindMode, Lwm2mRegistrationObject, Lwm2mRegistrationRequest,
+        },
+    };
+
+    fn address() -> SocketAddr {
+        "127.0.0.1:56830".parse().unwrap()
+    }
+
+    async fn setup(version: Lwm2mVersion) -> SendReceiver {
+        let registry = Arc::new(DeviceRegistry::new());
+        registry
+            .register(
+                Lwm2mRegistrationRequest {
+                    device_endpoint: "device123".to_owned(),
+                    lifetime: 3600,
+                    version,
+                    binding_mode: Lwm2mBindMode::Udp,
+                    sms_number: None,
+                    objects: ["</3/0>", "</3303/0>"]
+                        .into_iter()
+                        .map(|link| {
+                            Lwm2mRegistrationObject::new(&CoreLink::try_from(link).unwrap())
+                        })
+                        .collect(),
+                },
+                address(),
+                None,
+            )
+            .await;
+        let mut models = ObjectModelStore::default();
+        models.add_model(device_object());
+        models.add_model(temperature_object());
+        SendReceiver::new(registry, Arc::new(models))
+    }
+
+    fn send_request(records: &[(&str, SenmlValue)]) -> Lwm2mSendRequest {
+        Lwm2mSendRequest {
+            format: Lwm2mContentFormat::SenmlJson,
+            records: records
+                .iter()
+                .map(|(link, value)| ResolvedRecord {
+                    link: CoreLink::try_from(*link).unwrap(),
+                    time: None,
+                    value: Some(value.clone()),
+                })
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive() {
+        let receiver = setup(Lwm2mVersion::V11).await;
+        let mut subscriber = receiver.subscribe();
+        let request = send_request(&[
+            ("</3303/0/5700>", SenmlValue::Float(21.5)),
+            ("</3/0/9>", SenmlValue::Integer(87)),
+        ]);
+
+        let sent = receiver.receive(request, address(), None).await.unwrap();
+        let expected = SentValues {
+            device_endpoint: "device123".to_owned(),
+            values: HashMap::from([
+                (
+                    CoreLink::try_from("</3303/0/5700>").unwrap(),
+                    ResourceValue::Float(21.5),
+                ),
+                (
+                    CoreLink::try_from("</3/0/9>").unwrap(),
+                    ResourceValue::Integer(87),
+                ),
+            ]),
+        };
+        assert_eq!(sent, expected);
+        assert_eq!(subscriber.recv().await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_receive_refused() {
+        let receiver = setup(Lwm2mVersion::V11).await;
+        let mut subscriber = receiver.subscribe();
+        let refused = [
+            // Unknown sender
+            (
+                send_request(&[("</3/0/9>", SenmlValue::Integer(87))]),
+                "127.0.0.1:56831".parse().unwrap(),
+                ResponseType::Forbidden,
+            ),
+            // Instance that was not registered
+            (
+                send_request(&[("</3303/1/5700>", SenmlValue::Float(21.5))]),
+                address(),
+                ResponseType::NotFound,
+            ),
+            // Value that does not fit the resource
+            (
+                send_request(&[("</3303/0/5700>", SenmlValue::String("warm".to_owned()))]),
+                address(),
+                ResponseType::BadRequest,
+            ),
+            // Multiple resource without resource instance
+            (
+                send_request(&[("</3/0/7>", SenmlValue::Integer(3800))]),
+                address(),
+                ResponseType::BadRequest,
+            ),
+        ];
+        for (request, address, code) in refused {
+            let result = receiver.receive(request, address, None).await;
+            assert_eq!(result.unwrap_err().code, Some(code));
+        }
+        assert!(subscriber.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_receive_other_identity() {
+        let receiver = setup(Lwm2mVersion::V11).await;
+        let mut subscriber = receiver.subscribe();
+        let request = send_request(&[("</3/0/9>", SenmlValue::Integer(87))]);
+        let identity = PeerIdentity::Psk("device123".to_owned());
+        let result = receiver.receive(request, address(), Some(&identity)).await;
+        assert_eq!(result.unwrap_err().code, Some(ResponseType::Forbidden));
+        assert!(subscriber.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_receive_v10() {
+        let receiver = setup(Lwm2mVersion::V10).await;
+        let request = send_request(&[("</3/0/9>", SenmlValue::Integer(87))]);
+        let result = receiver.receive(request, address(), None).await;
+        assert_eq!(
+            result.unwrap_err().code,
+            Some(ResponseType::MethodNotAllowed)
+        );
+    }
+}
@@ -0,0 +1,426 @@
+use coap_lite::{
+    option_value::OptionValueU16, CoapOption, MessageClass, Packet, RequestType, ResponseType,
+};
+use object_model::{core_link::CoreLink, value::ResourceValue};
+use std::collections::HashMap;
+
+use super::{
+    err::OperationError,
+    observe::{Decoder, Observation},
+    response_format, Lwm2mServer, Target,
+};
+use crate::{
+    content_format::{self, Lwm2mContentFormat},
+    lwm2m_requests::registration_request::Lwm2mVersion,
+};
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#6-3-8-0-638-Read-Composite-Operation
+// Composite operations are sent to the root path, the links are in the payload.
+fn composite_request(
+    method: RequestType,
+    target: &Target,
+) -> Result<(Packet, Lwm2mContentFormat), OperationError> {
+    if target.version == Lwm2mVersion::V10 {
+        return Err(OperationError::UnsupportedVersion(target.version));
+    }
+    let format = content_format::composite_format(&target.content_formats);
+
+    let mut request = Packet::new();
+    request.header.code = MessageClass::Request(method);
+    request.add_option_as(CoapOption::ContentFormat, OptionValueU16(format.into()));
+    Ok((request, format))
+}
+
+// Read-Composite and Observe-Composite send a FETCH with the list of links to read
+fn fetch_request(
+    target: &Target,
+    links: &[CoreLink],
+) -> Result<(Packet, Lwm2mContentFormat), OperationError> {
+    let (mut request, format) = composite_request(RequestType::Fetch, target)?;
+    request.add_option_as(CoapOption::Accept, OptionValueU16(format.into()));
+    request.payload = content_format::encode_paths(format, links)?;
+    Ok((request, format))
+}
+
+impl Lwm2mServer {
+    /// Reads several objects, object instances or resources of a registered device at once.
+    /// Returns the value of every resource (instance) that was read, keyed by its link.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `links` - The links to read, e.g. </3/0/9> and </3303/0>
+    pub async fn read_composite(
+        &self,
+        endpoint: &str,
+        links: Vec<CoreLink>,
+    ) -> Result<HashMap<CoreLink, ResourceValue>, OperationError> {
+        let target = self.target(endpoint, root_link(&links)?).await?;
+        let (request, accept) = fetch_request(&target, &links)?;
+        let models = self.object_models(endpoint, &links).await?;
+
+        let response = self.send(&target, request).await?;
+        OperationError::check_response(&response, ResponseType::Content)?;
+        let format = response_format(&response, accept)?;
+        Ok(content_format::decode_composite(
+            format,
+            &response.payload,
+            &models,
+        )?)
+    }
+
+    /// Observes several objects, object instances or resources of a registered device at once.
+    /// Every notification holds the values of all observed links, see `observe`.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `links` - The links to observe, e.g. </3/0/9> and </3303/0>
+    pub async fn observe_composite(
+        &self,
+        endpoint: &str,
+        links: Vec<CoreLink>,
+    ) -> Result<Observation, OperationError> {
+        let target = self.target(endpoint, root_link(&links)?).await?;
+        let (request, accept) = fetch_request(&target, &links)?;
+        let models = self.object_models(endpoint, &links).await?;
+
+        self.start_observation(
+            endpoint,
+            target.peer,
+            request,
+            accept,
+            Decoder::Composite { models },
+        )
+        .await
+    }
+
+    /// Writes resources of several objects of a registered device at once.
+    /// Every value is checked against the object model before anything is sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `values` - The values to write, keyed by the link of each resource (instance)
+    pub async fn write_composite(
+        &self,
+        endpoint: &str,
+        values: HashMap<CoreLink, ResourceValue>,
+    ) -> Result<(), OperationError> {
+        let links: Vec<CoreLink> = values.keys().cloned().collect();
+        let target = self.target(endpoint, root_link(&links)?).await?;
+        let (mut request, format) = composite_request(RequestType::IPatch, &target)?;
+        let models = self.object_models(endpoint, &links).await?;
+        for (link, value) in &values {
+            if link.resource_id.is_none() {
+                return Err(OperationError::InvalidLink {
+                    link: link.clone(),
+                    message: "only resources and resource instances can be written".to_owned(),
+                });
+            }
+            models[&link.object_id].validate_write(link, value)?;
+        }
+
+        request.payload = content_format::encode_composite(format, &values)?;
+        let response = self.send(&target, request).await?;
+        OperationError::check_response(&response, ResponseType::Changed)
+    }
+}
+
+// Any of the links will do to look up the device, the operation itself is not sent to it
+fn root_link(links: &[CoreLink]) -> Result<&CoreLink, OperationError> {
+    links.first().ok_or(OperationError::InvalidArgument(
+        "a composite operation needs at least one link".to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        content_format::senml::{self, SenmlRecord, SenmlValue},
+        lwm2m_operations::test_device::{response, setup, setup_with_objects, uri_path, ENDPOINT},
+    };
+    use futures::StreamExt;
+
+    fn links() -> Vec<CoreLink> {
+        vec![
+            CoreLink::try_from("</3/0/9>").unwrap(),
+            CoreLink::try_from("</3303/0>").unwrap(),
+        ]
+    }
+
+    fn values() -> HashMap<CoreLink, ResourceValue> {
+        HashMap::from([
+            (
+                CoreLink::try_from("</3/0/9>").unwrap(),
+                ResourceValue::Integer(87),
+            ),
+            (
+                CoreLink::try_from("</3303/0/5700>").unwrap(),
+                ResourceValue::Float(21.5),
+            ),
+            (
+                CoreLink::try_from("</3303/0/5701>").unwrap(),
+                ResourceValue::String("Cel".to_owned()),
+            ),
+        ])
+    }
+
+    const PAYLOAD: &[u8] =
+        br#"[{"n":"/3/0/9","v":87},{"bn":"/3303/0/","n":"5700","v":21.5},{"n":"5701","vs":"Cel"}]"#;
+
+    #[tokio::test]
+    async fn test_read_composite() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+
+        let (result, request) = tokio::join!(
+            server.read_composite(ENDPOINT, links()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::SenmlJson),
+                PAYLOAD
+            ))
+        );
+
+        assert_eq!(
+            request.header.code,
+            MessageClass::Request(RequestType::Fetch)
+        );
+        assert!(uri_path(&request).is_empty());
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat),
+            Some(Ok(OptionValueU16(110)))
+        );
+        let paths: Vec<Option<String>> = senml::from_json(&request.payload)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.name)
+            .collect();
+        assert_eq!(
+            paths,
+            vec![Some("/3/0/9".to_owned()), Some("/3303/0".to_owned())]
+        );
+        assert_eq!(result.unwrap(), values());
+    }
+
+    #[tokio::test]
+    async fn test_read_composite_cbor_response() {
+        let (server, mut device) = setup(Lwm2mVersion::V12).await;
+        let payload = senml::to_cbor(&[
+            SenmlRecord {
+                name: Some("/3/0/9".to_owned()),
+                value: Some(SenmlValue::Integer(87)),
+                ..Default::default()
+            },
+            SenmlRecord {
+                base_name: Some("/3303/0/".to_owned()),
+                name: Some("5700".to_owned()),
+                value: Some(SenmlValue::Float(21.5)),
+                ..Default::default()
+            },
+            SenmlRecord {
+                name: Some("5701".to_owned()),
+                value: Some(SenmlValue::String("Cel".to_owned())),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let (result, _) = tokio::join!(
+            server.read_composite(ENDPOINT, links()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::SenmlCbor),
+                &payload
+            ))
+        );
+        assert_eq!(result.unwrap(), values());
+    }
+
+    #[tokio::test]
+    async fn test_composite_registered_content_type() {
+        let (server, mut device) =
+            setup_with_objects(Lwm2mVersion::V11, "</>;ct=\"11542 112\",</3/0>,</3303/0>").await;
+        let payload = senml::to_cbor(&[SenmlRecord {
+            name: Some("/3/0/9".to_owned()),
+            value: Some(SenmlValue::Integer(87)),
+            ..Default::default()
+        }])
+        .unwrap();
+
+        let (result, request) = tokio::join!(
+            server.read_composite(ENDPOINT, links()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::SenmlCbor),
+                &payload
+            ))
+        );
+
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat),
+            Some(Ok(OptionValueU16(112)))
+        );
+        assert_eq!(senml::from_cbor(&request.payload).unwrap().len(), 2);
+        assert_eq!(result.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_composite_v10() {
+        let (server, mut device) = setup(Lwm2mVersion::V10).await;
+        let result = server.read_composite(ENDPOINT, links()).await;
+        assert!(matches!(
+            result,
+            Err(OperationError::UnsupportedVersion(Lwm2mVersion::V10))
+        ));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_composite_unknown_object() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let links = vec![CoreLink::try_from("</3304/0>").unwrap()];
+        let result = server.read_composite(ENDPOINT, links).await;
+        assert!(matches!(result, Err(OperationError::ModelNotFound(_))));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_observe_composite() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let mut notification = response(
+            ResponseType::Content,
+            Some(Lwm2mContentFormat::SenmlJson),
+            PAYLOAD,
+        );
+        notification.set_observe_value(1);
+
+        let (observation, request) = tokio::join!(
+            server.observe_composite(ENDPOINT, links()),
+            device.respond(notification.clone())
+        );
+        let mut observation = observation.unwrap();
+        assert_eq!(
+            request.header.code,
+            MessageClass::Request(RequestType::Fetch)
+        );
+        assert_eq!(request.get_observe_value(), Some(Ok(0)));
+        assert_eq!(observation.next().await.unwrap().unwrap(), values());
+
+        notification.set_observe_value(2);
+        notification.payload = br#"[{"n":"/3/0/9","v":86}]"#.to_vec();
+        device.notify(&request, notification);
+        assert_eq!(
+            observation.next().await.unwrap().unwrap(),
+            HashMap::from([(
+                CoreLink::try_from("</3/0/9>").unwrap(),
+                ResourceValue::Integer(86)
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_composite() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let values = HashMap::from([
+            (
+                CoreLink::try_from("</5/0/1>").unwrap(),
+                ResourceValue::String("coap://[::1]/fw".to_owned()),
+            ),
+            (
+                CoreLink::try_from("</3/0/13>").unwrap(),
+                ResourceValue::Time(1700000000),
+            ),
+        ]);
+
+        let (result, request) = tokio::join!(
+            server.write_composite(ENDPOINT, values),
+            device.respond(response(ResponseType::Changed, None, b""))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            request.header.code,
+            MessageClass::Request(RequestType::IPatch)
+        );
+        assert!(uri_path(&request).is_empty());
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat),
+            Some(Ok(OptionValueU16(110)))
+        );
+        assert_eq!(
+            senml::from_json(&request.payload).unwrap(),
+            vec![
+                SenmlRecord {
+                    name: Some("/3/0/13".to_owned()),
+                    value: Some(SenmlValue::Integer(1700000000)),
+                    ..Default::default()
+                },
+                SenmlRecord {
+                    name: Some("/5/0/1".to_owned()),
+                    value: Some(SenmlValue::String("coap://[::1]/fw".to_owned())),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_composite_refused() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let writable = (
+            CoreLink::try_from("</3/0/13>").unwrap(),
+            ResourceValue::Time(1700000000),
+        );
+
+        let read_only = HashMap::from([
+            writable.clone(),
+            (
+                CoreLink::try_from("</3/0/9>").unwrap(),
+                ResourceValue::Integer(87),
+            ),
+        ]);
+        let result = server.write_composite(ENDPOINT, read_only).await;
+        assert!(matches!(result, Err(OperationError::NotWritable(_))));
+
+        let wrong_type = HashMap::from([
+            writable.clone(),
+            (
+                CoreLink::try_from("</5/0/1>").unwrap(),
+                ResourceValue::Integer(1),
+            ),
+        ]);
+        let result = server.write_composite(ENDPOINT, wrong_type).await;
+        assert!(matches!(result, Err(OperationError::InvalidValue { .. })));
+
+        let instance = HashMap::from([
+            writable,
+            (
+                CoreLink::try_from("</5/0>").unwrap(),
+                ResourceValue::String("fw".to_owned()),
+            ),
+        ]);
+        let result = server.write_composite(ENDPOINT, instance).await;
+        assert!(matches!(result, Err(OperationError::InvalidLink { .. })));
+
+        let result = server.write_composite(ENDPOINT, HashMap::new()).await;
+        assert!(matches!(result, Err(OperationError::InvalidArgument(_))));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_write_composite_v10() {
+        let (server, mut device) = setup(Lwm2mVersion::V10).await;
+        let values = HashMap::from([(
+            CoreLink::try_from("</3/0/13>").unwrap(),
+            ResourceValue::Time(1700000000),
+        )]);
+        let result = server.write_composite(ENDPOINT, values).await;
+        assert!(matches!(
+            result,
+            Err(OperationError::UnsupportedVersion(Lwm2mVersion::V10))
+        ));
+        assert!(device.try_recv().is_none());
+    }
+}
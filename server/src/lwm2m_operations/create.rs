@@ -0,0 +1,366 @@
+use coap_lite::{option_value::OptionValueU16, CoapOption, Packet, RequestType, ResponseType};
+use object_model::{
+    core_link::CoreLink,
+    value::{ObjectInstance, ResourceValue},
+};
+use std::collections::HashMap;
+
+use super::{err::OperationError, new_request, Lwm2mServer};
+use crate::{
+    content_format::{self, Lwm2mContentFormat},
+    lwm2m_requests::registration_request::Lwm2mVersion,
+};
+
+impl Lwm2mServer {
+    /// Creates an object instance on a registered device and returns the id it was created with.
+    /// The values are checked against the object model before anything is sent,
+    /// every mandatory resource of the object needs a value.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `link` - The object instance to create, e.g. </3303/1>, or the object, e.g. </3303>,
+    ///   to let the device assign the id
+    /// * `values` - The initial values of the instance, keyed by the link of each resource (instance).
+    ///   When the device assigns the id, the instance id of these links is not sent
+    pub async fn create(
+        &self,
+        endpoint: &str,
+        link: CoreLink,
+        values: HashMap<CoreLink, ResourceValue>,
+    ) -> Result<u16, OperationError> {
+        let target = self.target(endpoint, &link).await?;
+        let model = self.object_model(&target, &link)?;
+        if link.resource_id.is_some() {
+            return Err(OperationError::InvalidLink {
+                link,
+                message: "only objects and object instances can be created".to_owned(),
+            });
+        }
+        let assigned_by_device = link.object_instance.is_none();
+        let instance_id = match link.object_instance {
+            Some(instance_id) => instance_id,
+            None => values_instance(&link, &values)?,
+        };
+        let instance_link = CoreLink::new(link.object_id, Some(instance_id), None, None);
+
+        let instances = self
+            .registry
+            .with_device(endpoint, |device| device.object_instances(link.object_id))
+            .await
+            .unwrap_or_default();
+        if let Some(existing) = instances.iter().find(|existing| {
+            (!assigned_by_device && **existing == instance_id) || !model.multiple()
+        }) {
+            return Err(OperationError::InstanceExists(CoreLink::new(
+                link.object_id,
+                Some(*existing),
+                None,
+                None,
+            )));
+        }
+
+        // Read-only resources can be set when the instance is created
+        for (value_link, value) in &values {
+            content_format::check_below(value_link, &instance_link)?;
+            model.validate_value(value_link, value)?;
+        }
+        let instance =
+            ObjectInstance::from_values(instance_id, &values, &model).map_err(|err| {
+                OperationError::InvalidValue {
+                    link: link.clone(),
+                    message: err.to_string(),
+                }
+            })?;
+        let mut mandatory: Vec<u16> = model
+            .resources()
+            .values()
+            .filter(|resource| resource.mandatory() && resource.resourcetype().is_some())
+            .map(|resource| resource.id())
+            .collect();
+        mandatory.sort_unstable();
+        if let Some(missing) = mandatory
+            .into_iter()
+            .find(|resource_id| !instance.resources().contains_key(resource_id))
+        {
+            return Err(OperationError::InvalidValue {
+                link: CoreLink::new(link.object_id, Some(instance_id), Some(missing), None),
+                message: "mandatory resource is missing".to_owned(),
+            });
+        }
+
+        let object_link = CoreLink::new(link.object_id, None, None, None);
+        let (format, payload_link) = if assigned_by_device {
+            // Only TLV can carry the resources of an instance without its id
+            let tlv = target.version == Lwm2mVersion::V10
+                || target.content_formats.contains(&Lwm2mContentFormat::Tlv);
+            if !tlv {
+                return Err(OperationError::InvalidLink {
+                    link,
+                    message: "the device has to support TLV to assign the instance id".to_owned(),
+                });
+            }
+            (Lwm2mContentFormat::Tlv, instance_link)
+        } else {
+            // Encoded below the object, so the payload carries the id of the instance to create
+            let format = content_format::preferred_format(
+                target.version,
+                &target.content_formats,
+                &link,
+                &model,
+            );
+            (format, object_link.clone())
+        };
+        let payload = content_format::encode(format, &values, &payload_link, &model)?;
+
+        let mut request = new_request(RequestType::Post, &object_link);
+        request.add_option_as(CoapOption::ContentFormat, OptionValueU16(format.into()));
+        request.payload = payload;
+        let response = self.send(&target, request).await?;
+        OperationError::check_response(&response, ResponseType::Created)?;
+
+        let instance_id = match created_instance(&response)? {
+            Some(instance_id) => instance_id,
+            None if assigned_by_device => {
+                return Err(OperationError::InvalidResponse(
+                    "Location-Path of the assigned instance is missing".to_owned(),
+                ))
+            }
+            None => instance_id,
+        };
+        self.registry
+            .with_device_mut(endpoint, |device| {
+                device.add_object_instance(link.object_id, instance_id)
+            })
+            .await;
+        Ok(instance_id)
+    }
+}
+
+// The values of an instance the device assigns the id of all share one placeholder instance id
+fn values_instance(
+    link: &CoreLink,
+    values: &HashMap<CoreLink, ResourceValue>,
+) -> Result<u16, OperationError> {
+    let mut instances = values.keys().map(|value_link| value_link.object_instance);
+    let instance_id = instances.next().flatten().unwrap_or_default();
+    if instances.any(|other| other != Some(instance_id)) {
+        return Err(OperationError::InvalidLink {
+            link: link.clone(),
+            message: "values of more than one instance".to_owned(),
+        });
+    }
+    Ok(instance_id)
+}
+
+// The device returns the Location-Path /{obj}/{inst} of the new instance
+// when it did not use the instance id of the payload.
+fn created_instance(response: &Packet) -> Result<Option<u16>, OperationError> {
+    let location = match response.get_option(CoapOption::LocationPath) {
+        Some(location) => location,
+        None => return Ok(None),
+    };
+    location
+        .back()
+        .and_then(|segment| std::str::from_utf8(segment).ok())
+        .and_then(|segment| segment.parse().ok())
+        .map(Some)
+        .ok_or_else(|| {
+            OperationError::InvalidResponse("Location-Path is not an object instance".to_owned())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lwm2m_operations::test_device::{
+        response, setup, setup_with_objects, uri_path, ENDPOINT,
+    };
+
+    #[test]
+    fn test_created_instance() {
+        let mut created = response(ResponseType::Created, None, b"");
+        assert_eq!(created_instance(&created).unwrap(), None);
+
+        created.add_option(CoapOption::LocationPath, b"3303".to_vec());
+        created.add_option(CoapOption::LocationPath, b"2".to_vec());
+        assert_eq!(created_instance(&created).unwrap(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_create_tlv() {
+        let (server, mut device) = setup(Lwm2mVersion::V10).await;
+        let link = CoreLink::try_from("</3303/1>").unwrap();
+        let values = HashMap::from([(
+            CoreLink::try_from("</3303/1/5700>").unwrap(),
+            ResourceValue::Float(21.5),
+        )]);
+        let mut created = response(ResponseType::Created, None, b"");
+        created.add_option(CoapOption::LocationPath, b"3303".to_vec());
+        created.add_option(CoapOption::LocationPath, b"1".to_vec());
+
+        let (result, request) = tokio::join!(
+            server.create(ENDPOINT, link, values),
+            device.respond(created)
+        );
+
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(uri_path(&request), vec!["3303"]);
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat),
+            Some(Ok(OptionValueU16(11542)))
+        );
+        // Object instance 1 holding resource 5700 as a single precision float
+        assert_eq!(
+            request.payload,
+            vec![0x07, 0x01, 0xE4, 0x16, 0x44, 0x41, 0xAC, 0x00, 0x00]
+        );
+        let instances = server
+            .registry
+            .with_device(ENDPOINT, |device| device.object_instances(3303))
+            .await;
+        assert_eq!(instances, Some(vec![1]));
+    }
+
+    #[tokio::test]
+    async fn test_create_senml() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3303/0>").unwrap();
+        let values = HashMap::from([(
+            CoreLink::try_from("</3303/0/5700>").unwrap(),
+            ResourceValue::Float(21.5),
+        )]);
+
+        let (result, request) = tokio::join!(
+            server.create(ENDPOINT, link, values),
+            device.respond(response(ResponseType::Created, None, b""))
+        );
+
+        // Without a Location-Path the device used the instance id of the payload
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(uri_path(&request), vec!["3303"]);
+        assert_eq!(request.payload, br#"[{"n":"/3303/0/5700","v":21.5}]"#);
+    }
+
+    #[tokio::test]
+    async fn test_create_assigned_by_device() {
+        let (server, mut device) = setup(Lwm2mVersion::V10).await;
+        let link = CoreLink::try_from("</3303>").unwrap();
+        let values = HashMap::from([(
+            CoreLink::try_from("</3303/0/5700>").unwrap(),
+            ResourceValue::Float(21.5),
+        )]);
+        let mut created = response(ResponseType::Created, None, b"");
+        created.add_option(CoapOption::LocationPath, b"3303".to_vec());
+        created.add_option(CoapOption::LocationPath, b"4".to_vec());
+
+        let (result, request) = tokio::join!(
+            server.create(ENDPOINT, link, values),
+            device.respond(created)
+        );
+
+        assert_eq!(result.unwrap(), 4);
+        assert_eq!(uri_path(&request), vec!["3303"]);
+        // Resource 5700 without an object instance around it
+        assert_eq!(
+            request.payload,
+            vec![0xE4, 0x16, 0x44, 0x41, 0xAC, 0x00, 0x00]
+        );
+        let instances = server
+            .registry
+            .with_device(ENDPOINT, |device| device.object_instances(3303))
+            .await;
+        assert_eq!(instances, Some(vec![4]));
+    }
+
+    #[tokio::test]
+    async fn test_create_assigned_without_location() {
+        let (server, mut device) = setup(Lwm2mVersion::V10).await;
+        let values = HashMap::from([(
+            CoreLink::try_from("</3303/0/5700>").unwrap(),
+            ResourceValue::Float(21.5),
+        )]);
+
+        let (result, _) = tokio::join!(
+            server.create(ENDPOINT, CoreLink::try_from("</3303>").unwrap(), values),
+            device.respond(response(ResponseType::Created, None, b""))
+        );
+        assert!(matches!(result, Err(OperationError::InvalidResponse(_))));
+    }
+
+    #[tokio::test]
+    async fn test_create_assigned_without_tlv() {
+        let (server, mut device) = setup_with_objects(Lwm2mVersion::V11, "</>;ct=110,</3/0>").await;
+        let values = HashMap::from([(
+            CoreLink::try_from("</3303/0/5700>").unwrap(),
+            ResourceValue::Float(21.5),
+        )]);
+
+        let result = server
+            .create(ENDPOINT, CoreLink::try_from("</3303>").unwrap(), values)
+            .await;
+        assert!(matches!(result, Err(OperationError::InvalidLink { .. })));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_single_instance_object() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/1>").unwrap();
+        let values = HashMap::from([(
+            CoreLink::try_from("</3/1/14>").unwrap(),
+            ResourceValue::String("+02".into()),
+        )]);
+
+        let result = server.create(ENDPOINT, link, values).await;
+        assert!(matches!(result, Err(OperationError::InstanceExists(_))));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_existing_instance() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        server
+            .registry
+            .with_device_mut(ENDPOINT, |device| device.add_object_instance(3303, 0))
+            .await;
+        let link = CoreLink::try_from("</3303/0>").unwrap();
+        let values = HashMap::from([(
+            CoreLink::try_from("</3303/0/5700>").unwrap(),
+            ResourceValue::Float(21.5),
+        )]);
+
+        let result = server.create(ENDPOINT, link, values).await;
+        assert!(matches!(result, Err(OperationError::InstanceExists(_))));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_missing_mandatory_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3303/0>").unwrap();
+        let values = HashMap::from([(
+            CoreLink::try_from("</3303/0/5701>").unwrap(),
+            ResourceValue::String("Cel".into()),
+        )]);
+
+        let result = server.create(ENDPOINT, link, values).await;
+        match result {
+            Err(OperationError::InvalidValue { link, .. }) => {
+                assert_eq!(link, CoreLink::try_from("</3303/0/5700>").unwrap())
+            }
+            _ => panic!("Expected the missing mandatory resource, got {:?}", result),
+        }
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_resource_link() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3303/0/5700>").unwrap();
+
+        let result = server.create(ENDPOINT, link, HashMap::new()).await;
+        assert!(matches!(result, Err(OperationError::InvalidLink { .. })));
+        assert!(device.try_recv().is_none());
+    }
+}
@@ -0,0 +1,100 @@
+use coap_lite::{RequestType, ResponseType};
+use object_model::core_link::CoreLink;
+
+use super::{err::OperationError, new_request, Lwm2mServer};
+
+impl Lwm2mServer {
+    /// Deletes an object instance of a registered device.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `link` - The object instance to delete, e.g. </3303/1>
+    pub async fn delete(&self, endpoint: &str, link: CoreLink) -> Result<(), OperationError> {
+        let target = self.target(endpoint, &link).await?;
+        // Makes sure the object is known, even though its model is not needed for a delete
+        self.object_model(&target, &link)?;
+        let instance_id = match (link.object_instance, link.resource_id) {
+            (Some(instance_id), None) => instance_id,
+            _ => {
+                return Err(OperationError::InvalidLink {
+                    link,
+                    message: "only object instances can be deleted".to_owned(),
+                })
+            }
+        };
+
+        let request = new_request(RequestType::Delete, &link);
+        let response = self.send(&target, request).await?;
+        OperationError::check_response(&response, ResponseType::Deleted)?;
+
+        self.registry
+            .with_device_mut(endpoint, |device| {
+                device.remove_object_instance(link.object_id, instance_id)
+            })
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lwm2m_operations::test_device::{response, setup, uri_path, ENDPOINT},
+        lwm2m_requests::registration_request::Lwm2mVersion,
+    };
+    use coap_lite::MessageClass;
+
+    #[tokio::test]
+    async fn test_delete_instance() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        server
+            .registry
+            .with_device_mut(ENDPOINT, |device| device.add_object_instance(3303, 1))
+            .await;
+        let link = CoreLink::try_from("</3303/1>").unwrap();
+
+        let (result, request) = tokio::join!(
+            server.delete(ENDPOINT, link),
+            device.respond(response(ResponseType::Deleted, None, b""))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            request.header.code,
+            MessageClass::Request(RequestType::Delete)
+        );
+        assert_eq!(uri_path(&request), vec!["3303", "1"]);
+        let instances = server
+            .registry
+            .with_device(ENDPOINT, |device| device.object_instances(3303))
+            .await;
+        assert_eq!(instances, Some(vec![]));
+    }
+
+    #[tokio::test]
+    async fn test_delete_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let result = server
+            .delete(ENDPOINT, CoreLink::try_from("</3/0/13>").unwrap())
+            .await;
+        assert!(matches!(result, Err(OperationError::InvalidLink { .. })));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delete_error_response() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (result, _) = tokio::join!(
+            server.delete(ENDPOINT, CoreLink::try_from("</3/0>").unwrap()),
+            device.respond(response(ResponseType::MethodNotAllowed, None, b""))
+        );
+        assert!(matches!(result, Err(OperationError::MethodNotAllowed)));
+        let instances = server
+            .registry
+            .with_device(ENDPOINT, |device| device.object_instances(3))
+            .await;
+        assert_eq!(instances, Some(vec![0]));
+    }
+}
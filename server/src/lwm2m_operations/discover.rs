@@ -0,0 +1,255 @@
+use coap_lite::{option_value::OptionValueU16, CoapOption, RequestType, ResponseType};
+use object_model::core_link::CoreLink;
+use std::{collections::BTreeMap, str};
+
+use super::{err::OperationError, new_request, response_format, Lwm2mServer};
+use crate::{
+    content_format::Lwm2mContentFormat,
+    lwm2m_requests::{attributes::Lwm2mAttribute, registration_request::parse_link_format},
+};
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#5-4-2-0-542-Discover-Operation
+/// An object as discovered on a device, with the attributes attached to each level.
+#[derive(Debug, Default)]
+pub struct DiscoveredObject {
+    pub id: u16,
+    pub attributes: Vec<Lwm2mAttribute>,
+    pub instances: BTreeMap<u16, DiscoveredInstance>,
+}
+
+#[derive(Debug, Default)]
+pub struct DiscoveredInstance {
+    pub id: u16,
+    pub attributes: Vec<Lwm2mAttribute>,
+    pub resources: BTreeMap<u16, DiscoveredResource>,
+}
+
+#[derive(Debug, Default)]
+pub struct DiscoveredResource {
+    pub id: u16,
+    // Includes the dimension (dim) of multiple resources
+    pub attributes: Vec<Lwm2mAttribute>,
+    // Attributes per resource instance, only reported by LwM2M 1.1 and later
+    pub instances: BTreeMap<u16, Vec<Lwm2mAttribute>>,
+}
+
+impl DiscoveredObject {
+    // Adds a link of the discover response, creating the levels above it if they were not listed.
+    fn add(&mut self, link: &CoreLink, attributes: Vec<Lwm2mAttribute>) {
+        let instance_id = match link.object_instance {
+            Some(instance_id) => instance_id,
+            None => {
+                self.attributes = attributes;
+                return;
+            }
+        };
+        let instance = self
+            .instances
+            .entry(instance_id)
+            .or_insert_with(|| DiscoveredInstance {
+                id: instance_id,
+                ..Default::default()
+            });
+
+        let resource_id = match link.resource_id {
+            Some(resource_id) => resource_id,
+            None => {
+                instance.attributes = attributes;
+                return;
+            }
+        };
+        let resource =
+            instance
+                .resources
+                .entry(resource_id)
+                .or_insert_with(|| DiscoveredResource {
+                    id: resource_id,
+                    ..Default::default()
+                });
+
+        match link.resource_instance {
+            Some(resource_instance) => {
+                resource.instances.insert(resource_instance, attributes);
+            }
+            None => resource.attributes = attributes,
+        }
+    }
+}
+
+impl Lwm2mServer {
+    /// Discovers the instances, resources and attached attributes of an object,
+    /// object instance or resource on a registered device.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `link` - The link to discover, e.g. </3> or </3/0>
+    pub async fn discover(
+        &self,
+        endpoint: &str,
+        link: CoreLink,
+    ) -> Result<DiscoveredObject, OperationError> {
+        let target = self.target(endpoint, &link).await?;
+
+        let mut request = new_request(RequestType::Get, &link);
+        request.add_option_as(
+            CoapOption::Accept,
+            OptionValueU16(Lwm2mContentFormat::LinkFormat.into()),
+        );
+        let response = self.send(&target, request).await?;
+        OperationError::check_response(&response, ResponseType::Content)?;
+        if response_format(&response, Lwm2mContentFormat::LinkFormat)?
+            != Lwm2mContentFormat::LinkFormat
+        {
+            return Err(OperationError::InvalidResponse(
+                "discover response is not link-format".to_owned(),
+            ));
+        }
+
+        let payload = str::from_utf8(&response.payload)
+            .map_err(|_| OperationError::InvalidResponse("unreadable utf8 content".to_owned()))?;
+        let links = parse_link_format(payload)
+            .map_err(|err| OperationError::InvalidResponse(err.message))?;
+
+        let mut object = DiscoveredObject {
+            id: link.object_id,
+            ..Default::default()
+        };
+        for discovered in links {
+            let discovered_link = discovered.link().ok_or_else(|| {
+                OperationError::InvalidResponse("link is not an object (instance)".to_owned())
+            })?;
+            if !discovered_link.ids().starts_with(&link.ids()) {
+                return Err(OperationError::InvalidResponse(format!(
+                    "{} is not part of {}",
+                    discovered_link, link
+                )));
+            }
+            object.add(&discovered_link, discovered.into_attributes());
+        }
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lwm2m_operations::test_device::{response, setup, uri_path, ENDPOINT},
+        lwm2m_requests::registration_request::Lwm2mVersion,
+    };
+
+    #[tokio::test]
+    async fn test_discover_object() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let payload = "</3>;ver=1.1,</3/0>;pmin=10,</3/0/1>,</3/0/7>;dim=2;gt=50,\
+                       </3/0/7/0>,</3/0/7/1>;lt=3.5";
+
+        let (result, request) = tokio::join!(
+            server.discover(ENDPOINT, CoreLink::try_from("</3>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::LinkFormat),
+                payload.as_bytes()
+            ))
+        );
+
+        assert_eq!(uri_path(&request), vec!["3"]);
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::Accept),
+            Some(Ok(OptionValueU16(40)))
+        );
+
+        let object = result.unwrap();
+        assert_eq!(object.id, 3);
+        assert!(matches!(
+            object.attributes[..],
+            [Lwm2mAttribute::ObjectVersion(ref version)] if version == "1.1"
+        ));
+        let instance = &object.instances[&0];
+        assert!(matches!(
+            instance.attributes[..],
+            [Lwm2mAttribute::MinPeriod(10)]
+        ));
+        assert_eq!(
+            instance.resources.keys().copied().collect::<Vec<u16>>(),
+            vec![1, 7]
+        );
+        let resource = &instance.resources[&7];
+        assert!(matches!(
+            resource.attributes[..],
+            [Lwm2mAttribute::Dimension(2), Lwm2mAttribute::GreaterThan(gt)] if gt == 50.0
+        ));
+        assert!(resource.instances[&0].is_empty());
+        assert!(matches!(
+            resource.instances[&1][..],
+            [Lwm2mAttribute::LessThan(lt)] if lt == 3.5
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_discover_instance_without_object_link() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+
+        let (result, _) = tokio::join!(
+            server.discover(ENDPOINT, CoreLink::try_from("</3/0>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::LinkFormat),
+                b"</3/0/13>;pmax=60"
+            ))
+        );
+
+        let object = result.unwrap();
+        assert!(object.attributes.is_empty());
+        assert!(matches!(
+            object.instances[&0].resources[&13].attributes[..],
+            [Lwm2mAttribute::MaxPeriod(60)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_discover_foreign_object() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+
+        let (result, _) = tokio::join!(
+            server.discover(ENDPOINT, CoreLink::try_from("</3>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::LinkFormat),
+                b"</3/0>,</5/0>"
+            ))
+        );
+        assert!(matches!(result, Err(OperationError::InvalidResponse(_))));
+    }
+
+    #[tokio::test]
+    async fn test_discover_foreign_instance() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+
+        let (result, _) = tokio::join!(
+            server.discover(ENDPOINT, CoreLink::try_from("</3/0/7>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::LinkFormat),
+                b"</3/0/7>;dim=1,</3/1/7>"
+            ))
+        );
+        assert!(matches!(result, Err(OperationError::InvalidResponse(_))));
+    }
+
+    #[tokio::test]
+    async fn test_discover_invalid_attribute() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+
+        let (result, _) = tokio::join!(
+            server.discover(ENDPOINT, CoreLink::try_from("</3>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::LinkFormat),
+                b"</3/0>;pmin=soon"
+            ))
+        );
+        assert!(matches!(result, Err(OperationError::InvalidResponse(_))));
+    }
+}
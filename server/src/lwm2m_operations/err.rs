@@ -0,0 +1,157 @@
+use coap_lite::{MessageClass, Packet, ResponseType};
+use object_model::{
+    core_link::CoreLink,
+    err::{ModelNotFoundError, ValidationError},
+};
+use std::{error::Error, fmt};
+
+use crate::{
+    content_format::CodecError, lwm2m_requests::registration_request::Lwm2mVersion,
+    transport::client::ClientError,
+};
+
+#[derive(Debug)]
+pub enum OperationError {
+    DeviceNotRegistered(String),
+    ModelNotFound(ModelNotFoundError),
+    Client(ClientError),
+    Codec(CodecError),
+    // The operation was refused before anything was sent to the device
+    NotWritable(CoreLink),
+    NotExecutable(CoreLink),
+    InvalidValue { link: CoreLink, message: String },
+    InvalidArgument(String),
+    InvalidAttribute(String),
+    InvalidLink { link: CoreLink, message: String },
+    InstanceExists(CoreLink),
+    UnsupportedVersion(Lwm2mVersion),
+    // Error responses of the device
+    BadRequest,
+    Unauthorized,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    UnsupportedContentFormat,
+    InternalServerError,
+    UnexpectedResponse(ResponseType),
+    InvalidResponse(String),
+}
+
+impl OperationError {
+    /// Maps the response code of a device to an error, `expected` is the code on success.
+    pub fn check_response(response: &Packet, expected: ResponseType) -> Result<(), Self> {
+        let code = match response.header.code {
+            MessageClass::Response(code) => code,
+            _ => ResponseType::UnKnown,
+        };
+        match code {
+            code if code == expected => Ok(()),
+            ResponseType::BadRequest => Err(OperationError::BadRequest),
+            ResponseType::Unauthorized => Err(OperationError::Unauthorized),
+            ResponseType::NotFound => Err(OperationError::NotFound),
+            ResponseType::MethodNotAllowed => Err(OperationError::MethodNotAllowed),
+            ResponseType::NotAcceptable => Err(OperationError::NotAcceptable),
+            ResponseType::UnsupportedContentFormat => Err(OperationError::UnsupportedContentFormat),
+            ResponseType::InternalServerError => Err(OperationError::InternalServerError),
+            code => Err(OperationError::UnexpectedResponse(code)),
+        }
+    }
+}
+
+impl fmt::Display for OperationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            OperationError::DeviceNotRegistered(endpoint) => {
+                write!(f, "Device {} is not registered", endpoint)
+            }
+            OperationError::ModelNotFound(err) => write!(f, "{}", err),
+            OperationError::Client(err) => write!(f, "{}", err),
+            OperationError::Codec(err) => write!(f, "{}", err),
+            OperationError::NotWritable(link) => write!(f, "Resource {} is not writable", link),
+            OperationError::NotExecutable(link) => {
+                write!(f, "Resource {} is not executable", link)
+            }
+            OperationError::InvalidValue { link, message } => {
+                write!(f, "Invalid value for {}: {}", link, message)
+            }
+            OperationError::InvalidArgument(message) => {
+                write!(f, "Invalid argument: {}", message)
+            }
+            OperationError::InvalidAttribute(message) => {
+                write!(f, "Invalid attribute: {}", message)
+            }
+            OperationError::InvalidLink { link, message } => {
+                write!(f, "Operation not possible on {}: {}", link, message)
+            }
+            OperationError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "Operation is not supported by LwM2M {:?} devices",
+                    version
+                )
+            }
+            OperationError::InstanceExists(link) => {
+                write!(f, "Object instance {} already exists", link)
+            }
+            OperationError::BadRequest => write!(f, "Device responded with 4.00 Bad Request"),
+            OperationError::Unauthorized => write!(f, "Device responded with 4.01 Unauthorized"),
+            OperationError::NotFound => write!(f, "Device responded with 4.04 Not Found"),
+            OperationError::MethodNotAllowed => {
+                write!(f, "Device responded with 4.05 Method Not Allowed")
+            }
+            OperationError::NotAcceptable => {
+                write!(f, "Device responded with 4.06 Not Acceptable")
+            }
+            OperationError::UnsupportedContentFormat => {
+                write!(f, "Device responded with 4.15 Unsupported Content Format")
+            }
+            OperationError::InternalServerError => {
+                write!(f, "Device responded with 5.00 Internal Server Error")
+            }
+            OperationError::UnexpectedResponse(code) => {
+                write!(f, "Device responded with unexpected code {:?}", code)
+            }
+            OperationError::InvalidResponse(message) => {
+                write!(f, "Invalid response of the device: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for OperationError {}
+
+impl From<ModelNotFoundError> for OperationError {
+    fn from(err: ModelNotFoundError) -> Self {
+        OperationError::ModelNotFound(err)
+    }
+}
+
+impl From<ValidationError> for OperationError {
+    fn from(err: ValidationError) -> Self {
+        let message = err.to_string();
+        match err {
+            ValidationError::ModelNotFound(err) => OperationError::ModelNotFound(err),
+            ValidationError::NotWritable { link, .. } => OperationError::NotWritable(link),
+            ValidationError::UnknownResource(link)
+            | ValidationError::NoType(link)
+            | ValidationError::MissingResourceInstance(link)
+            | ValidationError::UnexpectedResourceInstance(link)
+            | ValidationError::WrongType { link, .. }
+            | ValidationError::OutOfRange { link, .. } => {
+                OperationError::InvalidValue { link, message }
+            }
+        }
+    }
+}
+
+impl From<ClientError> for OperationError {
+    fn from(err: ClientError) -> Self {
+        OperationError::Client(err)
+    }
+}
+
+impl From<CodecError> for OperationError {
+    fn from(err: CodecError) -> Self {
+        OperationError::Codec(err)
+    }
+}
@@ -0,0 +1,194 @@
+use coap_lite::{RequestType, ResponseType};
+use object_model::{core_link::CoreLink, ResourceOperation};
+use std::fmt;
+
+use super::{err::OperationError, new_request, Lwm2mServer};
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#5-4-3-0-543-Execute-Operation
+// An argument is a single digit, optionally followed by a quoted value: 0='value',1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecuteArgument {
+    digit: u8,
+    value: Option<String>,
+}
+
+impl ExecuteArgument {
+    /// Creates an argument, `digit` must be 0-9 and `value` may only contain
+    /// printable characters other than space, ", ' and \.
+    pub fn new(digit: u8, value: Option<&str>) -> Result<Self, OperationError> {
+        if digit > 9 {
+            return Err(OperationError::InvalidArgument(format!(
+                "{} is not a single digit",
+                digit
+            )));
+        }
+        if let Some(value) = value {
+            if let Some(c) = value
+                .chars()
+                .find(|c| !c.is_ascii_graphic() || matches!(c, '"' | '\'' | '\\'))
+            {
+                return Err(OperationError::InvalidArgument(format!(
+                    "{:?} is not allowed in the value of argument {}",
+                    c, digit
+                )));
+            }
+        }
+        Ok(ExecuteArgument {
+            digit,
+            value: value.map(str::to_owned),
+        })
+    }
+}
+
+impl fmt::Display for ExecuteArgument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}='{}'", self.digit, value),
+            None => write!(f, "{}", self.digit),
+        }
+    }
+}
+
+impl Lwm2mServer {
+    /// Executes a resource of a registered device, e.g. a reboot through </3/0/4>.
+    /// Resources the object model does not mark as executable are refused.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `link` - The resource to execute
+    /// * `args` - The arguments to send along, may be empty
+    pub async fn execute(
+        &self,
+        endpoint: &str,
+        link: CoreLink,
+        args: &[ExecuteArgument],
+    ) -> Result<(), OperationError> {
+        let target = self.target(endpoint, &link).await?;
+        let model = self.object_model(&target, &link)?;
+
+        let operations = match (link.object_instance, link.resource_instance) {
+            (Some(_), None) => link
+                .resource_id
+                .and_then(|resource_id| model.resources().get(&resource_id))
+                .and_then(|resource| resource.operations()),
+            _ => None,
+        };
+        if operations != Some(ResourceOperation::Execute) {
+            return Err(OperationError::NotExecutable(link));
+        }
+        let mut digits: Vec<u8> = args.iter().map(|arg| arg.digit).collect();
+        digits.sort_unstable();
+        digits.dedup();
+        if digits.len() != args.len() {
+            return Err(OperationError::InvalidArgument(
+                "every argument digit may only be used once".to_owned(),
+            ));
+        }
+
+        let mut request = new_request(RequestType::Post, &link);
+        request.payload = args
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<String>>()
+            .join(",")
+            .into_bytes();
+        let response = self.send(&target, request).await?;
+        OperationError::check_response(&response, ResponseType::Changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lwm2m_operations::test_device::{response, setup, uri_path, ENDPOINT},
+        lwm2m_requests::registration_request::Lwm2mVersion,
+    };
+    use coap_lite::MessageClass;
+
+    #[test]
+    fn test_argument_format() {
+        assert_eq!(
+            ExecuteArgument::new(0, Some("value")).unwrap().to_string(),
+            "0='value'"
+        );
+        assert_eq!(ExecuteArgument::new(1, None).unwrap().to_string(), "1");
+        assert!(ExecuteArgument::new(10, None).is_err());
+        assert!(ExecuteArgument::new(0, Some("it's")).is_err());
+        assert!(ExecuteArgument::new(0, Some("two words")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_reboot() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/4>").unwrap();
+
+        let (result, request) = tokio::join!(
+            server.execute(ENDPOINT, link, &[]),
+            device.respond(response(ResponseType::Changed, None, b""))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            request.header.code,
+            MessageClass::Request(RequestType::Post)
+        );
+        assert_eq!(uri_path(&request), vec!["3", "0", "4"]);
+        assert!(request.payload.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_arguments() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</5/0/2>").unwrap();
+        let args = [
+            ExecuteArgument::new(0, Some("value")).unwrap(),
+            ExecuteArgument::new(1, None).unwrap(),
+        ];
+
+        let (result, request) = tokio::join!(
+            server.execute(ENDPOINT, link, &args),
+            device.respond(response(ResponseType::Changed, None, b""))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(request.payload, b"0='value',1".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_execute_not_executable() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        for link in ["</3/0/13>", "</3/0>", "</3/0/4/0>"] {
+            let result = server
+                .execute(ENDPOINT, CoreLink::try_from(link).unwrap(), &[])
+                .await;
+            assert!(matches!(result, Err(OperationError::NotExecutable(_))));
+        }
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_execute_duplicate_arguments() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let args = [
+            ExecuteArgument::new(1, None).unwrap(),
+            ExecuteArgument::new(1, Some("again")).unwrap(),
+        ];
+        let result = server
+            .execute(ENDPOINT, CoreLink::try_from("</5/0/2>").unwrap(), &args)
+            .await;
+        assert!(matches!(result, Err(OperationError::InvalidArgument(_))));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_execute_error_response() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (result, _) = tokio::join!(
+            server.execute(ENDPOINT, CoreLink::try_from("</3/0/4>").unwrap(), &[]),
+            device.respond(response(ResponseType::MethodNotAllowed, None, b""))
+        );
+        assert!(matches!(result, Err(OperationError::MethodNotAllowed)));
+    }
+}
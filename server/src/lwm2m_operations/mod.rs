@@ -0,0 +1,129 @@
+use coap_lite::{option_value::OptionValueU16, CoapOption, MessageClass, Packet, RequestType};
+use object_model::{core_link::CoreLink, ObjectModel, ObjectModelStore, Version};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    content_format::Lwm2mContentFormat,
+    device::registry::DeviceRegistry,
+    lwm2m_requests::registration_request::Lwm2mVersion,
+    transport::{client::CoapClient, Peer},
+};
+use err::OperationError;
+
+pub mod composite;
+mod create;
+mod delete;
+pub mod discover;
+pub mod err;
+pub mod execute;
+pub mod observe;
+mod read;
+#[cfg(test)]
+pub(crate) mod test_device;
+pub mod write;
+pub mod write_attributes;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#6-3-0-63-Device-Management-and-Service-Enablement-Interface
+/// Sends operations from the server to registered devices.
+pub struct Lwm2mServer {
+    registry: Arc<DeviceRegistry>,
+    models: Arc<ObjectModelStore>,
+    client: CoapClient,
+}
+
+// What an operation needs to know about the device it is sent to
+struct Target {
+    peer: Peer,
+    version: Lwm2mVersion,
+    // The formats the device listed with ct when it registered
+    content_formats: Vec<Lwm2mContentFormat>,
+    object_version: Version,
+}
+
+impl Lwm2mServer {
+    pub fn new(
+        registry: Arc<DeviceRegistry>,
+        models: Arc<ObjectModelStore>,
+        client: CoapClient,
+    ) -> Self {
+        Lwm2mServer {
+            registry,
+            models,
+            client,
+        }
+    }
+
+    // Looks up the registered device an operation on `link` is sent to
+    async fn target(&self, endpoint: &str, link: &CoreLink) -> Result<Target, OperationError> {
+        self.registry
+            .with_device(endpoint, |device| Target {
+                peer: device.peer(),
+                version: device.version(),
+                content_formats: device.content_formats(),
+                object_version: device.object_version(link.object_id),
+            })
+            .await
+            .ok_or_else(|| OperationError::DeviceNotRegistered(endpoint.to_owned()))
+    }
+
+    // Model of the object `link` points to, in the version the device registered it with
+    fn object_model(
+        &self,
+        target: &Target,
+        link: &CoreLink,
+    ) -> Result<ObjectModel, OperationError> {
+        self.model(link.object_id, target.object_version.clone())
+    }
+
+    // Models of every object the links point to, in the versions the device registered them with
+    async fn object_models(
+        &self,
+        endpoint: &str,
+        links: &[CoreLink],
+    ) -> Result<HashMap<u16, ObjectModel>, OperationError> {
+        let versions: Vec<(u16, Version)> = self
+            .registry
+            .with_device(endpoint, |device| {
+                links
+                    .iter()
+                    .map(|link| (link.object_id, device.object_version(link.object_id)))
+                    .collect()
+            })
+            .await
+            .ok_or_else(|| OperationError::DeviceNotRegistered(endpoint.to_owned()))?;
+        versions
+            .into_iter()
+            .map(|(object_id, version)| Ok((object_id, self.model(object_id, version)?)))
+            .collect()
+    }
+
+    fn model(&self, object_id: u16, version: Version) -> Result<ObjectModel, OperationError> {
+        Ok(self.models.get_object_model(object_id, Some(version))?)
+    }
+
+    async fn send(&self, target: &Target, request: Packet) -> Result<Packet, OperationError> {
+        Ok(self.client.send(request, target.peer.clone()).await?)
+    }
+}
+
+// Creates a request for `link`, the client takes care of the message type, ID and token.
+fn new_request(method: RequestType, link: &CoreLink) -> Packet {
+    let mut request = Packet::new();
+    request.header.code = MessageClass::Request(method);
+    for id in link.ids() {
+        request.add_option(CoapOption::UriPath, id.to_string().into_bytes());
+    }
+    request
+}
+
+// Content format of a response, `default` is used when the device did not set one.
+fn response_format(
+    response: &Packet,
+    default: Lwm2mContentFormat,
+) -> Result<Lwm2mContentFormat, OperationError> {
+    match response.get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat) {
+        Some(Ok(format)) => Ok(Lwm2mContentFormat::try_from(format.0)?),
+        Some(Err(_)) => Err(OperationError::UnsupportedContentFormat),
+        None => Ok(default),
+    }
+}
@@ -0,0 +1,514 @@
+use coap_lite::{option_value::OptionValueU16, CoapOption, Packet, RequestType, ResponseType};
+use futures::Stream;
+use object_model::{core_link::CoreLink, value::ResourceValue, ObjectModel};
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot},
+    time::Instant,
+};
+
+use super::{err::OperationError, new_request, response_format, Lwm2mServer};
+use crate::{
+    content_format::{self, Lwm2mContentFormat},
+    device::registry::{DeviceRegistry, RegistrationEvent},
+    transport::{client::Exchange, Peer},
+};
+
+// Values of the Observe option in a request, https://datatracker.ietf.org/doc/html/rfc7641#section-2
+const OBSERVE_REGISTER: u32 = 0;
+const OBSERVE_DEREGISTER: u32 = 1;
+// Notifications older than this are always fresh, https://datatracker.ietf.org/doc/html/rfc7641#section-3.4
+const NOTIFICATION_MAX_AGE: Duration = Duration::from_secs(128);
+// Notifications waiting to be taken from an observation, when they are not taken fast enough
+// the newest one replaces those that did not fit
+const NOTIFICATION_BUFFER: usize = 16;
+
+type Notification = Result<HashMap<CoreLink, ResourceValue>, OperationError>;
+type CancelRequest = oneshot::Sender<Result<(), OperationError>>;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#6-4-0-64-Information-Reporting-Interface
+/// A stream of the decoded notifications of an observation, starting with the current value.
+/// Dropping it forgets the token, the device is told to stop with a reset on its next notification.
+/// The stream ends when the device de-registers, its registration expires
+/// or it responds with an error. A stream that is not read fast enough skips notifications.
+pub struct Observation {
+    notifications_rx: mpsc::Receiver<Notification>,
+    cancel_tx: mpsc::Sender<CancelRequest>,
+}
+
+impl Stream for Observation {
+    type Item = Notification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.notifications_rx.poll_recv(cx)
+    }
+}
+
+// Everything the task of an observation needs to decode notifications and cancel it
+struct Observer {
+    endpoint: String,
+    // The registration the observation belongs to, at `location` in `registry`
+    registry: Arc<DeviceRegistry>,
+    location: String,
+    // The observe request without the Observe option, sent again with Observe=1 to cancel
+    request: Packet,
+    accept: Lwm2mContentFormat,
+    decoder: Decoder,
+    peer: Peer,
+    exchange: Exchange,
+    // Observe sequence number and arrival of the last notification
+    last: Option<(u32, Instant)>,
+}
+
+// How the payload of a notification is decoded
+pub(super) enum Decoder {
+    Single { link: CoreLink, model: ObjectModel },
+    Composite { models: HashMap<u16, ObjectModel> },
+}
+
+impl Lwm2mServer {
+    /// Observes an object, object instance or resource of a registered device.
+    /// Returns once the device accepted the observation, its current value is the
+    /// first item of the returned stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `link` - The link to observe, e.g. </3/0/9>
+    pub async fn observe(
+        &self,
+        endpoint: &str,
+        link: CoreLink,
+    ) -> Result<Observation, OperationError> {
+        let target = self.target(endpoint, &link).await?;
+        let model = self.object_model(&target, &link)?;
+        let accept = content_format::preferred_format(
+            target.version,
+            &target.content_formats,
+            &link,
+            &model,
+        );
+
+        let mut request = new_request(RequestType::Get, &link);
+        request.add_option_as(CoapOption::Accept, OptionValueU16(accept.into()));
+        self.start_observation(
+            endpoint,
+            target.peer,
+            request,
+            accept,
+            Decoder::Single { link, model },
+        )
+        .await
+    }
+
+    /// Actively cancels an observation by sending a GET with Observe=1 in its exchange.
+    /// To cancel passively, drop the observation.
+    pub async fn cancel_observe(&self, observation: Observation) -> Result<(), OperationError> {
+        let (result_tx, result_rx) = oneshot::channel();
+        if observation.cancel_tx.send(result_tx).await.is_err() {
+            // The observation already ended
+            return Ok(());
+        }
+        result_rx.await.unwrap_or(Ok(()))
+    }
+
+    // Sends an observe request and hands its exchange to a task that forwards the notifications
+    pub(super) async fn start_observation(
+        &self,
+        endpoint: &str,
+        peer: Peer,
+        request: Packet,
+        accept: Lwm2mContentFormat,
+        decoder: Decoder,
+    ) -> Result<Observation, OperationError> {
+        // Subscribed before the request, so a de-registration in between is not missed
+        let events = self.registry.subscribe();
+        let location = self
+            .registry
+            .location(endpoint)
+            .await
+            .ok_or_else(|| OperationError::DeviceNotRegistered(endpoint.to_owned()))?;
+
+        let mut observer = Observer {
+            endpoint: endpoint.to_owned(),
+            registry: self.registry.clone(),
+            location,
+            request,
+            accept,
+            decoder,
+            peer,
+            exchange: self.client.open_exchange(),
+            last: None,
+        };
+        let response = observer
+            .exchange
+            .send(observer.request(OBSERVE_REGISTER), observer.peer.clone())
+            .await?;
+        OperationError::check_response(&response, ResponseType::Content)?;
+        let first = observer.decode(&response);
+        if response.get_observe_value().is_none() {
+            // The device returned the value, but will not send notifications
+            return Err(OperationError::InvalidResponse(
+                "device does not support observing this link".to_owned(),
+            ));
+        }
+
+        let (notifications_tx, notifications_rx) = mpsc::channel(NOTIFICATION_BUFFER);
+        let (cancel_tx, cancel_rx) = mpsc::channel(1);
+        let _ = notifications_tx.try_send(first);
+        tokio::spawn(observer.run(notifications_tx, cancel_rx, events));
+        Ok(Observation {
+            notifications_rx,
+            cancel_tx,
+        })
+    }
+}
+
+impl Observer {
+    fn request(&self, observe: u32) -> Packet {
+        let mut request = self.request.clone();
+        request.set_observe_value(observe);
+        request
+    }
+
+    fn decode(&mut self, response: &Packet) -> Notification {
+        if let Some(Ok(sequence)) = response.get_observe_value() {
+            self.last = Some((sequence, Instant::now()));
+        }
+        let format = response_format(response, self.accept)?;
+        let values = match &self.decoder {
+            Decoder::Single { link, model } => {
+                content_format::decode(format, &response.payload, link, model)?
+            }
+            Decoder::Composite { models } => {
+                content_format::decode_composite(format, &response.payload, models)?
+            }
+        };
+        Ok(values)
+    }
+
+    // Whether a notification is newer than the last one, https://datatracker.ietf.org/doc/html/rfc7641#section-3.4
+    fn is_fresh(&self, sequence: u32) -> bool {
+        let (last, received) = match self.last {
+            Some(last) => last,
+            None => return true,
+        };
+        const WRAP: u32 = 1 << 23;
+        (last < sequence && sequence - last < WRAP)
+            || (last > sequence && last - sequence > WRAP)
+            || received.elapsed() > NOTIFICATION_MAX_AGE
+    }
+
+    // Forwards notifications until the observation is cancelled or ends
+    async fn run(
+        mut self,
+        notifications_tx: mpsc::Sender<Notification>,
+        mut cancel_rx: mpsc::Receiver<CancelRequest>,
+        mut events: broadcast::Receiver<RegistrationEvent>,
+    ) {
+        // The newest notification that did not fit in the channel, sent once there is room
+        let mut pending = None;
+        loop {
+            tokio::select! {
+                packet = self.exchange.recv() => {
+                    let packet = match packet {
+                        Some(packet) => packet,
+                        None => break,
+                    };
+                    if let Err(err) = OperationError::check_response(&packet, ResponseType::Content) {
+                        // An error response ends the observation on the device as well
+                        forward(&notifications_tx, &mut pending, Err(err));
+                        break;
+                    }
+                    match packet.get_observe_value() {
+                        Some(Ok(sequence)) if !self.is_fresh(sequence) => continue,
+                        Some(Ok(_)) => {}
+                        // Without the option this is the final response of the observation
+                        _ => {
+                            forward(&notifications_tx, &mut pending, self.decode(&packet));
+                            break;
+                        }
+                    }
+                    forward(&notifications_tx, &mut pending, self.decode(&packet));
+                }
+                permit = notifications_tx.reserve(), if pending.is_some() => match (permit, pending.take()) {
+                    (Ok(permit), Some(notification)) => permit.send(notification),
+                    _ => break,
+                },
+                Some(result_tx) = cancel_rx.recv() => {
+                    let request = self.request(OBSERVE_DEREGISTER);
+                    let result = match self.exchange.send(request, self.peer.clone()).await {
+                        Ok(response) => {
+                            OperationError::check_response(&response, ResponseType::Content)
+                        }
+                        Err(err) => Err(err.into()),
+                    };
+                    let _ = result_tx.send(result);
+                    break;
+                }
+                event = events.recv() => match event {
+                    Ok(RegistrationEvent::Deregistered { device_endpoint, .. })
+                    | Ok(RegistrationEvent::Expired { device_endpoint, .. })
+                    // A new registration of the same device does not keep its observations
+                    | Ok(RegistrationEvent::Registered { device_endpoint, .. })
+                        if device_endpoint == self.endpoint => break,
+                    // Events were missed, the registration may have ended with one of them
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        let location = self.registry.location(&self.endpoint).await;
+                        if location.as_ref() != Some(&self.location) {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                    _ => {}
+                },
+                _ = notifications_tx.closed() => break,
+            }
+        }
+    }
+}
+
+// Hands a notification to the observation without waiting for it to be taken. While the
+// channel is full the newest notification waits in `pending`, replacing the one before.
+fn forward(
+    notifications_tx: &mpsc::Sender<Notification>,
+    pending: &mut Option<Notification>,
+    notification: Notification,
+) {
+    if pending.is_some() {
+        *pending = Some(notification);
+        return;
+    }
+    if let Err(mpsc::error::TrySendError::Full(notification)) =
+        notifications_tx.try_send(notification)
+    {
+        *pending = Some(notification);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lwm2m_operations::test_device::{response, setup, uri_path, TestDevice, ENDPOINT},
+        lwm2m_requests::registration_request::{
+            Lwm2mBindMode, Lwm2mRegistrationRequest, Lwm2mVersion,
+        },
+    };
+    use futures::StreamExt;
+
+    fn notification(sequence: u32, payload: &[u8]) -> Packet {
+        let mut packet = response(
+            ResponseType::Content,
+            Some(Lwm2mContentFormat::TextPlain),
+            payload,
+        );
+        packet.set_observe_value(sequence);
+        packet
+    }
+
+    async fn observe_battery(
+        server: &Lwm2mServer,
+        device: &mut TestDevice,
+    ) -> (Observation, Packet) {
+        let (observation, request) = tokio::join!(
+            server.observe(ENDPOINT, CoreLink::try_from("</3/0/9>").unwrap()),
+            device.respond(notification(1, b"90"))
+        );
+        (observation.unwrap(), request)
+    }
+
+    fn battery_level(level: i64) -> HashMap<CoreLink, ResourceValue> {
+        HashMap::from([(
+            CoreLink::try_from("</3/0/9>").unwrap(),
+            ResourceValue::Integer(level),
+        )])
+    }
+
+    #[tokio::test]
+    async fn test_observe() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (mut observation, request) = observe_battery(&server, &mut device).await;
+
+        assert_eq!(uri_path(&request), vec!["3", "0", "9"]);
+        assert_eq!(request.get_observe_value(), Some(Ok(OBSERVE_REGISTER)));
+        assert_eq!(
+            observation.next().await.unwrap().unwrap(),
+            battery_level(90)
+        );
+
+        device.notify(&request, notification(2, b"89"));
+        device.notify(&request, notification(3, b"88"));
+        assert_eq!(
+            observation.next().await.unwrap().unwrap(),
+            battery_level(89)
+        );
+        assert_eq!(
+            observation.next().await.unwrap().unwrap(),
+            battery_level(88)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stale_notifications_are_dropped() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (mut observation, request) = observe_battery(&server, &mut device).await;
+        observation.next().await;
+
+        device.notify(&request, notification(5, b"85"));
+        // Reordered in the network, older than the one before
+        device.notify(&request, notification(4, b"86"));
+        device.notify(&request, notification(6, b"84"));
+        assert_eq!(
+            observation.next().await.unwrap().unwrap(),
+            battery_level(85)
+        );
+        assert_eq!(
+            observation.next().await.unwrap().unwrap(),
+            battery_level(84)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sequence_number_wraps() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (observation, request) = tokio::join!(
+            server.observe(ENDPOINT, CoreLink::try_from("</3/0/9>").unwrap()),
+            device.respond(notification(0xFF_FFFF, b"90"))
+        );
+        let mut observation = observation.unwrap();
+        observation.next().await;
+
+        device.notify(&request, notification(0, b"89"));
+        assert_eq!(
+            observation.next().await.unwrap().unwrap(),
+            battery_level(89)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_observe() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (observation, request) = observe_battery(&server, &mut device).await;
+
+        let (result, cancel) = tokio::join!(
+            server.cancel_observe(observation),
+            device.respond(notification(2, b"89"))
+        );
+        assert!(result.is_ok());
+        assert_eq!(cancel.get_token(), request.get_token());
+        assert_eq!(cancel.get_observe_value(), Some(Ok(OBSERVE_DEREGISTER)));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_observation_forgets_token() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (observation, request) = observe_battery(&server, &mut device).await;
+        drop(observation);
+        tokio::task::yield_now().await;
+
+        // The device is told to stop with a reset
+        device.notify(&request, notification(2, b"89"));
+        let reset = device.try_recv().unwrap();
+        assert_eq!(reset.header.get_type(), coap_lite::MessageType::Reset);
+    }
+
+    #[tokio::test]
+    async fn test_observation_ends_on_deregistration() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (mut observation, _) = observe_battery(&server, &mut device).await;
+        observation.next().await;
+
+        let location = server.registry.location(ENDPOINT).await.unwrap();
+        server.registry.deregister(&location).await;
+        assert!(observation.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_observation_ends_when_deregistration_is_missed() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (mut observation, _) = observe_battery(&server, &mut device).await;
+        observation.next().await;
+
+        // The de-registration is pushed out of the events by others before the observation sees it
+        let location = server.registry.location(ENDPOINT).await.unwrap();
+        let other = || Lwm2mRegistrationRequest {
+            device_endpoint: "other".to_owned(),
+            lifetime: 3600,
+            version: Lwm2mVersion::V11,
+            binding_mode: Lwm2mBindMode::Udp,
+            sms_number: None,
+            objects: vec![],
+        };
+        tokio::task::unconstrained(async {
+            server.registry.deregister(&location).await;
+            for _ in 0..600 {
+                let address = "127.0.0.1:56831".parse().unwrap();
+                let other = server.registry.register(other(), address, None).await;
+                server.registry.deregister(&other).await;
+            }
+        })
+        .await;
+        assert!(observation.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_notifications_are_coalesced() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (mut observation, request) = observe_battery(&server, &mut device).await;
+
+        // Nobody takes the notifications, the last ones replace each other
+        let count = NOTIFICATION_BUFFER as u32 + 4;
+        for sequence in 2..=count {
+            device.notify(&request, notification(sequence, b"80"));
+        }
+        device.notify(&request, notification(count + 1, b"70"));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+
+        let mut levels = vec![];
+        while let Ok(Some(Ok(level))) =
+            tokio::time::timeout(Duration::from_millis(100), observation.next()).await
+        {
+            levels.push(level);
+        }
+        assert_eq!(levels.len(), NOTIFICATION_BUFFER + 1);
+        assert_eq!(levels.first(), Some(&battery_level(90)));
+        assert_eq!(levels.last(), Some(&battery_level(70)));
+    }
+
+    #[tokio::test]
+    async fn test_observation_ends_on_error_notification() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (mut observation, request) = observe_battery(&server, &mut device).await;
+        observation.next().await;
+
+        device.notify(&request, response(ResponseType::NotFound, None, b""));
+        assert!(matches!(
+            observation.next().await,
+            Some(Err(OperationError::NotFound))
+        ));
+        assert!(observation.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_observe_not_supported() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let (result, _) = tokio::join!(
+            server.observe(ENDPOINT, CoreLink::try_from("</3/0/9>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::TextPlain),
+                b"90"
+            ))
+        );
+        assert!(matches!(result, Err(OperationError::InvalidResponse(_))));
+    }
+}
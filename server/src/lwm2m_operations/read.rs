@@ -0,0 +1,328 @@
+use coap_lite::{option_value::OptionValueU16, CoapOption, RequestType, ResponseType};
+use object_model::{core_link::CoreLink, value::ResourceValue};
+use std::collections::HashMap;
+
+use super::{err::OperationError, new_request, response_format, Lwm2mServer};
+use crate::content_format;
+
+impl Lwm2mServer {
+    /// Reads an object, object instance, resource or resource instance from a registered device.
+    /// Returns the value of every resource (instance) that was read, keyed by its link.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `link` - The link to read, e.g. </3/0/0>
+    pub async fn read(
+        &self,
+        endpoint: &str,
+        link: CoreLink,
+    ) -> Result<HashMap<CoreLink, ResourceValue>, OperationError> {
+        let target = self.target(endpoint, &link).await?;
+        let model = self.object_model(&target, &link)?;
+        let accept = content_format::preferred_format(
+            target.version,
+            &target.content_formats,
+            &link,
+            &model,
+        );
+
+        let mut request = new_request(RequestType::Get, &link);
+        request.add_option_as(CoapOption::Accept, OptionValueU16(accept.into()));
+        let response = self.send(&target, request).await?;
+        OperationError::check_response(&response, ResponseType::Content)?;
+
+        let format = response_format(&response, accept)?;
+        Ok(content_format::decode(
+            format,
+            &response.payload,
+            &link,
+            &model,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        content_format::Lwm2mContentFormat,
+        lwm2m_operations::test_device::{response, setup, setup_with_objects, uri_path, ENDPOINT},
+        lwm2m_requests::registration_request::Lwm2mVersion,
+    };
+    use coap_lite::MessageClass;
+
+    #[tokio::test]
+    async fn test_read_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/9>").unwrap();
+
+        let (result, request) = tokio::join!(
+            server.read(ENDPOINT, link.clone()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::TextPlain),
+                b"87"
+            ))
+        );
+
+        assert_eq!(request.header.code, MessageClass::Request(RequestType::Get));
+        assert_eq!(uri_path(&request), vec!["3", "0", "9"]);
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::Accept),
+            Some(Ok(OptionValueU16(0)))
+        );
+        assert_eq!(
+            result.unwrap(),
+            HashMap::from([(link, ResourceValue::Integer(87))])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_opaque_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</5/0/0>").unwrap();
+
+        let (result, request) = tokio::join!(
+            server.read(ENDPOINT, link.clone()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::OctetStream),
+                &[0x00, 0xFF]
+            ))
+        );
+
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::Accept),
+            Some(Ok(OptionValueU16(42)))
+        );
+        assert_eq!(
+            result.unwrap(),
+            HashMap::from([(link, ResourceValue::Opaque(vec![0x00, 0xFF]))])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_instance_senml() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+
+        let (result, request) = tokio::join!(
+            server.read(ENDPOINT, CoreLink::try_from("</3/0>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::SenmlJson),
+                br#"[{"bn":"/3/0/","n":"0","vs":"ACME"},{"n":"7/0","v":3800},{"n":"7/1","v":5000}]"#
+            ))
+        );
+
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::Accept),
+            Some(Ok(OptionValueU16(110)))
+        );
+        assert_eq!(
+            result.unwrap(),
+            HashMap::from([
+                (
+                    CoreLink::try_from("</3/0/0>").unwrap(),
+                    ResourceValue::String("ACME".to_owned())
+                ),
+                (
+                    CoreLink::try_from("</3/0/7/0>").unwrap(),
+                    ResourceValue::Integer(3800)
+                ),
+                (
+                    CoreLink::try_from("</3/0/7/1>").unwrap(),
+                    ResourceValue::Integer(5000)
+                ),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_registered_content_type() {
+        let (server, mut device) =
+            setup_with_objects(Lwm2mVersion::V11, "</>;ct=\"112 110\",</3/0>").await;
+        let payload = content_format::senml::to_cbor(&[content_format::senml::SenmlRecord {
+            name: Some("/3/0/9".to_owned()),
+            value: Some(content_format::senml::SenmlValue::Integer(87)),
+            ..Default::default()
+        }])
+        .unwrap();
+
+        let (result, request) = tokio::join!(
+            server.read(ENDPOINT, CoreLink::try_from("</3/0>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::SenmlCbor),
+                &payload
+            ))
+        );
+
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::Accept),
+            Some(Ok(OptionValueU16(112)))
+        );
+        assert_eq!(
+            result.unwrap(),
+            HashMap::from([(
+                CoreLink::try_from("</3/0/9>").unwrap(),
+                ResourceValue::Integer(87)
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_lwm2m_cbor() {
+        let (server, mut device) = setup(Lwm2mVersion::V12).await;
+        // {[3, 0]: {9: 87, 13: 1(1700000000)}}
+        let payload = [
+            0xA1, 0x82, 0x03, 0x00, 0xA2, 0x09, 0x18, 0x57, 0x0D, 0xC1, 0x1A, 0x65, 0x53, 0xF1,
+            0x00,
+        ];
+
+        let (result, request) = tokio::join!(
+            server.read(ENDPOINT, CoreLink::try_from("</3/0>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::Lwm2mCbor),
+                &payload
+            ))
+        );
+
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::Accept),
+            Some(Ok(OptionValueU16(11544)))
+        );
+        assert_eq!(
+            result.unwrap(),
+            HashMap::from([
+                (
+                    CoreLink::try_from("</3/0/9>").unwrap(),
+                    ResourceValue::Integer(87)
+                ),
+                (
+                    CoreLink::try_from("</3/0/13>").unwrap(),
+                    ResourceValue::Time(1700000000)
+                ),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_v12_without_lwm2m_cbor() {
+        let (server, mut device) = setup_with_objects(Lwm2mVersion::V12, "</>;ct=110,</3/0>").await;
+
+        let (_, request) = tokio::join!(
+            server.read(ENDPOINT, CoreLink::try_from("</3/0>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::SenmlJson),
+                b"[]"
+            ))
+        );
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::Accept),
+            Some(Ok(OptionValueU16(110)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_senml_outside_link() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+
+        let (result, _) = tokio::join!(
+            server.read(ENDPOINT, CoreLink::try_from("</3/0>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::SenmlJson),
+                br#"[{"n":"/3/1/9","v":87}]"#
+            ))
+        );
+        assert!(matches!(result, Err(OperationError::Codec(_))));
+    }
+
+    #[tokio::test]
+    async fn test_read_multiple_resource_plain_text() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+
+        // Only one instance of the multiple resource fits into plain text
+        let (result, _) = tokio::join!(
+            server.read(ENDPOINT, CoreLink::try_from("</3/0/7>").unwrap()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::TextPlain),
+                b"3800"
+            ))
+        );
+        assert!(matches!(result, Err(OperationError::Codec(_))));
+
+        let link = CoreLink::try_from("</3/0/7/1>").unwrap();
+        let (result, request) = tokio::join!(
+            server.read(ENDPOINT, link.clone()),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::TextPlain),
+                b"5000"
+            ))
+        );
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::Accept),
+            Some(Ok(OptionValueU16(0)))
+        );
+        assert_eq!(
+            result.unwrap(),
+            HashMap::from([(link, ResourceValue::Integer(5000))])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_error_response() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/0>").unwrap();
+
+        let (result, _) = tokio::join!(
+            server.read(ENDPOINT, link),
+            device.respond(response(ResponseType::NotFound, None, b""))
+        );
+        assert!(matches!(result, Err(OperationError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_read_undecodable_response() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/9>").unwrap();
+
+        let (result, _) = tokio::join!(
+            server.read(ENDPOINT, link),
+            device.respond(response(
+                ResponseType::Content,
+                Some(Lwm2mContentFormat::TextPlain),
+                b"eighty-seven"
+            ))
+        );
+        assert!(matches!(result, Err(OperationError::Codec(_))));
+    }
+
+    #[tokio::test]
+    async fn test_read_unknown_device() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let result = server
+            .read("unknown", CoreLink::try_from("</3/0/0>").unwrap())
+            .await;
+        assert!(matches!(
+            result,
+            Err(OperationError::DeviceNotRegistered(_))
+        ));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_unknown_object() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let result = server
+            .read(ENDPOINT, CoreLink::try_from("</3304/0/5700>").unwrap())
+            .await;
+        assert!(matches!(result, Err(OperationError::ModelNotFound(_))));
+        assert!(device.try_recv().is_none());
+    }
+}
@@ -0,0 +1,430 @@
+// Shared setup for the operation tests: a server with a single registered device that is
+// simulated on the client's outgoing channel, without any sockets involved.
+use coap_lite::{
+    option_value::OptionValueU16, CoapOption, MessageClass, MessageType, Packet, ResponseType,
+};
+use object_model::{
+    ObjectModel, ObjectModelBuilder, ObjectModelStore, ResourceModel, ResourceModelBuilder,
+    ResourceOperation, ResourceType, Version,
+};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use tokio::sync::mpsc;
+
+use super::Lwm2mServer;
+use crate::{
+    content_format::Lwm2mContentFormat,
+    device::registry::DeviceRegistry,
+    lwm2m_requests::registration_request::{
+        parse_link_format, Lwm2mBindMode, Lwm2mRegistrationRequest, Lwm2mVersion,
+    },
+    transport::{client::CoapClient, Peer, Route},
+};
+
+pub const ENDPOINT: &str = "device123";
+pub const ADDRESS: &str = "127.0.0.1:56830";
+
+pub struct TestDevice {
+    outgoing_rx: mpsc::UnboundedReceiver<(Packet, SocketAddr)>,
+    client: CoapClient,
+    message_id: u16,
+}
+
+impl TestDevice {
+    /// Waits for the next request of the server and answers it with a piggybacked response.
+    /// Returns the request so tests can check it.
+    pub async fn respond(&mut self, response: Packet) -> Packet {
+        let (request, peer) = self.outgoing_rx.recv().await.unwrap();
+        let mut response = response;
+        response.header.set_type(MessageType::Acknowledgement);
+        response.header.message_id = request.header.message_id;
+        response.set_token(request.get_token().to_vec());
+        self.client.handle(response, Peer::new(peer, Route::Udp));
+        request
+    }
+
+    /// Sends a confirmable notification for an observe request of the server.
+    pub fn notify(&mut self, request: &Packet, notification: Packet) {
+        let mut notification = notification;
+        self.message_id = self.message_id.wrapping_add(1);
+        notification.header.set_type(MessageType::Confirmable);
+        notification.header.message_id = self.message_id;
+        notification.set_token(request.get_token().to_vec());
+        self.client.handle(notification, device_peer());
+    }
+
+    /// Returns the next packet the server sent, if any.
+    pub fn try_recv(&mut self) -> Option<Packet> {
+        self.outgoing_rx.try_recv().ok().map(|(packet, _)| packet)
+    }
+}
+
+/// The device at `ADDRESS` as the client sees it, registered over plain UDP.
+pub fn device_peer() -> Peer {
+    Peer::new(ADDRESS.parse().unwrap(), Route::Udp)
+}
+
+pub async fn setup(version: Lwm2mVersion) -> (Lwm2mServer, TestDevice) {
+    setup_with_objects(version, "</3/0>,</5/0>").await
+}
+
+/// Like `setup`, with the link-format payload the device registers with, e.g. `</>;ct=112,</3/0>`.
+pub async fn setup_with_objects(version: Lwm2mVersion, objects: &str) -> (Lwm2mServer, TestDevice) {
+    let (client, device) = new_device();
+    let registry = Arc::new(DeviceRegistry::new());
+    registry
+        .register(
+            Lwm2mRegistrationRequest {
+                device_endpoint: ENDPOINT.to_owned(),
+                lifetime: 3600,
+                version,
+                binding_mode: Lwm2mBindMode::Udp,
+                sms_number: None,
+                objects: parse_link_format(objects).unwrap(),
+            },
+            ADDRESS.parse().unwrap(),
+            None,
+        )
+        .await;
+
+    let mut models = ObjectModelStore::default();
+    models.add_model(device_object());
+    models.add_model(firmware_object());
+    models.add_model(temperature_object());
+    let server = Lwm2mServer::new(registry, Arc::new(models), client);
+    (server, device)
+}
+
+/// A client for the server under test and the device at `ADDRESS` that answers its requests.
+pub fn new_device() -> (CoapClient, TestDevice) {
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+    let client = CoapClient::new(outgoing_tx);
+    (
+        client.clone(),
+        TestDevice {
+            outgoing_rx,
+            client,
+            message_id: 0,
+        },
+    )
+}
+
+pub fn response(code: ResponseType, format: Option<Lwm2mContentFormat>, payload: &[u8]) -> Packet {
+    let mut packet = Packet::new();
+    packet.header.code = MessageClass::Response(code);
+    if let Some(format) = format {
+        packet.add_option_as(CoapOption::ContentFormat, OptionValueU16(format.into()));
+    }
+    packet.payload = payload.to_vec();
+    packet
+}
+
+pub fn uri_path(request: &Packet) -> Vec<String> {
+    request
+        .get_option(CoapOption::UriPath)
+        .map(|segments| {
+            segments
+                .iter()
+                .map(|segment| String::from_utf8(segment.clone()).unwrap())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn resource(
+    id: u16,
+    name: &str,
+    operations: impl Into<Option<ResourceOperation>>,
+    resourcetype: Option<ResourceType>,
+    multiple: bool,
+    mandatory: bool,
+) -> ResourceModel {
+    ResourceModelBuilder::default()
+        .id(id)
+        .name(name.to_owned())
+        .operations(operations.into())
+        .resourcetype(resourcetype)
+        .multiple(multiple)
+        .mandatory(mandatory)
+        .build()
+        .unwrap()
+}
+
+// A subset of the Device object (3)
+pub fn device_object() -> ObjectModel {
+    use ResourceOperation::*;
+    let resources = [
+        resource(
+            0,
+            "Manufacturer",
+            Read,
+            Some(ResourceType::String),
+            false,
+            false,
+        ),
+        resource(4, "Reboot", Execute, None, false, true),
+        resource(
+            7,
+            "Power Source Voltage",
+            Read,
+            Some(ResourceType::Integer),
+            true,
+            false,
+        ),
+        resource(
+            9,
+            "Battery Level",
+            Read,
+            Some(ResourceType::Integer),
+            false,
+            false,
+        ),
+        resource(
+            13,
+            "Current Time",
+            ReadWrite,
+            Some(ResourceType::Time),
+            false,
+            false,
+        ),
+        resource(
+            14,
+            "UTC Offset",
+            ReadWrite,
+            Some(ResourceType::String),
+            false,
+            false,
+        ),
+        resource(
+            16,
+            "Supported Binding and Modes",
+            Read,
+            Some(ResourceType::String),
+            false,
+            true,
+        ),
+        resource(
+            22,
+            "ExtDevInfo",
+            Read,
+            Some(ResourceType::ObjectLink),
+            true,
+            false,
+        ),
+    ];
+    object(3, "Device", true, false, resources)
+}
+
+// A subset of the Firmware Update object (5)
+pub fn firmware_object() -> ObjectModel {
+    use ResourceOperation::*;
+    let resources = [
+        resource(0, "Package", Write, Some(ResourceType::Opaque), false, true),
+        resource(
+            1,
+            "Package URI",
+            ReadWrite,
+            Some(ResourceType::String),
+            false,
+            true,
+        ),
+        resource(2, "Update", Execute, None, false, true),
+        resource(3, "State", Read, Some(ResourceType::Integer), false, true),
+        resource(6, "PkgName", Read, Some(ResourceType::String), false, false),
+    ];
+    object(5, "Firmware Update", false, false, resources)
+}
+
+// A subset of the Temperature object (3303)
+pub fn temperature_object() -> ObjectModel {
+    use ResourceOperation::*;
+    let resources = [
+        resource(
+            5700,
+            "Sensor Value",
+            Read,
+            Some(ResourceType::Float),
+            false,
+            true,
+        ),
+        resource(
+            5701,
+            "Sensor Units",
+            Read,
+            Some(ResourceType::String),
+            false,
+            false,
+        ),
+    ];
+    object(3303, "Temperature", false, true, resources)
+}
+
+fn object(
+    id: u16,
+    name: &str,
+    mandatory: bool,
+    multiple: bool,
+    resources: impl IntoIterator<Item = ResourceModel>,
+) -> ObjectModel {
+    versioned_object(id, name, ("1.0", "1.0"), mandatory, multiple, resources)
+}
+
+// An object in the given (object version, LwM2M version) as the registry lists it
+fn versioned_object(
+    id: u16,
+    name: &str,
+    (version, lwm2m_version): (&str, &str),
+    mandatory: bool,
+    multiple: bool,
+    resources: impl IntoIterator<Item = ResourceModel>,
+) -> ObjectModel {
+    // The OMA range ends at 1023, objects above are registered by third parties
+    let kind = if id < 1024 { "oma" } else { "ext" };
+    let urn = match version {
+        "1.0" => format!("urn:oma:lwm2m:{}:{}", kind, id),
+        version => format!("urn:oma:lwm2m:{}:{}:{}", kind, id, version),
+    };
+    ObjectModelBuilder::default()
+        .id(id)
+        .name(name.to_owned())
+        .version(Version::try_from(version).unwrap())
+        .lwm2m_version(Version::try_from(lwm2m_version).unwrap())
+        .urn(urn)
+        .mandatory(mandatory)
+        .multiple(multiple)
+        .resources(HashMap::from_iter(
+            resources
+                .into_iter()
+                .map(|resource| (resource.id(), resource)),
+        ))
+        .build()
+        .unwrap()
+}
+
+// A resource only a bootstrap server writes, like those of the Security and OSCORE objects
+fn credential(id: u16, name: &str, resourcetype: ResourceType) -> ResourceModel {
+    resource(id, name, None, Some(resourcetype), false, false)
+}
+
+// A subset of the LwM2M Security object (0) 1.0, its resources have no operations
+pub fn security_object() -> ObjectModel {
+    versioned_object(
+        0,
+        "LWM2M Security",
+        ("1.0", "1.0"),
+        true,
+        true,
+        security_resources(),
+    )
+}
+
+// A subset of the LwM2M Security object (0) 1.1 of LwM2M 1.1, which links to the OSCORE object
+pub fn security_object_v11() -> ObjectModel {
+    let mut resources = security_resources();
+    resources.push(credential(
+        17,
+        "OSCORE Security Mode",
+        ResourceType::ObjectLink,
+    ));
+    versioned_object(0, "LWM2M Security", ("1.1", "1.1"), true, true, resources)
+}
+
+fn security_resources() -> Vec<ResourceModel> {
+    vec![
+        credential(0, "LWM2M  Server URI", ResourceType::String),
+        credential(1, "Bootstrap-Server", ResourceType::Boolean),
+        credential(2, "Security Mode", ResourceType::Integer),
+        credential(3, "Public Key or Identity", ResourceType::Opaque),
+        credential(4, "Server Public Key", ResourceType::Opaque),
+        credential(5, "Secret Key", ResourceType::Opaque),
+        credential(10, "Short Server ID", ResourceType::Integer),
+        credential(11, "Client Hold Off Time", ResourceType::Integer),
+    ]
+}
+
+// A subset of the OSCORE object (21) 2.0 of LwM2M 1.1
+pub fn oscore_object() -> ObjectModel {
+    versioned_object(
+        21,
+        "OSCORE",
+        ("2.0", "1.1"),
+        false,
+        true,
+        [
+            credential(0, "OSCORE Master Secret", ResourceType::Opaque),
+            credential(1, "OSCORE Sender ID", ResourceType::Opaque),
+            credential(2, "OSCORE Recipient ID", ResourceType::Opaque),
+            credential(3, "OSCORE AEAD Algorithm", ResourceType::Integer),
+            credential(5, "OSCORE Master Salt", ResourceType::Opaque),
+            credential(6, "OSCORE ID Context", ResourceType::Opaque),
+        ],
+    )
+}
+
+// A subset of the LwM2M Server object (1)
+pub fn server_object() -> ObjectModel {
+    use ResourceOperation::*;
+    object(
+        1,
+        "LwM2M Server",
+        true,
+        true,
+        [
+            resource(
+                0,
+                "Short Server ID",
+                Read,
+                Some(ResourceType::Integer),
+                false,
+                true,
+            ),
+            resource(
+                1,
+                "Lifetime",
+                ReadWrite,
+                Some(ResourceType::Integer),
+                false,
+                true,
+            ),
+            resource(
+                2,
+                "Default Minimum Period",
+                ReadWrite,
+                Some(ResourceType::Integer),
+                false,
+                false,
+            ),
+            resource(
+                3,
+                "Default Maximum Period",
+                ReadWrite,
+                Some(ResourceType::Integer),
+                false,
+                false,
+            ),
+            resource(
+                5,
+                "Disable Timeout",
+                ReadWrite,
+                Some(ResourceType::Integer),
+                false,
+                false,
+            ),
+            resource(
+                6,
+                "Notification Storing",
+                ReadWrite,
+                Some(ResourceType::Boolean),
+                false,
+                true,
+            ),
+            resource(
+                7,
+                "Binding",
+                ReadWrite,
+                Some(ResourceType::String),
+                false,
+                true,
+            ),
+        ],
+    )
+}
@@ -0,0 +1,241 @@
+use coap_lite::{option_value::OptionValueU16, CoapOption, RequestType, ResponseType};
+use object_model::{core_link::CoreLink, value::ResourceValue, ObjectModel};
+use std::collections::HashMap;
+
+use super::{err::OperationError, new_request, Lwm2mServer};
+use crate::content_format;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#6-3-3-0-633-Write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    // PUT, the target is replaced by the written values
+    Replace,
+    // POST, only the written values are changed
+    PartialUpdate,
+}
+
+impl Lwm2mServer {
+    /// Writes values to an object instance, resource or resource instance of a registered device.
+    /// Values are checked against the object model before anything is sent,
+    /// resources that can only be read or executed are refused.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `link` - The link to write to, e.g. </3/0/13>
+    /// * `values` - The values to write, keyed by the link of each resource (instance)
+    /// * `mode` - Whether the target is replaced or partially updated
+    pub async fn write(
+        &self,
+        endpoint: &str,
+        link: CoreLink,
+        values: HashMap<CoreLink, ResourceValue>,
+        mode: WriteMode,
+    ) -> Result<(), OperationError> {
+        let target = self.target(endpoint, &link).await?;
+        let model = self.object_model(&target, &link)?;
+        check_write(&link, &values, mode, &model)?;
+
+        let format = content_format::preferred_format(
+            target.version,
+            &target.content_formats,
+            &link,
+            &model,
+        );
+        let payload = content_format::encode(format, &values, &link, &model)?;
+
+        let method = match mode {
+            WriteMode::Replace => RequestType::Put,
+            WriteMode::PartialUpdate => RequestType::Post,
+        };
+        let mut request = new_request(method, &link);
+        request.add_option_as(CoapOption::ContentFormat, OptionValueU16(format.into()));
+        request.payload = payload;
+        let response = self.send(&target, request).await?;
+        OperationError::check_response(&response, ResponseType::Changed)
+    }
+}
+
+// Checks that every value is below `link` and fits a writable resource of the model
+fn check_write(
+    link: &CoreLink,
+    values: &HashMap<CoreLink, ResourceValue>,
+    mode: WriteMode,
+    model: &ObjectModel,
+) -> Result<(), OperationError> {
+    let invalid_link = |message: &str| OperationError::InvalidLink {
+        link: link.clone(),
+        message: message.to_owned(),
+    };
+
+    if link.object_instance.is_none() {
+        return Err(invalid_link(
+            "only object instances and resources can be written",
+        ));
+    }
+    if let Some(resource_id) = link.resource_id {
+        let multiple = model
+            .resources()
+            .get(&resource_id)
+            .map(|resource| resource.multiple())
+            .unwrap_or(false);
+        // A POST on a single resource is an Execute, not a Write
+        if mode == WriteMode::PartialUpdate && (!multiple || link.resource_instance.is_some()) {
+            return Err(invalid_link(
+                "partial updates are only possible on object instances and multiple resources",
+            ));
+        }
+    }
+
+    for (value_link, value) in values {
+        content_format::check_below(value_link, link)?;
+        model.validate_write(value_link, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        content_format::Lwm2mContentFormat,
+        lwm2m_operations::test_device::{response, setup, uri_path, ENDPOINT},
+        lwm2m_requests::registration_request::Lwm2mVersion,
+    };
+    use coap_lite::MessageClass;
+
+    #[tokio::test]
+    async fn test_write_replace_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/13>").unwrap();
+        let values = HashMap::from([(link.clone(), ResourceValue::Time(1700000000))]);
+
+        let (result, request) = tokio::join!(
+            server.write(ENDPOINT, link, values, WriteMode::Replace),
+            device.respond(response(ResponseType::Changed, None, b""))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(request.header.code, MessageClass::Request(RequestType::Put));
+        assert_eq!(uri_path(&request), vec!["3", "0", "13"]);
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat),
+            Some(Ok(OptionValueU16(Lwm2mContentFormat::TextPlain.into())))
+        );
+        assert_eq!(request.payload, b"1700000000".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_write_opaque_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</5/0/0>").unwrap();
+        let values = HashMap::from([(link.clone(), ResourceValue::Opaque(vec![0xCA, 0xFE]))]);
+
+        let (result, request) = tokio::join!(
+            server.write(ENDPOINT, link, values, WriteMode::Replace),
+            device.respond(response(ResponseType::Changed, None, b""))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat),
+            Some(Ok(OptionValueU16(Lwm2mContentFormat::OctetStream.into())))
+        );
+        assert_eq!(request.payload, vec![0xCA, 0xFE]);
+    }
+
+    #[tokio::test]
+    async fn test_partial_update_instance() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0>").unwrap();
+        let values = HashMap::from([(
+            CoreLink::try_from("</3/0/14>").unwrap(),
+            ResourceValue::String("+02".to_owned()),
+        )]);
+
+        let (result, request) = tokio::join!(
+            server.write(ENDPOINT, link, values, WriteMode::PartialUpdate),
+            device.respond(response(ResponseType::Changed, None, b""))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            request.header.code,
+            MessageClass::Request(RequestType::Post)
+        );
+        assert_eq!(uri_path(&request), vec!["3", "0"]);
+        assert_eq!(
+            request.get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat),
+            Some(Ok(OptionValueU16(Lwm2mContentFormat::SenmlJson.into())))
+        );
+        assert_eq!(request.payload, br#"[{"n":"/3/0/14","vs":"+02"}]"#);
+    }
+
+    #[tokio::test]
+    async fn test_write_read_only_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/0>").unwrap();
+        let values = HashMap::from([(link.clone(), ResourceValue::String("ACME".into()))]);
+
+        let result = server
+            .write(ENDPOINT, link, values, WriteMode::Replace)
+            .await;
+        assert!(matches!(result, Err(OperationError::NotWritable(_))));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_write_executable_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0>").unwrap();
+        let values = HashMap::from([(
+            CoreLink::try_from("</3/0/4>").unwrap(),
+            ResourceValue::String("now".into()),
+        )]);
+
+        let result = server
+            .write(ENDPOINT, link, values, WriteMode::PartialUpdate)
+            .await;
+        assert!(matches!(result, Err(OperationError::NotWritable(_))));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_write_wrong_type() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/13>").unwrap();
+        let values = HashMap::from([(link.clone(), ResourceValue::String("noon".into()))]);
+
+        let result = server
+            .write(ENDPOINT, link, values, WriteMode::Replace)
+            .await;
+        assert!(matches!(result, Err(OperationError::InvalidValue { .. })));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_partial_update_single_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/14>").unwrap();
+        let values = HashMap::from([(link.clone(), ResourceValue::String("+02".into()))]);
+
+        let result = server
+            .write(ENDPOINT, link, values, WriteMode::PartialUpdate)
+            .await;
+        assert!(matches!(result, Err(OperationError::InvalidLink { .. })));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_write_error_response() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/14>").unwrap();
+        let values = HashMap::from([(link.clone(), ResourceValue::String("+02".into()))]);
+
+        let (result, _) = tokio::join!(
+            server.write(ENDPOINT, link, values, WriteMode::Replace),
+            device.respond(response(ResponseType::MethodNotAllowed, None, b""))
+        );
+        assert!(matches!(result, Err(OperationError::MethodNotAllowed)));
+    }
+}
@@ -0,0 +1,326 @@
+use coap_lite::{CoapOption, RequestType, ResponseType};
+use object_model::{core_link::CoreLink, ObjectModel, ResourceType};
+
+use super::{err::OperationError, new_request, Lwm2mServer};
+use crate::lwm2m_requests::{attributes::Lwm2mAttribute, registration_request::Lwm2mVersion};
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#5-1-2-0-512-lt-NOTIFICATION-gt-Class-Attributes
+/// The notification attributes to set with a Write-Attributes operation.
+/// Attributes that are not set are left unchanged on the device.
+#[derive(Debug, Default, Clone)]
+pub struct WriteAttributes {
+    min_period: Option<u64>,
+    max_period: Option<u64>,
+    greater_than: Option<f64>,
+    less_than: Option<f64>,
+    step: Option<f64>,
+    min_eval_period: Option<u64>,
+    max_eval_period: Option<u64>,
+    edge: Option<bool>,
+    confirmable: Option<bool>,
+    max_historical_queue: Option<u64>,
+}
+
+impl WriteAttributes {
+    pub fn new() -> Self {
+        WriteAttributes::default()
+    }
+
+    pub fn min_period(mut self, seconds: u64) -> Self {
+        self.min_period = Some(seconds);
+        self
+    }
+
+    pub fn max_period(mut self, seconds: u64) -> Self {
+        self.max_period = Some(seconds);
+        self
+    }
+
+    pub fn greater_than(mut self, value: f64) -> Self {
+        self.greater_than = Some(value);
+        self
+    }
+
+    pub fn less_than(mut self, value: f64) -> Self {
+        self.less_than = Some(value);
+        self
+    }
+
+    pub fn step(mut self, value: f64) -> Self {
+        self.step = Some(value);
+        self
+    }
+
+    pub fn min_eval_period(mut self, seconds: u64) -> Self {
+        self.min_eval_period = Some(seconds);
+        self
+    }
+
+    pub fn max_eval_period(mut self, seconds: u64) -> Self {
+        self.max_eval_period = Some(seconds);
+        self
+    }
+
+    pub fn edge(mut self, rising: bool) -> Self {
+        self.edge = Some(rising);
+        self
+    }
+
+    pub fn confirmable(mut self, confirmable: bool) -> Self {
+        self.confirmable = Some(confirmable);
+        self
+    }
+
+    pub fn max_historical_queue(mut self, size: u64) -> Self {
+        self.max_historical_queue = Some(size);
+        self
+    }
+
+    /// The attributes that are set, in the order they are sent.
+    pub fn attributes(&self) -> Vec<Lwm2mAttribute> {
+        [
+            self.min_period.map(Lwm2mAttribute::MinPeriod),
+            self.max_period.map(Lwm2mAttribute::MaxPeriod),
+            self.greater_than.map(Lwm2mAttribute::GreaterThan),
+            self.less_than.map(Lwm2mAttribute::LessThan),
+            self.step.map(Lwm2mAttribute::Step),
+            self.min_eval_period.map(Lwm2mAttribute::MinEvalPeriod),
+            self.max_eval_period.map(Lwm2mAttribute::MaxEvalPeriod),
+            self.edge.map(Lwm2mAttribute::Edge),
+            self.confirmable.map(Lwm2mAttribute::Confirmable),
+            self.max_historical_queue
+                .map(Lwm2mAttribute::MaxHistoricalQueue),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    // Checks the constraints between the attributes and the resource they are written to
+    fn validate(
+        &self,
+        link: &CoreLink,
+        version: Lwm2mVersion,
+        model: &ObjectModel,
+    ) -> Result<(), OperationError> {
+        let invalid = |message: String| Err(OperationError::InvalidAttribute(message));
+
+        if let (Some(pmin), Some(pmax)) = (self.min_period, self.max_period) {
+            if pmin > pmax {
+                return invalid(format!("pmin {} is greater than pmax {}", pmin, pmax));
+            }
+        }
+        if let (Some(epmin), Some(epmax)) = (self.min_eval_period, self.max_eval_period) {
+            if epmin > epmax {
+                return invalid(format!("epmin {} is greater than epmax {}", epmin, epmax));
+            }
+        }
+        if let Some(step) = self.step {
+            if step < 0.0 {
+                return invalid(format!("st {} is negative", step));
+            }
+        }
+        if let (Some(lt), Some(gt)) = (self.less_than, self.greater_than) {
+            // The thresholds must be further apart than the step in both directions
+            let step = self.step.unwrap_or(0.0);
+            if lt + 2.0 * step >= gt {
+                return invalid(format!(
+                    "lt {} plus twice st {} must be less than gt {}",
+                    lt, step, gt
+                ));
+            }
+        }
+
+        let unsupported = match version {
+            Lwm2mVersion::V10 => {
+                self.min_eval_period.is_some()
+                    || self.max_eval_period.is_some()
+                    || self.edge.is_some()
+                    || self.confirmable.is_some()
+                    || self.max_historical_queue.is_some()
+            }
+            Lwm2mVersion::V11 => {
+                self.edge.is_some()
+                    || self.confirmable.is_some()
+                    || self.max_historical_queue.is_some()
+            }
+            Lwm2mVersion::V12 => false,
+        };
+        if unsupported {
+            return invalid(format!("attributes not supported by LwM2M {:?}", version));
+        }
+
+        let numeric =
+            self.greater_than.is_some() || self.less_than.is_some() || self.step.is_some();
+        if numeric || self.edge.is_some() {
+            let resourcetype = link
+                .resource_id
+                .and_then(|resource_id| model.resources().get(&resource_id))
+                .and_then(|resource| resource.resourcetype());
+            let (is_numeric, is_boolean) = match resourcetype {
+                Some(ResourceType::Integer)
+                | Some(ResourceType::UnsignedInteger)
+                | Some(ResourceType::Float) => (true, false),
+                Some(ResourceType::Boolean) => (false, true),
+                _ => (false, false),
+            };
+            if numeric && !is_numeric {
+                return invalid(format!(
+                    "gt, lt and st need a numeric resource, {} is not",
+                    link
+                ));
+            }
+            if self.edge.is_some() && !is_boolean {
+                return invalid(format!("edge needs a boolean resource, {} is not", link));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Lwm2mServer {
+    /// Sets the notification attributes of an object, object instance or resource of a
+    /// registered device. The attributes are validated before anything is sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint name the device registered with
+    /// * `link` - The link to set the attributes on, e.g. </3/0/9>
+    /// * `attributes` - The attributes to set
+    pub async fn write_attributes(
+        &self,
+        endpoint: &str,
+        link: CoreLink,
+        attributes: &WriteAttributes,
+    ) -> Result<(), OperationError> {
+        let target = self.target(endpoint, &link).await?;
+        let model = self.object_model(&target, &link)?;
+        attributes.validate(&link, target.version, &model)?;
+
+        let mut request = new_request(RequestType::Put, &link);
+        for parameter in attributes
+            .attributes()
+            .iter()
+            .filter_map(|attribute| attribute.query_parameter())
+        {
+            request.add_option(CoapOption::UriQuery, parameter.into_bytes());
+        }
+        let response = self.send(&target, request).await?;
+        OperationError::check_response(&response, ResponseType::Changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lwm2m_operations::test_device::{response, setup, uri_path, ENDPOINT};
+    use coap_lite::MessageClass;
+
+    fn uri_query(request: &coap_lite::Packet) -> Vec<String> {
+        request
+            .get_option(CoapOption::UriQuery)
+            .map(|parameters| {
+                parameters
+                    .iter()
+                    .map(|parameter| String::from_utf8(parameter.clone()).unwrap())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_write_attributes() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let attributes = WriteAttributes::new()
+            .min_period(10)
+            .max_period(60)
+            .greater_than(80.0)
+            .less_than(20.5)
+            .step(5.0);
+
+        let (result, request) = tokio::join!(
+            server.write_attributes(
+                ENDPOINT,
+                CoreLink::try_from("</3/0/9>").unwrap(),
+                &attributes
+            ),
+            device.respond(response(ResponseType::Changed, None, b""))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(request.header.code, MessageClass::Request(RequestType::Put));
+        assert_eq!(uri_path(&request), vec!["3", "0", "9"]);
+        assert_eq!(
+            uri_query(&request),
+            vec!["pmin=10", "pmax=60", "gt=80", "lt=20.5", "st=5"]
+        );
+        assert!(request.payload.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_attributes_constraints() {
+        let (server, mut device) = setup(Lwm2mVersion::V11).await;
+        let link = CoreLink::try_from("</3/0/9>").unwrap();
+        let invalid = [
+            WriteAttributes::new().min_period(60).max_period(10),
+            WriteAttributes::new().min_eval_period(5).max_eval_period(1),
+            WriteAttributes::new().less_than(50.0).greater_than(40.0),
+            WriteAttributes::new()
+                .less_than(40.0)
+                .greater_than(50.0)
+                .step(5.0),
+            WriteAttributes::new().step(-1.0),
+            // Not supported by LwM2M 1.1
+            WriteAttributes::new().confirmable(true),
+        ];
+        for attributes in invalid {
+            let result = server
+                .write_attributes(ENDPOINT, link.clone(), &attributes)
+                .await;
+            assert!(
+                matches!(result, Err(OperationError::InvalidAttribute(_))),
+                "{:?} should be refused",
+                attributes
+            );
+        }
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_numeric_attributes_on_non_numeric_resource() {
+        let (server, mut device) = setup(Lwm2mVersion::V12).await;
+        for link in ["</3/0/0>", "</3/0>"] {
+            let result = server
+                .write_attributes(
+                    ENDPOINT,
+                    CoreLink::try_from(link).unwrap(),
+                    &WriteAttributes::new().greater_than(1.0),
+                )
+                .await;
+            assert!(matches!(result, Err(OperationError::InvalidAttribute(_))));
+        }
+        let result = server
+            .write_attributes(
+                ENDPOINT,
+                CoreLink::try_from("</3/0/9>").unwrap(),
+                &WriteAttributes::new().edge(true),
+            )
+            .await;
+        assert!(matches!(result, Err(OperationError::InvalidAttribute(_))));
+        assert!(device.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_write_attributes_on_instance() {
+        let (server, mut device) = setup(Lwm2mVersion::V12).await;
+        let attributes = WriteAttributes::new().max_period(300).confirmable(true);
+
+        let (result, request) = tokio::join!(
+            server.write_attributes(ENDPOINT, CoreLink::try_from("</3/0>").unwrap(), &attributes),
+            device.respond(response(ResponseType::Changed, None, b""))
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(uri_query(&request), vec!["pmax=300", "con=1"]);
+    }
+}
@@ -1,4 +1,5 @@
 use super::registration_request::Lwm2mVersion;
+use crate::content_format::Lwm2mContentFormat;
 use coap_lite::link_format::Unquote;
 use coap_server::app::CoapError;
 
@@ -20,7 +21,8 @@ pub enum Lwm2mAttribute {
     Edge(bool),
     Confirmable(bool),
     MaxHistoricalQueue(u64),
-    ContentType(coap_lite::ContentFormat),
+    // A device can list several formats, e.g. ct="60 110"
+    ContentType(Vec<Lwm2mContentFormat>),
     Unknown(String),
 }
 
@@ -62,22 +64,43 @@ impl Lwm2mAttribute {
                 Lwm2mAttribute::Confirmable(false),
             ),
             "hqmax" => parse_u64_attribute(attr, &attr_value, "Maximum Historical Queue"),
-            "ct" => {
-                let ct = attr_value.parse::<usize>().map_err(|_| CoapError {
-                    code: Some(coap_lite::ResponseType::NotAcceptable),
-                    message: String::from("ct value should be an integer"),
-                })?;
-                let cf = coap_lite::ContentFormat::try_from(ct).map_err(|_| CoapError {
-                    code: Some(coap_lite::ResponseType::NotAcceptable),
-                    message: format!("ct value {} not recognized as content format", ct),
-                })?;
-                Ok(Lwm2mAttribute::ContentType(cf))
-            }
+            // Formats the server does not know are left out, without any the version default applies
+            "ct" => attr_value
+                .split_whitespace()
+                .filter_map(|ct| match ct.parse::<u16>() {
+                    Ok(number) => Lwm2mContentFormat::try_from(number).ok().map(Ok),
+                    Err(_) => Some(Err(CoapError {
+                        code: Some(coap_lite::ResponseType::NotAcceptable),
+                        message: String::from("ct value should be an integer"),
+                    })),
+                })
+                .collect::<Result<Vec<Lwm2mContentFormat>, CoapError>>()
+                .map(Lwm2mAttribute::ContentType),
             _ => Ok(Lwm2mAttribute::Unknown(attr_value)),
         }
     }
 }
 
+impl Lwm2mAttribute {
+    /// The attribute as `name=value` query parameter of a Write-Attributes request.
+    /// Returns None for attributes that can not be written.
+    pub fn query_parameter(&self) -> Option<String> {
+        match self {
+            Lwm2mAttribute::MinPeriod(value) => Some(format!("pmin={}", value)),
+            Lwm2mAttribute::MaxPeriod(value) => Some(format!("pmax={}", value)),
+            Lwm2mAttribute::GreaterThan(value) => Some(format!("gt={}", value)),
+            Lwm2mAttribute::LessThan(value) => Some(format!("lt={}", value)),
+            Lwm2mAttribute::Step(value) => Some(format!("st={}", value)),
+            Lwm2mAttribute::MinEvalPeriod(value) => Some(format!("epmin={}", value)),
+            Lwm2mAttribute::MaxEvalPeriod(value) => Some(format!("epmax={}", value)),
+            Lwm2mAttribute::Edge(value) => Some(format!("edge={}", u8::from(*value))),
+            Lwm2mAttribute::Confirmable(value) => Some(format!("con={}", u8::from(*value))),
+            Lwm2mAttribute::MaxHistoricalQueue(value) => Some(format!("hqmax={}", value)),
+            _ => None,
+        }
+    }
+}
+
 fn parse_f64_attribute(
     attr_name: &str,
     attr_value: &str,
@@ -169,3 +192,48 @@ fn parse_bool_attribute(
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_parameter_round_trip() {
+        let attributes = [
+            Lwm2mAttribute::MinPeriod(10),
+            Lwm2mAttribute::GreaterThan(20.5),
+            Lwm2mAttribute::Step(2.0),
+            Lwm2mAttribute::Edge(true),
+        ];
+        for attribute in attributes {
+            let parameter = attribute.query_parameter().unwrap();
+            let (name, value) = parameter.split_once('=').unwrap();
+            let parsed = Lwm2mAttribute::new((name, Unquote::new(value))).unwrap();
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", attribute));
+        }
+        assert_eq!(
+            Lwm2mAttribute::Step(2.0).query_parameter(),
+            Some("st=2".to_string())
+        );
+        assert!(Lwm2mAttribute::Dimension(2).query_parameter().is_none());
+    }
+
+    #[test]
+    fn test_content_type() {
+        let parsed = Lwm2mAttribute::new(("ct", Unquote::new("11542 110"))).unwrap();
+        assert!(matches!(
+            parsed,
+            Lwm2mAttribute::ContentType(ref formats)
+                if formats == &[Lwm2mContentFormat::Tlv, Lwm2mContentFormat::SenmlJson]
+        ));
+        assert!(Lwm2mAttribute::new(("ct", Unquote::new("cbor"))).is_err());
+        // Unknown formats are skipped
+        let parsed = Lwm2mAttribute::new(("ct", Unquote::new("50 112"))).unwrap();
+        assert!(matches!(
+            parsed,
+            Lwm2mAttribute::ContentType(ref formats) if formats == &[Lwm2mContentFormat::SenmlCbor]
+        ));
+        let parsed = Lwm2mAttribute::new(("ct", Unquote::new("50"))).unwrap();
+        assert!(matches!(parsed, Lwm2mAttribute::ContentType(ref formats) if formats.is_empty()));
+    }
+}
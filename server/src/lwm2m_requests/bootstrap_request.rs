@@ -0,0 +1,76 @@
+use coap_server::app::{CoapError, Request};
+use serde::Deserialize;
+use serde_querystring::from_str;
+use std::net::SocketAddr;
+
+use super::registration_request::get_query;
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#6-1-3-0-613-Bootstrap-Request-Operation
+#[derive(Debug, Deserialize)]
+pub struct Lwm2mBootstrapRequest {
+    #[serde(rename = "ep")]
+    pub device_endpoint: String,
+    // The content format the device prefers for the Bootstrap-Write payloads, LwM2M 1.1 and later
+    #[serde(rename = "pct")]
+    pub preferred_format: Option<u16>,
+}
+
+impl Lwm2mBootstrapRequest {
+    pub fn new(request: Request<SocketAddr>) -> Result<Self, CoapError> {
+        let query = get_query(&request)?.ok_or(CoapError {
+            code: Some(coap_lite::ResponseType::BadOption),
+            message: String::from("Missing all URL query parameters"),
+        })?;
+
+        from_str(query.as_str(), serde_querystring::ParseMode::UrlEncoded).map_err(|err| {
+            CoapError {
+                code: Some(coap_lite::ResponseType::UnprocessableEntity),
+                message: format!("Incorrect URL query format: {}", err.message),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coap_lite::{CoapOption, CoapRequest, Packet, ResponseType};
+
+    fn request(query: Option<&str>) -> Request<SocketAddr> {
+        let mut packet = Packet::new();
+        if let Some(query) = query {
+            for parameter in query.split('&') {
+                packet.add_option(CoapOption::UriQuery, parameter.as_bytes().to_vec());
+            }
+        }
+        Request {
+            original: CoapRequest::from_packet(packet, "127.0.0.1:56830".parse().unwrap()),
+            unmatched_path: vec![],
+        }
+    }
+
+    #[test]
+    fn test_bootstrap_request() {
+        let bootstrap = Lwm2mBootstrapRequest::new(request(Some("ep=device123"))).unwrap();
+        assert_eq!(bootstrap.device_endpoint, "device123");
+        assert_eq!(bootstrap.preferred_format, None);
+
+        let bootstrap =
+            Lwm2mBootstrapRequest::new(request(Some("ep=device123&pct=11542"))).unwrap();
+        assert_eq!(bootstrap.preferred_format, Some(11542));
+    }
+
+    #[test]
+    fn test_bootstrap_request_invalid() {
+        let result = Lwm2mBootstrapRequest::new(request(None));
+        assert_eq!(result.unwrap_err().code, Some(ResponseType::BadOption));
+
+        for query in ["pct=11542", "ep=device123&pct=tlv"] {
+            let result = Lwm2mBootstrapRequest::new(request(Some(query)));
+            assert_eq!(
+                result.unwrap_err().code,
+                Some(ResponseType::UnprocessableEntity)
+            );
+        }
+    }
+}
@@ -1,2 +1,5 @@
-mod attributes;
+pub mod attributes;
+pub mod bootstrap_request;
 pub mod registration_request;
+pub mod send_request;
+pub mod update_request;
@@ -2,6 +2,7 @@ use coap_lite::link_format::LinkAttributeParser;
 use coap_lite::CoapOption;
 use coap_lite::{link_format::LinkFormatParser, option_value::OptionValueString};
 use coap_server::app::{CoapError, Request};
+use object_model::{core_link::CoreLink, Version};
 use serde::Deserialize;
 use serde_querystring::from_str;
 use std::net::SocketAddr;
@@ -15,7 +16,29 @@ pub struct Lwm2mRegistrationObject {
     attributes: Vec<Lwm2mAttribute>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Lwm2mRegistrationObject {
+    pub fn new(link: &CoreLink) -> Self {
+        Lwm2mRegistrationObject {
+            object: link.path(),
+            attributes: vec![],
+        }
+    }
+
+    /// The registered link, None for links that are not an object (instance) such as </>
+    pub fn link(&self) -> Option<CoreLink> {
+        CoreLink::try_from(format!("<{}>", self.object).as_str()).ok()
+    }
+
+    pub fn attributes(&self) -> &[Lwm2mAttribute] {
+        &self.attributes
+    }
+
+    pub fn into_attributes(self) -> Vec<Lwm2mAttribute> {
+        self.attributes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 #[serde(rename = "lwm2m")]
 pub enum Lwm2mVersion {
     #[serde(alias = "v1.0")]
@@ -29,7 +52,18 @@ pub enum Lwm2mVersion {
     V12,
 }
 
-#[derive(Debug, Deserialize)]
+impl From<Lwm2mVersion> for Version {
+    fn from(version: Lwm2mVersion) -> Self {
+        let version = match version {
+            Lwm2mVersion::V10 => "1.0",
+            Lwm2mVersion::V11 => "1.1",
+            Lwm2mVersion::V12 => "1.2",
+        };
+        Version::try_from(version).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 #[serde(rename = "b")]
 pub enum Lwm2mBindMode {
     #[serde(alias = "u")]
@@ -38,6 +72,9 @@ pub enum Lwm2mBindMode {
     #[serde(alias = "t")]
     #[serde(alias = "T")]
     Tcp,
+    #[serde(alias = "s")]
+    #[serde(alias = "S")]
+    Sms,
 }
 
 #[derive(Debug, Deserialize)]
@@ -50,6 +87,8 @@ pub struct Lwm2mRegistrationRequest {
     pub version: Lwm2mVersion,
     #[serde(rename = "b")]
     pub binding_mode: Lwm2mBindMode,
+    #[serde(rename = "sms")]
+    pub sms_number: Option<String>,
     #[serde(skip)]
     pub objects: Vec<Lwm2mRegistrationObject>,
 }
@@ -57,64 +96,80 @@ pub struct Lwm2mRegistrationRequest {
 impl Lwm2mRegistrationRequest {
     pub fn new(request: Request<SocketAddr>) -> Result<Self, CoapError> {
         // Get the URL query parameters
-        let options = request
-            .original
-            .message
-            .get_first_option_as::<OptionValueString>(CoapOption::UriQuery)
-            .ok_or(CoapError {
-                code: Some(coap_lite::ResponseType::BadOption),
-                message: String::from("Missing all URL query parameters"),
-            })?;
-
-        // Try to read the options
-        let option = options.map_err(|_| CoapError {
-            code: Some(coap_lite::ResponseType::InternalServerError),
-            message: String::from("Failed to read options"),
+        let query = get_query(&request)?.ok_or(CoapError {
+            code: Some(coap_lite::ResponseType::BadOption),
+            message: String::from("Missing all URL query parameters"),
         })?;
 
         let content_type = request.original.message.get_content_format();
-        let payload = request.original.message.payload;
-        let payload_str = str::from_utf8(&payload).map_err(|_| CoapError {
-            code: Some(coap_lite::ResponseType::UnprocessableEntity),
-            message: String::from("Unreadable utf8 content"),
-        })?;
+        let payload_str = get_link_format_payload(&request)?;
 
-        // Check if the content type is application/link-format
-        match content_type {
-            // If no content-type specified, check if at least not empty.
-            // Determining correct format is done when the content is parsed
-            None => {
-                if payload_str.trim().is_empty() {
-                    return Err(CoapError {
-                        code: Some(coap_lite::ResponseType::UnprocessableEntity),
-                        message: String::from("Registration requires objects in payload"),
-                    });
-                }
-            }
-            Some(coap_lite::ContentFormat::ApplicationLinkFormat) => (),
-            _ => {
-                return Err(CoapError {
-                    code: Some(coap_lite::ResponseType::UnsupportedContentFormat),
-                    message: String::from("Content Type unsupported"),
-                });
-            }
-        };
+        // If no content-type specified, check if at least not empty.
+        // Determining correct format is done when the content is parsed
+        if content_type.is_none() && payload_str.trim().is_empty() {
+            return Err(CoapError {
+                code: Some(coap_lite::ResponseType::UnprocessableEntity),
+                message: String::from("Registration requires objects in payload"),
+            });
+        }
 
         // Deserialize the options into a request
         let mut regreq: Lwm2mRegistrationRequest =
-            from_str(option.0.as_str(), serde_querystring::ParseMode::UrlEncoded).map_err(
-                |err| CoapError {
+            from_str(query.as_str(), serde_querystring::ParseMode::UrlEncoded).map_err(|err| {
+                CoapError {
                     code: Some(coap_lite::ResponseType::UnprocessableEntity),
                     message: format!("Incorrect URL query format: {}", err.message),
-                },
-            )?;
+                }
+            })?;
 
         regreq.objects = parse_link_format(payload_str)?;
         Ok(regreq)
     }
 }
 
-fn parse_link_format(payload: &str) -> Result<Vec<Lwm2mRegistrationObject>, CoapError> {
+/// Joins all Uri-Query options of the request into a single query string (e.g. `ep=dev&lt=60`).
+/// Returns `None` when the request has no query parameters at all.
+pub(super) fn get_query(request: &Request<SocketAddr>) -> Result<Option<String>, CoapError> {
+    let options = match request
+        .original
+        .message
+        .get_options_as::<OptionValueString>(CoapOption::UriQuery)
+    {
+        Some(options) => options,
+        None => return Ok(None),
+    };
+
+    // Try to read the options
+    let query = options
+        .into_iter()
+        .map(|option| option.map(|value| value.0))
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|_| CoapError {
+            code: Some(coap_lite::ResponseType::InternalServerError),
+            message: String::from("Failed to read options"),
+        })?
+        .join("&");
+    Ok(Some(query))
+}
+
+/// Returns the payload of the request as a str, checking that it is (or could be) link-format.
+pub(super) fn get_link_format_payload(request: &Request<SocketAddr>) -> Result<&str, CoapError> {
+    let payload_str = str::from_utf8(&request.original.message.payload).map_err(|_| CoapError {
+        code: Some(coap_lite::ResponseType::UnprocessableEntity),
+        message: String::from("Unreadable utf8 content"),
+    })?;
+
+    // Check if the content type is application/link-format
+    match request.original.message.get_content_format() {
+        None | Some(coap_lite::ContentFormat::ApplicationLinkFormat) => Ok(payload_str),
+        _ => Err(CoapError {
+            code: Some(coap_lite::ResponseType::UnsupportedContentFormat),
+            message: String::from("Content Type unsupported"),
+        }),
+    }
+}
+
+pub(crate) fn parse_link_format(payload: &str) -> Result<Vec<Lwm2mRegistrationObject>, CoapError> {
     let mut parser = LinkFormatParser::new(payload);
 
     parser.try_fold(vec![], |mut acc, link_result| {
@@ -0,0 +1,106 @@
+use coap_lite::{option_value::OptionValueU16, CoapOption, ResponseType};
+use coap_server::app::{CoapError, Request};
+use std::net::SocketAddr;
+
+use crate::content_format::{self, senml::ResolvedRecord, Lwm2mContentFormat};
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#6-4-3-0-643-Send-Operation
+// The values are kept as sent, they can only be converted once the sender and its object versions are known.
+#[derive(Debug)]
+pub struct Lwm2mSendRequest {
+    pub format: Lwm2mContentFormat,
+    pub records: Vec<ResolvedRecord>,
+}
+
+impl Lwm2mSendRequest {
+    pub fn new(request: Request<SocketAddr>) -> Result<Self, CoapError> {
+        let format = request
+            .original
+            .message
+            .get_first_option_as::<OptionValueU16>(CoapOption::ContentFormat)
+            .and_then(|format| format.ok())
+            .and_then(|format| Lwm2mContentFormat::try_from(format.0).ok());
+        let format = match format {
+            Some(format @ (Lwm2mContentFormat::SenmlJson | Lwm2mContentFormat::SenmlCbor)) => {
+                format
+            }
+            _ => {
+                return Err(CoapError {
+                    code: Some(ResponseType::UnsupportedContentFormat),
+                    message: String::from("Send requires a SenML JSON or CBOR payload"),
+                })
+            }
+        };
+
+        let records = content_format::decode_records(format, &request.original.message.payload)
+            .map_err(|err| CoapError::bad_request(err.to_string()))?;
+        if records.is_empty() {
+            return Err(CoapError::bad_request("Send requires at least one value"));
+        }
+        Ok(Lwm2mSendRequest { format, records })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_format::senml::SenmlValue;
+    use coap_lite::{CoapRequest, Packet};
+    use object_model::core_link::CoreLink;
+
+    fn request(format: Option<u16>, payload: &[u8]) -> Request<SocketAddr> {
+        let mut packet = Packet::new();
+        if let Some(format) = format {
+            packet.add_option_as(CoapOption::ContentFormat, OptionValueU16(format));
+        }
+        packet.payload = payload.to_vec();
+        Request {
+            original: CoapRequest::from_packet(packet, "127.0.0.1:56830".parse().unwrap()),
+            unmatched_path: vec!["dp".to_owned()],
+        }
+    }
+
+    #[test]
+    fn test_send_request() {
+        let send = Lwm2mSendRequest::new(request(
+            Some(110),
+            br#"[{"bn":"/3303/0/","n":"5700","v":21.5},{"n":"5701","vs":"Cel"}]"#,
+        ))
+        .unwrap();
+        assert_eq!(send.format, Lwm2mContentFormat::SenmlJson);
+        assert_eq!(
+            send.records,
+            vec![
+                ResolvedRecord {
+                    link: CoreLink::try_from("</3303/0/5700>").unwrap(),
+                    time: None,
+                    value: Some(SenmlValue::Float(21.5))
+                },
+                ResolvedRecord {
+                    link: CoreLink::try_from("</3303/0/5701>").unwrap(),
+                    time: None,
+                    value: Some(SenmlValue::String("Cel".to_owned()))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_send_request_unsupported_format() {
+        for format in [None, Some(0), Some(11542)] {
+            let result = Lwm2mSendRequest::new(request(format, b"21.5"));
+            assert_eq!(
+                result.unwrap_err().code,
+                Some(ResponseType::UnsupportedContentFormat)
+            );
+        }
+    }
+
+    #[test]
+    fn test_send_request_invalid_payload() {
+        for payload in [&b"[{"[..], b"[]", br#"[{"n":"temperature","v":1}]"#] {
+            let result = Lwm2mSendRequest::new(request(Some(110), payload));
+            assert_eq!(result.unwrap_err().code, Some(ResponseType::BadRequest));
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use coap_server::app::{CoapError, Request};
+use serde::Deserialize;
+use serde_querystring::from_str;
+use std::net::SocketAddr;
+
+use super::registration_request::{
+    get_link_format_payload, get_query, parse_link_format, Lwm2mBindMode, Lwm2mRegistrationObject,
+};
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Core-V1_2-20201110-A.html#6-2-2-0-622-Update
+// Every parameter is optional, only the ones that changed since the last registration are sent.
+#[derive(Debug, Default, Deserialize)]
+pub struct Lwm2mUpdateRequest {
+    #[serde(rename = "lt")]
+    pub lifetime: Option<u64>,
+    #[serde(rename = "b")]
+    pub binding_mode: Option<Lwm2mBindMode>,
+    #[serde(rename = "sms")]
+    pub sms_number: Option<String>,
+    #[serde(skip)]
+    pub objects: Option<Vec<Lwm2mRegistrationObject>>,
+}
+
+impl Lwm2mUpdateRequest {
+    pub fn new(request: Request<SocketAddr>) -> Result<Self, CoapError> {
+        // An update without any query parameters is valid, it only refreshes the registration
+        let mut update_request: Lwm2mUpdateRequest = match get_query(&request)? {
+            Some(query) => from_str(query.as_str(), serde_querystring::ParseMode::UrlEncoded)
+                .map_err(|err| CoapError {
+                    code: Some(coap_lite::ResponseType::UnprocessableEntity),
+                    message: format!("Incorrect URL query format: {}", err.message),
+                })?,
+            None => Lwm2mUpdateRequest::default(),
+        };
+
+        // The object list is only sent when it changed
+        let payload_str = get_link_format_payload(&request)?;
+        if !payload_str.trim().is_empty() {
+            update_request.objects = Some(parse_link_format(payload_str)?);
+        }
+
+        Ok(update_request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coap_lite::{CoapOption, CoapRequest, ContentFormat, Packet};
+
+    fn request(query: Option<&str>, payload: &str) -> Request<SocketAddr> {
+        let mut packet = Packet::new();
+        if let Some(query) = query {
+            packet.add_option(CoapOption::UriQuery, query.as_bytes().to_vec());
+        }
+        if !payload.is_empty() {
+            packet.set_content_format(ContentFormat::ApplicationLinkFormat);
+            packet.payload = payload.as_bytes().to_vec();
+        }
+        Request {
+            original: CoapRequest::from_packet(packet, "127.0.0.1:56830".parse().unwrap()),
+            unmatched_path: vec![],
+        }
+    }
+
+    #[test]
+    fn test_empty_update() {
+        let update = Lwm2mUpdateRequest::new(request(None, "")).unwrap();
+        assert!(update.lifetime.is_none());
+        assert!(update.binding_mode.is_none());
+        assert!(update.sms_number.is_none());
+        assert!(update.objects.is_none());
+    }
+
+    #[test]
+    fn test_update_parameters() {
+        let update =
+            Lwm2mUpdateRequest::new(request(Some("lt=600&b=T&sms=%2B3212345678"), "")).unwrap();
+        assert_eq!(update.lifetime, Some(600));
+        assert_eq!(update.binding_mode, Some(Lwm2mBindMode::Tcp));
+        assert_eq!(update.sms_number, Some("+3212345678".to_string()));
+        assert!(update.objects.is_none());
+    }
+
+    #[test]
+    fn test_update_objects() {
+        let update = Lwm2mUpdateRequest::new(request(None, "</1/0>,</3/0>,</5/0>")).unwrap();
+        assert_eq!(update.objects.map(|objects| objects.len()), Some(3));
+    }
+
+    #[test]
+    fn test_update_invalid_lifetime() {
+        let update = Lwm2mUpdateRequest::new(request(Some("lt=aaa"), ""));
+        assert!(update.is_err());
+    }
+}
@@ -1,28 +1,399 @@
 #![allow(dead_code, unused_variables)]
 
+use std::fs;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
 
-use crate::lwm2m_requests::registration_request::Lwm2mRegistrationRequest;
-use coap_server::app::{CoapError, Request, Response};
-use coap_server::{app, CoapServer, FatalServerError, UdpTransport};
+use crate::bootstrap::{config::BootstrapConfigStore, BootstrapServer};
+use crate::device::registry::DeviceRegistry;
+use crate::device::send::SendReceiver;
+use crate::lwm2m_requests::{
+    bootstrap_request::Lwm2mBootstrapRequest, registration_request::Lwm2mRegistrationRequest,
+    send_request::Lwm2mSendRequest, update_request::Lwm2mUpdateRequest,
+};
+use crate::transport::dtls::{DtlsCredentials, DtlsSessions, ServerCertificate};
+use crate::transport::{oscore::OscoreLayer, Peer, PeerIdentity, Route};
+use coap_lite::{CoapOption, ResponseType};
+use coap_server::app::{AppBuilder, CoapError, Request, Response};
+use coap_server::transport::TransportError;
+use coap_server::{app, CoapServer, FatalServerError};
+use object_model::ObjectModelStore;
+use openssl::x509::X509;
 
+mod bootstrap;
+mod content_format;
 mod device;
+mod lwm2m_operations;
 mod lwm2m_requests;
+mod oscore;
+mod transport;
+
+const OBJECT_MODELS_PATH: &str = "object_model/lwm2m-registry/version_history";
+// Loaded when it exists, without it no device can be bootstrapped
+const BOOTSTRAP_CONFIG_PATH: &str = "bootstrap.toml";
+// The certificate chain and key of the server, loaded when both exist. Without them devices
+// can only connect with pre-shared keys.
+const SERVER_CERTIFICATE_PATH: &str = "server.pem";
+const SERVER_KEY_PATH: &str = "server.key";
+// The certificate authorities that sign the certificates of devices, loaded when it exists
+const TRUSTED_CERTIFICATES_PATH: &str = "trusted.pem";
+// The sequence numbers reserved by the OSCORE contexts, so a restart reuses no nonce
+const OSCORE_STATE_PATH: &str = "oscore_state.json";
 
 #[tokio::main]
 async fn main() -> Result<(), FatalServerError> {
-    let server = CoapServer::bind(UdpTransport::new("0.0.0.0:5683")).await?;
-    server
-        .serve(
-            app::new()
-                .resource(app::resource("/hello").get(handle_get_hello))
-                .resource(app::resource("/rd").post(handle_register_device)),
+    let models = ObjectModelStore::new(Path::new(OBJECT_MODELS_PATH)).map_err(|err| {
+        FatalServerError::InternalError(format!("Could not load object models: {}", err))
+    })?;
+    let bootstrap_configs = match Path::new(BOOTSTRAP_CONFIG_PATH) {
+        path if path.exists() => BootstrapConfigStore::load(path, &models).map_err(|err| {
+            FatalServerError::InternalError(format!(
+                "Could not load bootstrap configuration: {}",
+                err
+            ))
+        })?,
+        _ => BootstrapConfigStore::default(),
+    };
+    let models = Arc::new(models);
+    let registry = Arc::new(DeviceRegistry::new());
+    let send_receiver = Arc::new(SendReceiver::new(registry.clone(), models.clone()));
+
+    // The client shares the sockets of the server, devices expect requests from the address they registered to
+    let credentials = dtls_credentials(&bootstrap_configs)?;
+    let oscore_contexts = bootstrap_configs
+        .oscore_contexts()
+        .and_then(|contexts| contexts.with_state_file(OSCORE_STATE_PATH))
+        .map_err(|err| {
+            FatalServerError::InternalError(format!("Could not load OSCORE contexts: {}", err))
+        })?;
+    let transports =
+        transport::bind_secure("0.0.0.0:5683", "0.0.0.0:5684", credentials, oscore_contexts)
+            .await
+            .map_err(|err| TransportError::IoError(Some(err)))?;
+    let bootstrap_server = Arc::new(BootstrapServer::new(
+        bootstrap_configs,
+        models,
+        transports.client,
+    ));
+
+    let udp_server = CoapServer::bind(transports.udp).await?;
+    let dtls_server = CoapServer::bind(transports.dtls).await?;
+    tokio::try_join!(
+        udp_server.serve(app(
+            registry.clone(),
+            send_receiver.clone(),
+            bootstrap_server.clone(),
+            Authentication::Oscore(transports.oscore)
+        )),
+        dtls_server.serve(app(
+            registry,
+            send_receiver,
+            bootstrap_server,
+            Authentication::Dtls(transports.sessions)
+        )),
+    )?;
+    Ok(())
+}
+// The pre-shared keys and raw public keys of the bootstrap configurations, along with the
+// server certificate and trusted certificates from their files
+fn dtls_credentials(
+    bootstrap_configs: &BootstrapConfigStore,
+) -> Result<DtlsCredentials, FatalServerError> {
+    let read_error = |path: &str, err: &dyn std::fmt::Display| {
+        FatalServerError::InternalError(format!("Could not read {}: {}", path, err))
+    };
+    let read = |path: &str| fs::read(path).map_err(|err| read_error(path, &err));
+
+    let mut trust_store = bootstrap_configs.trust_store();
+    if Path::new(TRUSTED_CERTIFICATES_PATH).exists() {
+        let authorities = X509::stack_from_pem(&read(TRUSTED_CERTIFICATES_PATH)?)
+            .map_err(|err| read_error(TRUSTED_CERTIFICATES_PATH, &err))?;
+        for authority in authorities {
+            trust_store.add_authority(authority);
+        }
+    }
+    let certificate = match (
+        Path::new(SERVER_CERTIFICATE_PATH).exists(),
+        Path::new(SERVER_KEY_PATH).exists(),
+    ) {
+        (true, true) => Some(
+            ServerCertificate::from_pem(&read(SERVER_CERTIFICATE_PATH)?, &read(SERVER_KEY_PATH)?)
+                .map_err(|err| read_error(SERVER_CERTIFICATE_PATH, &err))?,
+        ),
+        _ => None,
+    };
+    Ok(DtlsCredentials {
+        psk: bootstrap_configs.psk_store(),
+        certificate,
+        trust_store: Some(Arc::new(trust_store)),
+    })
+}
+// How the devices on a socket authenticate: in the DTLS handshake, or with OSCORE on the plain
+// UDP socket where devices without a security context stay anonymous
+#[derive(Clone)]
+enum Authentication {
+    Dtls(DtlsSessions),
+    Oscore(OscoreLayer),
+}
+// The resources of the server. `authentication` holds the identities of the devices, which may
+// then only register and bootstrap with the endpoint name of their identity.
+fn app(
+    registry: Arc<DeviceRegistry>,
+    send_receiver: Arc<SendReceiver>,
+    bootstrap_server: Arc<BootstrapServer>,
+    authentication: Authentication,
+) -> AppBuilder<SocketAddr> {
+    let deregister_registry = registry.clone();
+    let deregister_authentication = authentication.clone();
+    let register_bootstrap_server = bootstrap_server.clone();
+    let bootstrap_authentication = authentication.clone();
+    let send_registry = registry.clone();
+    let send_bootstrap_server = bootstrap_server.clone();
+    let send_authentication = authentication.clone();
+    app::new()
+        .resource(app::resource("/hello").get(handle_get_hello))
+        .resource(
+            app::resource("/rd")
+                .post(move |request| {
+                    handle_post_rd(
+                        request,
+                        registry.clone(),
+                        register_bootstrap_server.clone(),
+                        authentication.clone(),
+                    )
+                })
+                .delete(move |request| {
+                    handle_deregister_device(
+                        request,
+                        deregister_registry.clone(),
+                        deregister_authentication.clone(),
+                    )
+                }),
         )
+        .resource(app::resource("/bs").post(move |request| {
+            handle_bootstrap(
+                request,
+                bootstrap_server.clone(),
+                bootstrap_authentication.clone(),
+            )
+        }))
+        .resource(app::resource("/dp").post(move |request| {
+            handle_send(
+                request,
+                send_receiver.clone(),
+                send_registry.clone(),
+                send_bootstrap_server.clone(),
+                send_authentication.clone(),
+            )
+        }))
+}
+// The identity the sender of a request authenticated with, None for a request on the plain UDP
+// socket that was not protected with OSCORE
+fn peer_identity(
+    request: &Request<SocketAddr>,
+    authentication: &Authentication,
+) -> Result<Option<PeerIdentity>, CoapError> {
+    let source = request.original.source;
+    match authentication {
+        Authentication::Dtls(sessions) => source
+            .and_then(|source| sessions.identity(source))
+            .map(Some)
+            .ok_or_else(|| CoapError::for_code(ResponseType::Unauthorized, "No DTLS session")),
+        Authentication::Oscore(oscore) => {
+            Ok(source
+                .and_then(|source| oscore.identity(source, request.original.message.get_token())))
+        }
+    }
+}
+// Devices that authenticated can only act as an endpoint their identity is bound to, devices
+// the bootstrap configuration gives credentials can not act anonymously.
+// Returns the identity of the device, None for an anonymous device.
+fn authorize_endpoint(
+    request: &Request<SocketAddr>,
+    authentication: &Authentication,
+    bootstrap_server: &BootstrapServer,
+    device_endpoint: &str,
+) -> Result<Option<PeerIdentity>, CoapError> {
+    match peer_identity(request, authentication)? {
+        Some(identity) if !identity.matches(device_endpoint) => Err(CoapError::for_code(
+            ResponseType::Forbidden,
+            format!(
+                "Endpoint {} does not match the {}",
+                device_endpoint, identity
+            ),
+        )),
+        None if bootstrap_server.requires_authentication(device_endpoint) => {
+            Err(CoapError::for_code(
+                ResponseType::Unauthorized,
+                format!("Endpoint {} has to authenticate", device_endpoint),
+            ))
+        }
+        identity => Ok(identity),
+    }
+}
+// A registration can only be changed with the identity the device registered with
+async fn authorize_location(
+    request: &Request<SocketAddr>,
+    authentication: &Authentication,
+    registry: &DeviceRegistry,
+    location: &str,
+) -> Result<(), CoapError> {
+    let identity = peer_identity(request, authentication)?;
+    match registry
+        .with_device_at(location, |device| device.identity().cloned())
         .await
+    {
+        Some(registered) if registered != identity => Err(CoapError::for_code(
+            ResponseType::Forbidden,
+            format!("Registration {} belongs to another identity", location),
+        )),
+        // Unknown locations are left to the handlers
+        _ => Ok(()),
+    }
 }
-async fn handle_register_device(request: Request<SocketAddr>) -> Result<Response, CoapError> {
+// POST /rd registers a device, POST /rd/{location} updates an existing registration
+async fn handle_post_rd(
+    request: Request<SocketAddr>,
+    registry: Arc<DeviceRegistry>,
+    bootstrap_server: Arc<BootstrapServer>,
+    authentication: Authentication,
+) -> Result<Response, CoapError> {
+    match request.unmatched_path.as_slice() {
+        [] => handle_register_device(request, registry, &bootstrap_server, authentication).await,
+        [location] => {
+            let location = location.clone();
+            authorize_location(&request, &authentication, &registry, &location).await?;
+            handle_update_device(request, registry, &location).await
+        }
+        _ => Err(CoapError::not_found()),
+    }
+}
+async fn handle_register_device(
+    request: Request<SocketAddr>,
+    registry: Arc<DeviceRegistry>,
+    bootstrap_server: &BootstrapServer,
+    authentication: Authentication,
+) -> Result<Response, CoapError> {
     let registration_request = Lwm2mRegistrationRequest::new(request.clone())?;
-    Ok(request.new_response())
+    let identity = authorize_endpoint(
+        &request,
+        &authentication,
+        bootstrap_server,
+        &registration_request.device_endpoint,
+    )?;
+    let address = request
+        .original
+        .source
+        .ok_or_else(|| CoapError::internal("Registration request has no source address"))?;
+    let location = registry
+        .register(registration_request, address, identity)
+        .await;
+
+    // The device uses the returned Location-Path (/rd/{location}) for updates and de-registration
+    let mut response = request.new_response();
+    response.set_status(ResponseType::Created);
+    response
+        .message
+        .add_option(CoapOption::LocationPath, b"rd".to_vec());
+    response
+        .message
+        .add_option(CoapOption::LocationPath, location.into_bytes());
+    Ok(response)
+}
+async fn handle_update_device(
+    request: Request<SocketAddr>,
+    registry: Arc<DeviceRegistry>,
+    location: &str,
+) -> Result<Response, CoapError> {
+    let update_request = Lwm2mUpdateRequest::new(request.clone())?;
+    let address = request
+        .original
+        .source
+        .ok_or_else(|| CoapError::internal("Update request has no source address"))?;
+    if !registry.update(location, update_request, address).await {
+        return Err(CoapError::not_found());
+    }
+
+    let mut response = request.new_response();
+    response.set_status(ResponseType::Changed);
+    Ok(response)
+}
+async fn handle_deregister_device(
+    request: Request<SocketAddr>,
+    registry: Arc<DeviceRegistry>,
+    authentication: Authentication,
+) -> Result<Response, CoapError> {
+    let location = match request.unmatched_path.as_slice() {
+        [location] => location.clone(),
+        _ => return Err(CoapError::method_not_allowed()),
+    };
+    authorize_location(&request, &authentication, &registry, &location).await?;
+    registry
+        .deregister(&location)
+        .await
+        .ok_or_else(CoapError::not_found)?;
+
+    let mut response = request.new_response();
+    response.set_status(ResponseType::Deleted);
+    Ok(response)
+}
+// POST /dp carries the values a registered device pushes with the Send operation
+async fn handle_send(
+    request: Request<SocketAddr>,
+    receiver: Arc<SendReceiver>,
+    registry: Arc<DeviceRegistry>,
+    bootstrap_server: Arc<BootstrapServer>,
+    authentication: Authentication,
+) -> Result<Response, CoapError> {
+    let address = request
+        .original
+        .source
+        .ok_or_else(|| CoapError::internal("Send request has no source address"))?;
+    // Values are only taken from the identity the device registered with
+    let device_endpoint = registry
+        .device_endpoint_at(address)
+        .await
+        .ok_or_else(|| CoapError::for_code(ResponseType::Forbidden, "Sender is not registered"))?;
+    let identity = authorize_endpoint(
+        &request,
+        &authentication,
+        &bootstrap_server,
+        &device_endpoint,
+    )?;
+    let send_request = Lwm2mSendRequest::new(request.clone())?;
+    receiver
+        .receive(send_request, address, identity.as_ref())
+        .await?;
+
+    let mut response = request.new_response();
+    response.set_status(ResponseType::Changed);
+    Ok(response)
+}
+// POST /bs?ep={endpoint} asks to be provisioned with the configuration stored for the device
+async fn handle_bootstrap(
+    request: Request<SocketAddr>,
+    bootstrap_server: Arc<BootstrapServer>,
+    authentication: Authentication,
+) -> Result<Response, CoapError> {
+    let bootstrap_request = Lwm2mBootstrapRequest::new(request.clone())?;
+    let identity = authorize_endpoint(
+        &request,
+        &authentication,
+        &bootstrap_server,
+        &bootstrap_request.device_endpoint,
+    )?;
+    let address = request
+        .original
+        .source
+        .ok_or_else(|| CoapError::internal("Bootstrap request has no source address"))?;
+    let peer = Peer::new(address, Route::of(identity.as_ref()));
+    let session = bootstrap_server.accept(&bootstrap_request, peer)?;
+    bootstrap_server.start(session, request.original.message.get_token());
+
+    let mut response = request.new_response();
+    response.set_status(ResponseType::Changed);
+    Ok(response)
 }
 async fn handle_get_hello(request: Request<SocketAddr>) -> Result<Response, CoapError> {
     let whom = request
@@ -35,3 +406,478 @@ async fn handle_get_hello(request: Request<SocketAddr>) -> Result<Response, Coap
     response.message.payload = format!("Hello, {whom}").into_bytes();
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootstrap::{
+        config::{BootstrapConfig, OscoreInstance, SecurityInstance, SecurityMode, ServerInstance},
+        BootstrapStep, StepOutcome,
+    };
+    use crate::lwm2m_operations::test_device::{
+        oscore_object, security_object, security_object_v11, server_object,
+    };
+    use crate::lwm2m_requests::registration_request::Lwm2mVersion;
+    use crate::oscore::{
+        context::{tests::client_parameters, SecurityContext},
+        OscoreContexts,
+    };
+    use crate::transport::{
+        dtls::PskStore,
+        test_client::{
+            authority, certificate, free_address, server_certificate, CertificateName,
+            DtlsTestClient,
+        },
+        trust_store::LocalTrustStore,
+    };
+    use coap_lite::{MessageClass, MessageType, Packet, RequestType};
+    use openssl::pkey::{PKey, Private};
+    use std::{collections::BTreeMap, future::Future};
+    use tokio::net::UdpSocket;
+
+    const KEY: [u8; 4] = [0x01, 0x02, 0x03, 0x04];
+
+    // Runs `test` against the DTLS socket of a new server, which trusts the certificates signed
+    // by the authority handed to `test`
+    async fn with_server<F: Future<Output = ()>>(
+        test: impl FnOnce(SocketAddr, (X509, PKey<Private>)) -> F,
+    ) {
+        let authority = authority("Device CA", None);
+        let mut psk = PskStore::default();
+        psk.add(b"device123", &KEY);
+        psk.add(b"device456", &KEY);
+        let mut trust_store = LocalTrustStore::default();
+        trust_store.add_authority(authority.0.clone());
+        let credentials = DtlsCredentials {
+            psk,
+            certificate: Some(server_certificate()),
+            trust_store: Some(Arc::new(trust_store)),
+        };
+        let dtls_address = free_address();
+        let transports = transport::bind_secure(
+            free_address(),
+            dtls_address,
+            credentials,
+            OscoreContexts::default(),
+        )
+        .await
+        .unwrap();
+        let registry = Arc::new(DeviceRegistry::new());
+        let models = Arc::new(ObjectModelStore::default());
+        let send_receiver = Arc::new(SendReceiver::new(registry.clone(), models.clone()));
+        let bootstrap_server = Arc::new(BootstrapServer::new(
+            BootstrapConfigStore::default(),
+            models,
+            transports.client,
+        ));
+        let server = CoapServer::bind(transports.dtls).await.unwrap();
+        let app = app(
+            registry,
+            send_receiver,
+            bootstrap_server,
+            Authentication::Dtls(transports.sessions),
+        );
+        tokio::select! {
+            _ = server.serve(app) => panic!("Server stopped"),
+            _ = test(dtls_address, authority) => {}
+        }
+    }
+
+    // Runs `test` against the UDP socket of a new server whose bootstrap configuration gives
+    // device123 the OSCORE context from RFC 8613, and device456 no credentials
+    async fn with_oscore_server<F: Future<Output = ()>>(
+        test: impl FnOnce(SocketAddr, Arc<BootstrapServer>) -> F,
+    ) {
+        let client = client_parameters();
+        let mut configs = BootstrapConfigStore::default();
+        configs.add(
+            "device456",
+            BootstrapConfig {
+                lwm2m_version: Lwm2mVersion::V10,
+                security: vec![SecurityInstance {
+                    instance_id: 0,
+                    server_uri: "coap://lwm2m.example.com:5683".to_owned(),
+                    bootstrap_server: false,
+                    security_mode: SecurityMode::NoSec,
+                    public_key_or_identity: vec![],
+                    server_public_key: vec![],
+                    secret_key: vec![],
+                    short_server_id: Some(101),
+                    oscore_instance: None,
+                    resources: BTreeMap::new(),
+                }],
+                servers: vec![ServerInstance {
+                    instance_id: 0,
+                    short_server_id: 101,
+                    lifetime: 3600,
+                    default_min_period: None,
+                    default_max_period: None,
+                    notification_storing: false,
+                    binding: "U".to_owned(),
+                    resources: BTreeMap::new(),
+                }],
+                oscore: vec![],
+            },
+        );
+        configs.add(
+            "device123",
+            BootstrapConfig {
+                lwm2m_version: Lwm2mVersion::V11,
+                security: vec![SecurityInstance {
+                    instance_id: 0,
+                    server_uri: "coap://lwm2m.example.com:5683".to_owned(),
+                    bootstrap_server: false,
+                    security_mode: SecurityMode::NoSec,
+                    public_key_or_identity: vec![],
+                    server_public_key: vec![],
+                    secret_key: vec![],
+                    short_server_id: Some(101),
+                    oscore_instance: Some(0),
+                    resources: BTreeMap::new(),
+                }],
+                servers: vec![],
+                oscore: vec![OscoreInstance {
+                    instance_id: 0,
+                    master_secret: client.master_secret,
+                    sender_id: client.sender_id,
+                    recipient_id: client.recipient_id,
+                    master_salt: client.master_salt,
+                    id_context: client.id_context,
+                    resources: BTreeMap::new(),
+                }],
+            },
+        );
+        let contexts = configs.oscore_contexts().unwrap();
+        let udp_address = free_address();
+        let transports = transport::bind_secure(
+            udp_address,
+            free_address(),
+            DtlsCredentials::default(),
+            contexts,
+        )
+        .await
+        .unwrap();
+        let registry = Arc::new(DeviceRegistry::new());
+        let mut models = ObjectModelStore::default();
+        for model in [
+            security_object(),
+            security_object_v11(),
+            server_object(),
+            oscore_object(),
+        ] {
+            models.add_model(model);
+        }
+        let models = Arc::new(models);
+        let send_receiver = Arc::new(SendReceiver::new(registry.clone(), models.clone()));
+        let bootstrap_server = Arc::new(BootstrapServer::new(configs, models, transports.client));
+        let server = CoapServer::bind(transports.udp).await.unwrap();
+        let app = app(
+            registry,
+            send_receiver,
+            bootstrap_server.clone(),
+            Authentication::Oscore(transports.oscore),
+        );
+        tokio::select! {
+            _ = server.serve(app) => panic!("Server stopped"),
+            _ = test(udp_address, bootstrap_server) => {}
+        }
+    }
+
+    fn request(method: RequestType, path: &[&str], query: &[&str], payload: &[u8]) -> Packet {
+        let mut request = Packet::new();
+        request.header.set_type(MessageType::Confirmable);
+        request.header.code = MessageClass::Request(method);
+        request.header.message_id = 1;
+        for segment in path {
+            request.add_option(CoapOption::UriPath, segment.as_bytes().to_vec());
+        }
+        for parameter in query {
+            request.add_option(CoapOption::UriQuery, parameter.as_bytes().to_vec());
+        }
+        request.payload = payload.to_vec();
+        request
+    }
+
+    fn register(device_endpoint: &str) -> Packet {
+        let endpoint = format!("ep={}", device_endpoint);
+        request(
+            RequestType::Post,
+            &["rd"],
+            &[&endpoint, "lt=3600", "lwm2m=1.1", "b=U"],
+            b"</3/0>",
+        )
+    }
+
+    #[tokio::test]
+    async fn test_register_with_psk_identity() {
+        with_server(|server, _| async move {
+            let mut device = DtlsTestClient::connect(server, b"device123", &KEY)
+                .await
+                .unwrap();
+
+            device.send(&register("device123")).await;
+            let response = device.recv().await;
+            assert_eq!(
+                response.header.code,
+                MessageClass::Response(ResponseType::Created)
+            );
+            let location = response
+                .get_option(CoapOption::LocationPath)
+                .unwrap()
+                .back()
+                .unwrap()
+                .clone();
+            let location = String::from_utf8(location).unwrap();
+
+            // Another device can not update or remove the registration
+            let mut other = DtlsTestClient::connect(server, b"device456", &KEY)
+                .await
+                .unwrap();
+            other
+                .send(&request(RequestType::Post, &["rd", &location], &[], b""))
+                .await;
+            assert_eq!(
+                other.recv().await.header.code,
+                MessageClass::Response(ResponseType::Forbidden)
+            );
+            other
+                .send(&request(RequestType::Delete, &["rd", &location], &[], b""))
+                .await;
+            assert_eq!(
+                other.recv().await.header.code,
+                MessageClass::Response(ResponseType::Forbidden)
+            );
+
+            device
+                .send(&request(RequestType::Delete, &["rd", &location], &[], b""))
+                .await;
+            assert_eq!(
+                device.recv().await.header.code,
+                MessageClass::Response(ResponseType::Deleted)
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_register_with_other_endpoint() {
+        with_server(|server, _| async move {
+            let mut device = DtlsTestClient::connect(server, b"device123", &KEY)
+                .await
+                .unwrap();
+
+            device.send(&register("device456")).await;
+            assert_eq!(
+                device.recv().await.header.code,
+                MessageClass::Response(ResponseType::Forbidden)
+            );
+            device
+                .send(&request(RequestType::Post, &["bs"], &["ep=device456"], b""))
+                .await;
+            assert_eq!(
+                device.recv().await.header.code,
+                MessageClass::Response(ResponseType::Forbidden)
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_register_with_certificate() {
+        with_server(|server, authority| async move {
+            let (certificate, key) = certificate(
+                "sensor-1",
+                &[CertificateName::Uri("urn:imei:490154203237518")],
+                Some(&authority),
+            );
+            let mut device = DtlsTestClient::connect_with_certificate(server, &[certificate], &key)
+                .await
+                .unwrap();
+
+            device.send(&register("device123")).await;
+            assert_eq!(
+                device.recv().await.header.code,
+                MessageClass::Response(ResponseType::Forbidden)
+            );
+            device.send(&register("urn:imei:490154203237518")).await;
+            assert_eq!(
+                device.recv().await.header.code,
+                MessageClass::Response(ResponseType::Created)
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_register_anonymously() {
+        with_oscore_server(|server, _| async move {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let exchange = |request: Packet| {
+                let socket = &socket;
+                async move {
+                    socket
+                        .send_to(&request.to_bytes().unwrap(), server)
+                        .await
+                        .unwrap();
+                    let mut buffer = [0; 1500];
+                    let (length, _) = socket.recv_from(&mut buffer).await.unwrap();
+                    Packet::from_bytes(&buffer[..length]).unwrap().header.code
+                }
+            };
+
+            // device123 has an OSCORE context, it can not register or bootstrap without it
+            assert_eq!(
+                exchange(register("device123")).await,
+                MessageClass::Response(ResponseType::Unauthorized)
+            );
+            assert_eq!(
+                exchange(request(RequestType::Post, &["bs"], &["ep=device123"], b"")).await,
+                MessageClass::Response(ResponseType::Unauthorized)
+            );
+            // Devices without credentials stay anonymous
+            assert_eq!(
+                exchange(register("device456")).await,
+                MessageClass::Response(ResponseType::Created)
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_register_with_oscore() {
+        with_oscore_server(|server, _| async move {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let mut context = SecurityContext::derive(&client_parameters()).unwrap();
+            let mut buffer = [0; 1500];
+            let protect = |context: &mut SecurityContext, mut request: Packet, message_id: u16| {
+                request.header.message_id = message_id;
+                request.set_token(message_id.to_be_bytes().to_vec());
+                context.protect_request(&request).unwrap()
+            };
+
+            let (protected, request_id) = protect(&mut context, register("device456"), 1);
+            socket
+                .send_to(&protected.to_bytes().unwrap(), server)
+                .await
+                .unwrap();
+            let (length, _) = socket.recv_from(&mut buffer).await.unwrap();
+            let response = Packet::from_bytes(&buffer[..length]).unwrap();
+            let response = context.unprotect_response(&response, &request_id).unwrap();
+            assert_eq!(
+                response.header.code,
+                MessageClass::Response(ResponseType::Forbidden)
+            );
+
+            let (protected, request_id) = protect(&mut context, register("device123"), 2);
+            socket
+                .send_to(&protected.to_bytes().unwrap(), server)
+                .await
+                .unwrap();
+            let (length, _) = socket.recv_from(&mut buffer).await.unwrap();
+            let response = Packet::from_bytes(&buffer[..length]).unwrap();
+            // Proxies only see the outer code
+            assert_eq!(
+                response.header.code,
+                MessageClass::Response(ResponseType::Changed)
+            );
+            let response = context.unprotect_response(&response, &request_id).unwrap();
+            assert_eq!(
+                response.header.code,
+                MessageClass::Response(ResponseType::Created)
+            );
+            let location = response
+                .get_option(CoapOption::LocationPath)
+                .unwrap()
+                .back()
+                .unwrap()
+                .clone();
+            let location = String::from_utf8(location).unwrap();
+
+            // Without protection the registration can not be removed
+            let mut deregister = request(RequestType::Delete, &["rd", &location], &[], b"");
+            deregister.header.message_id = 3;
+            socket
+                .send_to(&deregister.to_bytes().unwrap(), server)
+                .await
+                .unwrap();
+            let (length, _) = socket.recv_from(&mut buffer).await.unwrap();
+            let response = Packet::from_bytes(&buffer[..length]).unwrap();
+            assert_eq!(
+                response.header.code,
+                MessageClass::Response(ResponseType::Forbidden)
+            );
+
+            // Nor can values be sent for it from its address
+            let mut send = request(RequestType::Post, &["dp"], &[], b"");
+            send.header.message_id = 4;
+            socket
+                .send_to(&send.to_bytes().unwrap(), server)
+                .await
+                .unwrap();
+            let (length, _) = socket.recv_from(&mut buffer).await.unwrap();
+            let response = Packet::from_bytes(&buffer[..length]).unwrap();
+            assert_eq!(
+                response.header.code,
+                MessageClass::Response(ResponseType::Unauthorized)
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_after_response() {
+        with_oscore_server(|server, bootstrap_server| async move {
+            let mut reports = bootstrap_server.subscribe();
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let recv = || async {
+                let mut buffer = [0; 1500];
+                let (length, _) = socket.recv_from(&mut buffer).await.unwrap();
+                Packet::from_bytes(&buffer[..length]).unwrap()
+            };
+            let mut bootstrap_request = request(RequestType::Post, &["bs"], &["ep=device456"], b"");
+            bootstrap_request.set_token(vec![0x42]);
+            socket
+                .send_to(&bootstrap_request.to_bytes().unwrap(), server)
+                .await
+                .unwrap();
+
+            // The response comes first, the sequence only starts once it was sent
+            let response = recv().await;
+            assert_eq!(
+                response.header.code,
+                MessageClass::Response(ResponseType::Changed)
+            );
+            assert_eq!(response.get_token(), &[0x42]);
+
+            // Neither Bootstrap-Discover nor Bootstrap-Delete is supported by the device
+            for (method, code) in [
+                (RequestType::Get, ResponseType::NotFound),
+                (RequestType::Delete, ResponseType::MethodNotAllowed),
+            ] {
+                let request = recv().await;
+                assert_eq!(request.header.code, MessageClass::Request(method));
+                let mut response = Packet::new();
+                response.header.set_type(MessageType::Acknowledgement);
+                response.header.code = MessageClass::Response(code);
+                response.header.message_id = request.header.message_id;
+                response.set_token(request.get_token().to_vec());
+                socket
+                    .send_to(&response.to_bytes().unwrap(), server)
+                    .await
+                    .unwrap();
+            }
+
+            let report = reports.recv().await.unwrap();
+            assert_eq!(report.device_endpoint, "device456");
+            assert!(!report.is_complete());
+            assert!(matches!(
+                report.steps[..],
+                [
+                    (BootstrapStep::Discover, StepOutcome::Failed(_)),
+                    (BootstrapStep::Delete, StepOutcome::Failed(_))
+                ]
+            ));
+        })
+        .await;
+    }
+}
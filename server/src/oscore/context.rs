@@ -0,0 +1,384 @@
+use ciborium::value::{Integer, Value as CborValue};
+use openssl::{
+    cipher::Cipher, cipher_ctx::CipherCtx, md::Md, pkey::Id, pkey_ctx::PkeyCtx, sha::sha256,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{err::OscoreError, replay::ReplayWindow};
+
+// Based on https://www.rfc-editor.org/rfc/rfc8613#section-3.2
+// The default AEAD algorithm AES-CCM-16-64-128 and its COSE identifier
+const AEAD_ALGORITHM: i64 = 10;
+pub(super) const KEY_LENGTH: usize = 16;
+pub(super) const NONCE_LENGTH: usize = 13;
+pub(super) const TAG_LENGTH: usize = 8;
+// Sender and recipient IDs are padded into the nonce, together with a 5 byte Partial IV
+pub(super) const MAX_ID_LENGTH: usize = NONCE_LENGTH - 6;
+pub(super) const MAX_PARTIAL_IV_LENGTH: usize = 5;
+const MAX_SEQUENCE_NUMBER: u64 = (1 << 40) - 1;
+// Based on https://www.rfc-editor.org/rfc/rfc8613#appendix-B.1.1
+// Sequence numbers are reserved this many at a time, so not every message is stored
+const SEQUENCE_NUMBER_STEP: u64 = 32;
+
+/// The input to an OSCORE security context, e.g. from the OSCORE object (21) of a device.
+/// Sender and recipient are seen from the server, the sender ID of the server is the
+/// recipient ID of the device and vice versa.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OscoreParameters {
+    pub master_secret: Vec<u8>,
+    pub master_salt: Vec<u8>,
+    pub sender_id: Vec<u8>,
+    pub recipient_id: Vec<u8>,
+    pub id_context: Option<Vec<u8>>,
+}
+
+/// The part of a security context that has to survive a restart of the server, so no nonce
+/// is used twice and no request is accepted twice, see https://www.rfc-editor.org/rfc/rfc8613#appendix-B.1
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextState {
+    // Identifies the keys the state belongs to, a context with new keys starts over
+    pub key_digest: Vec<u8>,
+    // Every sender sequence number below it may have been used
+    pub sender_sequence_number: u64,
+    // Every recipient sequence number below it may have been received, the replay window
+    // itself is lost with a restart
+    pub recipient_sequence_number: u64,
+}
+
+/// The keys and state shared by the server and one device to protect their messages.
+#[derive(Debug, Clone)]
+pub struct SecurityContext {
+    pub(super) sender_id: Vec<u8>,
+    pub(super) recipient_id: Vec<u8>,
+    pub(super) id_context: Option<Vec<u8>>,
+    pub(super) sender_key: [u8; KEY_LENGTH],
+    pub(super) recipient_key: [u8; KEY_LENGTH],
+    pub(super) common_iv: [u8; NONCE_LENGTH],
+    pub(super) sender_sequence_number: u64,
+    pub(super) replay_window: ReplayWindow,
+    // The state last stored, the context may not advance past it before it is stored again
+    stored: ContextState,
+}
+
+impl SecurityContext {
+    // Based on https://www.rfc-editor.org/rfc/rfc8613#section-3.2.1
+    pub fn derive(parameters: &OscoreParameters) -> Result<Self, OscoreError> {
+        if parameters.sender_id.len() > MAX_ID_LENGTH
+            || parameters.recipient_id.len() > MAX_ID_LENGTH
+        {
+            return Err(OscoreError::InvalidParameters(format!(
+                "sender and recipient IDs are limited to {} bytes",
+                MAX_ID_LENGTH
+            )));
+        }
+        if parameters.sender_id == parameters.recipient_id {
+            return Err(OscoreError::InvalidParameters(
+                "sender and recipient IDs must differ".to_owned(),
+            ));
+        }
+
+        let derive = |id: &[u8], kind: &str, buffer: &mut [u8]| {
+            hkdf(
+                &parameters.master_secret,
+                &parameters.master_salt,
+                &info(id, parameters.id_context.as_deref(), kind, buffer.len()),
+                buffer,
+            )
+        };
+        let mut sender_key = [0; KEY_LENGTH];
+        derive(&parameters.sender_id, "Key", &mut sender_key)?;
+        let mut recipient_key = [0; KEY_LENGTH];
+        derive(&parameters.recipient_id, "Key", &mut recipient_key)?;
+        let mut common_iv = [0; NONCE_LENGTH];
+        derive(&[], "IV", &mut common_iv)?;
+
+        let key_digest = sha256(&[sender_key, recipient_key].concat()).to_vec();
+        Ok(SecurityContext {
+            sender_id: parameters.sender_id.clone(),
+            recipient_id: parameters.recipient_id.clone(),
+            id_context: parameters.id_context.clone(),
+            sender_key,
+            recipient_key,
+            common_iv,
+            sender_sequence_number: 0,
+            replay_window: ReplayWindow::default(),
+            stored: ContextState {
+                key_digest,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Continues from a state stored before a restart. A state of other keys is ignored.
+    pub(super) fn restore(&mut self, state: &ContextState) {
+        if state.key_digest != self.stored.key_digest {
+            return;
+        }
+        self.sender_sequence_number = state.sender_sequence_number;
+        self.replay_window = ReplayWindow::below(state.recipient_sequence_number);
+        self.stored = state.clone();
+    }
+
+    /// The state to store when the context advanced past the stored one, with the next
+    /// sender and recipient sequence numbers reserved. Call `stored` once it is stored.
+    pub(super) fn pending_state(&self) -> Option<ContextState> {
+        let sender_advanced = self.sender_sequence_number > self.stored.sender_sequence_number;
+        let received = self
+            .replay_window
+            .highest()
+            .map_or(0, |highest| highest + 1);
+        let recipient_advanced = received > self.stored.recipient_sequence_number;
+        if !sender_advanced && !recipient_advanced {
+            return None;
+        }
+        let reserve = |advanced: bool, next: u64, stored: u64| match advanced {
+            true => next + SEQUENCE_NUMBER_STEP,
+            false => stored,
+        };
+        Some(ContextState {
+            key_digest: self.stored.key_digest.clone(),
+            sender_sequence_number: reserve(
+                sender_advanced,
+                self.sender_sequence_number,
+                self.stored.sender_sequence_number,
+            ),
+            recipient_sequence_number: reserve(
+                recipient_advanced,
+                received,
+                self.stored.recipient_sequence_number,
+            ),
+        })
+    }
+
+    pub(super) fn stored(&mut self, state: ContextState) {
+        self.stored = state;
+    }
+
+    pub fn sender_id(&self) -> &[u8] {
+        &self.sender_id
+    }
+
+    pub fn recipient_id(&self) -> &[u8] {
+        &self.recipient_id
+    }
+
+    pub fn id_context(&self) -> Option<&[u8]> {
+        self.id_context.as_deref()
+    }
+
+    /// The Partial IV of the next message the server protects with its own sequence number.
+    pub(super) fn next_partial_iv(&mut self) -> Result<Vec<u8>, OscoreError> {
+        if self.sender_sequence_number > MAX_SEQUENCE_NUMBER {
+            return Err(OscoreError::SequenceNumberExhausted);
+        }
+        let partial_iv = encode_partial_iv(self.sender_sequence_number);
+        self.sender_sequence_number += 1;
+        Ok(partial_iv)
+    }
+
+    // Based on https://www.rfc-editor.org/rfc/rfc8613#section-5.2
+    pub(super) fn nonce(&self, id: &[u8], partial_iv: &[u8]) -> [u8; NONCE_LENGTH] {
+        let mut nonce = [0; NONCE_LENGTH];
+        nonce[0] = id.len() as u8;
+        nonce[1 + MAX_ID_LENGTH - id.len()..1 + MAX_ID_LENGTH].copy_from_slice(id);
+        nonce[NONCE_LENGTH - partial_iv.len()..].copy_from_slice(partial_iv);
+        for (byte, iv) in nonce.iter_mut().zip(self.common_iv) {
+            *byte ^= iv;
+        }
+        nonce
+    }
+
+    /// Encrypts `plaintext` with the sender key, the tag is appended to the ciphertext.
+    pub(super) fn encrypt(
+        &self,
+        nonce: &[u8],
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, OscoreError> {
+        let mut context = CipherCtx::new()?;
+        context.encrypt_init(Some(Cipher::aes_128_ccm()), None, None)?;
+        // CCM takes the lengths before the key
+        context.set_iv_length(NONCE_LENGTH)?;
+        context.set_tag_length(TAG_LENGTH)?;
+        context.encrypt_init(None, Some(&self.sender_key), Some(nonce))?;
+        context.set_data_len(plaintext.len())?;
+        context.cipher_update(aad, None)?;
+        let mut ciphertext = Vec::new();
+        context.cipher_update_vec(plaintext, &mut ciphertext)?;
+        context.cipher_final_vec(&mut ciphertext)?;
+        let mut tag = [0; TAG_LENGTH];
+        context.tag(&mut tag)?;
+        ciphertext.extend_from_slice(&tag);
+        Ok(ciphertext)
+    }
+
+    /// Decrypts and verifies `ciphertext` with the recipient key.
+    pub(super) fn decrypt(
+        &self,
+        nonce: &[u8],
+        aad: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, OscoreError> {
+        if ciphertext.len() < TAG_LENGTH {
+            return Err(OscoreError::DecryptionFailed);
+        }
+        let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - TAG_LENGTH);
+        let decrypt = || {
+            let mut context = CipherCtx::new()?;
+            context.decrypt_init(Some(Cipher::aes_128_ccm()), None, None)?;
+            context.set_iv_length(NONCE_LENGTH)?;
+            context.set_tag(tag)?;
+            context.decrypt_init(None, Some(&self.recipient_key), Some(nonce))?;
+            context.set_data_len(ciphertext.len())?;
+            context.cipher_update(aad, None)?;
+            // CCM verifies the tag in the update, there is nothing to finalize
+            let mut plaintext = Vec::new();
+            context.cipher_update_vec(ciphertext, &mut plaintext)?;
+            Ok::<_, openssl::error::ErrorStack>(plaintext)
+        };
+        decrypt().map_err(|_| OscoreError::DecryptionFailed)
+    }
+}
+
+/// The minimal big-endian encoding of a sequence number, at least one byte.
+pub(super) fn encode_partial_iv(sequence_number: u64) -> Vec<u8> {
+    let bytes = sequence_number.to_be_bytes();
+    let start = bytes
+        .iter()
+        .position(|&byte| byte != 0)
+        .unwrap_or(bytes.len() - 1);
+    bytes[start..].to_vec()
+}
+
+pub(super) fn decode_partial_iv(partial_iv: &[u8]) -> u64 {
+    partial_iv
+        .iter()
+        .fold(0, |number, &byte| (number << 8) | byte as u64)
+}
+
+// Based on https://www.rfc-editor.org/rfc/rfc8613#section-5.4
+// The additional authenticated data: a COSE Enc_structure with the request kid and Partial IV
+pub(super) fn aad(request_kid: &[u8], request_partial_iv: &[u8]) -> Vec<u8> {
+    let external_aad = CborValue::Array(vec![
+        CborValue::Integer(Integer::from(1)),
+        CborValue::Array(vec![CborValue::Integer(Integer::from(AEAD_ALGORITHM))]),
+        CborValue::Bytes(request_kid.to_vec()),
+        CborValue::Bytes(request_partial_iv.to_vec()),
+        CborValue::Bytes(Vec::new()),
+    ]);
+    let enc_structure = CborValue::Array(vec![
+        CborValue::Text("Encrypt0".to_owned()),
+        CborValue::Bytes(Vec::new()),
+        CborValue::Bytes(to_cbor(&external_aad)),
+    ]);
+    to_cbor(&enc_structure)
+}
+
+// Based on https://www.rfc-editor.org/rfc/rfc8613#section-3.2.1
+fn info(id: &[u8], id_context: Option<&[u8]>, kind: &str, length: usize) -> Vec<u8> {
+    to_cbor(&CborValue::Array(vec![
+        CborValue::Bytes(id.to_vec()),
+        id_context.map_or(CborValue::Null, |context| {
+            CborValue::Bytes(context.to_vec())
+        }),
+        CborValue::Integer(Integer::from(AEAD_ALGORITHM)),
+        CborValue::Text(kind.to_owned()),
+        CborValue::Integer(Integer::from(length)),
+    ]))
+}
+
+fn to_cbor(value: &CborValue) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    // Writing into a Vec does not fail
+    ciborium::ser::into_writer(value, &mut bytes).unwrap();
+    bytes
+}
+
+fn hkdf(secret: &[u8], salt: &[u8], info: &[u8], output: &mut [u8]) -> Result<(), OscoreError> {
+    let mut context = PkeyCtx::new_id(Id::HKDF)?;
+    context.derive_init()?;
+    context.set_hkdf_md(Md::sha256())?;
+    context.set_hkdf_key(secret)?;
+    // An empty salt is the default, OpenSSL rejects it when set explicitly
+    if !salt.is_empty() {
+        context.set_hkdf_salt(salt)?;
+    }
+    context.add_hkdf_info(info)?;
+    context.derive(Some(output))?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // The context of the client in https://www.rfc-editor.org/rfc/rfc8613#appendix-C.1
+    pub(crate) fn client_parameters() -> OscoreParameters {
+        OscoreParameters {
+            master_secret: hex("0102030405060708090a0b0c0d0e0f10"),
+            master_salt: hex("9e7ca92223786340"),
+            sender_id: vec![],
+            recipient_id: vec![0x01],
+            id_context: None,
+        }
+    }
+
+    pub(crate) fn server_parameters() -> OscoreParameters {
+        let client = client_parameters();
+        OscoreParameters {
+            sender_id: client.recipient_id,
+            recipient_id: client.sender_id,
+            ..client
+        }
+    }
+
+    #[test]
+    fn test_derive() {
+        let client = SecurityContext::derive(&client_parameters()).unwrap();
+        assert_eq!(
+            client.sender_key.to_vec(),
+            hex("f0910ed7295e6ad4b54fc793154302ff")
+        );
+        assert_eq!(
+            client.recipient_key.to_vec(),
+            hex("ffb14e093c94c9cac9471648b4f98710")
+        );
+        assert_eq!(client.common_iv.to_vec(), hex("4622d4dd6d944168eefb54987c"));
+
+        let server = SecurityContext::derive(&server_parameters()).unwrap();
+        assert_eq!(server.sender_key, client.recipient_key);
+        assert_eq!(server.recipient_key, client.sender_key);
+        assert_eq!(server.common_iv, client.common_iv);
+
+        let mut invalid = server_parameters();
+        invalid.sender_id = vec![0; 8];
+        assert!(matches!(
+            SecurityContext::derive(&invalid),
+            Err(OscoreError::InvalidParameters(_))
+        ));
+    }
+
+    #[test]
+    fn test_partial_iv() {
+        assert_eq!(encode_partial_iv(0), vec![0]);
+        assert_eq!(encode_partial_iv(20), vec![0x14]);
+        assert_eq!(encode_partial_iv(0x0102), vec![0x01, 0x02]);
+        assert_eq!(decode_partial_iv(&[0x01, 0x02]), 0x0102);
+
+        let mut context = SecurityContext::derive(&server_parameters()).unwrap();
+        assert_eq!(context.next_partial_iv(), Ok(vec![0]));
+        assert_eq!(context.next_partial_iv(), Ok(vec![1]));
+        context.sender_sequence_number = MAX_SEQUENCE_NUMBER + 1;
+        assert_eq!(
+            context.next_partial_iv(),
+            Err(OscoreError::SequenceNumberExhausted)
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use openssl::error::ErrorStack;
+use std::{error::Error, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OscoreError {
+    // The parameters cannot make a context, e.g. an ID longer than the nonce allows
+    InvalidParameters(String),
+    // The OSCORE option is missing or malformed
+    InvalidOption,
+    // No context has the kid (and kid context) of a request
+    UnknownContext,
+    // The Partial IV of a request was already received or is older than the replay window
+    Replay,
+    // The message did not decrypt, or its plaintext is not a CoAP message
+    DecryptionFailed,
+    // All sequence numbers were used, the context has to be renewed
+    SequenceNumberExhausted,
+    // The state of a context could not be stored or loaded
+    Storage(String),
+    Crypto(String),
+}
+
+impl fmt::Display for OscoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            OscoreError::InvalidParameters(message) => {
+                write!(f, "Invalid OSCORE parameters: {}", message)
+            }
+            OscoreError::InvalidOption => write!(f, "Invalid OSCORE option"),
+            OscoreError::UnknownContext => write!(f, "Security context not found"),
+            OscoreError::Replay => write!(f, "Replay detected"),
+            OscoreError::DecryptionFailed => write!(f, "Decryption failed"),
+            OscoreError::SequenceNumberExhausted => {
+                write!(f, "Sender sequence numbers are exhausted")
+            }
+            OscoreError::Storage(message) => write!(f, "OSCORE state storage: {}", message),
+            OscoreError::Crypto(message) => write!(f, "OSCORE crypto error: {}", message),
+        }
+    }
+}
+
+impl Error for OscoreError {}
+
+impl From<ErrorStack> for OscoreError {
+    fn from(err: ErrorStack) -> Self {
+        OscoreError::Crypto(err.to_string())
+    }
+}
@@ -0,0 +1,415 @@
+use coap_lite::{CoapOption, MessageClass, Packet, RequestType, ResponseType};
+
+use super::{
+    context::{aad, decode_partial_iv, SecurityContext, MAX_PARTIAL_IV_LENGTH},
+    err::OscoreError,
+};
+
+// Based on https://www.rfc-editor.org/rfc/rfc8613#section-6.1
+const FLAG_KID: u8 = 0x08;
+const FLAG_KID_CONTEXT: u8 = 0x10;
+const FLAGS_PARTIAL_IV_LENGTH: u8 = 0x07;
+const FLAGS_RESERVED: u8 = 0xe0;
+
+/// The value of the OSCORE option, which carries what a recipient needs to find the
+/// security context and the nonce of a message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OscoreOption {
+    pub partial_iv: Option<Vec<u8>>,
+    pub kid_context: Option<Vec<u8>>,
+    pub kid: Option<Vec<u8>>,
+}
+
+impl OscoreOption {
+    pub fn decode(value: &[u8]) -> Result<Self, OscoreError> {
+        let (&flags, mut rest) = match value.split_first() {
+            Some(split) => split,
+            // An empty option has all flags unset
+            None => return Ok(OscoreOption::default()),
+        };
+        let partial_iv_length = (flags & FLAGS_PARTIAL_IV_LENGTH) as usize;
+        if flags & FLAGS_RESERVED != 0 || partial_iv_length > MAX_PARTIAL_IV_LENGTH {
+            return Err(OscoreError::InvalidOption);
+        }
+
+        let mut take = |length: usize| {
+            if rest.len() < length {
+                return Err(OscoreError::InvalidOption);
+            }
+            let (taken, remaining) = rest.split_at(length);
+            rest = remaining;
+            Ok(taken.to_vec())
+        };
+        let partial_iv = match partial_iv_length {
+            0 => None,
+            length => Some(take(length)?),
+        };
+        let kid_context = if flags & FLAG_KID_CONTEXT != 0 {
+            let length = take(1)?[0] as usize;
+            Some(take(length)?)
+        } else {
+            None
+        };
+        // The kid is the rest of the value
+        let kid = match flags & FLAG_KID != 0 {
+            true => Some(rest.to_vec()),
+            false if rest.is_empty() => None,
+            false => return Err(OscoreError::InvalidOption),
+        };
+        Ok(OscoreOption {
+            partial_iv,
+            kid_context,
+            kid,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        let mut value = vec![];
+        if let Some(partial_iv) = &self.partial_iv {
+            flags |= partial_iv.len() as u8;
+            value.extend_from_slice(partial_iv);
+        }
+        if let Some(kid_context) = &self.kid_context {
+            flags |= FLAG_KID_CONTEXT;
+            value.push(kid_context.len() as u8);
+            value.extend_from_slice(kid_context);
+        }
+        if let Some(kid) = &self.kid {
+            flags |= FLAG_KID;
+            value.extend_from_slice(kid);
+        }
+        if flags == 0 {
+            return vec![];
+        }
+        [vec![flags], value].concat()
+    }
+}
+
+/// Identifies the request a response belongs to, its nonce and AAD are derived from it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId {
+    pub kid: Vec<u8>,
+    pub partial_iv: Vec<u8>,
+}
+
+impl SecurityContext {
+    // Based on https://www.rfc-editor.org/rfc/rfc8613#section-8.1
+    /// Protects a request of the server, e.g. a Read or an Observe, with a new sequence number.
+    pub fn protect_request(&mut self, packet: &Packet) -> Result<(Packet, RequestId), OscoreError> {
+        let partial_iv = self.next_partial_iv()?;
+        let request = RequestId {
+            kid: self.sender_id.clone(),
+            partial_iv: partial_iv.clone(),
+        };
+        let option = OscoreOption {
+            partial_iv: Some(partial_iv),
+            kid_context: self.id_context.clone(),
+            kid: Some(self.sender_id.clone()),
+        };
+        let nonce = self.nonce(&request.kid, &request.partial_iv);
+        let packet = self.seal(packet, &nonce, &request, &option)?;
+        Ok((packet, request))
+    }
+
+    // Based on https://www.rfc-editor.org/rfc/rfc8613#section-8.2
+    /// Verifies and decrypts a request of the device. Whether the kid and kid context belong
+    /// to this context is up to the caller, they are used to find it.
+    pub fn unprotect_request(
+        &mut self,
+        packet: &Packet,
+    ) -> Result<(Packet, RequestId), OscoreError> {
+        let option = oscore_option(packet)?;
+        let (partial_iv, kid) = match (option.partial_iv, option.kid) {
+            (Some(partial_iv), Some(kid)) => (partial_iv, kid),
+            _ => return Err(OscoreError::InvalidOption),
+        };
+        if kid != self.recipient_id {
+            return Err(OscoreError::UnknownContext);
+        }
+        let sequence_number = decode_partial_iv(&partial_iv);
+        if !self.replay_window.check(sequence_number) {
+            return Err(OscoreError::Replay);
+        }
+
+        let request = RequestId { kid, partial_iv };
+        let nonce = self.nonce(&request.kid, &request.partial_iv);
+        let packet = self.open(packet, &nonce, &request)?;
+        // Only a verified request moves the window
+        self.replay_window.update(sequence_number);
+        Ok((packet, request))
+    }
+
+    // Based on https://www.rfc-editor.org/rfc/rfc8613#section-8.3
+    /// Protects the response to `request`. A response reuses the nonce of its request,
+    /// an Observe notification gets a new sequence number as there may be many.
+    pub fn protect_response(
+        &mut self,
+        packet: &Packet,
+        request: &RequestId,
+    ) -> Result<Packet, OscoreError> {
+        let (nonce, option) = if packet.get_option(CoapOption::Observe).is_some() {
+            let partial_iv = self.next_partial_iv()?;
+            let nonce = self.nonce(&self.sender_id, &partial_iv);
+            let option = OscoreOption {
+                partial_iv: Some(partial_iv),
+                ..OscoreOption::default()
+            };
+            (nonce, option)
+        } else {
+            let nonce = self.nonce(&request.kid, &request.partial_iv);
+            (nonce, OscoreOption::default())
+        };
+        self.seal(packet, &nonce, request, &option)
+    }
+
+    // Based on https://www.rfc-editor.org/rfc/rfc8613#section-8.4
+    /// Verifies and decrypts the response of the device to `request`.
+    pub fn unprotect_response(
+        &self,
+        packet: &Packet,
+        request: &RequestId,
+    ) -> Result<Packet, OscoreError> {
+        let option = oscore_option(packet)?;
+        let nonce = match &option.partial_iv {
+            Some(partial_iv) => self.nonce(&self.recipient_id, partial_iv),
+            None => self.nonce(&request.kid, &request.partial_iv),
+        };
+        self.open(packet, &nonce, request)
+    }
+
+    // Encrypts the code, the inner options and the payload of `packet` into the payload of a
+    // message with the outer options
+    fn seal(
+        &self,
+        packet: &Packet,
+        nonce: &[u8],
+        request: &RequestId,
+        option: &OscoreOption,
+    ) -> Result<Packet, OscoreError> {
+        let mut inner = Packet::new();
+        inner.header.code = packet.header.code;
+        inner.payload = packet.payload.clone();
+        let mut outer = Packet::new();
+        outer.header = packet.header.clone();
+        outer.set_token(packet.get_token().to_vec());
+        for (&number, values) in packet.options() {
+            let option = CoapOption::from(number);
+            if option == CoapOption::Oscore {
+                continue;
+            }
+            if is_outer(option) {
+                outer.set_option(option, values.clone());
+            }
+            if is_inner(option) {
+                inner.set_option(option, values.clone());
+            }
+        }
+
+        let bytes = inner.to_bytes().map_err(|_| OscoreError::InvalidOption)?;
+        // The plaintext is the code followed by what comes after the header of the inner message
+        let plaintext = [&[u8::from(packet.header.code)], &bytes[4..]].concat();
+        let aad = aad(&request.kid, &request.partial_iv);
+        outer.payload = self.encrypt(nonce, &aad, &plaintext)?;
+        outer.header.code = outer_code(packet);
+        outer.add_option(CoapOption::Oscore, option.encode());
+        Ok(outer)
+    }
+
+    fn open(
+        &self,
+        packet: &Packet,
+        nonce: &[u8],
+        request: &RequestId,
+    ) -> Result<Packet, OscoreError> {
+        let aad = aad(&request.kid, &request.partial_iv);
+        let plaintext = self.decrypt(nonce, &aad, &packet.payload)?;
+        let (&code, rest) = plaintext
+            .split_first()
+            .ok_or(OscoreError::DecryptionFailed)?;
+        let bytes = [&[0x40, code, 0, 0], rest].concat();
+        let mut inner = Packet::from_bytes(&bytes).map_err(|_| OscoreError::DecryptionFailed)?;
+        if inner.get_option(CoapOption::Oscore).is_some() {
+            return Err(OscoreError::DecryptionFailed);
+        }
+
+        let code = inner.header.code;
+        inner.header = packet.header.clone();
+        inner.header.code = code;
+        inner.set_token(packet.get_token().to_vec());
+        for (&number, values) in packet.options() {
+            let option = CoapOption::from(number);
+            if option == CoapOption::Oscore || !is_outer(option) {
+                continue;
+            }
+            if !is_inner(option) || inner.get_option(option).is_none() {
+                inner.set_option(option, values.clone());
+            }
+        }
+        Ok(inner)
+    }
+}
+
+fn oscore_option(packet: &Packet) -> Result<OscoreOption, OscoreError> {
+    let value = packet
+        .get_first_option(CoapOption::Oscore)
+        .ok_or(OscoreError::InvalidOption)?;
+    OscoreOption::decode(value)
+}
+
+// Based on https://www.rfc-editor.org/rfc/rfc8613#section-4.1
+// Options of class U are only readable by proxies, Observe is both as proxies need it too
+fn is_outer(option: CoapOption) -> bool {
+    matches!(
+        option,
+        CoapOption::UriHost
+            | CoapOption::UriPort
+            | CoapOption::ProxyUri
+            | CoapOption::ProxyScheme
+            | CoapOption::Observe
+    )
+}
+
+fn is_inner(option: CoapOption) -> bool {
+    option == CoapOption::Observe || !is_outer(option)
+}
+
+// Based on https://www.rfc-editor.org/rfc/rfc8613#section-4.2
+// Proxies only see a POST and Changed, or FETCH and Content for observations
+fn outer_code(packet: &Packet) -> MessageClass {
+    let observe = packet.get_option(CoapOption::Observe).is_some();
+    match (packet.header.code, observe) {
+        (MessageClass::Request(_), false) => MessageClass::Request(RequestType::Post),
+        (MessageClass::Request(_), true) => MessageClass::Request(RequestType::Fetch),
+        (_, false) => MessageClass::Response(ResponseType::Changed),
+        (_, true) => MessageClass::Response(ResponseType::Content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oscore::context::tests::{client_parameters, hex, server_parameters};
+
+    #[test]
+    fn test_option() {
+        let option = OscoreOption {
+            partial_iv: Some(vec![0x14]),
+            kid_context: Some(vec![0x37, 0xcb]),
+            kid: Some(vec![0x01]),
+        };
+        let value = option.encode();
+        assert_eq!(value, hex("19140237cb01"));
+        assert_eq!(OscoreOption::decode(&value), Ok(option));
+
+        assert_eq!(OscoreOption::default().encode(), Vec::<u8>::new());
+        assert_eq!(OscoreOption::decode(&[]), Ok(OscoreOption::default()));
+        assert_eq!(
+            OscoreOption::decode(&hex("0914")),
+            Ok(OscoreOption {
+                partial_iv: Some(vec![0x14]),
+                kid_context: None,
+                kid: Some(vec![]),
+            })
+        );
+        // Truncated Partial IV, reserved flag and a missing kid context
+        assert!(OscoreOption::decode(&[0x02, 0x14]).is_err());
+        assert!(OscoreOption::decode(&[0x80]).is_err());
+        assert!(OscoreOption::decode(&[0x11, 0x14, 0x02, 0x37]).is_err());
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc8613#appendix-C.4 and C.7
+    #[test]
+    fn test_request_and_response() {
+        let mut client = SecurityContext::derive(&client_parameters()).unwrap();
+        client.sender_sequence_number = 20;
+        let mut server = SecurityContext::derive(&server_parameters()).unwrap();
+
+        let request_bytes = hex("44015d1f00003974396c6f63616c686f737483747631");
+        let request = Packet::from_bytes(&request_bytes).unwrap();
+        let (protected, request_id) = client.protect_request(&request).unwrap();
+        assert_eq!(
+            protected.to_bytes().unwrap(),
+            hex("44025d1f00003974396c6f63616c686f7374620914ff612f1092f1776f1c1668b3825e")
+        );
+
+        let (unprotected, server_request_id) = server.unprotect_request(&protected).unwrap();
+        assert_eq!(unprotected.to_bytes().unwrap(), request_bytes);
+        assert_eq!(server_request_id, request_id);
+        assert!(matches!(
+            server.unprotect_request(&protected),
+            Err(OscoreError::Replay)
+        ));
+
+        let response_bytes = hex("64455d1f00003974ff48656c6c6f20576f726c6421");
+        let response = Packet::from_bytes(&response_bytes).unwrap();
+        let protected = server.protect_response(&response, &request_id).unwrap();
+        assert_eq!(
+            protected.to_bytes().unwrap(),
+            hex("64445d1f0000397490ffdbaad1e9a7e7b2a813d3c31524378303cdafae119106")
+        );
+        let unprotected = client.unprotect_response(&protected, &request_id).unwrap();
+        assert_eq!(unprotected.to_bytes().unwrap(), response_bytes);
+    }
+
+    #[test]
+    fn test_tampered_request() {
+        let mut client = SecurityContext::derive(&client_parameters()).unwrap();
+        let mut server = SecurityContext::derive(&server_parameters()).unwrap();
+        let mut request = Packet::new();
+        request.header.code = MessageClass::Request(RequestType::Get);
+        request.add_option(CoapOption::UriPath, b"3".to_vec());
+
+        let (mut protected, _) = client.protect_request(&request).unwrap();
+        let last = protected.payload.len() - 1;
+        protected.payload[last] ^= 1;
+        assert!(matches!(
+            server.unprotect_request(&protected),
+            Err(OscoreError::DecryptionFailed)
+        ));
+        // The failed request did not move the replay window
+        protected.payload[last] ^= 1;
+        assert!(server.unprotect_request(&protected).is_ok());
+
+        let mut other = server_parameters();
+        other.recipient_id = vec![0x02];
+        let mut other = SecurityContext::derive(&other).unwrap();
+        assert!(matches!(
+            other.unprotect_request(&protected),
+            Err(OscoreError::UnknownContext)
+        ));
+        request.clear_option(CoapOption::UriPath);
+        assert!(matches!(
+            server.unprotect_request(&request),
+            Err(OscoreError::InvalidOption)
+        ));
+    }
+
+    #[test]
+    fn test_observe_notification() {
+        let mut client = SecurityContext::derive(&client_parameters()).unwrap();
+        let mut server = SecurityContext::derive(&server_parameters()).unwrap();
+        let mut request = Packet::new();
+        request.header.code = MessageClass::Request(RequestType::Get);
+        request.set_observe_value(0);
+        let (protected, request_id) = client.protect_request(&request).unwrap();
+        assert_eq!(
+            protected.header.code,
+            MessageClass::Request(RequestType::Fetch)
+        );
+        assert!(protected.get_option(CoapOption::Observe).is_some());
+        server.unprotect_request(&protected).unwrap();
+
+        let mut notification = Packet::new();
+        notification.header.code = MessageClass::Response(ResponseType::Content);
+        notification.set_observe_value(5);
+        notification.payload = b"22.5".to_vec();
+        let first = server.protect_response(&notification, &request_id).unwrap();
+        let second = server.protect_response(&notification, &request_id).unwrap();
+        // Each notification has its own nonce
+        assert_ne!(first.payload, second.payload);
+        let unprotected = client.unprotect_response(&second, &request_id).unwrap();
+        assert_eq!(unprotected.payload, b"22.5");
+        assert_eq!(unprotected.get_observe_value().unwrap().unwrap(), 5);
+    }
+}
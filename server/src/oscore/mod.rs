@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use context::{ContextState, OscoreParameters, SecurityContext};
+use err::OscoreError;
+
+pub mod context;
+pub mod err;
+pub mod message;
+pub mod replay;
+
+/// The OSCORE security contexts of the devices, keyed by their endpoint names.
+/// Requests of a device are matched to its context by the kid and kid context in the
+/// OSCORE option, the kid is the Sender ID of the device.
+///
+/// With a state file the sequence numbers of the contexts survive a restart of the server,
+/// see `with_state_file`.
+#[derive(Debug, Clone, Default)]
+pub struct OscoreContexts {
+    contexts: HashMap<String, SecurityContext>,
+    // Recipient ID of the server to the endpoint names of the devices that use it
+    recipients: HashMap<Vec<u8>, Vec<String>>,
+    state_file: Option<PathBuf>,
+    // The states in the state file by endpoint name
+    states: HashMap<String, ContextState>,
+}
+
+impl OscoreContexts {
+    /// Derives the context of a device from its parameters and adds it, replacing the one
+    /// the device had.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_endpoint` - The endpoint name of the device
+    /// * `parameters` - The parameters of the context, as seen from the server
+    pub fn add(
+        &mut self,
+        device_endpoint: &str,
+        parameters: &OscoreParameters,
+    ) -> Result<(), OscoreError> {
+        let mut context = SecurityContext::derive(parameters)?;
+        if let Some(state) = self.states.get(device_endpoint) {
+            context.restore(state);
+        }
+        let taken = self
+            .recipients
+            .get(context.recipient_id())
+            .is_some_and(|endpoints| {
+                endpoints.iter().any(|endpoint| {
+                    endpoint != device_endpoint
+                        && self.contexts[endpoint].id_context() == context.id_context()
+                })
+            });
+        if taken {
+            return Err(OscoreError::InvalidParameters(format!(
+                "recipient ID {:02x?} is used by another device",
+                context.recipient_id()
+            )));
+        }
+
+        self.remove(device_endpoint);
+        self.recipients
+            .entry(context.recipient_id().to_vec())
+            .or_default()
+            .push(device_endpoint.to_owned());
+        self.contexts.insert(device_endpoint.to_owned(), context);
+        Ok(())
+    }
+
+    /// Keeps the state of the contexts in `path`, the contexts continue from the state it
+    /// holds. A context that advanced has to be stored with `store` before its message is
+    /// sent or accepted.
+    pub fn with_state_file(mut self, path: impl AsRef<Path>) -> Result<Self, OscoreError> {
+        let path = path.as_ref();
+        if path.exists() {
+            let content = fs::read(path).map_err(|err| OscoreError::Storage(err.to_string()))?;
+            self.states = serde_json::from_slice(&content)
+                .map_err(|err| OscoreError::Storage(err.to_string()))?;
+        }
+        for (device_endpoint, context) in &mut self.contexts {
+            if let Some(state) = self.states.get(device_endpoint) {
+                context.restore(state);
+            }
+        }
+        self.state_file = Some(path.to_owned());
+        Ok(self)
+    }
+
+    /// Stores the state of the context of a device in the state file when it used up the
+    /// sequence numbers reserved in the stored state, see `SecurityContext::pending_state`.
+    /// Without a state file nothing is stored.
+    pub fn store(&mut self, device_endpoint: &str) -> Result<(), OscoreError> {
+        let path = match &self.state_file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let context = match self.contexts.get_mut(device_endpoint) {
+            Some(context) => context,
+            None => return Ok(()),
+        };
+        let state = match context.pending_state() {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        let mut states = self.states.clone();
+        states.insert(device_endpoint.to_owned(), state.clone());
+        // Replaced in one step, a crash while writing leaves the previous state
+        let content =
+            serde_json::to_vec(&states).map_err(|err| OscoreError::Storage(err.to_string()))?;
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, content)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|err| OscoreError::Storage(err.to_string()))?;
+        self.states = states;
+        context.stored(state);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, device_endpoint: &str) -> Option<SecurityContext> {
+        let context = self.contexts.remove(device_endpoint)?;
+        if let Some(endpoints) = self.recipients.get_mut(context.recipient_id()) {
+            endpoints.retain(|endpoint| endpoint != device_endpoint);
+            if endpoints.is_empty() {
+                self.recipients.remove(context.recipient_id());
+            }
+        }
+        Some(context)
+    }
+
+    pub fn get(&self, device_endpoint: &str) -> Option<&SecurityContext> {
+        self.contexts.get(device_endpoint)
+    }
+
+    pub fn get_mut(&mut self, device_endpoint: &str) -> Option<&mut SecurityContext> {
+        self.contexts.get_mut(device_endpoint)
+    }
+
+    /// The endpoint name of the device that sends with `kid`. Without a kid context any
+    /// context with the kid matches, devices only send one when the kid is ambiguous.
+    pub fn find(&self, kid: &[u8], kid_context: Option<&[u8]>) -> Option<&str> {
+        self.recipients
+            .get(kid)?
+            .iter()
+            .find(|endpoint| {
+                kid_context.is_none() || self.contexts[*endpoint].id_context() == kid_context
+            })
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coap_lite::{MessageClass, Packet, RequestType};
+    use context::tests::{client_parameters, server_parameters};
+
+    #[test]
+    fn test_contexts() {
+        let mut contexts = OscoreContexts::default();
+        contexts.add("device1", &server_parameters()).unwrap();
+        assert_eq!(contexts.find(&[], None), Some("device1"));
+        assert_eq!(contexts.find(&[0x01], None), None);
+
+        // The same kid in another ID context
+        let mut parameters = server_parameters();
+        parameters.id_context = Some(vec![0x37, 0xcb]);
+        contexts.add("device2", &parameters).unwrap();
+        assert_eq!(contexts.find(&[], Some(&[0x37, 0xcb])), Some("device2"));
+        assert_eq!(contexts.find(&[], Some(&[0x37])), None);
+
+        // A kid that is taken in the same ID context
+        assert!(matches!(
+            contexts.add("device3", &server_parameters()),
+            Err(OscoreError::InvalidParameters(_))
+        ));
+
+        // Replacing the context of a device
+        let mut parameters = server_parameters();
+        parameters.recipient_id = vec![0x02];
+        contexts.add("device1", &parameters).unwrap();
+        assert_eq!(contexts.find(&[0x02], None), Some("device1"));
+        assert_eq!(contexts.find(&[], None), Some("device2"));
+
+        assert!(contexts.remove("device1").is_some());
+        assert_eq!(contexts.find(&[0x02], None), None);
+        assert!(contexts.get_mut("device1").is_none());
+    }
+
+    #[test]
+    fn test_restart() {
+        let path = std::env::temp_dir().join("lwm2m-oscore-state-test.json");
+        let _ = fs::remove_file(&path);
+        let start = || {
+            let mut contexts = OscoreContexts::default();
+            contexts.add("device1", &server_parameters()).unwrap();
+            contexts.with_state_file(&path).unwrap()
+        };
+        let mut request = Packet::new();
+        request.header.code = MessageClass::Request(RequestType::Get);
+
+        let mut contexts = start();
+        let context = contexts.get_mut("device1").unwrap();
+        let (_, sent) = context.protect_request(&request).unwrap();
+        contexts.store("device1").unwrap();
+        let mut device = SecurityContext::derive(&client_parameters()).unwrap();
+        let (received, _) = device.protect_request(&request).unwrap();
+        let context = contexts.get_mut("device1").unwrap();
+        assert!(context.unprotect_request(&received).is_ok());
+        contexts.store("device1").unwrap();
+
+        let mut contexts = start();
+        let context = contexts.get_mut("device1").unwrap();
+        // The sequence number of the first request is not used again
+        let (_, resent) = context.protect_request(&request).unwrap();
+        assert!(context::decode_partial_iv(&resent.partial_iv) > 0);
+        assert_ne!(resent.partial_iv, sent.partial_iv);
+        // and the request of the device is still a replay
+        assert_eq!(
+            context.unprotect_request(&received).unwrap_err(),
+            OscoreError::Replay
+        );
+
+        // as is any request the device may have sent before the restart
+        let (received, _) = device.protect_request(&request).unwrap();
+        assert_eq!(
+            context.unprotect_request(&received).unwrap_err(),
+            OscoreError::Replay
+        );
+        device.sender_sequence_number = context.replay_window.highest().unwrap() + 1;
+        let (received, _) = device.protect_request(&request).unwrap();
+        assert!(context.unprotect_request(&received).is_ok());
+        contexts.store("device1").unwrap();
+
+        // Only a message past the reserved sequence numbers is stored again
+        fs::remove_file(&path).unwrap();
+        for _ in 0..8 {
+            let (received, _) = device.protect_request(&request).unwrap();
+            let context = contexts.get_mut("device1").unwrap();
+            assert!(context.unprotect_request(&received).is_ok());
+            context.protect_request(&request).unwrap();
+            contexts.store("device1").unwrap();
+        }
+        assert!(!path.exists());
+        for _ in 0..32 {
+            let (received, _) = device.protect_request(&request).unwrap();
+            let context = contexts.get_mut("device1").unwrap();
+            assert!(context.unprotect_request(&received).is_ok());
+        }
+        contexts.store("device1").unwrap();
+        assert!(path.exists());
+
+        // Contexts with other keys start over
+        let mut parameters = server_parameters();
+        parameters.master_secret = vec![0x01; 16];
+        let mut contexts = OscoreContexts::default();
+        contexts.add("device1", &parameters).unwrap();
+        let mut contexts = contexts.with_state_file(&path).unwrap();
+        let context = contexts.get_mut("device1").unwrap();
+        let (_, request_id) = context.protect_request(&request).unwrap();
+        assert_eq!(request_id.partial_iv, vec![0]);
+        fs::remove_file(&path).unwrap();
+    }
+}
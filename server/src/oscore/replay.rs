@@ -0,0 +1,106 @@
+// Based on https://www.rfc-editor.org/rfc/rfc8613#section-7.4
+// Sequence numbers within this distance of the highest one received are remembered
+const WINDOW_SIZE: u64 = 32;
+
+/// The sequence numbers of the requests a recipient received, so none is accepted twice.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayWindow {
+    highest: Option<u64>,
+    // Bit n is set when `highest - n` was received
+    received: u64,
+}
+
+impl ReplayWindow {
+    /// A window that rejects every sequence number below `sequence_number`, for a recipient
+    /// that does not know which of them it received.
+    pub fn below(sequence_number: u64) -> Self {
+        match sequence_number {
+            0 => ReplayWindow::default(),
+            _ => ReplayWindow {
+                highest: Some(sequence_number - 1),
+                received: u64::MAX,
+            },
+        }
+    }
+
+    /// The highest sequence number received, if any.
+    pub fn highest(&self) -> Option<u64> {
+        self.highest
+    }
+
+    /// Whether a request with `sequence_number` may be accepted. Numbers that are older than the
+    /// window are rejected as it is unknown whether they were received.
+    pub fn check(&self, sequence_number: u64) -> bool {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => return true,
+        };
+        if sequence_number > highest {
+            return true;
+        }
+        let age = highest - sequence_number;
+        age < WINDOW_SIZE && self.received & (1 << age) == 0
+    }
+
+    /// Marks `sequence_number` as received, only once the request it came with was verified.
+    pub fn update(&mut self, sequence_number: u64) {
+        match self.highest {
+            Some(highest) if sequence_number <= highest => {
+                let age = highest - sequence_number;
+                if age < WINDOW_SIZE {
+                    self.received |= 1 << age;
+                }
+            }
+            Some(highest) => {
+                let shift = sequence_number - highest;
+                self.received = if shift < u64::BITS as u64 {
+                    (self.received << shift) | 1
+                } else {
+                    1
+                };
+                self.highest = Some(sequence_number);
+            }
+            None => {
+                self.received = 1;
+                self.highest = Some(sequence_number);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::default();
+        assert!(window.check(5));
+        window.update(5);
+        assert!(!window.check(5));
+
+        // Out of order within the window
+        assert!(window.check(3));
+        window.update(3);
+        assert!(!window.check(3));
+        assert!(window.check(4));
+
+        window.update(40);
+        assert!(!window.check(40));
+        assert!(window.check(39));
+        assert!(window.check(9));
+        // Too old to tell
+        assert!(!window.check(8));
+        assert!(!window.check(4));
+
+        window.update(1000);
+        assert!(!window.check(40));
+        assert!(window.check(999));
+
+        let window = ReplayWindow::below(40);
+        assert!(!window.check(39));
+        assert!(!window.check(8));
+        assert!(window.check(40));
+        assert!(ReplayWindow::below(0).check(0));
+    }
+}
@@ -0,0 +1,551 @@
+use coap_lite::{MessageClass, MessageType, Packet};
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time,
+};
+
+use super::{dtls::DtlsSessions, oscore::OscoreLayer, Peer, Route};
+use crate::oscore::err::OscoreError;
+
+// Transmission parameters from https://datatracker.ietf.org/doc/html/rfc7252#section-4.8
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+const ACK_RANDOM_FACTOR: f64 = 1.5;
+const MAX_RETRANSMIT: u32 = 4;
+// MAX_TRANSMIT_WAIT, used as the time a device gets to send a separate response after its ACK
+const MAX_TRANSMIT_WAIT: Duration = Duration::from_secs(93);
+const TOKEN_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    // The device did not acknowledge or answer the request in time
+    Timeout,
+    // The device rejected the request with a Reset message
+    Reset,
+    // The transport the client sends on was closed
+    Closed,
+    // The device authenticated over DTLS but has no session to send the request in
+    NoSession,
+    // The request to a device with OSCORE could not be protected, e.g. without a context
+    Oscore(OscoreError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            ClientError::Timeout => write!(f, "Request timed out"),
+            ClientError::Reset => write!(f, "Request was reset by the peer"),
+            ClientError::Closed => write!(f, "Transport is closed"),
+            ClientError::NoSession => write!(f, "Device has no DTLS session"),
+            ClientError::Oscore(err) => write!(f, "Request not protected: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+#[derive(Default)]
+struct Exchanges {
+    // Open exchanges by token, every packet for the exchange is sent on the channel
+    by_token: HashMap<Vec<u8>, mpsc::UnboundedSender<Packet>>,
+    // Message ID of the last request sent for an exchange, to match empty ACKs and resets
+    by_message_id: HashMap<(SocketAddr, u16), Vec<u8>>,
+    // Waiting for the response of the server to a request of a device to go out, by the
+    // address and token of the request
+    responses: HashMap<(SocketAddr, Vec<u8>), oneshot::Sender<()>>,
+}
+
+// The sockets the client sends on
+#[derive(Clone)]
+enum Outgoing {
+    // A single socket every device is reached on, whatever its route
+    Socket(mpsc::UnboundedSender<(Packet, SocketAddr)>),
+    // The UDP and the DTLS socket of `bind_secure`, a DTLS peer needs a session and an
+    // OSCORE peer a context
+    Secure {
+        udp_tx: mpsc::UnboundedSender<(Packet, SocketAddr)>,
+        dtls_tx: mpsc::UnboundedSender<(Packet, SocketAddr)>,
+        sessions: DtlsSessions,
+        oscore: OscoreLayer,
+    },
+}
+
+impl Outgoing {
+    // Never falls back to plain UDP, that would send the request unprotected
+    fn send(&self, packet: Packet, peer: &Peer) -> Result<(), ClientError> {
+        let (socket_tx, packet) = match (self, &peer.route) {
+            (Outgoing::Socket(socket_tx), _) => (socket_tx, packet),
+            (Outgoing::Secure { udp_tx, .. }, Route::Udp) => (udp_tx, packet),
+            (
+                Outgoing::Secure {
+                    dtls_tx, sessions, ..
+                },
+                Route::Dtls,
+            ) => {
+                if sessions.identity(peer.address).is_none() {
+                    return Err(ClientError::NoSession);
+                }
+                (dtls_tx, packet)
+            }
+            // Only requests are protected, empty ACKs and resets have nothing to hide
+            (Outgoing::Secure { udp_tx, oscore, .. }, Route::Oscore(device_endpoint))
+                if matches!(packet.header.code, MessageClass::Request(_)) =>
+            {
+                let packet = oscore
+                    .protect_request(packet, peer.address, device_endpoint)
+                    .map_err(ClientError::Oscore)?;
+                (udp_tx, packet)
+            }
+            (Outgoing::Secure { udp_tx, .. }, Route::Oscore(_)) => (udp_tx, packet),
+        };
+        socket_tx
+            .send((packet, peer.address))
+            .map_err(|_| ClientError::Closed)
+    }
+
+    // Called when the exchange with `token` closed, protection kept for it is dropped
+    fn close(&self, token: &[u8]) {
+        if let Outgoing::Secure { oscore, .. } = self {
+            oscore.close_exchange(token);
+        }
+    }
+}
+
+/// Sends CoAP requests to devices over the transport the server is bound to and matches the
+/// responses coming back on it.
+#[derive(Clone)]
+pub struct CoapClient {
+    outgoing: Outgoing,
+    exchanges: Arc<Mutex<Exchanges>>,
+    message_id: Arc<AtomicU16>,
+}
+
+/// An open exchange, closed when dropped.
+pub struct Exchange {
+    client: CoapClient,
+    token: Vec<u8>,
+    packets_rx: mpsc::UnboundedReceiver<Packet>,
+}
+
+impl CoapClient {
+    /// Creates a client that sends every packet on `outgoing_tx`, the channel of a single socket.
+    pub fn new(outgoing_tx: mpsc::UnboundedSender<(Packet, SocketAddr)>) -> Self {
+        Self::with_outgoing(Outgoing::Socket(outgoing_tx))
+    }
+
+    // A client that sends on the UDP or the DTLS socket, depending on the route of the peer
+    pub(super) fn secure(
+        udp_tx: mpsc::UnboundedSender<(Packet, SocketAddr)>,
+        dtls_tx: mpsc::UnboundedSender<(Packet, SocketAddr)>,
+        sessions: DtlsSessions,
+        oscore: OscoreLayer,
+    ) -> Self {
+        Self::with_outgoing(Outgoing::Secure {
+            udp_tx,
+            dtls_tx,
+            sessions,
+            oscore,
+        })
+    }
+
+    fn with_outgoing(outgoing: Outgoing) -> Self {
+        CoapClient {
+            outgoing,
+            exchanges: Default::default(),
+            message_id: Arc::new(AtomicU16::new(rand::thread_rng().gen())),
+        }
+    }
+
+    /// Sends a request as a confirmable message and waits for its response. The request is
+    /// retransmitted until the device acknowledges it, the token and message ID are set here.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request packet, only the code, options and payload need to be set
+    /// * `peer` - The address of the device and the socket it is reached on
+    pub async fn send(&self, request: Packet, peer: Peer) -> Result<Packet, ClientError> {
+        let mut exchange = self.open_exchange();
+        exchange.send(request, peer).await
+    }
+
+    /// Opens an exchange with a new random token. Every packet that is received with that
+    /// token is delivered to the exchange until it is dropped.
+    pub fn open_exchange(&self) -> Exchange {
+        let token: Vec<u8> = rand::thread_rng().gen::<[u8; TOKEN_LENGTH]>().to_vec();
+        let (packets_tx, packets_rx) = mpsc::unbounded_channel();
+        self.exchanges
+            .lock()
+            .unwrap()
+            .by_token
+            .insert(token.clone(), packets_tx);
+        Exchange {
+            client: self.clone(),
+            token,
+            packets_rx,
+        }
+    }
+
+    /// Resolves once the CoAP server handed its response to the request of `address` with
+    /// `token` to the socket, requests sent afterwards go out after it. Fails when the response
+    /// could not be handed over.
+    pub fn response_sent(&self, address: SocketAddr, token: &[u8]) -> oneshot::Receiver<()> {
+        let (sent_tx, sent_rx) = oneshot::channel();
+        let mut exchanges = self.exchanges.lock().unwrap();
+        // Forgets about responses that are no longer waited for
+        exchanges
+            .responses
+            .retain(|_, sent_tx| !sent_tx.is_closed());
+        exchanges
+            .responses
+            .insert((address, token.to_vec()), sent_tx);
+        sent_rx
+    }
+
+    // Takes the waiter for a packet the CoAP server sends, see `response_sent`
+    pub(super) fn response_waiter(
+        &self,
+        packet: &Packet,
+        address: SocketAddr,
+    ) -> Option<oneshot::Sender<()>> {
+        if !matches!(packet.header.code, MessageClass::Response(_)) {
+            return None;
+        }
+        self.exchanges
+            .lock()
+            .unwrap()
+            .responses
+            .remove(&(address, packet.get_token().to_vec()))
+    }
+
+    /// Handles a packet received on the transport. Returns the packet again when it does not
+    /// belong to this client, so it can be handled by the server instead.
+    pub fn handle(&self, packet: Packet, peer: Peer) -> Option<Packet> {
+        match packet.header.code {
+            MessageClass::Empty => match packet.header.get_type() {
+                MessageType::Acknowledgement | MessageType::Reset => {
+                    let exchanges = self.exchanges.lock().unwrap();
+                    match exchanges
+                        .by_message_id
+                        .get(&(peer.address, packet.header.message_id))
+                        .and_then(|token| exchanges.by_token.get(token))
+                    {
+                        Some(packets_tx) => {
+                            let _ = packets_tx.send(packet);
+                            None
+                        }
+                        None => Some(packet),
+                    }
+                }
+                _ => Some(packet),
+            },
+            MessageClass::Response(_) => {
+                let packets_tx = self
+                    .exchanges
+                    .lock()
+                    .unwrap()
+                    .by_token
+                    .get(packet.get_token())
+                    .cloned();
+                let message_type = packet.header.get_type();
+                let message_id = packet.header.message_id;
+                let known = match packets_tx {
+                    Some(packets_tx) => packets_tx.send(packet).is_ok(),
+                    None => false,
+                };
+                // Separate responses and notifications are confirmable, they need an ACK.
+                // Confirmable responses we don't know about are rejected with a reset.
+                if message_type == MessageType::Confirmable {
+                    let reply_type = match known {
+                        true => MessageType::Acknowledgement,
+                        false => MessageType::Reset,
+                    };
+                    self.send_empty(reply_type, message_id, peer);
+                }
+                None
+            }
+            _ => Some(packet),
+        }
+    }
+
+    fn send_empty(&self, message_type: MessageType, message_id: u16, peer: Peer) {
+        let mut packet = Packet::new();
+        packet.header.set_type(message_type);
+        packet.header.code = MessageClass::Empty;
+        packet.header.message_id = message_id;
+        let _ = self.outgoing.send(packet, &peer);
+    }
+
+    fn next_message_id(&self) -> u16 {
+        self.message_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Exchange {
+    pub fn token(&self) -> &[u8] {
+        &self.token
+    }
+
+    /// Sends a confirmable request in this exchange and waits for the first response.
+    pub async fn send(&mut self, mut request: Packet, peer: Peer) -> Result<Packet, ClientError> {
+        let message_id = self.client.next_message_id();
+        request.header.set_type(MessageType::Confirmable);
+        request.header.message_id = message_id;
+        request.set_token(self.token.clone());
+        self.client
+            .exchanges
+            .lock()
+            .unwrap()
+            .by_message_id
+            .insert((peer.address, message_id), self.token.clone());
+
+        let result = self.transmit(request, &peer).await;
+        self.client
+            .exchanges
+            .lock()
+            .unwrap()
+            .by_message_id
+            .remove(&(peer.address, message_id));
+        result
+    }
+
+    /// Waits for the next packet of this exchange, e.g. a notification of an observation.
+    pub async fn recv(&mut self) -> Option<Packet> {
+        self.packets_rx.recv().await
+    }
+
+    async fn transmit(&mut self, request: Packet, peer: &Peer) -> Result<Packet, ClientError> {
+        let mut timeout = ACK_TIMEOUT.mul_f64(rand::thread_rng().gen_range(1.0..ACK_RANDOM_FACTOR));
+        for _ in 0..=MAX_RETRANSMIT {
+            self.client.outgoing.send(request.clone(), peer)?;
+
+            match time::timeout(timeout, self.next_response()).await {
+                Ok(Some(Ok(response))) => return Ok(response),
+                Ok(Some(Err(error))) => return Err(error),
+                // Empty ACK, the response will follow separately
+                Ok(None) => {
+                    return match time::timeout(MAX_TRANSMIT_WAIT, self.next_response()).await {
+                        Ok(Some(result)) => result,
+                        _ => Err(ClientError::Timeout),
+                    }
+                }
+                Err(_) => timeout *= 2,
+            }
+        }
+        Err(ClientError::Timeout)
+    }
+
+    // Waits for the next packet, returns None when it was an empty ACK
+    async fn next_response(&mut self) -> Option<Result<Packet, ClientError>> {
+        loop {
+            let packet = match self.packets_rx.recv().await {
+                Some(packet) => packet,
+                None => return Some(Err(ClientError::Closed)),
+            };
+            match (packet.header.code, packet.header.get_type()) {
+                (MessageClass::Response(_), _) => return Some(Ok(packet)),
+                (MessageClass::Empty, MessageType::Reset) => return Some(Err(ClientError::Reset)),
+                (MessageClass::Empty, MessageType::Acknowledgement) => return None,
+                _ => continue,
+            }
+        }
+    }
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        self.client
+            .exchanges
+            .lock()
+            .unwrap()
+            .by_token
+            .remove(&self.token);
+        self.client.outgoing.close(&self.token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coap_lite::{RequestType, ResponseType};
+
+    fn peer() -> Peer {
+        Peer::new("127.0.0.1:56830".parse().unwrap(), Route::Udp)
+    }
+
+    fn get_request() -> Packet {
+        let mut packet = Packet::new();
+        packet.header.code = MessageClass::Request(RequestType::Get);
+        packet
+    }
+
+    fn response_to(request: &Packet, message_type: MessageType, message_id: u16) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.set_type(message_type);
+        packet.header.code = MessageClass::Response(ResponseType::Content);
+        packet.header.message_id = message_id;
+        packet.set_token(request.get_token().to_vec());
+        packet.payload = b"42".to_vec();
+        packet
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_piggybacked_response() {
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        let client = CoapClient::new(outgoing_tx);
+
+        let device = client.clone();
+        tokio::spawn(async move {
+            let (request, _) = outgoing_rx.recv().await.unwrap();
+            assert_eq!(request.header.get_type(), MessageType::Confirmable);
+            let response = response_to(
+                &request,
+                MessageType::Acknowledgement,
+                request.header.message_id,
+            );
+            assert!(device.handle(response, peer()).is_none());
+        });
+
+        let response = client.send(get_request(), peer()).await.unwrap();
+        assert_eq!(response.payload, b"42".to_vec());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retransmission() {
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        let client = CoapClient::new(outgoing_tx);
+
+        let device = client.clone();
+        tokio::spawn(async move {
+            // Drop the first transmission, answer the retransmission
+            let (first, _) = outgoing_rx.recv().await.unwrap();
+            let (second, _) = outgoing_rx.recv().await.unwrap();
+            assert_eq!(first.header.message_id, second.header.message_id);
+            let response = response_to(
+                &second,
+                MessageType::Acknowledgement,
+                second.header.message_id,
+            );
+            device.handle(response, peer());
+        });
+
+        assert!(client.send(get_request(), peer()).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_separate_response() {
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        let client = CoapClient::new(outgoing_tx);
+
+        let device = client.clone();
+        let device_task = tokio::spawn(async move {
+            let (request, _) = outgoing_rx.recv().await.unwrap();
+            let mut ack = Packet::new();
+            ack.header.set_type(MessageType::Acknowledgement);
+            ack.header.code = MessageClass::Empty;
+            ack.header.message_id = request.header.message_id;
+            device.handle(ack, peer());
+
+            time::sleep(Duration::from_secs(30)).await;
+            let response = response_to(&request, MessageType::Confirmable, 1234);
+            device.handle(response, peer());
+
+            // The separate response is acknowledged by the client
+            let (ack, _) = outgoing_rx.recv().await.unwrap();
+            assert_eq!(ack.header.get_type(), MessageType::Acknowledgement);
+            assert_eq!(ack.header.message_id, 1234);
+        });
+
+        let response = client.send(get_request(), peer()).await.unwrap();
+        assert_eq!(response.payload, b"42".to_vec());
+        device_task.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout() {
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        let client = CoapClient::new(outgoing_tx);
+
+        let result = client.send(get_request(), peer()).await;
+        assert_eq!(result.unwrap_err(), ClientError::Timeout);
+
+        let mut transmissions = 0;
+        while outgoing_rx.try_recv().is_ok() {
+            transmissions += 1;
+        }
+        assert_eq!(transmissions, MAX_RETRANSMIT + 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unknown_confirmable_response_is_reset() {
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        let client = CoapClient::new(outgoing_tx);
+
+        let response = response_to(&get_request(), MessageType::Confirmable, 1234);
+        assert!(client.handle(response, peer()).is_none());
+        let (reset, _) = outgoing_rx.recv().await.unwrap();
+        assert_eq!(reset.header.get_type(), MessageType::Reset);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dtls_peer_without_session() {
+        let (udp_tx, mut udp_rx) = mpsc::unbounded_channel();
+        let (dtls_tx, mut dtls_rx) = mpsc::unbounded_channel();
+        let client = CoapClient::secure(
+            udp_tx,
+            dtls_tx,
+            DtlsSessions::default(),
+            OscoreLayer::new(Default::default()),
+        );
+
+        let dtls_peer = Peer::new(peer().address, Route::Dtls);
+        let result = client.send(get_request(), dtls_peer).await;
+        assert_eq!(result.unwrap_err(), ClientError::NoSession);
+        // Nothing went out, in particular not in plaintext
+        assert!(udp_rx.try_recv().is_err());
+        assert!(dtls_rx.try_recv().is_err());
+
+        // Plain peers are reached on the UDP socket
+        let udp_client = client.clone();
+        tokio::spawn(async move { udp_client.send(get_request(), peer()).await });
+        let (request, address) = udp_rx.recv().await.unwrap();
+        assert_eq!(request.header.get_type(), MessageType::Confirmable);
+        assert_eq!(address, peer().address);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_oscore_peer_without_context() {
+        let (udp_tx, mut udp_rx) = mpsc::unbounded_channel();
+        let (dtls_tx, _dtls_rx) = mpsc::unbounded_channel();
+        let client = CoapClient::secure(
+            udp_tx,
+            dtls_tx,
+            DtlsSessions::default(),
+            OscoreLayer::new(Default::default()),
+        );
+
+        let oscore_peer = Peer::new(peer().address, Route::Oscore("device123".to_owned()));
+        let result = client.send(get_request(), oscore_peer).await;
+        assert_eq!(
+            result.unwrap_err(),
+            ClientError::Oscore(OscoreError::UnknownContext)
+        );
+        assert!(udp_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_requests_are_not_handled() {
+        let (outgoing_tx, _outgoing_rx) = mpsc::unbounded_channel();
+        let client = CoapClient::new(outgoing_tx);
+        assert!(client.handle(get_request(), peer()).is_some());
+    }
+}
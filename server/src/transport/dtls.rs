@@ -0,0 +1,911 @@
+use coap_lite::Packet;
+use openssl::{
+    error::ErrorStack,
+    ex_data::Index,
+    hash::MessageDigest,
+    memcmp,
+    pkey::{PKey, Private},
+    rand::rand_bytes,
+    sign::Signer,
+    ssl::{Ssl, SslContext, SslMethod, SslOptions, SslRef, SslVerifyMode, SslVersion},
+    x509::X509,
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    ops::Range,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{ToSocketAddrs, UdpSocket},
+    sync::mpsc,
+    time,
+};
+use tokio_openssl::SslStream;
+
+use super::{
+    client::CoapClient, new_transport, trust_store::TrustStore, Lwm2mTransport, PacketRouter,
+    PeerIdentity, Route,
+};
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Transport-V1_2-20201110-A.html#5-2-8-1-0-5281-Pre-Shared-Keys
+// TLS_PSK_WITH_AES_128_CCM_8 is mandatory, the others are what devices commonly offer on top.
+const PSK_CIPHERS: &str =
+    "PSK-AES128-CCM8:PSK-AES128-CCM:PSK-AES128-GCM-SHA256:PSK-AES128-CBC-SHA256";
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Transport-V1_2-20201110-A.html#5-2-8-2-0-5282-Raw-Public-Key-Certificates
+// TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8 is mandatory for raw public keys and certificates
+const CERTIFICATE_CIPHERS: &str =
+    "ECDHE-ECDSA-AES128-CCM8:ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-ECDSA-AES128-CBC-SHA256";
+// Size of the handshake messages, small enough to not be fragmented on the way
+const MTU: u32 = 1280;
+// Room for the largest DTLS record
+const MAX_DATAGRAM_SIZE: usize = 16 * 1024 + 512;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+// Based on https://www.rfc-editor.org/rfc/rfc6347#section-4.1
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const HANDSHAKE: u8 = 22;
+const RECORD_HEADER_LENGTH: usize = 13;
+const HANDSHAKE_HEADER_LENGTH: usize = 12;
+const CLIENT_HELLO: u8 = 1;
+const HELLO_VERIFY_REQUEST: u8 = 3;
+// The version of a HelloVerifyRequest, RFC 6347 asks for DTLS 1.0 whatever is negotiated later
+const DTLS1_0: [u8; 2] = [0xfe, 0xff];
+
+/// The pre-shared keys of the devices, keyed by PSK identity.
+#[derive(Debug, Clone, Default)]
+pub struct PskStore {
+    keys: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PskStore {
+    pub fn add(&mut self, identity: &[u8], key: &[u8]) {
+        self.keys.insert(identity.to_vec(), key.to_vec());
+    }
+
+    pub fn key(&self, identity: &[u8]) -> Option<&[u8]> {
+        self.keys.get(identity).map(Vec::as_slice)
+    }
+}
+
+/// The certificate the server authenticates with when devices use raw public keys or
+/// certificates.
+#[derive(Clone)]
+pub struct ServerCertificate {
+    // The certificate of the server first, followed by its intermediates
+    pub chain: Vec<X509>,
+    pub private_key: PKey<Private>,
+}
+
+impl ServerCertificate {
+    /// Reads the certificate chain and private key from PEM files, the chain starting with the
+    /// certificate of the server.
+    pub fn from_pem(chain: &[u8], private_key: &[u8]) -> Result<Self, ErrorStack> {
+        Ok(ServerCertificate {
+            chain: X509::stack_from_pem(chain)?,
+            private_key: PKey::private_key_from_pem(private_key)?,
+        })
+    }
+}
+
+/// How devices authenticate to the DTLS socket. Devices with a raw public key or a certificate
+/// need both a server certificate and a trust store.
+#[derive(Clone, Default)]
+pub struct DtlsCredentials {
+    pub psk: PskStore,
+    pub certificate: Option<ServerCertificate>,
+    pub trust_store: Option<Arc<dyn TrustStore>>,
+}
+
+/// The identities of the peers that currently have an established DTLS session.
+#[derive(Debug, Clone, Default)]
+pub struct DtlsSessions {
+    identities: Arc<RwLock<HashMap<SocketAddr, PeerIdentity>>>,
+}
+
+impl DtlsSessions {
+    pub fn identity(&self, peer: SocketAddr) -> Option<PeerIdentity> {
+        self.identities.read().unwrap().get(&peer).cloned()
+    }
+
+    fn insert(&self, peer: SocketAddr, identity: PeerIdentity) {
+        self.identities.write().unwrap().insert(peer, identity);
+    }
+
+    fn remove(&self, peer: SocketAddr) {
+        self.identities.write().unwrap().remove(&peer);
+    }
+}
+
+/// Binds a DTLS socket that is shared by the CoAP server and the returned client. Devices
+/// authenticate with one of the pre-shared keys in `credentials` or with a raw public key or
+/// certificate its trust store accepts, only DTLS 1.2 is accepted.
+pub async fn bind(
+    addresses: impl ToSocketAddrs,
+    credentials: DtlsCredentials,
+) -> io::Result<(Lwm2mTransport, CoapClient, DtlsSessions)> {
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+    let client = CoapClient::new(outgoing_tx.clone());
+    let sessions = DtlsSessions::default();
+    let transport = start(
+        addresses,
+        credentials,
+        client.clone(),
+        outgoing_tx,
+        outgoing_rx,
+        sessions.clone(),
+    )
+    .await?;
+    Ok((transport, client, sessions))
+}
+
+// Binds the socket and starts its tasks, see `udp::start`. Established sessions are added to
+// `sessions`, packets on the outgoing channel are dropped for peers without a session.
+pub(super) async fn start(
+    addresses: impl ToSocketAddrs,
+    credentials: DtlsCredentials,
+    client: CoapClient,
+    outgoing_tx: mpsc::UnboundedSender<(Packet, SocketAddr)>,
+    outgoing_rx: mpsc::UnboundedReceiver<(Packet, SocketAddr)>,
+    sessions: DtlsSessions,
+) -> io::Result<Lwm2mTransport> {
+    let peer_index = Ssl::new_ex_index().map_err(io::Error::other)?;
+    let trust_store = credentials.trust_store.clone();
+    let cookie_secret = cookie_secret().map_err(io::Error::other)?;
+    let context =
+        server_context(credentials, peer_index, cookie_secret.clone()).map_err(io::Error::other)?;
+    let socket = Arc::new(UdpSocket::bind(addresses).await?);
+    let (transport, router) = new_transport(client, outgoing_tx, None, Route::Dtls);
+    let listener = Listener {
+        socket,
+        context,
+        peer_index,
+        router,
+        trust_store,
+        cookie_secret,
+        connections: Default::default(),
+        sessions,
+        next_id: Default::default(),
+    };
+
+    tokio::spawn(send(listener.connections.clone(), outgoing_rx));
+    tokio::spawn(listener.receive());
+    Ok(transport)
+}
+
+fn server_context(
+    credentials: DtlsCredentials,
+    peer_index: Index<Ssl, SocketAddr>,
+    cookie_secret: PKey<Private>,
+) -> Result<SslContext, ErrorStack> {
+    let mut builder = SslContext::builder(SslMethod::dtls_server())?;
+    builder.set_min_proto_version(Some(SslVersion::DTLS1_2))?;
+    builder.set_options(SslOptions::COOKIE_EXCHANGE | SslOptions::NO_QUERY_MTU);
+    let psk = credentials.psk;
+    builder.set_psk_server_callback(move |_, identity, key_buffer| {
+        match identity.and_then(|identity| psk.key(identity)) {
+            Some(key) if key.len() <= key_buffer.len() => {
+                key_buffer[..key.len()].copy_from_slice(key);
+                Ok(key.len())
+            }
+            // An unknown identity fails the handshake
+            _ => Ok(0),
+        }
+    });
+
+    match (credentials.certificate, credentials.trust_store) {
+        (Some(certificate), Some(trust_store)) => {
+            builder.set_cipher_list(&format!("{}:{}", PSK_CIPHERS, CERTIFICATE_CIPHERS))?;
+            let (server_certificate, intermediates) = certificate
+                .chain
+                .split_first()
+                .ok_or_else(ErrorStack::get)?;
+            builder.set_certificate(server_certificate)?;
+            for intermediate in intermediates {
+                builder.add_extra_chain_cert(intermediate.clone())?;
+            }
+            builder.set_private_key(&certificate.private_key)?;
+            // The trust store decides on the whole chain once OpenSSL reaches the certificate of
+            // the device, OpenSSL itself has no trusted certificates to check against
+            builder.set_verify_callback(
+                SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+                move |_, context| {
+                    if context.error_depth() > 0 {
+                        return true;
+                    }
+                    let chain: Vec<X509> = match context.chain() {
+                        Some(chain) => chain.iter().map(ToOwned::to_owned).collect(),
+                        None => return false,
+                    };
+                    trust_store.verify(&chain).is_some()
+                },
+            );
+        }
+        _ => builder.set_cipher_list(PSK_CIPHERS)?,
+    }
+
+    // The listener only starts a handshake for a ClientHello with a valid cookie, OpenSSL
+    // checks it once more
+    let secret = cookie_secret.clone();
+    let verify_secret = cookie_secret;
+    builder.set_cookie_generate_cb(move |ssl, buffer| {
+        let peer = ssl.ex_data(peer_index).copied();
+        let cookie = cookie(&secret, peer)?;
+        buffer[..cookie.len()].copy_from_slice(&cookie);
+        Ok(cookie.len())
+    });
+    builder.set_cookie_verify_cb(move |ssl, received| {
+        let peer = ssl.ex_data(peer_index).copied();
+        match cookie(&verify_secret, peer) {
+            Ok(cookie) => cookie.len() == received.len() && memcmp::eq(&cookie, received),
+            Err(_) => false,
+        }
+    });
+    Ok(builder.build())
+}
+
+fn cookie_secret() -> Result<PKey<Private>, ErrorStack> {
+    let mut secret = [0; 32];
+    rand_bytes(&mut secret)?;
+    PKey::hmac(&secret)
+}
+
+// The cookie proves the client owns its address before the server does any work for it
+fn cookie(secret: &PKey<Private>, peer: Option<SocketAddr>) -> Result<Vec<u8>, ErrorStack> {
+    let mut signer = Signer::new(MessageDigest::sha256(), secret)?;
+    signer.update(format!("{:?}", peer).as_bytes())?;
+    signer.sign_to_vec()
+}
+
+// A peer the listener hands the datagrams it receives from to a connection task
+struct Connection {
+    id: u64,
+    datagrams_tx: mpsc::UnboundedSender<Vec<u8>>,
+    packets_tx: mpsc::UnboundedSender<Packet>,
+}
+
+#[derive(Default)]
+struct Peers {
+    // Established sessions, the packets of the client go out on these
+    sessions: HashMap<SocketAddr, Connection>,
+    // Handshakes in progress, which replace the session of their peer once they complete
+    handshakes: HashMap<SocketAddr, Connection>,
+}
+
+type Connections = Arc<Mutex<Peers>>;
+
+struct Listener {
+    socket: Arc<UdpSocket>,
+    context: SslContext,
+    peer_index: Index<Ssl, SocketAddr>,
+    router: PacketRouter,
+    trust_store: Option<Arc<dyn TrustStore>>,
+    cookie_secret: PKey<Private>,
+    connections: Connections,
+    sessions: DtlsSessions,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Listener {
+    async fn receive(self) {
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            let (length, peer) = match self.socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(_) => continue,
+            };
+            let datagram = buffer[..length].to_vec();
+            if is_client_hello(&datagram) {
+                self.client_hello(datagram, peer).await;
+                continue;
+            }
+
+            // Handshake records go to a handshake in progress, the rest to the session.
+            // Records of peers the server does not know are dropped.
+            let connections = self.connections.lock().unwrap();
+            let handshake = connections.handshakes.get(&peer);
+            let connection = match (handshake, connections.sessions.get(&peer)) {
+                (Some(handshake), _) if is_handshake_record(&datagram) => Some(handshake),
+                (_, Some(session)) => Some(session),
+                (handshake, None) => handshake,
+            };
+            if let Some(connection) = connection {
+                let _ = connection.datagrams_tx.send(datagram);
+            }
+        }
+    }
+
+    // Based on https://www.rfc-editor.org/rfc/rfc6347#section-4.2.1
+    // A ClientHello without the cookie of its address is answered with a HelloVerifyRequest
+    // that needs no state, anyone can send one with a spoofed address. Only a peer that
+    // returns the cookie gets a handshake, which runs next to the session the peer may
+    // already have, e.g. after a reboot, and only replaces it once it completes.
+    async fn client_hello(&self, datagram: Vec<u8>, peer: SocketAddr) {
+        let received = match client_hello_cookie(&datagram) {
+            Some(received) => received,
+            None => return,
+        };
+        let expected = match cookie(&self.cookie_secret, Some(peer)) {
+            Ok(expected) => expected,
+            Err(_) => return,
+        };
+        if received.len() != expected.len() || !memcmp::eq(received, &expected) {
+            let request = hello_verify_request(&datagram, &expected);
+            let _ = self.socket.send_to(&request, peer).await;
+            return;
+        }
+
+        // A retransmitted ClientHello goes to the handshake it started
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(handshake) = connections.handshakes.get(&peer) {
+            let _ = handshake.datagrams_tx.send(datagram);
+            return;
+        }
+        // OpenSSL only takes the ClientHello with the cookie after it answered the one
+        // without, so that one is replayed first
+        let initial = match initial_client_hello(&datagram) {
+            Some(initial) => initial,
+            None => return,
+        };
+        let handshake = self.connect(peer);
+        let _ = handshake.datagrams_tx.send(initial);
+        let _ = handshake.datagrams_tx.send(datagram);
+        connections.handshakes.insert(peer, handshake);
+    }
+
+    // Starts the task of a new connection, which runs the handshake and then the session
+    fn connect(&self, peer: SocketAddr) -> Connection {
+        let (datagrams_tx, datagrams_rx) = mpsc::unbounded_channel();
+        let (packets_tx, packets_rx) = mpsc::unbounded_channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let session = Session {
+            id,
+            peer,
+            router: self.router.clone(),
+            trust_store: self.trust_store.clone(),
+            connections: self.connections.clone(),
+            sessions: self.sessions.clone(),
+        };
+        let stream = DatagramStream {
+            socket: self.socket.clone(),
+            peer,
+            datagrams_rx,
+            // The listener already sent it
+            skip_hello_verify_request: true,
+        };
+        let ssl = Ssl::new(&self.context).and_then(|mut ssl| {
+            ssl.set_ex_data(self.peer_index, peer);
+            ssl.set_mtu(MTU)?;
+            SslStream::new(ssl, stream)
+        });
+        tokio::spawn(async move {
+            if let Ok(stream) = ssl {
+                session.run(stream, packets_rx).await;
+            }
+            session.close();
+        });
+        Connection {
+            id,
+            datagrams_tx,
+            packets_tx,
+        }
+    }
+}
+
+// A ClientHello of epoch 0, the start of a new handshake
+fn is_client_hello(datagram: &[u8]) -> bool {
+    datagram.len() > RECORD_HEADER_LENGTH
+        && datagram[0] == HANDSHAKE
+        && datagram[3..5] == [0, 0]
+        && datagram[RECORD_HEADER_LENGTH] == CLIENT_HELLO
+}
+
+// Whether the (first) record of a datagram belongs to a handshake rather than a session
+fn is_handshake_record(datagram: &[u8]) -> bool {
+    matches!(
+        datagram.first(),
+        Some(&CHANGE_CIPHER_SPEC) | Some(&HANDSHAKE)
+    )
+}
+
+// The cookie of a ClientHello that is not fragmented, empty when it has none
+fn client_hello_cookie(datagram: &[u8]) -> Option<&[u8]> {
+    datagram.get(cookie_range(datagram)?)
+}
+
+// Where the cookie of a ClientHello is in the datagram, right after its length
+fn cookie_range(datagram: &[u8]) -> Option<Range<usize>> {
+    let handshake = datagram.get(RECORD_HEADER_LENGTH..)?;
+    let length = big_endian(handshake.get(1..4)?);
+    let fragment_offset = big_endian(handshake.get(6..9)?);
+    let fragment_length = big_endian(handshake.get(9..12)?);
+    if fragment_offset != 0 || fragment_length != length {
+        return None;
+    }
+    // The session ID follows the client version and random
+    let body_start = RECORD_HEADER_LENGTH + HANDSHAKE_HEADER_LENGTH;
+    let cookie_start = body_start + 35 + *datagram.get(body_start + 34)? as usize;
+    let cookie_length = *datagram.get(cookie_start)? as usize;
+    let range = cookie_start + 1..cookie_start + 1 + cookie_length;
+    datagram.get(range.clone())?;
+    Some(range)
+}
+
+// The ClientHello the client sent before it had the cookie: the one with the cookie without
+// it, with the message and record sequence numbers before it
+fn initial_client_hello(client_hello: &[u8]) -> Option<Vec<u8>> {
+    let cookie = cookie_range(client_hello)?;
+    let mut initial = [
+        &client_hello[..cookie.start - 1],
+        &[0],
+        &client_hello[cookie.end..],
+    ]
+    .concat();
+    let removed = cookie.len();
+    subtract(&mut initial[5..11], 1);
+    subtract(&mut initial[11..13], removed);
+    let handshake = &mut initial[RECORD_HEADER_LENGTH..];
+    subtract(&mut handshake[1..4], removed);
+    handshake[4..6].copy_from_slice(&[0, 0]);
+    subtract(&mut handshake[9..12], removed);
+    Some(initial)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |value, &byte| (value << 8) | byte as usize)
+}
+
+fn subtract(field: &mut [u8], value: usize) {
+    let number = big_endian(field).saturating_sub(value);
+    for (index, byte) in field.iter_mut().rev().enumerate() {
+        *byte = (number >> (8 * index)) as u8;
+    }
+}
+
+// The HelloVerifyRequest with `cookie` for a ClientHello, it reuses the record sequence
+// number of the ClientHello so nothing has to be remembered
+fn hello_verify_request(client_hello: &[u8], cookie: &[u8]) -> Vec<u8> {
+    let body = [&DTLS1_0[..], &[cookie.len() as u8], cookie].concat();
+    let length = (body.len() as u32).to_be_bytes();
+    let handshake = [
+        &[HELLO_VERIFY_REQUEST][..],
+        &length[1..],
+        // Message sequence 0 and a single fragment
+        &[0, 0],
+        &[0, 0, 0],
+        &length[1..],
+        &body,
+    ]
+    .concat();
+    let record_length = (handshake.len() as u16).to_be_bytes();
+    [
+        &[HANDSHAKE][..],
+        &DTLS1_0,
+        // Epoch and sequence number
+        &client_hello[3..11],
+        &record_length,
+        &handshake,
+    ]
+    .concat()
+}
+
+struct Session {
+    id: u64,
+    peer: SocketAddr,
+    router: PacketRouter,
+    trust_store: Option<Arc<dyn TrustStore>>,
+    connections: Connections,
+    sessions: DtlsSessions,
+}
+
+impl Session {
+    async fn run(
+        &self,
+        mut stream: SslStream<DatagramStream>,
+        mut packets_rx: mpsc::UnboundedReceiver<Packet>,
+    ) {
+        match time::timeout(HANDSHAKE_TIMEOUT, Pin::new(&mut stream).accept()).await {
+            Ok(Ok(())) => {}
+            _ => return,
+        }
+        let identity = match self.identity(stream.ssl()) {
+            Some(identity) => identity,
+            None => return,
+        };
+        {
+            let mut connections = self.connections.lock().unwrap();
+            let connection = match connections.handshakes.remove(&self.peer) {
+                Some(connection) if connection.id == self.id => connection,
+                Some(other) => {
+                    connections.handshakes.insert(self.peer, other);
+                    return;
+                }
+                None => return,
+            };
+            // Replaces the previous session of the peer, which ends with its datagrams
+            connections.sessions.insert(self.peer, connection);
+            self.sessions.insert(self.peer, identity);
+        }
+
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            tokio::select! {
+                read = stream.read(&mut buffer) => match read {
+                    // The device closed the session, or the listener replaced it
+                    Ok(0) | Err(_) => break,
+                    Ok(length) => {
+                        if let Ok(packet) = Packet::from_bytes(&buffer[..length]) {
+                            self.router.route(packet, self.peer);
+                        }
+                    }
+                },
+                packet = packets_rx.recv() => match packet {
+                    Some(packet) => {
+                        let written = match packet.to_bytes() {
+                            Ok(bytes) => stream.write_all(&bytes).await,
+                            Err(_) => Ok(()),
+                        };
+                        if written.is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+            }
+        }
+    }
+
+    // The identity of the device, from its PSK identity or the certificate it presented
+    fn identity(&self, ssl: &SslRef) -> Option<PeerIdentity> {
+        if let Some(identity) = ssl.psk_identity() {
+            return String::from_utf8(identity.to_vec())
+                .ok()
+                .map(PeerIdentity::Psk);
+        }
+        // The chain of the peer does not include its own certificate on the server side
+        let mut chain = vec![ssl.peer_certificate()?];
+        if let Some(intermediates) = ssl.peer_cert_chain() {
+            chain.extend(intermediates.iter().map(ToOwned::to_owned));
+        }
+        self.trust_store.as_ref()?.verify(&chain)
+    }
+
+    // Forgets the handshake or session, unless the peer already has a new one
+    fn close(&self) {
+        let mut connections = self.connections.lock().unwrap();
+        let is_self = |connection: Option<&Connection>| matches!(connection, Some(connection) if connection.id == self.id);
+        if is_self(connections.handshakes.get(&self.peer)) {
+            connections.handshakes.remove(&self.peer);
+        }
+        if is_self(connections.sessions.get(&self.peer)) {
+            connections.sessions.remove(&self.peer);
+            self.sessions.remove(self.peer);
+        }
+    }
+}
+
+async fn send(
+    connections: Connections,
+    mut outgoing_rx: mpsc::UnboundedReceiver<(Packet, SocketAddr)>,
+) {
+    while let Some((packet, peer)) = outgoing_rx.recv().await {
+        if let Some(connection) = connections.lock().unwrap().sessions.get(&peer) {
+            let _ = connection.packets_tx.send(packet);
+        }
+    }
+}
+
+/// The datagrams of one peer as a stream for OpenSSL, every read returns one datagram
+/// and every write is sent as one datagram.
+pub(super) struct DatagramStream {
+    pub(super) socket: Arc<UdpSocket>,
+    pub(super) peer: SocketAddr,
+    pub(super) datagrams_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    // Drops the first HelloVerifyRequest written, the answer to a replayed ClientHello
+    pub(super) skip_hello_verify_request: bool,
+}
+
+impl AsyncRead for DatagramStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.datagrams_rx.poll_recv(cx) {
+            Poll::Ready(Some(datagram)) => {
+                let length = datagram.len().min(buf.remaining());
+                buf.put_slice(&datagram[..length]);
+                Poll::Ready(Ok(()))
+            }
+            // Nothing read is the end of the stream
+            Poll::Ready(None) => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncWrite for DatagramStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let hello_verify_request = buf.len() > RECORD_HEADER_LENGTH
+            && buf[0] == HANDSHAKE
+            && buf[RECORD_HEADER_LENGTH] == HELLO_VERIFY_REQUEST;
+        if self.skip_hello_verify_request && hello_verify_request {
+            self.skip_hello_verify_request = false;
+            return Poll::Ready(Ok(buf.len()));
+        }
+        self.socket.poll_send_to(cx, buf, self.peer)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{
+        test_client::{authority, certificate, free_address, server_certificate, DtlsTestClient},
+        trust_store::LocalTrustStore,
+        Peer,
+    };
+    use coap_lite::{CoapOption, MessageClass, MessageType, RequestType, ResponseType};
+    use coap_server::{
+        app::{self, CoapError, Request, Response},
+        CoapServer,
+    };
+
+    async fn handle_get(request: Request<SocketAddr>) -> Result<Response, CoapError> {
+        let mut response = request.new_response();
+        response.message.payload = b"server".to_vec();
+        Ok(response)
+    }
+
+    fn credentials(trust_store: LocalTrustStore) -> DtlsCredentials {
+        let mut psk = PskStore::default();
+        psk.add(b"device123", &[0x01, 0x02, 0x03, 0x04]);
+        DtlsCredentials {
+            psk,
+            certificate: Some(server_certificate()),
+            trust_store: Some(Arc::new(trust_store)),
+        }
+    }
+
+    fn hello() -> Packet {
+        let mut request = Packet::new();
+        request.header.set_type(MessageType::Confirmable);
+        request.header.code = MessageClass::Request(RequestType::Get);
+        request.header.message_id = 1;
+        request.add_option(CoapOption::UriPath, b"hello".to_vec());
+        request
+    }
+
+    #[tokio::test]
+    async fn test_psk_session() {
+        let server_address = free_address();
+        let (transport, client, sessions) =
+            bind(server_address, credentials(LocalTrustStore::default()))
+                .await
+                .unwrap();
+        let server = CoapServer::bind(transport).await.unwrap();
+
+        let device_task = async {
+            let mut device = DtlsTestClient::connect(server_address, b"device123", &[1, 2, 3, 4])
+                .await
+                .unwrap();
+            let device_address = device.local_addr();
+
+            // Request from the device to the server
+            device.send(&hello()).await;
+            assert_eq!(device.recv().await.payload, b"server".to_vec());
+            assert_eq!(
+                sessions.identity(device_address),
+                Some(PeerIdentity::Psk("device123".to_owned()))
+            );
+
+            // Request from the server to the device, sent within the session
+            let mut request = Packet::new();
+            request.header.code = MessageClass::Request(RequestType::Get);
+            let client_task = tokio::spawn(async move {
+                client
+                    .send(request, Peer::new(device_address, Route::Dtls))
+                    .await
+            });
+            let request = device.recv().await;
+            let mut response = Packet::new();
+            response.header.set_type(MessageType::Acknowledgement);
+            response.header.code = MessageClass::Response(ResponseType::Content);
+            response.header.message_id = request.header.message_id;
+            response.set_token(request.get_token().to_vec());
+            device.send(&response).await;
+            client_task.await.unwrap()
+        };
+
+        let app = app::new().resource(app::resource("/hello").get(handle_get));
+        tokio::select! {
+            _ = server.serve(app) => panic!("Server stopped"),
+            response = device_task => assert!(response.is_ok()),
+        }
+    }
+
+    // A ClientHello of DTLS 1.2 with `cookie` and a single cipher suite
+    fn client_hello(cookie: &[u8]) -> Vec<u8> {
+        let body = [
+            &[0xfe, 0xfd][..],
+            &[0x42; 32],
+            &[0],
+            &[cookie.len() as u8],
+            cookie,
+            &[0x00, 0x02, 0xc0, 0xa8, 0x01, 0x00],
+        ]
+        .concat();
+        let length = (body.len() as u32).to_be_bytes();
+        let handshake = [
+            &[CLIENT_HELLO][..],
+            &length[1..],
+            &[0, 1],
+            &[0, 0, 0],
+            &length[1..],
+            &body,
+        ]
+        .concat();
+        let record_length = (handshake.len() as u16).to_be_bytes();
+        [
+            &[HANDSHAKE, 0xfe, 0xfd, 0, 0, 0, 0, 0, 0, 0, 1][..],
+            &record_length,
+            &handshake,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_client_hello_cookie() {
+        let with_cookie = client_hello(&[0xaa; 32]);
+        assert!(is_client_hello(&with_cookie));
+        assert_eq!(client_hello_cookie(&with_cookie), Some(&[0xaa; 32][..]));
+        assert_eq!(client_hello_cookie(&client_hello(&[])), Some(&[][..]));
+        assert_eq!(client_hello_cookie(&with_cookie[..60]), None);
+
+        // The same ClientHello without the cookie, one message and record earlier
+        let mut expected = client_hello(&[]);
+        expected[10] = 0;
+        expected[RECORD_HEADER_LENGTH + 5] = 0;
+        assert_eq!(initial_client_hello(&with_cookie), Some(expected));
+
+        let request = hello_verify_request(&with_cookie, &[0xbb; 32]);
+        assert_eq!(request[..3], [HANDSHAKE, 0xfe, 0xff]);
+        assert_eq!(request[3..11], with_cookie[3..11]);
+        assert_eq!(request[RECORD_HEADER_LENGTH], HELLO_VERIFY_REQUEST);
+        assert_eq!(
+            request.len(),
+            RECORD_HEADER_LENGTH + HANDSHAKE_HEADER_LENGTH + 35
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_hello_keeps_session() {
+        let server_address = free_address();
+        let (transport, _, sessions) =
+            bind(server_address, credentials(LocalTrustStore::default()))
+                .await
+                .unwrap();
+        let server = CoapServer::bind(transport).await.unwrap();
+
+        let device_task = async {
+            let mut device = DtlsTestClient::connect(server_address, b"device123", &[1, 2, 3, 4])
+                .await
+                .unwrap();
+            device.send(&hello()).await;
+            assert_eq!(device.recv().await.payload, b"server".to_vec());
+
+            // Anyone can send a ClientHello with the address of the device, without the
+            // cookie it gets no handshake and the session stays
+            device.send_datagram(&client_hello(&[])).await;
+            device.send_datagram(&client_hello(&[0xaa; 32])).await;
+            time::sleep(Duration::from_millis(100)).await;
+            assert!(sessions.identity(device.local_addr()).is_some());
+            device.send(&hello()).await;
+            device.recv().await.payload
+        };
+
+        let app = app::new().resource(app::resource("/hello").get(handle_get));
+        tokio::select! {
+            _ = server.serve(app) => panic!("Server stopped"),
+            payload = device_task => assert_eq!(payload, b"server".to_vec()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_identity() {
+        let server_address = free_address();
+        let _transport = bind(server_address, credentials(LocalTrustStore::default()))
+            .await
+            .unwrap();
+        assert!(
+            DtlsTestClient::connect(server_address, b"device456", &[1, 2, 3, 4])
+                .await
+                .is_err()
+        );
+        assert!(
+            DtlsTestClient::connect(server_address, b"device123", &[4, 3, 2, 1])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_certificate_sessions() {
+        let root = authority("Root CA", None);
+        let (device, device_key) = certificate("device123", &[], Some(&root));
+        let (rpk_device, rpk_key) = certificate("rpk", &[], None);
+        let mut trust_store = LocalTrustStore::default();
+        trust_store.add_authority(root.0.clone());
+        trust_store.add_public_key(&rpk_key.public_key_to_der().unwrap(), "device456");
+        let server_address = free_address();
+        let (transport, _, sessions) = bind(server_address, credentials(trust_store))
+            .await
+            .unwrap();
+        let server = CoapServer::bind(transport).await.unwrap();
+
+        let devices_task = async {
+            let mut device =
+                DtlsTestClient::connect_with_certificate(server_address, &[device], &device_key)
+                    .await
+                    .unwrap();
+            device.send(&hello()).await;
+            assert_eq!(device.recv().await.payload, b"server".to_vec());
+            assert_eq!(
+                sessions.identity(device.local_addr()),
+                Some(PeerIdentity::Certificate {
+                    names: vec!["device123".to_owned()]
+                })
+            );
+
+            let mut device =
+                DtlsTestClient::connect_with_certificate(server_address, &[rpk_device], &rpk_key)
+                    .await
+                    .unwrap();
+            device.send(&hello()).await;
+            assert_eq!(device.recv().await.payload, b"server".to_vec());
+            assert_eq!(
+                sessions.identity(device.local_addr()),
+                Some(PeerIdentity::RawPublicKey {
+                    endpoint_name: "device456".to_owned(),
+                    public_key: rpk_key.public_key_to_der().unwrap(),
+                })
+            );
+
+            let (untrusted, untrusted_key) = certificate("device123", &[], None);
+            assert!(DtlsTestClient::connect_with_certificate(
+                server_address,
+                &[untrusted],
+                &untrusted_key
+            )
+            .await
+            .is_err());
+        };
+
+        let app = app::new().resource(app::resource("/hello").get(handle_get));
+        tokio::select! {
+            _ = server.serve(app) => panic!("Server stopped"),
+            _ = devices_task => {}
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use async_trait::async_trait;
+use coap_lite::Packet;
+use coap_server::transport::{
+    BoxedFramedBinding, FramedBinding, FramedItem, FramedReadError, Transport, TransportError,
+};
+use futures::{Sink, Stream};
+use std::{
+    fmt, io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{net::ToSocketAddrs, sync::mpsc};
+
+use crate::oscore::OscoreContexts;
+use client::CoapClient;
+use dtls::{DtlsCredentials, DtlsSessions};
+use oscore::OscoreLayer;
+
+pub mod client;
+pub mod dtls;
+pub mod oscore;
+#[cfg(test)]
+pub(crate) mod test_client;
+pub mod trust_store;
+pub mod udp;
+
+/// The identity a device authenticated with, in the DTLS handshake or with an OSCORE context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerIdentity {
+    Psk(String),
+    // The endpoint name the trust store binds the key to and the DER encoded SubjectPublicKeyInfo
+    RawPublicKey {
+        endpoint_name: String,
+        public_key: Vec<u8>,
+    },
+    // The common name and the subject alternative names of the certificate
+    Certificate {
+        names: Vec<String>,
+    },
+    // The endpoint name the security context belongs to and the Sender ID of the device
+    Oscore {
+        endpoint_name: String,
+        recipient_id: Vec<u8>,
+    },
+}
+
+impl PeerIdentity {
+    /// Whether a device with this identity may register with the endpoint name `device_endpoint`.
+    pub fn matches(&self, device_endpoint: &str) -> bool {
+        match self {
+            PeerIdentity::Psk(identity) => identity == device_endpoint,
+            PeerIdentity::RawPublicKey { endpoint_name, .. } => endpoint_name == device_endpoint,
+            PeerIdentity::Certificate { names } => names.iter().any(|name| name == device_endpoint),
+            PeerIdentity::Oscore { endpoint_name, .. } => endpoint_name == device_endpoint,
+        }
+    }
+}
+
+impl fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PeerIdentity::Psk(identity) => write!(f, "PSK identity {}", identity),
+            PeerIdentity::RawPublicKey { endpoint_name, .. } => {
+                write!(f, "raw public key of {}", endpoint_name)
+            }
+            PeerIdentity::Certificate { names } => {
+                write!(f, "certificate of {}", names.join(", "))
+            }
+            PeerIdentity::Oscore { endpoint_name, .. } => {
+                write!(f, "OSCORE context of {}", endpoint_name)
+            }
+        }
+    }
+}
+
+/// How the server reaches a device, it follows how the device authenticated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    // Plain UDP
+    Udp,
+    // The DTLS socket, only while the device has a session
+    Dtls,
+    // The UDP socket, with the requests protected by the OSCORE context of the endpoint
+    Oscore(String),
+}
+
+impl Route {
+    /// The route to a device that authenticated with `identity`, None for plain UDP.
+    pub fn of(identity: Option<&PeerIdentity>) -> Self {
+        match identity {
+            Some(PeerIdentity::Psk(_))
+            | Some(PeerIdentity::RawPublicKey { .. })
+            | Some(PeerIdentity::Certificate { .. }) => Route::Dtls,
+            Some(PeerIdentity::Oscore { endpoint_name, .. }) => {
+                Route::Oscore(endpoint_name.clone())
+            }
+            None => Route::Udp,
+        }
+    }
+}
+
+/// A device as seen by the [`CoapClient`]: its address and how it is reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub address: SocketAddr,
+    pub route: Route,
+}
+
+impl Peer {
+    pub fn new(address: SocketAddr, route: Route) -> Self {
+        Peer { address, route }
+    }
+}
+
+/// Transport for the CoAP server that shares its socket with a [`CoapClient`].
+/// Requests from devices are passed on to the server, responses to requests of the server
+/// are handed to the client. Created by binding one of the socket types, e.g. [`udp::bind`].
+pub struct Lwm2mTransport {
+    binding: ChannelBinding,
+}
+
+#[async_trait]
+impl Transport for Lwm2mTransport {
+    type Endpoint = SocketAddr;
+
+    async fn bind(self) -> Result<BoxedFramedBinding<Self::Endpoint>, TransportError> {
+        Ok(Box::pin(self.binding))
+    }
+}
+
+// Binding handed to the CoAP server, the socket itself is driven by the tasks of the transport.
+struct ChannelBinding {
+    incoming_rx: mpsc::UnboundedReceiver<FramedItem<SocketAddr>>,
+    outgoing_tx: mpsc::UnboundedSender<(Packet, SocketAddr)>,
+    mtu: Option<u32>,
+    // Told about the responses of the server, see `CoapClient::response_sent`
+    client: CoapClient,
+}
+
+impl Stream for ChannelBinding {
+    type Item = Result<FramedItem<SocketAddr>, FramedReadError<SocketAddr>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming_rx.poll_recv(cx).map(|item| item.map(Ok))
+    }
+}
+
+impl Sink<FramedItem<SocketAddr>> for ChannelBinding {
+    type Error = TransportError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: FramedItem<SocketAddr>) -> Result<(), Self::Error> {
+        let sent_tx = self.client.response_waiter(&item.0, item.1);
+        self.outgoing_tx
+            .send(item)
+            .map_err(|_| TransportError::Unspecified("Transport is closed".to_string()))?;
+        if let Some(sent_tx) = sent_tx {
+            let _ = sent_tx.send(());
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl FramedBinding<SocketAddr> for ChannelBinding {
+    fn mtu(&self) -> Option<u32> {
+        self.mtu
+    }
+}
+
+// Hands out incoming packets to either the client or the server
+#[derive(Clone)]
+struct PacketRouter {
+    client: CoapClient,
+    server_tx: mpsc::UnboundedSender<FramedItem<SocketAddr>>,
+    // The socket the packets were received on
+    route: Route,
+}
+
+impl PacketRouter {
+    fn route(&self, packet: Packet, peer: SocketAddr) {
+        if let Some(packet) = self
+            .client
+            .handle(packet, Peer::new(peer, self.route.clone()))
+        {
+            let _ = self.server_tx.send((packet, peer));
+        }
+    }
+}
+
+// Creates the transport around the outgoing channel of a socket, the socket feeds received
+// packets to the returned router which hands responses to `client`.
+fn new_transport(
+    client: CoapClient,
+    outgoing_tx: mpsc::UnboundedSender<(Packet, SocketAddr)>,
+    mtu: Option<u32>,
+    route: Route,
+) -> (Lwm2mTransport, PacketRouter) {
+    let (server_tx, incoming_rx) = mpsc::unbounded_channel();
+    let router = PacketRouter {
+        client: client.clone(),
+        server_tx,
+        route,
+    };
+    let transport = Lwm2mTransport {
+        binding: ChannelBinding {
+            incoming_rx,
+            outgoing_tx,
+            mtu,
+            client,
+        },
+    };
+    (transport, router)
+}
+
+/// The plain UDP and the DTLS transport of the server with the client they share.
+pub struct Transports {
+    pub udp: Lwm2mTransport,
+    pub dtls: Lwm2mTransport,
+    pub client: CoapClient,
+    pub sessions: DtlsSessions,
+    pub oscore: OscoreLayer,
+}
+
+/// Binds a UDP and a DTLS socket behind a single client. Requests to a device go out on the
+/// socket its [`Route`] names, a device that authenticated is never sent to in plaintext:
+/// without a DTLS session its requests fail with [`client::ClientError::NoSession`], without
+/// an OSCORE context with [`client::ClientError::Oscore`].
+///
+/// # Arguments
+///
+/// * `udp_addresses` - The addresses of the plain UDP socket, e.g. 0.0.0.0:5683
+/// * `dtls_addresses` - The addresses of the DTLS socket, e.g. 0.0.0.0:5684
+/// * `credentials` - The pre-shared keys, certificate and trust store devices authenticate with
+/// * `oscore_contexts` - The OSCORE security contexts of the devices
+pub async fn bind_secure(
+    udp_addresses: impl ToSocketAddrs,
+    dtls_addresses: impl ToSocketAddrs,
+    credentials: DtlsCredentials,
+    oscore_contexts: OscoreContexts,
+) -> io::Result<Transports> {
+    let (udp_tx, udp_rx) = mpsc::unbounded_channel();
+    let (dtls_tx, dtls_rx) = mpsc::unbounded_channel();
+    let sessions = DtlsSessions::default();
+    let oscore = OscoreLayer::new(oscore_contexts);
+    let client = CoapClient::secure(
+        udp_tx.clone(),
+        dtls_tx.clone(),
+        sessions.clone(),
+        oscore.clone(),
+    );
+    let udp = udp::start(
+        udp_addresses,
+        client.clone(),
+        udp_tx,
+        udp_rx,
+        Some(oscore.clone()),
+    )
+    .await?;
+    let dtls = dtls::start(
+        dtls_addresses,
+        credentials,
+        client.clone(),
+        dtls_tx,
+        dtls_rx,
+        sessions.clone(),
+    )
+    .await?;
+    Ok(Transports {
+        udp,
+        dtls,
+        client,
+        sessions,
+        oscore,
+    })
+}
@@ -0,0 +1,523 @@
+use coap_lite::{CoapOption, MessageClass, MessageType, Packet, ResponseType};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::PeerIdentity;
+use crate::oscore::{
+    err::OscoreError,
+    message::{OscoreOption, RequestId},
+    OscoreContexts,
+};
+
+// Based on https://datatracker.ietf.org/doc/html/rfc7252#section-4.8.2
+// How long an exchange is kept to match its responses and to recognize retransmissions
+const EXCHANGE_LIFETIME: Duration = Duration::from_secs(247);
+
+/// Protects the messages of devices that have an OSCORE security context, on the transport so
+/// the CoAP server and client only see plain messages. Proxies in between see the outer
+/// options only.
+///
+/// Responses are protected when their request was. Requests of the server are protected with
+/// the context of the endpoint the device registered with, see `protect_request`, so a plain
+/// packet from its address never turns the protection off.
+#[derive(Clone)]
+pub struct OscoreLayer {
+    state: Arc<Mutex<LayerState>>,
+}
+
+struct LayerState {
+    contexts: OscoreContexts,
+    // Protected requests of the devices and of the server, by address and token
+    incoming: HashMap<(SocketAddr, Vec<u8>), IncomingExchange>,
+    outgoing: HashMap<(SocketAddr, Vec<u8>), OutgoingExchange>,
+}
+
+struct IncomingExchange {
+    device_endpoint: String,
+    request: RequestId,
+    message_id: u16,
+    // Sent again when the request is retransmitted, a response never gets a second nonce
+    response: Option<Packet>,
+    started: Instant,
+}
+
+struct OutgoingExchange {
+    device_endpoint: String,
+    request: RequestId,
+    // Sent again when the client retransmits the request
+    protected: Packet,
+    // Observations are kept for their notifications until the client closes their exchange
+    observe: bool,
+    started: Instant,
+}
+
+impl OscoreLayer {
+    pub fn new(contexts: OscoreContexts) -> Self {
+        OscoreLayer {
+            state: Arc::new(Mutex::new(LayerState {
+                contexts,
+                incoming: HashMap::new(),
+                outgoing: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Unprotects a packet received from `peer`, plain packets are passed on as they are.
+    /// A request that cannot be verified is answered with the returned error response,
+    /// a response that cannot be verified is dropped.
+    pub fn incoming(&self, packet: Packet, peer: SocketAddr) -> Result<Packet, Option<Packet>> {
+        let mut state = self.state.lock().unwrap();
+        state.prune();
+        match packet.header.code {
+            MessageClass::Request(_) => state.incoming_request(packet, peer),
+            MessageClass::Response(_) => state.incoming_response(packet, peer).ok_or(None),
+            _ => Ok(packet),
+        }
+    }
+
+    /// Protects a response before it is sent to `peer` when its request was protected,
+    /// other packets go out as they are. None if the packet has to be dropped.
+    pub fn outgoing(&self, packet: Packet, peer: SocketAddr) -> Option<Packet> {
+        let mut state = self.state.lock().unwrap();
+        match packet.header.code {
+            MessageClass::Response(_) => state.outgoing_response(packet, peer),
+            _ => Some(packet),
+        }
+    }
+
+    /// Protects a request of the server to the device `device_endpoint` at `peer` with the
+    /// context of the device, a retransmission is the same protected message.
+    pub fn protect_request(
+        &self,
+        packet: Packet,
+        peer: SocketAddr,
+        device_endpoint: &str,
+    ) -> Result<Packet, OscoreError> {
+        let mut state = self.state.lock().unwrap();
+        state.protect_request(packet, peer, device_endpoint)
+    }
+
+    /// Forgets the requests of the server with `token`, once the client closed their exchange.
+    /// Ends the observation the token belongs to, its notifications are no longer unprotected.
+    pub fn close_exchange(&self, token: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .outgoing
+            .retain(|(_, exchange_token), _| exchange_token != token);
+    }
+
+    /// The identity of the device that sent the request with `token` from `peer`,
+    /// None if the request was not protected.
+    pub fn identity(&self, peer: SocketAddr, token: &[u8]) -> Option<PeerIdentity> {
+        let state = self.state.lock().unwrap();
+        let exchange = state.incoming.get(&(peer, token.to_vec()))?;
+        let context = state.contexts.get(&exchange.device_endpoint)?;
+        Some(PeerIdentity::Oscore {
+            endpoint_name: exchange.device_endpoint.clone(),
+            recipient_id: context.recipient_id().to_vec(),
+        })
+    }
+}
+
+impl LayerState {
+    fn prune(&mut self) {
+        self.incoming
+            .retain(|_, exchange| exchange.started.elapsed() < EXCHANGE_LIFETIME);
+        self.outgoing.retain(|_, exchange| {
+            exchange.observe || exchange.started.elapsed() < EXCHANGE_LIFETIME
+        });
+    }
+
+    fn incoming_request(
+        &mut self,
+        packet: Packet,
+        peer: SocketAddr,
+    ) -> Result<Packet, Option<Packet>> {
+        let key = (peer, packet.get_token().to_vec());
+        let value = match packet.get_first_option(CoapOption::Oscore) {
+            Some(value) => value.clone(),
+            // A plain request must not take over the exchange of a protected one, its
+            // response would go out unprotected
+            None if self.incoming.contains_key(&key) => return Err(None),
+            None => return Ok(packet),
+        };
+        if let Some(exchange) = self.incoming.get(&key) {
+            if exchange.message_id == packet.header.message_id {
+                return Err(exchange.response.clone());
+            }
+        }
+
+        let unprotected = OscoreOption::decode(&value).and_then(|option| {
+            let kid = option.kid.ok_or(OscoreError::InvalidOption)?;
+            let device_endpoint = self
+                .contexts
+                .find(&kid, option.kid_context.as_deref())
+                .ok_or(OscoreError::UnknownContext)?
+                .to_owned();
+            let context = self
+                .contexts
+                .get_mut(&device_endpoint)
+                .ok_or(OscoreError::UnknownContext)?;
+            let (unprotected, request) = context.unprotect_request(&packet)?;
+            // The request is only accepted once its sequence number would be a replay after a restart
+            self.contexts.store(&device_endpoint)?;
+            Ok((device_endpoint, unprotected, request))
+        });
+        match unprotected {
+            Ok((device_endpoint, unprotected, request)) => {
+                self.incoming.insert(
+                    key,
+                    IncomingExchange {
+                        device_endpoint,
+                        request,
+                        message_id: packet.header.message_id,
+                        response: None,
+                        started: Instant::now(),
+                    },
+                );
+                Ok(unprotected)
+            }
+            Err(err) => Err(Some(error_response(&packet, &err))),
+        }
+    }
+
+    fn incoming_response(&mut self, packet: Packet, peer: SocketAddr) -> Option<Packet> {
+        let key = (peer, packet.get_token().to_vec());
+        let exchange = match self.outgoing.get(&key) {
+            Some(exchange) => exchange,
+            None => return Some(packet),
+        };
+        if packet.get_option(CoapOption::Oscore).is_none() {
+            // A device that cannot verify a request answers with a plain error
+            return is_error(&packet).then_some(packet);
+        }
+        let context = self.contexts.get(&exchange.device_endpoint)?;
+        let unprotected = context
+            .unprotect_response(&packet, &exchange.request)
+            .ok()?;
+        if !exchange.observe || unprotected.get_option(CoapOption::Observe).is_none() {
+            self.outgoing.remove(&key);
+        }
+        Some(unprotected)
+    }
+
+    fn protect_request(
+        &mut self,
+        packet: Packet,
+        peer: SocketAddr,
+        device_endpoint: &str,
+    ) -> Result<Packet, OscoreError> {
+        self.prune();
+        let key = (peer, packet.get_token().to_vec());
+        if let Some(exchange) = self.outgoing.get(&key) {
+            if exchange.device_endpoint == device_endpoint
+                && exchange.protected.header.message_id == packet.header.message_id
+            {
+                return Ok(exchange.protected.clone());
+            }
+        }
+        let context = self
+            .contexts
+            .get_mut(device_endpoint)
+            .ok_or(OscoreError::UnknownContext)?;
+        let (protected, request) = context.protect_request(&packet)?;
+        // A sequence number only goes out once a restart can not use it again
+        self.contexts.store(device_endpoint)?;
+        self.outgoing.insert(
+            key,
+            OutgoingExchange {
+                device_endpoint: device_endpoint.to_owned(),
+                request,
+                protected: protected.clone(),
+                observe: matches!(packet.get_observe_value(), Some(Ok(0))),
+                started: Instant::now(),
+            },
+        );
+        Ok(protected)
+    }
+
+    fn outgoing_response(&mut self, packet: Packet, peer: SocketAddr) -> Option<Packet> {
+        let key = (peer, packet.get_token().to_vec());
+        let exchange = match self.incoming.get_mut(&key) {
+            Some(exchange) => exchange,
+            None => return Some(packet),
+        };
+        let observe = packet.get_option(CoapOption::Observe).is_some();
+        if let (Some(response), false) = (&exchange.response, observe) {
+            let mut response = response.clone();
+            response.header.set_type(packet.header.get_type());
+            response.header.message_id = packet.header.message_id;
+            return Some(response);
+        }
+        let context = self.contexts.get_mut(&exchange.device_endpoint)?;
+        let protected = context.protect_response(&packet, &exchange.request).ok()?;
+        self.contexts.store(&exchange.device_endpoint).ok()?;
+        exchange.response = Some(protected.clone());
+        Some(protected)
+    }
+}
+
+// Based on https://www.rfc-editor.org/rfc/rfc8613#section-8.2
+// Errors are sent without protection and must not be cached
+fn error_response(request: &Packet, err: &OscoreError) -> Packet {
+    let code = match err {
+        OscoreError::InvalidOption => ResponseType::BadOption,
+        OscoreError::UnknownContext | OscoreError::Replay => ResponseType::Unauthorized,
+        OscoreError::DecryptionFailed => ResponseType::BadRequest,
+        _ => ResponseType::InternalServerError,
+    };
+    let mut response = Packet::new();
+    let message_type = match request.header.get_type() {
+        MessageType::Confirmable => MessageType::Acknowledgement,
+        _ => MessageType::NonConfirmable,
+    };
+    response.header.set_type(message_type);
+    response.header.code = MessageClass::Response(code);
+    response.header.message_id = request.header.message_id;
+    response.set_token(request.get_token().to_vec());
+    response.add_option(CoapOption::MaxAge, vec![]);
+    response.payload = err.to_string().into_bytes();
+    response
+}
+
+fn is_error(packet: &Packet) -> bool {
+    u8::from(packet.header.code) >> 5 >= 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oscore::context::{
+        tests::{client_parameters, server_parameters},
+        SecurityContext,
+    };
+    use coap_lite::RequestType;
+
+    const PEER: &str = "127.0.0.1:5683";
+
+    fn layer() -> OscoreLayer {
+        let mut contexts = OscoreContexts::default();
+        contexts.add("device1", &server_parameters()).unwrap();
+        OscoreLayer::new(contexts)
+    }
+
+    fn request(code: RequestType, token: &[u8], message_id: u16) -> Packet {
+        let mut request = Packet::new();
+        request.header.set_type(MessageType::Confirmable);
+        request.header.code = MessageClass::Request(code);
+        request.header.message_id = message_id;
+        request.set_token(token.to_vec());
+        request
+    }
+
+    fn response(request: &Packet, payload: &[u8]) -> Packet {
+        let mut response = Packet::new();
+        response.header.set_type(MessageType::Acknowledgement);
+        response.header.code = MessageClass::Response(ResponseType::Content);
+        response.header.message_id = request.header.message_id;
+        response.set_token(request.get_token().to_vec());
+        response.payload = payload.to_vec();
+        response
+    }
+
+    #[test]
+    fn test_device_request() {
+        let layer = layer();
+        let peer = PEER.parse().unwrap();
+        let mut device = SecurityContext::derive(&client_parameters()).unwrap();
+
+        let mut registration = request(RequestType::Post, b"reg", 1);
+        registration.add_option(CoapOption::UriPath, b"rd".to_vec());
+        let (protected, request_id) = device.protect_request(&registration).unwrap();
+        let unprotected = layer.incoming(protected.clone(), peer).unwrap();
+        assert_eq!(
+            unprotected.get_first_option(CoapOption::UriPath),
+            Some(&b"rd".to_vec())
+        );
+        assert_eq!(
+            layer.identity(peer, b"reg"),
+            Some(PeerIdentity::Oscore {
+                endpoint_name: "device1".to_owned(),
+                recipient_id: vec![],
+            })
+        );
+        // No response was sent yet, the retransmission is dropped
+        assert!(matches!(layer.incoming(protected.clone(), peer), Err(None)));
+
+        let mut created = response(&registration, b"");
+        created.header.code = MessageClass::Response(ResponseType::Created);
+        let sent = layer.outgoing(created, peer).unwrap();
+        let received = device.unprotect_response(&sent, &request_id).unwrap();
+        assert_eq!(
+            received.header.code,
+            MessageClass::Response(ResponseType::Created)
+        );
+        // A retransmission is answered with the same response
+        match layer.incoming(protected.clone(), peer) {
+            Err(Some(response)) => assert_eq!(response.payload, sent.payload),
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        // A replay with another message ID
+        let mut replay = protected;
+        replay.header.message_id = 2;
+        match layer.incoming(replay, peer) {
+            Err(Some(response)) => {
+                assert_eq!(
+                    response.header.code,
+                    MessageClass::Response(ResponseType::Unauthorized)
+                );
+                assert_eq!(response.payload, b"Replay detected");
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        // Plain requests pass and have no identity
+        let plain = request(RequestType::Get, b"plain", 3);
+        assert!(layer.incoming(plain, peer).is_ok());
+        assert_eq!(layer.identity(peer, b"plain"), None);
+    }
+
+    #[test]
+    fn test_unknown_context() {
+        let layer = layer();
+        let peer = PEER.parse().unwrap();
+        let mut parameters = client_parameters();
+        parameters.sender_id = vec![0x05];
+        let mut device = SecurityContext::derive(&parameters).unwrap();
+        let (protected, _) = device
+            .protect_request(&request(RequestType::Post, b"reg", 1))
+            .unwrap();
+        match layer.incoming(protected, peer) {
+            Err(Some(response)) => {
+                assert_eq!(
+                    response.header.code,
+                    MessageClass::Response(ResponseType::Unauthorized)
+                );
+                assert_eq!(response.header.get_type(), MessageType::Acknowledgement);
+                assert_eq!(response.get_token(), b"reg");
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        let mut invalid = request(RequestType::Post, b"reg", 2);
+        invalid.add_option(CoapOption::Oscore, vec![0x80]);
+        match layer.incoming(invalid, peer) {
+            Err(Some(response)) => assert_eq!(
+                response.header.code,
+                MessageClass::Response(ResponseType::BadOption)
+            ),
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_server_request() {
+        let layer = layer();
+        let peer = PEER.parse().unwrap();
+        let mut device = SecurityContext::derive(&client_parameters()).unwrap();
+
+        let mut read = request(RequestType::Get, b"read", 11);
+        read.add_option(CoapOption::UriPath, b"3".to_vec());
+        let sent = layer
+            .protect_request(read.clone(), peer, "device1")
+            .unwrap();
+        assert!(sent.get_option(CoapOption::UriPath).is_none());
+        assert_eq!(sent.header.code, MessageClass::Request(RequestType::Post));
+        // A retransmission is the same message, which the socket sends as it is
+        let resent = layer
+            .protect_request(read.clone(), peer, "device1")
+            .unwrap();
+        assert_eq!(resent.payload, sent.payload);
+        assert_eq!(layer.outgoing(resent, peer).unwrap().payload, sent.payload);
+
+        let (received, request_id) = device.unprotect_request(&sent).unwrap();
+        assert_eq!(
+            received.get_first_option(CoapOption::UriPath),
+            Some(&b"3".to_vec())
+        );
+        let content = device
+            .protect_response(&response(&received, b"device"), &request_id)
+            .unwrap();
+        let unprotected = layer.incoming(content.clone(), peer).unwrap();
+        assert_eq!(unprotected.payload, b"device");
+        // The exchange is done, the same response is no longer unprotected
+        assert_eq!(
+            layer.incoming(content, peer).unwrap().header.code,
+            MessageClass::Response(ResponseType::Changed)
+        );
+
+        // Responses that do not verify are dropped, plain errors are passed on
+        let read = request(RequestType::Get, b"read2", 12);
+        let sent = layer.protect_request(read, peer, "device1").unwrap();
+        let mut forged = response(&sent, b"forged");
+        forged.add_option(CoapOption::Oscore, vec![]);
+        assert!(matches!(layer.incoming(forged, peer), Err(None)));
+        let plain = response(&sent, b"plain");
+        assert!(matches!(layer.incoming(plain, peer), Err(None)));
+        let mut error = response(&sent, b"");
+        error.header.code = MessageClass::Response(ResponseType::Unauthorized);
+        assert!(layer.incoming(error, peer).is_ok());
+
+        // Without a context the request is not sent at all
+        let read = request(RequestType::Get, b"read3", 13);
+        assert!(matches!(
+            layer.protect_request(read, peer, "device2"),
+            Err(OscoreError::UnknownContext)
+        ));
+    }
+
+    #[test]
+    fn test_observation() {
+        let layer = layer();
+        let peer = PEER.parse().unwrap();
+        let mut device = SecurityContext::derive(&client_parameters()).unwrap();
+
+        let mut observe = request(RequestType::Get, b"observe", 11);
+        observe.set_observe_value(0);
+        let sent = layer.protect_request(observe, peer, "device1").unwrap();
+        let (received, request_id) = device.unprotect_request(&sent).unwrap();
+        let mut notification = response(&received, b"21.5");
+        notification.set_observe_value(2);
+        let notification = device.protect_response(&notification, &request_id).unwrap();
+        assert!(layer.incoming(notification.clone(), peer).is_ok());
+
+        // Once the observation ended its notifications are no longer accepted
+        layer.close_exchange(b"observe");
+        assert_eq!(
+            layer.incoming(notification, peer).unwrap().header.code,
+            MessageClass::Response(ResponseType::Content)
+        );
+        assert!(layer.state.lock().unwrap().outgoing.is_empty());
+    }
+
+    #[test]
+    fn test_plain_request_keeps_protection() {
+        let layer = layer();
+        let peer = PEER.parse().unwrap();
+        let mut device = SecurityContext::derive(&client_parameters()).unwrap();
+
+        let registration = request(RequestType::Post, b"reg", 1);
+        let (protected, request_id) = device.protect_request(&registration).unwrap();
+        layer.incoming(protected, peer).unwrap();
+
+        // A spoofed plain request with the token of the protected one is dropped
+        let spoofed = request(RequestType::Post, b"reg", 2);
+        assert!(matches!(layer.incoming(spoofed, peer), Err(None)));
+        let sent = layer
+            .outgoing(response(&registration, b"created"), peer)
+            .unwrap();
+        assert!(device.unprotect_response(&sent, &request_id).is_ok());
+
+        // Other plain requests leave the requests of the server protected
+        let plain = request(RequestType::Get, b"plain", 3);
+        assert!(layer.incoming(plain, peer).is_ok());
+        let read = request(RequestType::Get, b"read", 11);
+        let sent = layer.protect_request(read, peer, "device1").unwrap();
+        assert!(sent.get_option(CoapOption::Oscore).is_some());
+    }
+}
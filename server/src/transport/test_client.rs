@@ -0,0 +1,216 @@
+use coap_lite::Packet;
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, MsbOption},
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    ssl::{Ssl, SslContext, SslContextBuilder, SslMethod, SslVerifyMode, SslVersion},
+    x509::{
+        extension::{BasicConstraints, KeyUsage, SubjectAlternativeName},
+        X509Name, X509,
+    },
+};
+use std::{io, net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+    sync::mpsc,
+    time,
+};
+use tokio_openssl::SslStream;
+
+use super::dtls::{DatagramStream, ServerCertificate};
+
+/// An address on localhost that was free a moment ago, for servers the tests bind.
+pub(crate) fn free_address() -> SocketAddr {
+    std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// A subject alternative name of a test certificate.
+pub(crate) enum CertificateName<'a> {
+    Dns(&'a str),
+    Uri(&'a str),
+}
+
+/// A certificate authority, self-signed without an issuer.
+pub(crate) fn authority(
+    common_name: &str,
+    issuer: Option<&(X509, PKey<Private>)>,
+) -> (X509, PKey<Private>) {
+    new_certificate(common_name, &[], issuer, true)
+}
+
+/// The certificate of a device or server with a new P-256 key, self-signed without an issuer.
+pub(crate) fn certificate(
+    common_name: &str,
+    alternative_names: &[CertificateName],
+    issuer: Option<&(X509, PKey<Private>)>,
+) -> (X509, PKey<Private>) {
+    new_certificate(common_name, alternative_names, issuer, false)
+}
+
+/// The credentials of a server with a self-signed certificate.
+pub(crate) fn server_certificate() -> ServerCertificate {
+    let (certificate, private_key) = certificate("lwm2m.example.com", &[], None);
+    ServerCertificate {
+        chain: vec![certificate],
+        private_key,
+    }
+}
+
+fn new_certificate(
+    common_name: &str,
+    alternative_names: &[CertificateName],
+    issuer: Option<&(X509, PKey<Private>)>,
+    is_authority: bool,
+) -> (X509, PKey<Private>) {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let mut name = X509Name::builder().unwrap();
+    name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+        .unwrap();
+    let name = name.build();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    let mut serial = BigNum::new().unwrap();
+    serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+    builder
+        .set_serial_number(&serial.to_asn1_integer().unwrap())
+        .unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder
+        .set_issuer_name(issuer.map_or(&name, |(issuer, _)| issuer.subject_name()))
+        .unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    if is_authority {
+        let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+        builder.append_extension(constraints).unwrap();
+        let usage = KeyUsage::new().key_cert_sign().build().unwrap();
+        builder.append_extension(usage).unwrap();
+    }
+    if !alternative_names.is_empty() {
+        let mut names = SubjectAlternativeName::new();
+        for name in alternative_names {
+            match name {
+                CertificateName::Dns(dns) => names.dns(dns),
+                CertificateName::Uri(uri) => names.uri(uri),
+            };
+        }
+        let names = names.build(&builder.x509v3_context(None, None)).unwrap();
+        builder.append_extension(names).unwrap();
+    }
+    let signing_key = issuer.map_or(&key, |(_, issuer_key)| issuer_key);
+    builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+    (builder.build(), key)
+}
+
+/// A device that talks to the server over DTLS with a pre-shared key or a certificate.
+pub(crate) struct DtlsTestClient {
+    stream: SslStream<DatagramStream>,
+}
+
+impl DtlsTestClient {
+    pub(crate) async fn connect(
+        server: SocketAddr,
+        identity: &[u8],
+        key: &[u8],
+    ) -> io::Result<Self> {
+        let identity = [identity, &[0]].concat();
+        let key = key.to_vec();
+        let mut builder = context_builder();
+        builder.set_cipher_list("PSK-AES128-CCM8").unwrap();
+        builder.set_psk_client_callback(move |_, _, identity_buffer, psk| {
+            identity_buffer[..identity.len()].copy_from_slice(&identity);
+            psk[..key.len()].copy_from_slice(&key);
+            Ok(key.len())
+        });
+        Self::handshake(server, builder.build()).await
+    }
+
+    /// Connects with the certificate `chain` of the device, its own certificate first.
+    /// The certificate of the server is not checked.
+    pub(crate) async fn connect_with_certificate(
+        server: SocketAddr,
+        chain: &[X509],
+        private_key: &PKey<Private>,
+    ) -> io::Result<Self> {
+        let mut builder = context_builder();
+        builder.set_cipher_list("ECDHE-ECDSA-AES128-CCM8").unwrap();
+        builder.set_certificate(&chain[0]).unwrap();
+        for intermediate in &chain[1..] {
+            builder.add_extra_chain_cert(intermediate.clone()).unwrap();
+        }
+        builder.set_private_key(private_key).unwrap();
+        builder.set_verify(SslVerifyMode::NONE);
+        Self::handshake(server, builder.build()).await
+    }
+
+    async fn handshake(server: SocketAddr, context: SslContext) -> io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let (datagrams_tx, datagrams_rx) = mpsc::unbounded_channel();
+        let receive_socket = socket.clone();
+        tokio::spawn(async move {
+            let mut buffer = vec![0; 16 * 1024];
+            while let Ok((length, _)) = receive_socket.recv_from(&mut buffer).await {
+                if datagrams_tx.send(buffer[..length].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let ssl = Ssl::new(&context).unwrap();
+        let stream = DatagramStream {
+            socket,
+            peer: server,
+            datagrams_rx,
+            skip_hello_verify_request: false,
+        };
+        let mut stream = SslStream::new(ssl, stream).unwrap();
+        // A wrong key only shows in the Finished message, which DTLS drops without an alert
+        time::timeout(Duration::from_secs(2), Pin::new(&mut stream).connect())
+            .await?
+            .map_err(io::Error::other)?;
+        Ok(DtlsTestClient { stream })
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.stream.get_ref().socket.local_addr().unwrap()
+    }
+
+    /// Sends a datagram from the address of the device outside of its session.
+    pub(crate) async fn send_datagram(&self, datagram: &[u8]) {
+        let stream = self.stream.get_ref();
+        stream.socket.send_to(datagram, stream.peer).await.unwrap();
+    }
+
+    pub(crate) async fn send(&mut self, packet: &Packet) {
+        let bytes = packet.to_bytes().unwrap();
+        self.stream.write_all(&bytes).await.unwrap();
+    }
+
+    pub(crate) async fn recv(&mut self) -> Packet {
+        let mut buffer = vec![0; 16 * 1024];
+        let length = self.stream.read(&mut buffer).await.unwrap();
+        Packet::from_bytes(&buffer[..length]).unwrap()
+    }
+}
+
+fn context_builder() -> SslContextBuilder {
+    let mut builder = SslContext::builder(SslMethod::dtls_client()).unwrap();
+    builder
+        .set_min_proto_version(Some(SslVersion::DTLS1_2))
+        .unwrap();
+    builder
+}
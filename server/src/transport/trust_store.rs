@@ -0,0 +1,164 @@
+use openssl::{
+    error::ErrorStack,
+    nid::Nid,
+    stack::Stack,
+    x509::{store::X509StoreBuilder, X509Ref, X509StoreContext, X509},
+};
+use std::collections::HashMap;
+
+use super::PeerIdentity;
+
+/// Decides which devices may connect with a raw public key or an X.509 certificate.
+pub trait TrustStore: Send + Sync {
+    /// The identity of a device that presented `chain`, its own certificate followed by any
+    /// intermediate certificates. None if the device is not trusted.
+    fn verify(&self, chain: &[X509]) -> Option<PeerIdentity>;
+}
+
+/// A trust store kept in memory: raw public keys bound to the endpoint name of their device,
+/// and the certificate authorities that sign the certificates of devices.
+///
+/// OpenSSL 3.0 has no support for raw public keys in the handshake (RFC 7250), devices in
+/// RPK mode present a self-signed certificate of which only the public key is pinned.
+#[derive(Debug, Clone, Default)]
+pub struct LocalTrustStore {
+    // DER encoded SubjectPublicKeyInfo to endpoint name
+    public_keys: HashMap<Vec<u8>, String>,
+    authorities: Vec<X509>,
+}
+
+impl LocalTrustStore {
+    /// Trusts the DER encoded SubjectPublicKeyInfo `public_key` for the device `device_endpoint`.
+    pub fn add_public_key(&mut self, public_key: &[u8], device_endpoint: &str) {
+        self.public_keys
+            .insert(public_key.to_vec(), device_endpoint.to_owned());
+    }
+
+    /// Trusts every certificate signed by `authority`, which may be a root or an intermediate.
+    pub fn add_authority(&mut self, authority: X509) {
+        self.authorities.push(authority);
+    }
+
+    // Whether `certificate` leads to one of the authorities through `intermediates`
+    fn verify_certificate(
+        &self,
+        certificate: &X509Ref,
+        intermediates: &[X509],
+    ) -> Result<bool, ErrorStack> {
+        let mut store = X509StoreBuilder::new()?;
+        for authority in &self.authorities {
+            store.add_cert(authority.clone())?;
+        }
+        let store = store.build();
+        let mut chain = Stack::new()?;
+        for intermediate in intermediates {
+            chain.push(intermediate.clone())?;
+        }
+        X509StoreContext::new()?.init(&store, certificate, &chain, |context| context.verify_cert())
+    }
+}
+
+impl TrustStore for LocalTrustStore {
+    fn verify(&self, chain: &[X509]) -> Option<PeerIdentity> {
+        let (certificate, intermediates) = chain.split_first()?;
+        let key = certificate.public_key().ok()?;
+        let public_key = key.public_key_to_der().ok()?;
+        if let Some(device_endpoint) = self.public_keys.get(&public_key) {
+            // The certificate only carries the key, it has to be signed with that key itself
+            let self_signed = intermediates.is_empty() && certificate.verify(&key).unwrap_or(false);
+            // A failed check leaves its errors behind, which would fail the handshake it runs in
+            ErrorStack::get();
+            if !self_signed {
+                return None;
+            }
+            return Some(PeerIdentity::RawPublicKey {
+                endpoint_name: device_endpoint.clone(),
+                public_key,
+            });
+        }
+
+        match self.verify_certificate(certificate, intermediates) {
+            Ok(true) => Some(PeerIdentity::Certificate {
+                names: certificate_names(certificate),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Based on https://www.openmobilealliance.org/release/LightweightM2M/V1_2-20201110-A/HTML-Version/OMA-TS-LightweightM2M_Transport-V1_2-20201110-A.html#5-2-8-3-0-5283-X509-Certificates
+// The endpoint name of a device is the common name of its certificate, or one of the DNS names
+// or URIs of the subject alternative names, e.g. urn:imei:...
+pub(super) fn certificate_names(certificate: &X509Ref) -> Vec<String> {
+    let common_names = certificate
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|entry| entry.data().to_string().ok());
+    let alternative_names = certificate
+        .subject_alt_names()
+        .into_iter()
+        .flatten()
+        .filter_map(|name| name.dnsname().or_else(|| name.uri()).map(str::to_owned));
+    common_names.chain(alternative_names).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test_client::{authority, certificate, CertificateName};
+
+    #[test]
+    fn test_raw_public_key() {
+        let (device, key) = certificate("device123", &[], None);
+        let mut store = LocalTrustStore::default();
+        assert_eq!(store.verify(std::slice::from_ref(&device)), None);
+
+        store.add_public_key(&key.public_key_to_der().unwrap(), "device123");
+        assert_eq!(
+            store.verify(&[device]),
+            Some(PeerIdentity::RawPublicKey {
+                endpoint_name: "device123".to_owned(),
+                public_key: key.public_key_to_der().unwrap(),
+            })
+        );
+
+        // A pinned key in a certificate someone else signed is no raw public key
+        let (signed, signed_key) = certificate("device456", &[], Some(&authority("CA", None)));
+        store.add_public_key(&signed_key.public_key_to_der().unwrap(), "device456");
+        assert_eq!(store.verify(&[signed]), None);
+    }
+
+    #[test]
+    fn test_certificate_chain() {
+        let root = authority("Root CA", None);
+        let intermediate = authority("Intermediate CA", Some(&root));
+        let (device, _) = certificate(
+            "device123",
+            &[
+                CertificateName::Dns("device123.example.com"),
+                CertificateName::Uri("urn:imei:490154203237518"),
+            ],
+            Some(&intermediate),
+        );
+        let mut store = LocalTrustStore::default();
+        store.add_authority(root.0.clone());
+
+        assert_eq!(
+            store.verify(&[device.clone(), intermediate.0.clone()]),
+            Some(PeerIdentity::Certificate {
+                names: vec![
+                    "device123".to_owned(),
+                    "device123.example.com".to_owned(),
+                    "urn:imei:490154203237518".to_owned()
+                ]
+            })
+        );
+        // Without the intermediate the chain is incomplete
+        assert_eq!(store.verify(std::slice::from_ref(&device)), None);
+
+        let other_root = authority("Other CA", None);
+        let (other_device, _) = certificate("device123", &[], Some(&other_root));
+        assert_eq!(store.verify(&[other_device]), None);
+        assert_eq!(store.verify(&[]), None);
+    }
+}
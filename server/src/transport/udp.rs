@@ -0,0 +1,156 @@
+use coap_lite::Packet;
+use std::{io, net::SocketAddr, sync::Arc};
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket},
+    sync::mpsc,
+};
+
+use super::{
+    client::CoapClient, new_transport, oscore::OscoreLayer, Lwm2mTransport, PacketRouter, Route,
+};
+
+// Large enough for any CoAP message over UDP, bigger messages use block-wise transfer
+const MAX_DATAGRAM_SIZE: usize = 1500;
+
+/// Binds a UDP socket that is shared by the CoAP server and the returned client, so requests
+/// to devices are sent from the same address the devices registered to.
+pub async fn bind(addresses: impl ToSocketAddrs) -> io::Result<(Lwm2mTransport, CoapClient)> {
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+    let client = CoapClient::new(outgoing_tx.clone());
+    let transport = start(addresses, client.clone(), outgoing_tx, outgoing_rx, None).await?;
+    Ok((transport, client))
+}
+
+// Binds the socket and starts its tasks, responses to requests of `client` are handed to it
+// and everything sent on the outgoing channel goes out on the socket. With an OSCORE layer
+// the packets of devices with a security context are protected on the way.
+pub(super) async fn start(
+    addresses: impl ToSocketAddrs,
+    client: CoapClient,
+    outgoing_tx: mpsc::UnboundedSender<(Packet, SocketAddr)>,
+    outgoing_rx: mpsc::UnboundedReceiver<(Packet, SocketAddr)>,
+    oscore: Option<OscoreLayer>,
+) -> io::Result<Lwm2mTransport> {
+    let socket = Arc::new(UdpSocket::bind(addresses).await?);
+    let (transport, router) = new_transport(client, outgoing_tx, None, Route::Udp);
+
+    tokio::spawn(receive(socket.clone(), router, oscore.clone()));
+    tokio::spawn(send(socket, outgoing_rx, oscore));
+    Ok(transport)
+}
+
+async fn receive(socket: Arc<UdpSocket>, router: PacketRouter, oscore: Option<OscoreLayer>) {
+    let mut buffer = [0; MAX_DATAGRAM_SIZE];
+    loop {
+        let (length, peer) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        // Malformed packets are dropped silently as described in RFC 7252
+        let packet = match Packet::from_bytes(&buffer[..length]) {
+            Ok(packet) => packet,
+            Err(_) => continue,
+        };
+        let packet = match &oscore {
+            Some(oscore) => match oscore.incoming(packet, peer) {
+                Ok(packet) => packet,
+                Err(response) => {
+                    if let Some(bytes) = response.and_then(|response| response.to_bytes().ok()) {
+                        let _ = socket.send_to(&bytes, peer).await;
+                    }
+                    continue;
+                }
+            },
+            None => packet,
+        };
+        router.route(packet, peer);
+    }
+}
+
+async fn send(
+    socket: Arc<UdpSocket>,
+    mut outgoing_rx: mpsc::UnboundedReceiver<(Packet, SocketAddr)>,
+    oscore: Option<OscoreLayer>,
+) {
+    while let Some((packet, peer)) = outgoing_rx.recv().await {
+        let packet = match &oscore {
+            Some(oscore) => match oscore.outgoing(packet, peer) {
+                Some(packet) => packet,
+                None => continue,
+            },
+            None => packet,
+        };
+        if let Ok(bytes) = packet.to_bytes() {
+            let _ = socket.send_to(&bytes, peer).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Peer;
+    use coap_lite::{CoapOption, MessageClass, MessageType, RequestType, ResponseType};
+    use coap_server::{
+        app::{self, CoapError, Request, Response},
+        CoapServer,
+    };
+
+    async fn handle_get(request: Request<SocketAddr>) -> Result<Response, CoapError> {
+        let mut response = request.new_response();
+        response.message.payload = b"server".to_vec();
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn test_server_and_client_share_socket() {
+        let (transport, client) = bind("127.0.0.1:0").await.unwrap();
+        let server = CoapServer::bind(transport).await.unwrap();
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let device_address = device.local_addr().unwrap();
+
+        let device_task = async {
+            // Request from the server to the device, answered with a piggybacked response
+            let mut request = Packet::new();
+            request.header.code = MessageClass::Request(RequestType::Get);
+            let client_task = tokio::spawn(async move {
+                client
+                    .send(request, Peer::new(device_address, Route::Udp))
+                    .await
+            });
+
+            let mut buffer = [0; MAX_DATAGRAM_SIZE];
+            let (length, server_address) = device.recv_from(&mut buffer).await.unwrap();
+            let request = Packet::from_bytes(&buffer[..length]).unwrap();
+            let mut response = Packet::new();
+            response.header.set_type(MessageType::Acknowledgement);
+            response.header.code = MessageClass::Response(ResponseType::Content);
+            response.header.message_id = request.header.message_id;
+            response.set_token(request.get_token().to_vec());
+            device
+                .send_to(&response.to_bytes().unwrap(), server_address)
+                .await
+                .unwrap();
+            assert!(client_task.await.unwrap().is_ok());
+
+            // Requests from the device to the same address still reach the server
+            let mut request = Packet::new();
+            request.header.set_type(MessageType::Confirmable);
+            request.header.code = MessageClass::Request(RequestType::Get);
+            request.header.message_id = 1;
+            request.add_option(CoapOption::UriPath, b"hello".to_vec());
+            device
+                .send_to(&request.to_bytes().unwrap(), server_address)
+                .await
+                .unwrap();
+            let (length, _) = device.recv_from(&mut buffer).await.unwrap();
+            Packet::from_bytes(&buffer[..length]).unwrap()
+        };
+
+        let app = app::new().resource(app::resource("/hello").get(handle_get));
+        tokio::select! {
+            _ = server.serve(app) => panic!("Server stopped"),
+            response = device_task => assert_eq!(response.payload, b"server".to_vec()),
+        }
+    }
+}
@@ -1,9 +1,6 @@
 use std::time::Duration;
 use timer_tracker::TimerTracker;
-use tokio::{
-    sync::{broadcast, mpsc},
-    time::{self},
-};
+use tokio::time::{self};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {